@@ -65,6 +65,14 @@ impl Code {
     pub const INVALID_ROW_BYTES: Code = Code(0x036F);
     pub const DUPLICATED_COLUMN_NAMES: Code = Code(0x263C);
     pub const NO_COLUMN_CAN_BE_DROPPED: Code = Code(0x2651);
+    /// The consumer is not a member of the group, e.g. evicted for polling too late.
+    pub const TMQ_CONSUMER_MISMATCH: Code = Code(0x4001);
+    /// The offset is out of the range of the vgroup.
+    pub const TMQ_VERSION_OUT_OF_RANGE: Code = Code(0x4007);
+    /// The vgroup is not assigned to the consumer.
+    pub const TMQ_INVALID_VGID: Code = Code(0x4008);
+    /// Nothing is committed for the vgroup, returned by `tmq_committed`.
+    pub const TMQ_NO_COMMITTED: Code = Code(0x4011);
 }
 
 macro_rules! _impl_fmt {
@@ -106,6 +114,38 @@ impl Code {
     pub const fn new(code: i32) -> Self {
         Code(code)
     }
+
+    /// Connection, timeout and not-ready class errors, including those of the websocket client,
+    /// which may succeed after reconnecting or retrying later.
+    ///
+    /// Other errors, like authentication failures or SQL syntax errors, will be raised again on
+    /// retry, and [Code::FAILED] is never recoverable as its cause is unknown.
+    pub const fn is_recoverable(&self) -> bool {
+        matches!(
+            self.0,
+            0x0005 // System not ready
+                | 0x0009 // Processing of request timed out
+                | 0x000B // Unable to establish connection
+                | 0x0014 // Database not ready
+                | 0x0015 // Unable to resolve FQDN
+                | 0x0018 // Conn is broken
+                | 0x0019 // Conn read timeout
+                | 0x0020 // some vnode/qnode/mnode(s) out of service
+                | 0x012C // Operation timeout
+                | 0x0213 // Disconnected from service
+                | 0x033C // Cluster not ready
+                | 0x03EA // Consumer not ready
+                | 0x0903 // Sync timeout
+                | 0x0911 // Sync not ready to propose
+                | 0x2402 // catalog is not ready
+                | 0x2504 // Task timeout
+                | 0xE001 // WebSocket error
+                | 0xE002 // WebSocket connection closed
+                | 0xE003 // WebSocket message send timeout
+                | 0xE004 // WebSocket message receive timeout
+                | 0xE005 // WebSocket io error
+        )
+    }
 }
 
 impl PartialEq<usize> for Code {
@@ -140,3 +180,17 @@ fn test_code() {
     let _: &i32 = &c;
     let _: &mut i32 = &mut c;
 }
+
+#[test]
+fn test_code_recoverable() {
+    for code in [0x0009, 0x000B, 0x0018, 0x0019, 0x0213, 0x03EA, 0xE002] {
+        assert!(Code::new(code).is_recoverable(), "{code:#06X}");
+    }
+    // Authentication failure, invalid password, syntax error in SQL, table not exist, invalid
+    // websocket DSN and websocket authentication failure.
+    for code in [0x0003, 0x0208, 0x0216, 0x2600, 0x2603, 0xE000, 0xE006] {
+        assert!(!Code::new(code).is_recoverable(), "{code:#06X}");
+    }
+    assert!(!Code::SUCCESS.is_recoverable());
+    assert!(!Code::FAILED.is_recoverable());
+}
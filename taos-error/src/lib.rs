@@ -205,6 +205,22 @@ impl Error {
     pub const fn code(&self) -> Code {
         self.code
     }
+    /// The TDengine error code, `None` for errors not raised by TDengine, e.g. io errors.
+    #[inline]
+    pub fn raw_code(&self) -> Option<i32> {
+        match self.code {
+            Code::FAILED => None,
+            code => Some(*code),
+        }
+    }
+
+    /// Whether the error is a connection or timeout class error worth retrying, see
+    /// [Code::is_recoverable].
+    #[inline]
+    pub const fn is_recoverable(&self) -> bool {
+        self.code.is_recoverable()
+    }
+
     #[inline]
     pub fn message(&self) -> String {
         self.source.to_string()
@@ -525,6 +541,23 @@ fn test_error() {
     assert_eq!(raise_error().unwrap_err().to_string(), "invalid driver mq");
 }
 
+#[test]
+fn test_error_recoverable() {
+    let err = Error::from_code(0x0018);
+    assert_eq!(err.raw_code(), Some(0x0018));
+    assert!(err.is_recoverable());
+    assert!(Error::new(0x000B, "Unable to establish connection").is_recoverable());
+
+    let err = Error::new(0x0216, r#"syntax error near "123);""#);
+    assert_eq!(err.raw_code(), Some(0x0216));
+    assert!(!err.is_recoverable());
+    assert!(!Error::from_code(0x0003).is_recoverable());
+
+    let err = Error::from_string("connection reset");
+    assert_eq!(err.raw_code(), None);
+    assert!(!err.is_recoverable());
+}
+
 #[cfg(feature = "serde")]
 #[test]
 fn test_serde_error() {
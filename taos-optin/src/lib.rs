@@ -14,6 +14,7 @@ use taos_query::{
     prelude::tokio::time,
     prelude::{Field, Precision, RawBlock, RawMeta, RawResult},
     util::Edition,
+    Dsn,
 };
 
 const MAX_CONNECT_RETRIES: u8 = 2;
@@ -84,7 +85,7 @@ pub(crate) mod types;
 
 pub mod tmq;
 pub use stmt::Stmt;
-pub use tmq::{Consumer, TmqBuilder};
+pub use tmq::{Consumer, ConsumerBuilder, TmqBuilder};
 
 pub mod prelude {
     pub use super::{Consumer, ResultSet, Stmt, Taos, TaosBuilder, TmqBuilder};
@@ -132,6 +133,8 @@ macro_rules! err_or {
 #[derive(Debug)]
 pub struct Taos {
     raw: RawTaos,
+    /// Dsn of the builder, to open other channels like tmq with the same params.
+    dsn: Arc<Dsn>,
 }
 
 impl Drop for Taos {
@@ -259,7 +262,7 @@ impl taos_query::AsyncQueryable for Taos {
 /// ```
 #[derive(Debug)]
 pub struct TaosBuilder {
-    // dsn: Dsn,
+    dsn: Arc<Dsn>,
     auth: Auth,
     lib: Arc<ApiEntry>,
     inner_conn: OnceCell<Taos>,
@@ -275,7 +278,10 @@ impl TaosBuilder {
                 .connect_with_retries(&self.auth, self.auth.max_retries())?;
 
             let raw = RawTaos::new(self.lib.clone(), ptr)?;
-            let taos = Ok(Taos { raw });
+            let taos = Ok(Taos {
+                raw,
+                dsn: self.dsn.clone(),
+            });
             self.inner_conn.get_or_try_init(|| taos)
         }
     }
@@ -334,6 +340,7 @@ impl taos_query::TBuilder for TaosBuilder {
 
     fn from_dsn<D: taos_query::IntoDsn>(dsn: D) -> RawResult<Self> {
         let mut dsn = dsn.into_dsn()?;
+        let origin = Arc::new(dsn.clone());
 
         let lib = if let Some(path) = dsn.params.remove("libraryPath") {
             tracing::trace!("using library path: {path}");
@@ -375,7 +382,7 @@ impl taos_query::TBuilder for TaosBuilder {
         }
 
         Ok(Self {
-            // dsn,
+            dsn: origin,
             auth,
             lib: Arc::new(lib),
             inner_conn: OnceCell::new(),
@@ -402,7 +409,10 @@ impl taos_query::TBuilder for TaosBuilder {
             .connect_with_retries(&self.auth, self.auth.max_retries())?;
 
         let raw = RawTaos::new(self.lib.clone(), ptr)?;
-        Ok(Taos { raw })
+        Ok(Taos {
+            raw,
+            dsn: self.dsn.clone(),
+        })
     }
 
     fn server_version(&self) -> RawResult<&str> {
@@ -480,6 +490,7 @@ impl taos_query::AsyncTBuilder for TaosBuilder {
 
     fn from_dsn<D: taos_query::IntoDsn>(dsn: D) -> RawResult<Self> {
         let mut dsn = dsn.into_dsn()?;
+        let origin = Arc::new(dsn.clone());
 
         let lib = if let Some(path) = dsn.params.remove("libraryPath") {
             tracing::trace!("using library path: {path}");
@@ -521,7 +532,7 @@ impl taos_query::AsyncTBuilder for TaosBuilder {
         }
 
         Ok(Self {
-            // dsn,
+            dsn: origin,
             auth,
             lib: Arc::new(lib),
             inner_conn: OnceCell::new(),
@@ -549,7 +560,10 @@ impl taos_query::AsyncTBuilder for TaosBuilder {
             .connect_with_retries(&self.auth, self.auth.max_retries())?;
 
         let raw = RawTaos::new(self.lib.clone(), ptr)?;
-        Ok(Taos { raw })
+        Ok(Taos {
+            raw,
+            dsn: self.dsn.clone(),
+        })
     }
 
     async fn server_version(&self) -> RawResult<&str> {
@@ -376,21 +376,15 @@ pub(crate) struct TmqApi {
     >,
 
     pub(crate) tmq_committed: Option<
-        unsafe extern "C" fn(
-            tmq: *mut tmq_t,
-            topic_name: *const c_char,
-            vgroup_id: i32,
-        ) -> tmq_resp_err_t,
+        unsafe extern "C" fn(tmq: *mut tmq_t, topic_name: *const c_char, vgroup_id: i32) -> i64,
     >,
 
     pub(crate) tmq_position: Option<
-        unsafe extern "C" fn(
-            tmq: *mut tmq_t,
-            topic_name: *const c_char,
-            vgroup_id: i32,
-        ) -> tmq_resp_err_t,
+        unsafe extern "C" fn(tmq: *mut tmq_t, topic_name: *const c_char, vgroup_id: i32) -> i64,
     >,
 
+    tmq_get_vgroup_offset: Option<unsafe extern "C" fn(res: *mut TAOS_RES) -> i64>,
+
     pub(crate) tmq_err2str: unsafe extern "C" fn(err: tmq_resp_err_t) -> *const c_char,
 
     pub(crate) conf_api: TmqConfApi,
@@ -636,7 +630,8 @@ impl ApiEntry {
                     tmq_commit_offset_sync,
                     tmq_commit_offset_async,
                     tmq_committed,
-                    tmq_position
+                    tmq_position,
+                    tmq_get_vgroup_offset
                 );
 
                 let conf_api = TmqConfApi {
@@ -677,6 +672,7 @@ impl ApiEntry {
                     tmq_offset_seek,
                     tmq_committed,
                     tmq_position,
+                    tmq_get_vgroup_offset,
                     tmq_err2str,
 
                     conf_api,
@@ -1560,6 +1556,14 @@ impl RawRes {
         }
     }
     #[inline]
+    pub(crate) fn tmq_vgroup_offset(&self) -> Option<i64> {
+        let tmq_get_vgroup_offset = self.c.tmq.as_ref().unwrap().tmq_get_vgroup_offset?;
+        match unsafe { tmq_get_vgroup_offset(self.as_ptr()) } {
+            offset if offset < 0 => None,
+            offset => Some(offset),
+        }
+    }
+    #[inline]
     pub(crate) fn tmq_get_json_meta(&self) -> String {
         unsafe {
             let meta = (self.c.tmq.as_ref().unwrap().tmq_get_json_meta)(self.as_ptr());
@@ -1,17 +1,25 @@
 // pub(crate) mod ffi;
 
-use std::{fmt::Debug, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 // pub(crate) use ffi::*;
 
 use anyhow::Context;
 use itertools::Itertools;
 use taos_query::{
-    common::{raw_data_t, RawData, RawMeta},
+    common::{raw_data_t, Field, RawData, RawMeta},
     prelude::{tokio::time, RawError, RawResult},
     tmq::{
-        AsAsyncConsumer, AsConsumer, Assignment, AsyncOnSync, IsAsyncData, IsData, IsMeta,
-        IsOffset, MessageSet, Timeout, VGroupId,
+        AsAsyncConsumer, AsConsumer, Assignment, AsyncOnSync, CommitHook, ConsumerCounters,
+        ConsumerInfo, ConsumerMetrics, ConsumerObserver, IsAsyncData, IsData, IsMeta, IsOffset,
+        MessageSet, PausedVGroups, PendingError, Timeout, TopicPattern, VGroupId, VGroupOrder,
+        VGROUP_ORDER_BATCH,
     },
     util::Edition,
     Dsn, IntoDsn, RawBlock,
@@ -27,13 +35,98 @@ use raw::RawTmq;
 
 use self::raw::{Conf, Topics};
 
+/// Native auto commit is enabled explicitly by `enable.auto.commit=true`.
+fn is_auto_commit(dsn: &Dsn) -> bool {
+    dsn.params.get("enable.auto.commit").map(String::as_str) == Some("true")
+}
+
 #[derive(Debug)]
 pub struct TmqBuilder {
     dsn: Dsn,
-    builder: TaosBuilder,
+    builder: Arc<TaosBuilder>,
     lib: Arc<ApiEntry>,
-    conf: Conf,
     timeout: Timeout,
+    observer: Option<Arc<dyn ConsumerObserver>>,
+    preserve_vgroup_order: bool,
+}
+
+/// Typed builder of [Consumer].
+pub type ConsumerBuilder = taos_query::tmq::ConsumerBuilder<TmqBuilder>;
+
+impl TmqBuilder {
+    /// Build a consumer of `group_id` with the dsn of an established connection.
+    ///
+    /// Only the params are cloned, the consumer opens its own connection.
+    pub fn from_taos(taos: &crate::Taos, group_id: &str) -> RawResult<Self> {
+        let mut dsn = (*taos.dsn).clone();
+        dsn.params
+            .insert("group.id".to_string(), group_id.to_string());
+        <Self as taos_query::TBuilder>::from_dsn(dsn)
+    }
+
+    /// Report polls, commits and errors of the consumer to `observer`, see [ConsumerObserver].
+    pub fn with_observer(mut self, observer: impl ConsumerObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Reorder received messages by offset within each vgroup, see [VGroupOrder].
+    ///
+    /// A poll then takes up to [VGROUP_ORDER_BATCH] messages which are immediately available,
+    /// and delivers them one by one in later polls. The native library would auto commit the
+    /// messages polled ahead, so building fails with `enable.auto.commit=true`.
+    pub fn preserve_vgroup_order(mut self, preserve: bool) -> Self {
+        self.preserve_vgroup_order = preserve;
+        self
+    }
+
+    fn vgroup_order(&self) -> RawResult<Option<VGroupOrder<Offset, Meta, Data>>> {
+        if !self.preserve_vgroup_order {
+            return Ok(None);
+        }
+        if is_auto_commit(&self.dsn) {
+            return Err(RawError::from_string(
+                "preserve_vgroup_order needs enable.auto.commit=false",
+            ));
+        }
+        Ok(Some(VGroupOrder::new(VGROUP_ORDER_BATCH)))
+    }
+
+    /// Deliver the data written before the topic is created, as `experimental.snapshot.enable`.
+    ///
+    /// Building fails if the server does not support it.
+    pub fn with_snapshot(mut self, enable: bool) -> Self {
+        self.dsn.params.insert(
+            "experimental.snapshot.enable".to_string(),
+            enable.to_string(),
+        );
+        self
+    }
+
+    /// Consume data messages in the order they are written, as `enable.replay`.
+    ///
+    /// Building fails if the server is older than 3.2.1.0, which ignores it.
+    pub fn with_replay(mut self, enable: bool) -> Self {
+        self.dsn
+            .params
+            .insert("enable.replay".to_string(), enable.to_string());
+        self
+    }
+
+    /// Enabled params which need a server of some version, see
+    /// [versioned_params](taos_query::tmq::versioned_params).
+    fn versioned_params(&self) -> Vec<(&'static str, &'static str)> {
+        taos_query::tmq::versioned_params(
+            self.dsn
+                .params
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+
+    fn conf(&self) -> RawResult<Conf> {
+        Conf::from_dsn(&self.dsn, self.lib.tmq.unwrap().conf_api)
+    }
 }
 
 unsafe impl Send for TmqBuilder {}
@@ -55,18 +148,20 @@ impl taos_query::TBuilder for TmqBuilder {
         } else {
             ApiEntry::open_default().map_err(|err| taos_query::RawError::any(err))?
         };
-        let conf = Conf::from_dsn(&dsn, lib.tmq.unwrap().conf_api)?;
+        // Validate the params early, the conf is created again at building.
+        let _ = Conf::from_dsn(&dsn, lib.tmq.unwrap().conf_api)?;
         let timeout = if let Some(timeout) = dsn.params.remove("timeout") {
             Timeout::from_str(&timeout).map_err(RawError::from_any)?
         } else {
             Timeout::from_millis(500)
         };
         Ok(Self {
-            builder: TaosBuilder::from_dsn(&dsn).map_err(RawError::from_any)?,
+            builder: Arc::new(TaosBuilder::from_dsn(&dsn).map_err(RawError::from_any)?),
             dsn,
             lib: Arc::new(lib),
-            conf,
             timeout,
+            observer: None,
+            preserve_vgroup_order: false,
         })
     }
 
@@ -83,7 +178,13 @@ impl taos_query::TBuilder for TmqBuilder {
     }
 
     fn build(&self) -> RawResult<Self::Target> {
-        let ptr = self.conf.build()?;
+        let params = self.versioned_params();
+        if !params.is_empty() {
+            let version = taos_query::TBuilder::server_version(self)?;
+            taos_query::tmq::check_server_version(version, &params)?;
+        }
+        let vgroup_order = self.vgroup_order()?;
+        let ptr = self.conf()?.build()?;
         let tmq = RawTmq {
             c: self.lib.clone(),
             tmq: self.lib.tmq.unwrap(),
@@ -93,6 +194,15 @@ impl taos_query::TBuilder for TmqBuilder {
             tmq,
             timeout: self.timeout,
             dsn: self.dsn.clone(),
+            builder: self.builder.clone(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: BTreeMap::new(),
+            metrics: ConsumerCounters::with_observer(self.observer.clone()),
+            commit_hook: CommitHook::default(),
+            vgroup_order,
+            paused: PausedVGroups::default(),
+            closed: false,
         })
     }
 
@@ -141,18 +251,20 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
         } else {
             ApiEntry::open_default().map_err(|err| taos_query::RawError::any(err))?
         };
-        let conf = Conf::from_dsn(&dsn, lib.tmq.unwrap().conf_api)?;
+        // Validate the params early, the conf is created again at building.
+        let _ = Conf::from_dsn(&dsn, lib.tmq.unwrap().conf_api)?;
         let timeout = if let Some(timeout) = dsn.params.remove("timeout") {
             Timeout::from_str(&timeout).map_err(RawError::from_any)?
         } else {
             Timeout::from_millis(500)
         };
         Ok(Self {
-            builder: TaosBuilder::from_dsn(&dsn).map_err(RawError::from_any)?,
+            builder: Arc::new(TaosBuilder::from_dsn(&dsn).map_err(RawError::from_any)?),
             dsn,
             lib: Arc::new(lib),
-            conf,
             timeout,
+            observer: None,
+            preserve_vgroup_order: false,
         })
     }
 
@@ -169,7 +281,13 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
     }
 
     async fn build(&self) -> RawResult<Self::Target> {
-        let ptr = self.conf.build()?;
+        let params = self.versioned_params();
+        if !params.is_empty() {
+            let version = taos_query::AsyncTBuilder::server_version(self).await?;
+            taos_query::tmq::check_server_version(version, &params)?;
+        }
+        let vgroup_order = self.vgroup_order()?;
+        let ptr = self.conf()?.build()?;
         let tmq = RawTmq {
             c: self.lib.clone(),
             tmq: self.lib.tmq.unwrap(),
@@ -179,6 +297,15 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
             tmq,
             timeout: self.timeout,
             dsn: self.dsn.clone(),
+            builder: self.builder.clone(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: BTreeMap::new(),
+            metrics: ConsumerCounters::with_observer(self.observer.clone()),
+            commit_hook: CommitHook::default(),
+            vgroup_order,
+            paused: PausedVGroups::default(),
+            closed: false,
         })
     }
 
@@ -230,8 +357,9 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
 
 /// Consumer offset.
 ///
-/// When offset is dropped, the message is destroyed.
-pub struct Offset(RawRes);
+/// When offset is dropped, the message is destroyed. It also keeps the time
+/// the message is received and the time spent polling it.
+pub struct Offset(RawRes, Instant, Duration);
 
 unsafe impl Send for Offset {}
 unsafe impl Sync for Offset {}
@@ -242,6 +370,7 @@ impl Debug for Offset {
             .field("ptr", &self.0)
             .field("topic", &self.topic())
             .field("vgroup_id", &self.vgroup_id())
+            .field("offset", &self.offset())
             .field("database", &self.database())
             .finish()
     }
@@ -263,6 +392,17 @@ impl IsOffset for Offset {
             .tmq_vgroup_id()
             .expect("a message should belong to a vgroup")
     }
+    fn offset(&self) -> i64 {
+        self.0.tmq_vgroup_offset().unwrap_or(-1)
+    }
+
+    fn timing(&self) -> Option<i64> {
+        i64::try_from(self.2.as_nanos()).ok()
+    }
+
+    fn received_at(&self) -> Option<Instant> {
+        Some(self.1)
+    }
 }
 
 impl Drop for Offset {
@@ -276,15 +416,154 @@ pub struct Consumer {
     tmq: RawTmq,
     timeout: Timeout,
     dsn: Dsn,
+    builder: Arc<TaosBuilder>,
+    pattern: TopicPattern,
+    pending_error: PendingError,
+    topic_timeouts: BTreeMap<String, Timeout>,
+    metrics: ConsumerCounters,
+    commit_hook: CommitHook,
+    vgroup_order: Option<VGroupOrder<Offset, Meta, Data>>,
+    paused: PausedVGroups,
+    closed: bool,
 }
 
 unsafe impl Send for Consumer {}
 unsafe impl Sync for Consumer {}
 
+/// Upper bound for the final commit and close on [`AsAsyncConsumer::shutdown`].
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Consumer {
+    /// Group id, client id and subscribed topics of the consumer.
+    pub fn info(&self) -> ConsumerInfo {
+        ConsumerInfo {
+            group_id: self.dsn.params.get("group.id").cloned().unwrap_or_default(),
+            client_id: self.dsn.params.get("client.id").cloned(),
+            topics: self.tmq.subscription().to_strings(),
+        }
+    }
+
+    /// Snapshot of the poll, message and commit counters of the consumer.
+    ///
+    /// Raw data stays in the native library until blocks are fetched, so `bytes` is not counted.
+    pub fn metrics(&self) -> ConsumerMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Reset the counters of [metrics](Self::metrics).
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+
+    /// Set a hook called with `(topic, vgroup_id, offset, result)` after each commit request,
+    /// replacing the previous one.
+    ///
+    /// Auto commits of `enable.auto.commit` are done inside the native library and are not
+    /// hooked. Panics in the hook are caught and logged.
+    pub fn set_commit_hook(
+        &mut self,
+        hook: impl Fn(&str, VGroupId, i64, &RawResult<()>) + Send + Sync + 'static,
+    ) {
+        self.commit_hook.set(hook);
+    }
+
+    /// The message polled since `polled_at`.
+    fn message_of(raw: RawRes, polled_at: Instant) -> (Offset, MessageSet<Meta, Data>) {
+        let received_at = Instant::now();
+        let offset = Offset(raw.clone(), received_at, received_at - polled_at);
+        (offset, raw.into())
+    }
+
+    /// Poll a message, skipping those of paused vgroups until `timeout`.
+    fn poll_sync(&self, timeout: Timeout) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        let timeout = timeout.into_deadline();
+        loop {
+            let polled_at = Instant::now();
+            let Some(raw) = self.tmq.poll_timeout(timeout.as_raw_timeout())? else {
+                return Ok(None);
+            };
+            let message = Self::message_of(raw, polled_at);
+            if !self.paused.skip(&message.0) {
+                return Ok(Some(message));
+            }
+            if timeout.is_expired() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Poll a message, skipping those of paused vgroups until `timeout`.
+    async fn poll_by(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        let timeout = timeout.into_deadline();
+        loop {
+            let Some(message) = self.poll_once(timeout).await? else {
+                return Ok(None);
+            };
+            if !self.paused.skip(&message.0) {
+                return Ok(Some(message));
+            }
+            if timeout.is_expired() {
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn poll_once(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        use taos_query::prelude::tokio;
+        let polled_at = Instant::now();
+        let timeout = match timeout {
+            // Poll once without waiting for new messages.
+            Timeout::None => {
+                let tmq = self.tmq.clone();
+                let raw = tokio::task::spawn_blocking(move || tmq.poll_timeout(0))
+                    .await
+                    .map_err(RawError::from_any)??;
+                return Ok(raw.map(|raw| Self::message_of(raw, polled_at)));
+            }
+            Timeout::Never => Duration::MAX,
+            Timeout::Duration(_) | Timeout::Deadline(_) => timeout.as_duration(),
+        };
+        let sleep = tokio::time::sleep(timeout);
+        tokio::pin!(sleep);
+        tokio::select! {
+            _ = &mut sleep, if !sleep.is_elapsed() => Ok(None),
+            raw = self.tmq.poll_async() => Ok(Some(Self::message_of(raw?, polled_at))),
+        }
+    }
+
+    /// Paused vgroups as `(topic, vgroup_id)`, see [pause](AsAsyncConsumer::pause).
+    pub fn paused(&self) -> BTreeSet<(String, VGroupId)> {
+        self.paused.vgroups()
+    }
+
+    /// Override the timeout of [recv](AsConsumer::recv) and [stream](AsAsyncConsumer::stream)
+    /// when `topic` is the only subscribed topic.
+    pub fn set_topic_timeout(&mut self, topic: &str, timeout: Timeout) {
+        self.topic_timeouts.insert(topic.to_string(), timeout);
+    }
+
+    /// Unsubscribe and close the consumer, only the first call takes effect.
+    fn unsubscribe_and_close(&mut self) -> RawResult<()> {
+        if std::mem::replace(&mut self.closed, true) {
+            return Ok(());
+        }
+        let unsubscribed = self.tmq.unsubscribe();
+        let closed = self.tmq.close();
+        unsubscribed.and(closed)
+    }
+}
+
 impl Drop for Consumer {
     fn drop(&mut self) {
-        self.tmq.unsubscribe();
-        self.tmq.close();
+        if let Err(err) = self.unsubscribe_and_close() {
+            tracing::warn!("close consumer error: {err}");
+        }
     }
 }
 
@@ -297,9 +576,17 @@ impl Iterator for Messages {
     type Item = (Offset, MessageSet<Meta, Data>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.tmq
+        let polled_at = Instant::now();
+        match self
+            .tmq
             .poll_timeout(self.timeout.map(|t| t.as_millis() as i64).unwrap_or(-1))
-            .map(|raw| (Offset(raw.clone()), MessageSet::from(raw)))
+        {
+            Ok(raw) => raw.map(|raw| Consumer::message_of(raw, polled_at)),
+            Err(err) => {
+                tracing::warn!("poll message error: {err}");
+                None
+            }
+        }
     }
 }
 
@@ -344,11 +631,33 @@ impl Meta {
 #[derive(Debug)]
 pub struct Data {
     raw: RawRes,
+    /// Block fetched for its fields, returned by the next fetch.
+    peeked: Mutex<Option<RawBlock>>,
 }
 
 impl Data {
     fn new(raw: RawRes) -> Self {
-        Self { raw }
+        Self {
+            raw,
+            peeked: Mutex::new(None),
+        }
+    }
+
+    fn next_block(&self) -> Option<RawBlock> {
+        self.peeked
+            .lock()
+            .unwrap()
+            .take()
+            .or_else(|| self.raw.fetch_raw_message())
+    }
+
+    /// The message is received as a whole, peeking the block copies it out of the result.
+    fn peek_fields(&self) -> Vec<Field> {
+        let mut peeked = self.peeked.lock().unwrap();
+        if peeked.is_none() {
+            *peeked = self.raw.fetch_raw_message();
+        }
+        peeked.as_ref().map(RawBlock::fields).unwrap_or_default()
     }
 }
 
@@ -359,7 +668,11 @@ impl IsAsyncData for Data {
     }
 
     async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
-        Ok(self.raw.fetch_raw_message())
+        Ok(self.next_block())
+    }
+
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        Ok(self.peek_fields())
     }
 }
 
@@ -369,7 +682,11 @@ impl IsData for Data {
     }
 
     fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
-        Ok(self.raw.fetch_raw_message())
+        Ok(self.next_block())
+    }
+
+    fn fields(&self) -> RawResult<Vec<Field>> {
+        Ok(self.peek_fields())
     }
 }
 // pub enum MessageSet {
@@ -392,7 +709,7 @@ impl Iterator for Data {
     type Item = RawResult<RawBlock>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.raw.fetch_raw_message().map(Ok)
+        self.next_block().map(Ok)
     }
 }
 
@@ -419,9 +736,8 @@ impl AsConsumer for Consumer {
         topics: I,
     ) -> RawResult<()> {
         let topics = topics.into_iter().map(|item| item.into()).collect_vec();
-        let topics = Topics::from_topics(self.tmq.tmq.list_api, topics)?;
-        // dbg!(&topics);
-        self.tmq.subscribe(&topics)
+        self.pattern.clear();
+        <Self as AsConsumer>::subscribe_matched(self, topics)
     }
 
     fn recv_timeout(
@@ -433,30 +749,37 @@ impl AsConsumer for Consumer {
             taos_query::tmq::MessageSet<Self::Meta, Self::Data>,
         )>,
     > {
-        Ok(self.tmq.poll_timeout(timeout.as_raw_timeout()).map(|raw| {
-            (
-                Offset(raw.clone()),
-                match raw.tmq_message_type() {
-                    tmq_res_t::TMQ_RES_INVALID => unreachable!(),
-                    tmq_res_t::TMQ_RES_DATA => taos_query::tmq::MessageSet::Data(Data::new(raw)),
-                    tmq_res_t::TMQ_RES_TABLE_META => {
-                        taos_query::tmq::MessageSet::Meta(Meta::new(raw))
-                    }
-                    tmq_res_t::TMQ_RES_METADATA => taos_query::tmq::MessageSet::MetaData(
-                        Meta::new(raw.clone()),
-                        Data::new(raw),
-                    ),
-                },
-            )
-        }))
+        <Self as AsConsumer>::refresh_subscription_if_due(self);
+        let start = Instant::now();
+        let message = match &self.vgroup_order {
+            Some(order) => {
+                let poll = |timeout| std::future::ready(self.poll_sync(timeout));
+                taos_query::block_in_place_or_global(order.recv(timeout, poll))?
+            }
+            None => self.poll_sync(timeout)?,
+        };
+        self.metrics
+            .record_poll(start.elapsed(), message.as_ref().map(|m| m.1.kind()));
+        Ok(message)
     }
 
     fn commit(&self, offset: Self::Offset) -> RawResult<()> {
-        self.tmq.commit_sync(offset.0.clone()).map(|_| ())
+        let res = self.tmq.commit_sync(offset.0.clone()).map(|_| ());
+        self.metrics.record_commit(offset.offset(), &res);
+        self.commit_hook
+            .call(offset.topic(), offset.vgroup_id(), offset.offset(), &res);
+        res
     }
 
     fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
-        self.tmq.commit_offset_sync(topic_name, vgroup_id, offset)
+        let res = self.tmq.commit_offset_sync(topic_name, vgroup_id, offset);
+        self.metrics.record_commit(offset, &res);
+        self.commit_hook.call(topic_name, vgroup_id, offset, &res);
+        res
+    }
+
+    fn unsubscribe(mut self) -> RawResult<()> {
+        self.unsubscribe_and_close()
     }
 
     fn list_topics(&self) -> RawResult<Vec<String>> {
@@ -465,21 +788,62 @@ impl AsConsumer for Consumer {
         Ok(topics)
     }
 
+    fn subscription(&self) -> Vec<String> {
+        self.tmq.subscription().to_strings()
+    }
+
+    fn topic_names(&self) -> RawResult<Vec<String>> {
+        use taos_query::{Fetchable, Queryable};
+
+        self.builder
+            .inner_connection()?
+            .query(taos_query::tmq::TOPICS_SQL)?
+            .deserialize::<String>()
+            .collect()
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending_error)
+    }
+
+    fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        let topics = Topics::from_topics(self.tmq.tmq.list_api, topics)?;
+        self.paused.clear();
+        self.tmq.subscribe(&topics)
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
+    }
+
     fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
         let topics = self.tmq.subscription();
         let topics = topics.to_strings();
         let ret = topics
             .into_iter()
             .map(|topic| {
-                let assignments = self.tmq.get_topic_assignment(&topic);
+                let assignments = self.tmq.get_topic_assignment(&topic).unwrap_or_default();
                 (topic, assignments)
             })
             .collect();
         Some(ret)
     }
 
-    fn offset_seek(&mut self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
-        self.tmq.offset_seek(topic, vg_id, offset)
+    fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        self.tmq.get_topic_assignment(topic)
+    }
+
+    fn offset_seek(&self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
+        self.tmq.offset_seek(topic, vg_id, offset)?;
+        if let Some(order) = &self.vgroup_order {
+            order.reset(topic, vg_id);
+        }
+        self.paused.reset(topic, vg_id);
+        Ok(())
     }
 
     fn committed(&self, topic: &str, vg_id: VGroupId) -> RawResult<i64> {
@@ -489,6 +853,38 @@ impl AsConsumer for Consumer {
     fn position(&self, topic: &str, vg_id: VGroupId) -> RawResult<i64> {
         self.tmq.position(topic, vg_id)
     }
+
+    fn resubscribe(&self) -> RawResult<()> {
+        self.tmq.subscribe(&self.tmq.subscription())
+    }
+
+    /// The native library has no pause, so it is done on the client side as [PausedVGroups]:
+    /// polled messages of the vgroup are skipped, and it is sought back to the first skipped one
+    /// on [resume](AsConsumer::resume). Fails with `enable.auto.commit=true`, which would commit
+    /// the skipped messages.
+    fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        if is_auto_commit(&self.dsn) {
+            return Err(RawError::from_string(
+                "pause needs enable.auto.commit=false",
+            ));
+        }
+        let assignments = self.tmq.get_topic_assignment(topic)?;
+        if !assignments.iter().any(|a| a.vgroup_id() == vgroup_id) {
+            return Err(RawError::from_string(format!(
+                "vgroup {vgroup_id} of topic {topic} is not assigned to current consumer"
+            )));
+        }
+        self.paused.pause(topic, vgroup_id);
+        Ok(())
+    }
+
+    /// Resume a paused vgroup, from the first message not delivered.
+    fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        match self.paused.resume(topic, vgroup_id) {
+            Some(offset) => <Self as AsConsumer>::offset_seek(self, topic, vgroup_id, offset),
+            None => Ok(()),
+        }
+    }
 }
 
 // impl AsyncOnSync for Consumer {}
@@ -505,9 +901,9 @@ impl AsAsyncConsumer for Consumer {
         &mut self,
         topics: I,
     ) -> RawResult<()> {
-        let topics =
-            Topics::from_topics(self.tmq.tmq.list_api, topics.into_iter().map(|s| s.into()))?;
-        let r = self.tmq.subscribe(&topics);
+        let topics = topics.into_iter().map(|s| s.into()).collect_vec();
+        self.pattern.clear();
+        let r = <Self as AsConsumer>::subscribe_matched(self, topics);
 
         if let Some(offset) = self.dsn.get("offset") {
             // dbg!(offset);
@@ -545,71 +941,34 @@ impl AsAsyncConsumer for Consumer {
             taos_query::tmq::MessageSet<Self::Meta, Self::Data>,
         )>,
     > {
-        use taos_query::prelude::tokio;
         tracing::trace!("Waiting for next message");
-        let res = match timeout {
-            Timeout::Never | Timeout::None => {
-                let timeout = Duration::MAX;
-                let sleep = tokio::time::sleep(timeout);
-                tokio::pin!(sleep);
-                tokio::select! {
-                    _ = &mut sleep, if !sleep.is_elapsed() => {
-                       Ok(None)
-                    }
-                    raw = self.tmq.poll_async() => {
-                        let message =    (
-                            Offset(raw.clone()),
-                            match raw.tmq_message_type() {
-                                tmq_res_t::TMQ_RES_INVALID => unreachable!(),
-                                tmq_res_t::TMQ_RES_DATA => taos_query::tmq::MessageSet::Data(Data::new(raw)),
-                                tmq_res_t::TMQ_RES_TABLE_META => {
-                                    taos_query::tmq::MessageSet::Meta(Meta::new(raw))
-                                }
-                                tmq_res_t::TMQ_RES_METADATA => taos_query::tmq::MessageSet::MetaData(Meta::new(raw.clone()), Data::new(raw))
-                            },
-                        );
-                        Ok(Some(message))
-                    }
-                }
-            }
-            Timeout::Duration(timeout) => {
-                let sleep = tokio::time::sleep(timeout);
-                tokio::pin!(sleep);
-                tokio::select! {
-                    _ = &mut sleep, if !sleep.is_elapsed() => {
-                       Ok(None)
-                    }
-                    raw = self.tmq.poll_async() => {
-                        let message =    (
-                            Offset(raw.clone()),
-                            match raw.tmq_message_type() {
-                                tmq_res_t::TMQ_RES_INVALID => unreachable!(),
-                                tmq_res_t::TMQ_RES_DATA => taos_query::tmq::MessageSet::Data(Data::new(raw)),
-                                tmq_res_t::TMQ_RES_TABLE_META => {
-                                    taos_query::tmq::MessageSet::Meta(Meta::new(raw))
-                                }
-                                tmq_res_t::TMQ_RES_METADATA => taos_query::tmq::MessageSet::MetaData(Meta::new(raw.clone()), Data::new(raw))
-                            },
-                        );
-                        Ok(Some(message))
-                    }
-                }
-            }
+        <Self as AsAsyncConsumer>::refresh_subscription_if_due(self).await;
+        let start = Instant::now();
+        let res = match &self.vgroup_order {
+            Some(order) => order.recv(timeout, |timeout| self.poll_by(timeout)).await,
+            None => self.poll_by(timeout).await,
         };
         match res {
             Ok(res) => {
                 tracing::trace!("Got a new message");
+                self.metrics
+                    .record_poll(start.elapsed(), res.as_ref().map(|m| m.1.kind()));
                 Ok(res)
             }
             Err(err) => {
                 tracing::warn!("Polling message error: {err:?}");
+                self.metrics.record_error(&err);
                 Err(err)
             }
         }
     }
 
     async fn commit(&self, offset: Self::Offset) -> RawResult<()> {
-        self.tmq.commit(offset.0.clone()).await.map(|_| ())
+        let res = self.tmq.commit(offset.0.clone()).await.map(|_| ());
+        self.metrics.record_commit(offset.offset(), &res);
+        self.commit_hook
+            .call(offset.topic(), offset.vgroup_id(), offset.offset(), &res);
+        res
     }
 
     async fn commit_offset(
@@ -618,22 +977,82 @@ impl AsAsyncConsumer for Consumer {
         vgroup_id: VGroupId,
         offset: i64,
     ) -> RawResult<()> {
-        self.tmq
+        let res = self
+            .tmq
             .commit_offset_async(topic_name, vgroup_id, offset)
             .await
-            .map(|_| ())
+            .map(|_| ());
+        self.metrics.record_commit(offset, &res);
+        self.commit_hook.call(topic_name, vgroup_id, offset, &res);
+        res
     }
 
     fn default_timeout(&self) -> Timeout {
         self.timeout
     }
 
+    async fn unsubscribe(mut self) -> RawResult<()> {
+        self.unsubscribe_and_close()
+    }
+
+    async fn shutdown(mut self) -> RawResult<()> {
+        use taos_query::prelude::tokio;
+        // The native close commits and waits for the server, keep it off the async runtime.
+        let closing = tokio::task::spawn_blocking(move || self.unsubscribe_and_close());
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, closing).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(err)) => Err(RawError::from_any(err)),
+            Err(_) => Err(RawError::from_string(format!(
+                "consumer shutdown timed out after {:?}",
+                SHUTDOWN_TIMEOUT
+            ))),
+        }
+    }
+
     async fn list_topics(&self) -> RawResult<Vec<String>> {
         let topics = self.tmq.subscription();
         let topics = topics.to_strings();
         Ok(topics)
     }
 
+    fn subscription(&self) -> Vec<String> {
+        self.tmq.subscription().to_strings()
+    }
+
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        use taos_query::prelude::tokio;
+
+        let builder = self.builder.clone();
+        // The native query blocks, keep it off the async runtime.
+        tokio::task::spawn_blocking(move || {
+            use taos_query::{Fetchable, Queryable};
+
+            builder
+                .inner_connection()?
+                .query(taos_query::tmq::TOPICS_SQL)?
+                .deserialize::<String>()
+                .collect()
+        })
+        .await
+        .map_err(RawError::from_any)?
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending_error)
+    }
+
+    async fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        <Self as AsConsumer>::subscribe_matched(self, topics)
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
+    }
+
     async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
         let topics = self.tmq.subscription();
         let topics = topics.to_strings();
@@ -641,24 +1060,24 @@ impl AsAsyncConsumer for Consumer {
         let ret: Vec<(String, Vec<Assignment>)> = topics
             .into_iter()
             .map(|topic| {
-                let assignments = self.tmq.get_topic_assignment(&topic);
+                let assignments = self.tmq.get_topic_assignment(&topic).unwrap_or_default();
                 (topic, assignments)
             })
             .collect();
         Some(ret)
     }
 
-    async fn topic_assignment(&self, topic: &str) -> Vec<Assignment> {
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
         self.tmq.get_topic_assignment(topic)
     }
 
-    async fn offset_seek(
-        &mut self,
-        topic: &str,
-        vgroup_id: VGroupId,
-        offset: i64,
-    ) -> RawResult<()> {
-        self.tmq.offset_seek(topic, vgroup_id, offset)
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+        self.tmq.offset_seek(topic, vgroup_id, offset)?;
+        if let Some(order) = &self.vgroup_order {
+            order.reset(topic, vgroup_id);
+        }
+        self.paused.reset(topic, vgroup_id);
+        Ok(())
     }
 
     async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
@@ -668,6 +1087,18 @@ impl AsAsyncConsumer for Consumer {
     async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
         self.tmq.position(topic, vgroup_id)
     }
+
+    async fn resubscribe(&self) -> RawResult<()> {
+        <Self as AsConsumer>::resubscribe(self)
+    }
+
+    async fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        <Self as AsConsumer>::pause(self, topic, vgroup_id)
+    }
+
+    async fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        <Self as AsConsumer>::resume(self, topic, vgroup_id)
+    }
 }
 #[cfg(test)]
 mod tests {
@@ -758,7 +1189,7 @@ mod tests {
             let _ = consumer.commit(offset);
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         let mut query = taos.query("describe stb1")?;
         for row in query.rows() {
@@ -858,7 +1289,7 @@ mod tests {
             let _ = consumer.commit(offset);
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         let mut query = taos.query("describe stb1")?;
         for row in query.rows() {
@@ -969,7 +1400,7 @@ mod tests {
             let _ = consumer.commit(offset);
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         let mut query = taos.query("describe stb1")?;
         for row in query.rows() {
@@ -1108,6 +1539,7 @@ mod tests {
                                 using: _,
                                 tags: _,
                                 tag_num: _,
+                                create_list: _,
                             } => {
                                 let _desc = taos.describe(table_name.as_str())?;
                                 // dbg!(_desc);
@@ -1163,7 +1595,7 @@ mod tests {
             consumer.commit(offset)?;
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         taos.exec_many([
             "drop database sys_tmq_meta_sync2",
@@ -1309,6 +1741,7 @@ mod tests {
                                 using: _,
                                 tags: _,
                                 tag_num: _,
+                                create_list: _,
                             } => {
                                 let desc = taos.describe(table_name.as_str())?;
                                 tracing::trace!("{:?}", desc);
@@ -1390,7 +1823,7 @@ mod tests {
         let assignments = consumer.assignments().unwrap();
         tracing::debug!("assignments: {:?}", assignments);
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         taos.exec_many([
             format!("drop database {target}").as_str(),
@@ -1488,7 +1921,7 @@ mod async_tests {
             let _ = consumer.commit(offset).await;
         }
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         taos.exec_many([
             format!("drop database {db}2").as_str(),
@@ -1652,7 +2085,7 @@ mod async_tests {
             })
             .await?;
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         taos.exec_many([
             "drop database sys_tmq_meta2",
@@ -1858,7 +2291,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         tracing::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -2118,13 +2551,15 @@ mod async_tests {
                     vgroup_id
                 );
 
-                if res.is_err() {
-                    tracing::error!("seek offset error: {:?}", res);
-                    let a = consumer.assignments().await.unwrap();
-                    tracing::error!("assignments: {:?}", a);
-                }
+                let err = res.unwrap_err();
+                assert_eq!(err.code(), Code::TMQ_VERSION_OUT_OF_RANGE);
+                assert!(err.message().contains("out of range"));
             }
 
+            let err = consumer.commit_offset(topic, -1, 0).await.unwrap_err();
+            assert_eq!(err.code(), Code::TMQ_INVALID_VGID);
+            assert!(!err.message().is_empty());
+
             let topic_assignment = consumer.topic_assignment(topic).await;
             tracing::debug!("topic assignment: {:?}", topic_assignment);
         }
@@ -2133,7 +2568,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         tracing::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -2339,7 +2774,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         tracing::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -3,8 +3,9 @@ pub(super) use list::Topics;
 pub(super) use tmq::RawTmq;
 
 pub(super) mod tmq {
-    use std::{ffi::CStr, sync::Arc, time::Duration};
+    use std::{ffi::CStr, fmt::Display, os::raw::c_void, sync::Arc, time::Duration};
     use taos_query::{
+        prelude::Code,
         tmq::{Assignment, VGroupId},
         RawError,
     };
@@ -32,13 +33,9 @@ pub(super) mod tmq {
         fn as_ptr(&self) -> *mut tmq_t {
             self.ptr
         }
-        pub(crate) fn subscribe(&mut self, topics: &Topics) -> RawResult<()> {
+        pub(crate) fn subscribe(&self, topics: &Topics) -> RawResult<()> {
             let rsp = unsafe { (self.tmq.tmq_subscribe)(self.as_ptr(), topics.as_ptr()) };
-            if rsp.is_err() {
-                let str = unsafe { CStr::from_ptr((self.tmq.tmq_err2str)(rsp)) }.to_string_lossy();
-                return Err(taos_query::RawError::new(rsp.0, str));
-            }
-            Ok(())
+            self.check_resp(rsp, "subscribe failed")
         }
 
         pub fn err_as_str(&self, tmq_resp: tmq_resp_err_t) -> String {
@@ -49,6 +46,18 @@ pub(super) mod tmq {
             }
         }
 
+        /// Error with the code and the string by `tmq_err2str`, prefixed by `context`.
+        fn check_resp(&self, tmq_resp: tmq_resp_err_t, context: impl Display) -> RawResult<()> {
+            if tmq_resp.is_err() {
+                let err_str = self.err_as_str(tmq_resp);
+                return Err(RawError::new(
+                    tmq_resp.errno(),
+                    format!("{context}: {err_str}"),
+                ));
+            }
+            Ok(())
+        }
+
         pub fn subscription(&self) -> Topics {
             let tl = Topics::new(self.tmq.list_api);
 
@@ -59,8 +68,8 @@ pub(super) mod tmq {
         }
 
         pub fn commit_sync(&self, msg: RawRes) -> RawResult<()> {
-            unsafe { (self.tmq.tmq_commit_sync)(self.as_ptr(), msg.as_ptr() as _) }
-                .ok_or("commit failed")
+            let tmq_resp = unsafe { (self.tmq.tmq_commit_sync)(self.as_ptr(), msg.as_ptr() as _) };
+            self.check_resp(tmq_resp, "commit failed")
         }
 
         pub fn commit_offset_sync(
@@ -70,34 +79,40 @@ pub(super) mod tmq {
             offset: i64,
         ) -> RawResult<()> {
             if let Some(tmq_commit_offset_sync) = self.tmq.tmq_commit_offset_sync {
-                unsafe {
+                let tmq_resp = unsafe {
                     tmq_commit_offset_sync(
                         self.as_ptr(),
                         topic_name.into_c_str().as_ptr(),
                         vgroup_id,
                         offset,
                     )
-                    .ok_or("commit failed")
-                }
+                };
+                self.check_resp(
+                    tmq_resp,
+                    format_args!(
+                        "commit offset {offset} of topic {topic_name} vgroup {vgroup_id} failed"
+                    ),
+                )
             } else {
-                unimplemented!("does not support tmq_commit_offset_sync");
+                Err(RawError::from_string(
+                    "tmq_commit_offset_sync is not supported by current client library",
+                ))
             }
         }
 
         pub async fn commit(&self, msg: RawRes) -> RawResult<()> {
             // use tokio::sync::oneshot::{channel, Sender};
             use std::sync::mpsc::{channel, Sender};
-            let (sender, rx) = channel::<RawResult<()>>();
+            let (sender, rx) = channel::<tmq_resp_err_t>();
             unsafe extern "C" fn tmq_commit_async_cb(
                 _tmq: *mut tmq_t,
                 resp: tmq_resp_err_t,
                 param: *mut std::os::raw::c_void,
             ) {
-                let offsets = resp.ok_or("commit failed").map(|_| ());
                 let sender = param as *mut Sender<_>;
                 let sender = Box::from_raw(sender);
                 tracing::trace!("commit async callback");
-                sender.send(offsets).unwrap();
+                sender.send(resp).unwrap();
             }
 
             unsafe {
@@ -109,7 +124,7 @@ pub(super) mod tmq {
                     Box::into_raw(Box::new(sender)) as *mut _,
                 )
             }
-            rx.recv().unwrap()
+            self.check_resp(rx.recv().unwrap(), "commit failed")
         }
 
         pub async fn commit_offset_async(
@@ -120,17 +135,16 @@ pub(super) mod tmq {
         ) -> RawResult<()> {
             if let Some(tmq_commit_offset_async) = self.tmq.tmq_commit_offset_async {
                 use std::sync::mpsc::{channel, Sender};
-                let (sender, rx) = channel::<RawResult<()>>();
+                let (sender, rx) = channel::<tmq_resp_err_t>();
                 unsafe extern "C" fn tmq_commit_offset_async_cb(
                     _tmq: *mut tmq_t,
                     resp: tmq_resp_err_t,
                     param: *mut std::os::raw::c_void,
                 ) {
-                    let offsets = resp.ok_or("commit offset failed").map(|_| ());
                     let sender = param as *mut Sender<_>;
                     let sender = Box::from_raw(sender);
                     tracing::trace!("commit offset async callback");
-                    sender.send(offsets).unwrap();
+                    sender.send(resp).unwrap();
                 }
 
                 unsafe {
@@ -144,23 +158,38 @@ pub(super) mod tmq {
                         Box::into_raw(Box::new(sender)) as *mut _,
                     )
                 }
-                rx.recv().unwrap()
+                self.check_resp(
+                    rx.recv().unwrap(),
+                    format_args!(
+                        "commit offset {offset} of topic {topic_name} vgroup {vgroup_id} failed"
+                    ),
+                )
             } else {
-                unimplemented!("does not support tmq_commit_offset_async");
+                Err(RawError::from_string(
+                    "tmq_commit_offset_async is not supported by current client library",
+                ))
             }
         }
 
-        pub fn poll_timeout(&self, timeout: i64) -> Option<RawRes> {
+        pub fn poll_timeout(&self, timeout: i64) -> RawResult<Option<RawRes>> {
             tracing::trace!("poll next message with timeout {}", timeout);
             let res = unsafe { (self.tmq.tmq_consumer_poll)(self.as_ptr(), timeout) };
-            if res.is_null() {
-                None
-            } else {
-                Some(unsafe { RawRes::from_ptr_unchecked(self.c.clone(), res) })
-            }
+            self.polled(res)
+        }
+
+        /// Message of a poll, or the error reported by the library when there's no message.
+        ///
+        /// Called on the polling thread since the error number is thread local.
+        fn polled(&self, res: *mut c_void) -> RawResult<Option<RawRes>> {
+            let res = (!res.is_null())
+                .then(|| unsafe { RawRes::from_ptr_unchecked(self.c.clone(), res) });
+            poll_result(res, || {
+                let null = std::ptr::null_mut();
+                (self.c.errno(null), self.c.err_str(null).into_owned())
+            })
         }
 
-        pub async fn poll_async(&self) -> RawRes {
+        pub async fn poll_async(&self) -> RawResult<RawRes> {
             let elapsed = std::time::Instant::now();
             #[cfg(not(test))]
             use taos_query::prelude::tokio;
@@ -173,55 +202,54 @@ pub(super) mod tmq {
                 let res = tokio::task::spawn_blocking(move || {
                     let raw = raw;
                     let res = unsafe { (raw.tmq.tmq_consumer_poll)(raw.as_ptr(), 200) };
-                    if res.is_null() {
-                        None
-                    } else {
-                        Some(unsafe { RawRes::from_ptr_unchecked(raw.c.clone(), res) })
-                    }
-                    // result
+                    raw.polled(res)
                 })
                 .await
-                .unwrap_or_default();
+                .map_err(RawError::from_any)??;
                 if let Some(res) = res {
                     tracing::trace!("received tmq message in {:?}", elapsed.elapsed());
-                    break res;
+                    break Ok(res);
                 } else {
                     tokio::time::sleep(Duration::from_millis(1)).await;
                 }
             }
         }
 
-        pub fn unsubscribe(&mut self) {
-            unsafe {
-                (self.tmq.tmq_unsubscribe)(self.as_ptr());
-            }
+        pub fn unsubscribe(&mut self) -> RawResult<()> {
+            let tmq_resp = unsafe { (self.tmq.tmq_unsubscribe)(self.as_ptr()) };
+            self.check_resp(tmq_resp, "unsubscribe failed")
         }
 
-        pub fn get_topic_assignment(&self, topic_name: &str) -> Vec<Assignment> {
+        pub fn get_topic_assignment(&self, topic_name: &str) -> RawResult<Vec<Assignment>> {
             let pt: *mut *mut Assignment = Box::into_raw(Box::new(std::ptr::null_mut()));
-            if let Some(tmq_get_topic_assignment) = self.tmq.tmq_get_topic_assignment {
-                let mut num: i32 = 0;
+            let Some(tmq_get_topic_assignment) = self.tmq.tmq_get_topic_assignment else {
+                return Err(RawError::from_string(
+                    "tmq_get_topic_assignment is not supported by the native library",
+                ));
+            };
+            let mut num: i32 = 0;
 
-                let tmq_resp = unsafe {
-                    tmq_get_topic_assignment(
-                        self.as_ptr(),
-                        topic_name.into_c_str().as_ptr(),
-                        pt,
-                        &mut num,
-                    )
-                };
+            let tmq_resp = unsafe {
+                tmq_get_topic_assignment(
+                    self.as_ptr(),
+                    topic_name.into_c_str().as_ptr(),
+                    pt,
+                    &mut num,
+                )
+            };
 
-                if tmq_resp.is_err() || num == 0 {
-                    return vec![];
-                }
-                unsafe { std::slice::from_raw_parts(*pt, num as usize).to_vec() }
-            } else {
-                vec![]
+            self.check_resp(
+                tmq_resp,
+                format_args!("get assignment of topic {topic_name} failed"),
+            )?;
+            if num == 0 {
+                return Ok(vec![]);
             }
+            Ok(unsafe { std::slice::from_raw_parts(*pt, num as usize).to_vec() })
         }
 
         pub fn offset_seek(
-            &mut self,
+            &self,
             topic_name: &str,
             vgroup_id: VGroupId,
             offset: i64,
@@ -248,74 +276,123 @@ pub(super) mod tmq {
                 offset
             );
 
-            let err_str = self.err_as_str(tmq_resp);
-            tracing::trace!("offset_seek tmq_resp as str: {}", err_str);
-
-            tmq_resp.ok_or(format!("offset seek failed: {err_str}"))
+            self.check_resp(
+                tmq_resp,
+                format_args!(
+                    "seek topic {topic_name} vgroup {vgroup_id} to offset {offset} failed"
+                ),
+            )
         }
 
         pub fn committed(&self, topic_name: &str, vgroup_id: VGroupId) -> RawResult<i64> {
-            let tmq_resp;
-            if let Some(tmq_committed) = self.tmq.tmq_committed {
-                tmq_resp = unsafe {
-                    tmq_committed(self.as_ptr(), topic_name.into_c_str().as_ptr(), vgroup_id)
-                };
+            let offset = if let Some(tmq_committed) = self.tmq.tmq_committed {
+                unsafe { tmq_committed(self.as_ptr(), topic_name.into_c_str().as_ptr(), vgroup_id) }
             } else {
-                unimplemented!("does not support tmq_committed");
-            }
+                return Err(RawError::from_string(
+                    "tmq_committed is not supported by current client library",
+                ));
+            };
             tracing::trace!(
-                "committed tmq_resp: {:?}, topic_name: {}, vgroup_id: {}",
-                tmq_resp,
+                "committed offset: {}, topic_name: {}, vgroup_id: {}",
+                offset,
                 topic_name,
                 vgroup_id
             );
 
-            if tmq_resp.0 as i32 > 0 {
-                return Ok(tmq_resp.0 as _);
+            // tmq_committed returns the committed offset, or a negative error code.
+            if offset >= 0 {
+                Ok(offset)
+            } else if Code::from(offset as i32) == Code::TMQ_NO_COMMITTED {
+                Ok(-1)
             } else {
+                let tmq_resp = tmq_resp_err_t(offset as i32);
                 let err_str = self.err_as_str(tmq_resp);
                 tracing::trace!("committed tmq_resp err string: {}", err_str);
 
-                return Err(RawError::new(
+                Err(RawError::new(
                     tmq_resp.0,
-                    format!("get committed failed: {err_str}"),
-                ));
+                    format!("get committed offset of topic {topic_name} vgroup {vgroup_id} failed: {err_str}"),
+                ))
             }
         }
 
         pub fn position(&self, topic_name: &str, vgroup_id: VGroupId) -> RawResult<i64> {
-            let tmq_resp;
-            if let Some(tmq_position) = self.tmq.tmq_position {
-                tmq_resp = unsafe {
-                    tmq_position(self.as_ptr(), topic_name.into_c_str().as_ptr(), vgroup_id)
-                };
+            let offset = if let Some(tmq_position) = self.tmq.tmq_position {
+                unsafe { tmq_position(self.as_ptr(), topic_name.into_c_str().as_ptr(), vgroup_id) }
             } else {
-                unimplemented!("does not support tmq_position");
-            }
+                return Err(RawError::from_string(
+                    "tmq_position is not supported by current client library",
+                ));
+            };
             tracing::trace!(
-                "position tmq_resp: {:?}, topic_name: {}, vgroup_id: {}",
-                tmq_resp,
+                "position offset: {}, topic_name: {}, vgroup_id: {}",
+                offset,
                 topic_name,
                 vgroup_id
             );
 
-            if tmq_resp.0 as i32 > 0 {
-                return Ok(tmq_resp.0 as _);
+            // tmq_position returns the current position, or a negative error code.
+            if offset >= 0 {
+                Ok(offset)
             } else {
+                let tmq_resp = tmq_resp_err_t(offset as i32);
                 let err_str = self.err_as_str(tmq_resp);
                 tracing::trace!("position tmq_resp err string: {}", err_str);
 
-                return Err(RawError::new(
+                Err(RawError::new(
                     tmq_resp.0,
-                    format!("get position failed: {err_str}"),
-                ));
+                    format!(
+                        "get position of topic {topic_name} vgroup {vgroup_id} failed: {err_str}"
+                    ),
+                ))
             }
         }
 
-        pub fn close(&mut self) {
-            unsafe {
-                (self.tmq.tmq_consumer_close)(self.as_ptr());
-            }
+        pub fn close(&mut self) -> RawResult<()> {
+            let tmq_resp = unsafe { (self.tmq.tmq_consumer_close)(self.as_ptr()) };
+            self.check_resp(tmq_resp, "close consumer failed")
+        }
+    }
+
+    /// The polled message `res`, or the error by `errno` if there's no message, as the library
+    /// returns NULL for both a timeout and a failure.
+    fn poll_result<T>(
+        res: Option<T>,
+        errno: impl FnOnce() -> (i32, String),
+    ) -> RawResult<Option<T>> {
+        if res.is_some() {
+            return Ok(res);
+        }
+        let (code, err_str) = errno();
+        let code = Code::from(code);
+        if code.success() {
+            Ok(None)
+        } else {
+            Err(RawError::new(code, format!("poll failed: {err_str}")))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use taos_query::prelude::Code;
+
+        use super::poll_result;
+
+        #[test]
+        fn test_poll_result() {
+            assert_eq!(poll_result(Some(1), || unreachable!()).unwrap(), Some(1));
+            assert_eq!(
+                poll_result(None::<i32>, || (0, "success".to_string())).unwrap(),
+                None
+            );
+
+            // The library reports the error number in the high bits, e.g. 0x80004001.
+            let err = poll_result(None::<i32>, || {
+                (0x80004001_u32 as i32, "Consumer mismatch".to_string())
+            })
+            .unwrap_err();
+            assert_eq!(err.code(), Code::TMQ_CONSUMER_MISMATCH);
+            assert!(err.to_string().contains("Consumer mismatch"), "{err}");
         }
     }
 }
@@ -23,6 +23,11 @@ impl tmq_resp_err_t {
         !self.is_ok()
     }
 
+    /// The error code, `0` for success.
+    pub fn errno(self) -> i32 {
+        self.0
+    }
+
     pub fn ok_or(self, s: impl Into<Cow<'static, str>>) -> Result<(), RawError> {
         match self {
             Self(0) => Ok(()),
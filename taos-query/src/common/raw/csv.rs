@@ -0,0 +1,82 @@
+use std::borrow::Cow;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use super::RawBlock;
+use crate::{common::BorrowedValue, RawError, RawResult};
+
+fn cell(value: BorrowedValue) -> Cow<str> {
+    match value {
+        BorrowedValue::Null(_) => Cow::Borrowed(""),
+        BorrowedValue::Timestamp(ts) => ts.to_rfc3339_utc().into(),
+        BorrowedValue::VarBinary(v) | BorrowedValue::Blob(v) | BorrowedValue::MediumBlob(v) => {
+            STANDARD.encode(v).into()
+        }
+        BorrowedValue::Decimal(v) => v.to_string().into(),
+        value => value.to_str().unwrap_or_default().into_owned().into(),
+    }
+}
+
+/// Render `blocks` as CSV with a header row of the field names.
+///
+/// Nulls are empty, timestamps are RFC3339 in UTC with the precision of the block, binary values
+/// are base64 encoded. Blocks must have the same field names.
+pub(crate) fn blocks_to_csv(blocks: &[RawBlock]) -> RawResult<String> {
+    let mut writer = ::csv::Writer::from_writer(Vec::new());
+    if let Some(first) = blocks.first() {
+        writer
+            .write_record(first.field_names())
+            .map_err(RawError::from_any)?;
+    }
+    for block in blocks {
+        if blocks[0].field_names() != block.field_names() {
+            return Err(RawError::from_string(format!(
+                "csv of blocks with different fields: {:?} and {:?}",
+                blocks[0].field_names(),
+                block.field_names()
+            )));
+        }
+        for row in 0..block.nrows() {
+            let record =
+                (0..block.ncols()).map(|col| cell(unsafe { block.get_ref_unchecked(row, col) }));
+            writer
+                .write_record(record.map(|s| s.into_owned()))
+                .map_err(RawError::from_any)?;
+        }
+    }
+    let bytes = writer.into_inner().map_err(RawError::from_any)?;
+    String::from_utf8(bytes).map_err(RawError::from_any)
+}
+
+#[test]
+fn test_blocks_to_csv() {
+    use crate::common::{ColumnView, Precision};
+
+    let block = |names: [&str; 3]| {
+        let mut block = RawBlock::from_views(
+            &[
+                ColumnView::from_millis_timestamp(vec![0, 1500]),
+                ColumnView::from_ints(vec![Some(1), None]),
+                ColumnView::from_varchar::<&str, _, _, _>(vec![Some("a,b"), None]),
+            ],
+            Precision::Millisecond,
+        );
+        block.with_field_names(names);
+        block
+    };
+
+    let csv = blocks_to_csv(&[block(["ts", "v", "s"]), block(["ts", "v", "s"])]).unwrap();
+    assert_eq!(
+        csv,
+        "ts,v,s\n\
+         1970-01-01T00:00:00.000Z,1,\"a,b\"\n\
+         1970-01-01T00:00:01.500Z,,\n\
+         1970-01-01T00:00:00.000Z,1,\"a,b\"\n\
+         1970-01-01T00:00:01.500Z,,\n"
+    );
+    assert_eq!(blocks_to_csv(&[]).unwrap(), "");
+
+    assert!(blocks_to_csv(&[block(["ts", "v", "s"]), block(["ts", "v", "t"])]).is_err());
+
+    assert_eq!(cell(BorrowedValue::VarBinary(b"\x00\xff")), "AP8=");
+}
@@ -3,9 +3,20 @@ use std::{borrow::Cow, ffi::c_void};
 use bytes::Bytes;
 
 use crate::util::{Inlinable, InlinableRead};
+use crate::{RawError, RawResult};
 
 const RAW_PTR_OFFSET: usize = std::mem::size_of::<u32>() + std::mem::size_of::<u16>();
 
+/// Magic of frames produced by [RawData::to_frame] and `RawMeta::to_frame`.
+const FRAME_MAGIC: &[u8; 4] = b"TRAW";
+/// Version of the frame layout.
+const FRAME_VERSION: u8 = 1;
+/// Magic, version, kind and payload length.
+const FRAME_HEADER_LEN: usize = FRAME_MAGIC.len() + 2 + std::mem::size_of::<u32>();
+
+pub(super) const FRAME_KIND_META: u8 = 1;
+pub(super) const FRAME_KIND_DATA: u8 = 2;
+
 /// C-struct for raw data, just a data view from native library.
 ///
 /// It can be copy/cloned, but should not use it outbound away a offset lifetime.
@@ -87,6 +98,96 @@ impl RawData {
     pub fn as_bytes(&self) -> Cow<Bytes> {
         Cow::Borrowed(&self.0)
     }
+
+    /// Encode as a self-describing frame, to forward the raw data out of the process and to
+    /// reconstruct it by [from_frame](Self::from_frame).
+    ///
+    /// The frame is laid out as:
+    ///
+    /// | magic     | version | kind              | length         | payload                     |
+    /// | --------- | ------- | ----------------- | -------------- | --------------------------- |
+    /// | `b"TRAW"` | `1u8`   | 1: meta, 2: data  | payload length | [as_bytes](Self::as_bytes) |
+    ///
+    /// Integers are little-endian.
+    pub fn to_frame(&self) -> Bytes {
+        self.encode_frame(FRAME_KIND_DATA)
+    }
+
+    /// Decode a frame produced by [to_frame](Self::to_frame).
+    pub fn from_frame(frame: Bytes) -> RawResult<Self> {
+        Self::decode_frame(frame, FRAME_KIND_DATA)
+    }
+
+    pub(super) fn encode_frame(&self, kind: u8) -> Bytes {
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + self.0.len());
+        frame.extend(FRAME_MAGIC);
+        frame.push(FRAME_VERSION);
+        frame.push(kind);
+        frame.extend((self.0.len() as u32).to_le_bytes());
+        frame.extend(&self.0);
+        Bytes::from(frame)
+    }
+
+    pub(super) fn decode_frame(frame: Bytes, kind: u8) -> RawResult<Self> {
+        if frame.len() < FRAME_HEADER_LEN || &frame[..FRAME_MAGIC.len()] != FRAME_MAGIC {
+            return Err(RawError::from_string("invalid raw frame: bad magic"));
+        }
+        let (version, actual) = (frame[4], frame[5]);
+        if version != FRAME_VERSION {
+            return Err(RawError::from_string(format!(
+                "unsupported raw frame version {version}"
+            )));
+        }
+        if actual != kind {
+            return Err(RawError::from_string(format!(
+                "invalid raw frame: expect kind {kind}, got {actual}"
+            )));
+        }
+        let len = u32::from_le_bytes(frame[6..FRAME_HEADER_LEN].try_into().unwrap()) as usize;
+        let payload = frame.slice(FRAME_HEADER_LEN..);
+        if payload.len() != len
+            || len < RAW_PTR_OFFSET
+            || u32::from_le_bytes(payload[..4].try_into().unwrap()) as usize + RAW_PTR_OFFSET != len
+        {
+            return Err(RawError::from_string(format!(
+                "invalid raw frame: payload of {} bytes is truncated or corrupted",
+                payload.len()
+            )));
+        }
+        // Copy to a new allocation, the header is read as aligned integers.
+        Ok(RawData(Bytes::copy_from_slice(&payload)))
+    }
+}
+
+#[test]
+fn test_raw_data_frame() {
+    let mut bytes = Vec::new();
+    bytes.extend(5u32.to_le_bytes());
+    bytes.extend(2u16.to_le_bytes());
+    bytes.extend(b"block");
+    let data = RawData::new(Bytes::from(bytes));
+
+    let frame = data.to_frame();
+    assert_eq!(&frame[..6], b"TRAW\x01\x02");
+    let decoded = RawData::from_frame(frame.clone()).unwrap();
+    assert_eq!(decoded.as_ref(), data.as_ref());
+    assert_eq!(decoded.raw_len(), 5);
+    assert_eq!(decoded.raw_type(), 2);
+
+    assert!(RawData::from_frame(frame.slice(..frame.len() - 1)).is_err());
+    assert!(RawData::from_frame(Bytes::from_static(b"TRAW")).is_err());
+    let mut other = frame.to_vec();
+    other[4] = 2;
+    assert!(RawData::from_frame(Bytes::from(other)).is_err());
+    let mut other = frame.to_vec();
+    other[0] = b'X';
+    assert!(RawData::from_frame(Bytes::from(other)).is_err());
+}
+
+impl AsRef<[u8]> for RawData {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
 }
 
 impl Inlinable for RawData {
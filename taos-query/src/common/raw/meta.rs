@@ -14,7 +14,7 @@ use crate::{
     util::Inlinable,
 };
 
-use super::RawData;
+use super::{RawData, FRAME_KIND_META};
 
 #[derive(Debug, Clone)]
 pub struct RawMeta(RawData);
@@ -37,6 +37,79 @@ impl RawMeta {
     pub fn new(raw: Bytes) -> Self {
         RawMeta(raw.into())
     }
+
+    /// The encoded meta buffer, including the length and type header, without copying.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+
+    /// Length in bytes of the encoded meta, including the length and type header.
+    pub fn len(&self) -> usize {
+        self.as_bytes().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Encode as a self-describing frame, see [RawData::to_frame].
+    pub fn to_frame(&self) -> Bytes {
+        self.0.encode_frame(FRAME_KIND_META)
+    }
+
+    /// Decode a frame produced by [to_frame](Self::to_frame), a data frame is rejected.
+    pub fn from_frame(frame: Bytes) -> crate::RawResult<Self> {
+        RawData::decode_frame(frame, FRAME_KIND_META).map(RawMeta)
+    }
+}
+
+#[test]
+fn test_raw_meta_frame() {
+    let mut bytes = Vec::new();
+    bytes.extend(4u32.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(b"meta");
+    let meta = RawMeta::new(Bytes::from(bytes));
+
+    let frame = meta.to_frame();
+    let decoded = RawMeta::from_frame(frame.clone()).unwrap();
+    assert_eq!(decoded.as_bytes(), meta.as_bytes());
+    assert_eq!(decoded.raw_type(), 1);
+
+    // Kinds are not interchangeable.
+    assert!(RawData::from_frame(frame).is_err());
+    let data = RawData::from_frame(RawData::from(meta.as_bytes().to_vec()).to_frame()).unwrap();
+    assert!(RawMeta::from_frame(data.to_frame()).is_err());
+}
+
+#[test]
+fn test_raw_meta_len() {
+    let mut bytes = Vec::new();
+    bytes.extend(3u32.to_le_bytes());
+    bytes.extend(1u16.to_le_bytes());
+    bytes.extend(b"abc");
+    let meta = RawMeta::new(Bytes::from(bytes.clone()));
+    assert_eq!(meta.len(), bytes.len());
+    assert_eq!(meta.raw_len(), 3);
+    assert!(!meta.is_empty());
+
+    assert!(RawMeta::new(Bytes::new()).is_empty());
+}
+
+#[test]
+fn test_raw_meta_as_bytes() {
+    let mut bytes = Vec::new();
+    bytes.extend(4u32.to_le_bytes());
+    bytes.extend(2u16.to_le_bytes());
+    bytes.extend(b"meta");
+    let meta = RawMeta::new(Bytes::from(bytes.clone()));
+    let slice = meta.as_bytes();
+    assert_eq!(slice, bytes.as_slice());
+
+    let copied = RawMeta::new(Bytes::copy_from_slice(slice));
+    assert_eq!(copied.as_bytes(), meta.as_bytes());
+    assert_eq!(copied.raw_len(), 4);
+    assert_eq!(copied.raw_type(), 2);
 }
 
 impl Inlinable for RawMeta {
@@ -163,6 +236,16 @@ pub struct TagWithValue {
     pub value: serde_json::Value,
 }
 
+/// A child table in `createList` of a batch create meta.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MetaCreateChild {
+    pub table_name: String,
+    pub using: String,
+    pub tags: Vec<TagWithValue>,
+    pub tag_num: Option<usize>,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(tag = "tableType")]
 #[serde(rename_all = "camelCase")]
@@ -179,6 +262,9 @@ pub enum MetaCreate {
         using: String,
         tags: Vec<TagWithValue>,
         tag_num: Option<usize>,
+        /// All child tables of a batch create, including the first one above.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        create_list: Vec<MetaCreateChild>,
     },
     #[serde(rename_all = "camelCase")]
     Normal {
@@ -213,6 +299,7 @@ impl Display for MetaCreate {
                 using,
                 tags,
                 tag_num,
+                ..
             } => {
                 if !tags.is_empty() {
                     f.write_fmt(format_args!(
@@ -419,6 +506,23 @@ pub enum JsonMeta {
     Delete(MetaDelete),
 }
 
+/// Kind of the DDL of a [JsonMeta].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetaType {
+    Create,
+    Alter,
+    Drop,
+    Delete,
+}
+
+/// Type of the table affected by a [JsonMeta].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TableType {
+    Super,
+    Child,
+    Normal,
+}
+
 impl Display for JsonMeta {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -431,6 +535,507 @@ impl Display for JsonMeta {
     }
 }
 
+impl JsonMeta {
+    /// Kind of the DDL of the meta.
+    pub fn meta_type(&self) -> MetaType {
+        match self {
+            JsonMeta::Create(_) => MetaType::Create,
+            JsonMeta::Alter(_) => MetaType::Alter,
+            JsonMeta::Drop(_) => MetaType::Drop,
+            JsonMeta::Delete(_) => MetaType::Delete,
+        }
+    }
+
+    /// Type of the affected table, `None` if the meta does not tell.
+    ///
+    /// Create metas have one, drop metas only for a super table: child and normal tables are
+    /// dropped in the same list.
+    pub fn table_type(&self) -> Option<TableType> {
+        match self {
+            JsonMeta::Create(MetaCreate::Super { .. }) => Some(TableType::Super),
+            JsonMeta::Create(MetaCreate::Child { .. }) => Some(TableType::Child),
+            JsonMeta::Create(MetaCreate::Normal { .. }) => Some(TableType::Normal),
+            JsonMeta::Drop(MetaDrop::Super { .. }) => Some(TableType::Super),
+            _ => None,
+        }
+    }
+
+    /// Name of the affected table, the first one if a batch create or drop affects more, see
+    /// [table_names](Self::table_names). Delete metas have none.
+    pub fn table_name(&self) -> Option<&str> {
+        match self {
+            JsonMeta::Create(MetaCreate::Super { table_name, .. })
+            | JsonMeta::Create(MetaCreate::Child { table_name, .. })
+            | JsonMeta::Create(MetaCreate::Normal { table_name, .. })
+            | JsonMeta::Alter(MetaAlter { table_name, .. })
+            | JsonMeta::Drop(MetaDrop::Super { table_name }) => Some(table_name),
+            JsonMeta::Drop(MetaDrop::Other { table_name_list }) => {
+                table_name_list.first().map(String::as_str)
+            }
+            JsonMeta::Delete(_) => None,
+        }
+    }
+
+    /// Names of all the affected tables, for batch creates of child tables and drops of
+    /// multiple tables.
+    pub fn table_names(&self) -> Vec<&str> {
+        match self {
+            JsonMeta::Create(MetaCreate::Child { create_list, .. }) if !create_list.is_empty() => {
+                create_list.iter().map(|c| c.table_name.as_str()).collect()
+            }
+            JsonMeta::Drop(MetaDrop::Other { table_name_list }) => {
+                table_name_list.iter().map(String::as_str).collect()
+            }
+            _ => self.table_name().into_iter().collect(),
+        }
+    }
+
+    /// Typed events of the meta, a batch create or drop meta yields one event for each table.
+    pub fn events(&self) -> Vec<MetaEvent> {
+        match self.clone() {
+            JsonMeta::Create(MetaCreate::Super {
+                table_name,
+                columns,
+                tags,
+            }) => vec![MetaEvent::CreateSuperTable {
+                table_name,
+                columns,
+                tags,
+            }],
+            JsonMeta::Create(MetaCreate::Child {
+                table_name,
+                using,
+                tags,
+                tag_num,
+                create_list,
+            }) => {
+                if create_list.is_empty() {
+                    return vec![MetaEvent::CreateChildTable {
+                        table_name,
+                        using,
+                        tags,
+                        tag_num,
+                    }];
+                }
+                create_list
+                    .into_iter()
+                    .map(|child| MetaEvent::CreateChildTable {
+                        table_name: child.table_name,
+                        using: child.using,
+                        tags: child.tags,
+                        tag_num: child.tag_num,
+                    })
+                    .collect()
+            }
+            JsonMeta::Create(MetaCreate::Normal {
+                table_name,
+                columns,
+            }) => vec![MetaEvent::CreateNormalTable {
+                table_name,
+                columns,
+            }],
+            JsonMeta::Alter(alter) => vec![MetaEvent::AlterTable {
+                table_name: alter.table_name,
+                alter_type: alter.alter_type,
+                field: alter.field,
+                col_new_name: alter.col_new_name,
+                col_value: alter.col_value,
+                col_value_null: alter.col_value_null,
+            }],
+            JsonMeta::Drop(MetaDrop::Super { table_name }) => {
+                vec![MetaEvent::DropSuperTable { table_name }]
+            }
+            JsonMeta::Drop(MetaDrop::Other { table_name_list }) => table_name_list
+                .into_iter()
+                .map(|table_name| MetaEvent::DropTable { table_name })
+                .collect(),
+            JsonMeta::Delete(MetaDelete { sql }) => vec![MetaEvent::DeleteData { sql }],
+        }
+    }
+}
+
+/// Typed event of a meta message, see [JsonMeta::events] and [MetaEvent::from_json].
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub enum MetaEvent {
+    #[serde(rename_all = "camelCase")]
+    CreateSuperTable {
+        table_name: String,
+        columns: Vec<Field>,
+        tags: Vec<Field>,
+    },
+    #[serde(rename_all = "camelCase")]
+    CreateChildTable {
+        table_name: String,
+        using: String,
+        tags: Vec<TagWithValue>,
+        tag_num: Option<usize>,
+    },
+    #[serde(rename_all = "camelCase")]
+    CreateNormalTable {
+        table_name: String,
+        columns: Vec<Field>,
+    },
+    #[serde(rename_all = "camelCase")]
+    AlterTable {
+        table_name: String,
+        alter_type: AlterType,
+        field: Field,
+        col_new_name: Option<String>,
+        col_value: Option<String>,
+        col_value_null: Option<bool>,
+    },
+    #[serde(rename_all = "camelCase")]
+    DropSuperTable {
+        table_name: String,
+    },
+    #[serde(rename_all = "camelCase")]
+    DropTable {
+        table_name: String,
+    },
+    DeleteData {
+        sql: String,
+    },
+    /// Meta of unknown type or format, kept as is.
+    Other(serde_json::Value),
+}
+
+impl MetaEvent {
+    /// Parse events from the json meta payload.
+    ///
+    /// Batch created child tables in `createList` are split into events, and any unknown
+    /// meta is preserved as [MetaEvent::Other] instead of failing the whole payload.
+    pub fn from_json(value: serde_json::Value) -> Vec<Self> {
+        let create_list = value
+            .get("createList")
+            .and_then(|list| list.as_array())
+            .filter(|list| !list.is_empty());
+        if let Some(list) = create_list {
+            return list
+                .iter()
+                .flat_map(|item| {
+                    let mut item = item.clone();
+                    if let Some(object) = item.as_object_mut() {
+                        object.entry("type").or_insert("create".into());
+                        object.entry("tableType").or_insert("child".into());
+                    }
+                    Self::from_meta_value(item)
+                })
+                .collect();
+        }
+        Self::from_meta_value(value)
+    }
+
+    fn from_meta_value(value: serde_json::Value) -> Vec<Self> {
+        match JsonMeta::deserialize(&value) {
+            Ok(meta) => meta.events(),
+            Err(_) => vec![MetaEvent::Other(value)],
+        }
+    }
+}
+
+/// Schema changes between two metas of a table, see [JsonMeta::diff].
+#[derive(Debug, Default, Serialize, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonMetaDiff {
+    pub added_columns: Vec<Field>,
+    pub removed_columns: Vec<Field>,
+    pub modified_columns: Vec<FieldChange>,
+    pub added_tags: Vec<Field>,
+    pub removed_tags: Vec<Field>,
+    pub modified_tags: Vec<FieldChange>,
+    /// Tag value changes of child tables.
+    pub modified_tag_values: Vec<TagValueChange>,
+}
+
+/// A column or tag changed in type, length or name.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct FieldChange {
+    pub from: Field,
+    pub to: Field,
+}
+
+/// A tag value change of a child table, `from` is `None` if the old value is unknown.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct TagValueChange {
+    pub name: String,
+    pub from: Option<serde_json::Value>,
+    pub to: serde_json::Value,
+}
+
+impl JsonMetaDiff {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+impl JsonMeta {
+    /// Columns and tags of a create meta.
+    fn schema(&self) -> Option<(&[Field], Vec<&Field>)> {
+        match self {
+            JsonMeta::Create(MetaCreate::Super { columns, tags, .. }) => {
+                Some((columns, tags.iter().collect()))
+            }
+            JsonMeta::Create(MetaCreate::Normal { columns, .. }) => Some((columns, Vec::new())),
+            JsonMeta::Create(MetaCreate::Child { tags, .. }) => {
+                Some((&[], tags.iter().map(|t| &t.field).collect()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Schema changes from `self` to the newer `other`.
+    ///
+    /// If `other` is a create meta, the columns and tags of both creates are compared by name,
+    /// and tag values too for child tables. If `other` is an alter meta, it describes the
+    /// change itself, `self` is only used to look up the previous column or tag. Drop and
+    /// delete metas have no schema changes.
+    pub fn diff(&self, other: &JsonMeta) -> JsonMetaDiff {
+        match other {
+            JsonMeta::Alter(alter) => self.diff_alter(alter),
+            JsonMeta::Create(create) => {
+                let mut diff = JsonMetaDiff::default();
+                let (old_columns, old_tags) = self.schema().unwrap_or_default();
+                let (new_columns, new_tags) = other.schema().unwrap_or_default();
+                diff_fields(
+                    old_columns.iter(),
+                    new_columns.iter(),
+                    &mut diff.added_columns,
+                    &mut diff.removed_columns,
+                    &mut diff.modified_columns,
+                );
+                diff_fields(
+                    old_tags.into_iter(),
+                    new_tags.into_iter(),
+                    &mut diff.added_tags,
+                    &mut diff.removed_tags,
+                    &mut diff.modified_tags,
+                );
+                if let MetaCreate::Child { tags, .. } = create {
+                    for tag in tags {
+                        let old = self.child_tag_value(tag.field.name());
+                        if old != Some(&tag.value) {
+                            diff.modified_tag_values.push(TagValueChange {
+                                name: tag.field.name().to_string(),
+                                from: old.cloned(),
+                                to: tag.value.clone(),
+                            });
+                        }
+                    }
+                }
+                diff
+            }
+            JsonMeta::Drop(_) | JsonMeta::Delete(_) => JsonMetaDiff::default(),
+        }
+    }
+
+    fn child_tag_value(&self, name: &str) -> Option<&serde_json::Value> {
+        match self {
+            JsonMeta::Create(MetaCreate::Child { tags, .. }) => tags
+                .iter()
+                .find(|t| t.field.name() == name)
+                .map(|t| &t.value),
+            _ => None,
+        }
+    }
+
+    fn diff_alter(&self, alter: &MetaAlter) -> JsonMetaDiff {
+        let (columns, tags) = self.schema().unwrap_or_default();
+        let column = || {
+            columns
+                .iter()
+                .find(|c| c.name() == alter.field.name())
+                .unwrap_or(&alter.field)
+                .clone()
+        };
+        let tag = || {
+            tags.iter()
+                .find(|t| t.name() == alter.field.name())
+                .copied()
+                .unwrap_or(&alter.field)
+                .clone()
+        };
+        let renamed = |from: Field| {
+            let to = Field {
+                name: alter.col_new_name.clone().unwrap_or_default(),
+                ..from.clone()
+            };
+            FieldChange { from, to }
+        };
+        let mut diff = JsonMetaDiff::default();
+        match alter.alter_type {
+            AlterType::AddColumn => diff.added_columns.push(alter.field.clone()),
+            AlterType::DropColumn => diff.removed_columns.push(column()),
+            AlterType::ModifyColumnLength => diff.modified_columns.push(FieldChange {
+                from: column(),
+                to: alter.field.clone(),
+            }),
+            AlterType::RenameColumn => diff.modified_columns.push(renamed(column())),
+            AlterType::AddTag => diff.added_tags.push(alter.field.clone()),
+            AlterType::DropTag => diff.removed_tags.push(tag()),
+            AlterType::ModifyTagLength => diff.modified_tags.push(FieldChange {
+                from: tag(),
+                to: alter.field.clone(),
+            }),
+            AlterType::RenameTag => diff.modified_tags.push(renamed(tag())),
+            AlterType::SetTagValue => {
+                let to = match (&alter.col_value, alter.col_value_null) {
+                    (_, Some(true)) | (None, _) => serde_json::Value::Null,
+                    (Some(value), _) => serde_json::Value::String(value.clone()),
+                };
+                diff.modified_tag_values.push(TagValueChange {
+                    name: alter.field.name().to_string(),
+                    from: self.child_tag_value(alter.field.name()).cloned(),
+                    to,
+                });
+            }
+            AlterType::ModifyTableOption => (),
+        }
+        diff
+    }
+}
+
+fn diff_fields<'a>(
+    old: impl Iterator<Item = &'a Field> + Clone,
+    new: impl Iterator<Item = &'a Field> + Clone,
+    added: &mut Vec<Field>,
+    removed: &mut Vec<Field>,
+    modified: &mut Vec<FieldChange>,
+) {
+    for field in new.clone() {
+        match old.clone().find(|f| f.name() == field.name()) {
+            Some(from) if from != field => modified.push(FieldChange {
+                from: from.clone(),
+                to: field.clone(),
+            }),
+            Some(_) => (),
+            None => added.push(field.clone()),
+        }
+    }
+    removed.extend(
+        old.filter(|f| new.clone().all(|n| n.name() != f.name()))
+            .cloned(),
+    );
+}
+
+#[test]
+fn test_meta_events() {
+    let events = MetaEvent::from_json(serde_json::json!({
+        "type": "create",
+        "tableType": "child",
+        "tableName": "t1",
+        "using": "st",
+        "tagNum": 1,
+        "tags": [{"name": "t", "type": 4, "value": 1}],
+        "createList": [
+            {"tableName": "t1", "using": "st", "tagNum": 1,
+             "tags": [{"name": "t", "type": 4, "value": 1}]},
+            {"tableName": "t2", "using": "st", "tagNum": 1,
+             "tags": [{"name": "t", "type": 4, "value": 2}]}
+        ]
+    }));
+    let names: Vec<_> = events
+        .iter()
+        .map(|event| match event {
+            MetaEvent::CreateChildTable { table_name, .. } => table_name.as_str(),
+            _ => unreachable!(),
+        })
+        .collect();
+    assert_eq!(names, ["t1", "t2"]);
+
+    let events = MetaEvent::from_json(serde_json::json!({
+        "type": "drop",
+        "tableNameList": ["t1", "t2"]
+    }));
+    assert_eq!(events.len(), 2);
+    assert!(matches!(&events[1], MetaEvent::DropTable { table_name } if table_name == "t2"));
+
+    let events = MetaEvent::from_json(serde_json::json!({
+        "type": "alter",
+        "tableName": "st",
+        "alterType": 5,
+        "colName": "v",
+        "colType": 4
+    }));
+    assert!(matches!(
+        &events[0],
+        MetaEvent::AlterTable { alter_type: AlterType::AddColumn, field, .. } if field.name() == "v"
+    ));
+
+    let unknown = serde_json::json!({"type": "rename", "tableName": "t1"});
+    let events = MetaEvent::from_json(unknown.clone());
+    assert!(matches!(&events[..], [MetaEvent::Other(value)] if value == &unknown));
+}
+
+#[test]
+fn test_json_meta_accessors() {
+    let meta = |value: serde_json::Value| -> JsonMeta { serde_json::from_value(value).unwrap() };
+
+    let create = meta(serde_json::json!({
+        "type": "create",
+        "tableType": "super",
+        "tableName": "st",
+        "columns": [{"name": "ts", "type": 9}, {"name": "v", "type": 4}],
+        "tags": [{"name": "t", "type": 4}]
+    }));
+    assert_eq!(create.meta_type(), MetaType::Create);
+    assert_eq!(create.table_type(), Some(TableType::Super));
+    assert_eq!(create.table_name(), Some("st"));
+
+    let create = meta(serde_json::json!({
+        "type": "create",
+        "tableType": "normal",
+        "tableName": "nt",
+        "columns": [{"name": "ts", "type": 9}]
+    }));
+    assert_eq!(create.table_type(), Some(TableType::Normal));
+    assert_eq!(create.table_names(), ["nt"]);
+
+    // Batch create of child tables.
+    let create = meta(serde_json::json!({
+        "type": "create",
+        "tableType": "child",
+        "tableName": "t1",
+        "using": "st",
+        "tagNum": 1,
+        "tags": [{"name": "t", "type": 4, "value": 1}],
+        "createList": [
+            {"tableName": "t1", "using": "st", "tagNum": 1,
+             "tags": [{"name": "t", "type": 4, "value": 1}]},
+            {"tableName": "t2", "using": "st", "tagNum": 1,
+             "tags": [{"name": "t", "type": 4, "value": 2}]}
+        ]
+    }));
+    assert_eq!(create.table_type(), Some(TableType::Child));
+    assert_eq!(create.table_name(), Some("t1"));
+    assert_eq!(create.table_names(), ["t1", "t2"]);
+    assert_eq!(create.events().len(), 2);
+
+    let alter = meta(serde_json::json!({
+        "type": "alter",
+        "tableName": "st",
+        "alterType": 5,
+        "colName": "c",
+        "colType": 6
+    }));
+    assert_eq!(alter.meta_type(), MetaType::Alter);
+    assert_eq!(alter.table_type(), None);
+    assert_eq!(alter.table_name(), Some("st"));
+
+    let drop = meta(serde_json::json!({"type": "drop", "tableName": "st"}));
+    assert_eq!(drop.meta_type(), MetaType::Drop);
+    assert_eq!(drop.table_type(), Some(TableType::Super));
+    let drop = meta(serde_json::json!({"type": "drop", "tableNameList": ["t1", "nt"]}));
+    assert_eq!(drop.table_type(), None);
+    assert_eq!(drop.table_name(), Some("t1"));
+    assert_eq!(drop.table_names(), ["t1", "nt"]);
+
+    let delete = meta(serde_json::json!({"type": "delete", "sql": "delete from t1"}));
+    assert_eq!(delete.meta_type(), MetaType::Delete);
+    assert_eq!(delete.table_name(), None);
+    assert!(delete.table_names().is_empty());
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(remote = "Field")]
 pub struct ColField {
@@ -453,3 +1058,87 @@ impl From<ColField> for Field {
         }
     }
 }
+
+#[test]
+fn test_meta_diff() {
+    let create: JsonMeta = serde_json::from_value(serde_json::json!({
+        "type": "create",
+        "tableType": "super",
+        "tableName": "st",
+        "columns": [{"name": "ts", "type": 9}, {"name": "v", "type": 4}],
+        "tags": [{"name": "t1", "type": 4}, {"name": "t2", "type": 8, "length": 16}]
+    }))
+    .unwrap();
+
+    // Add a column by alter.
+    let add_column: JsonMeta = serde_json::from_value(serde_json::json!({
+        "type": "alter",
+        "tableName": "st",
+        "alterType": 5,
+        "colName": "c",
+        "colType": 6
+    }))
+    .unwrap();
+    let diff = create.diff(&add_column);
+    assert_eq!(diff.added_columns, [Field::new("c", Ty::Float, 0)]);
+    assert!(diff.removed_columns.is_empty() && diff.added_tags.is_empty());
+
+    // Drop a tag by alter, the dropped tag is looked up from the create meta.
+    let drop_tag: JsonMeta = serde_json::from_value(serde_json::json!({
+        "type": "alter",
+        "tableName": "st",
+        "alterType": 2,
+        "colName": "t2"
+    }))
+    .unwrap();
+    let diff = create.diff(&drop_tag);
+    assert_eq!(diff.removed_tags, [Field::new("t2", Ty::VarChar, 16)]);
+
+    // Compare two creates: a column added, a tag dropped and a tag modified.
+    let recreate: JsonMeta = serde_json::from_value(serde_json::json!({
+        "type": "create",
+        "tableType": "super",
+        "tableName": "st",
+        "columns": [{"name": "ts", "type": 9}, {"name": "v", "type": 4}, {"name": "c", "type": 6}],
+        "tags": [{"name": "t1", "type": 5}]
+    }))
+    .unwrap();
+    let diff = create.diff(&recreate);
+    assert_eq!(diff.added_columns, [Field::new("c", Ty::Float, 0)]);
+    assert_eq!(diff.removed_tags, [Field::new("t2", Ty::VarChar, 16)]);
+    assert_eq!(
+        diff.modified_tags,
+        [FieldChange {
+            from: Field::new("t1", Ty::Int, 0),
+            to: Field::new("t1", Ty::BigInt, 0),
+        }]
+    );
+    assert!(recreate.diff(&recreate).is_empty());
+
+    let json = serde_json::to_value(&diff).unwrap();
+    assert_eq!(json["addedColumns"][0]["name"], "c");
+    assert_eq!(json["removedTags"][0]["name"], "t2");
+
+    // Tag values of child tables.
+    let child = |value: i32| -> JsonMeta {
+        serde_json::from_value(serde_json::json!({
+            "type": "create",
+            "tableType": "child",
+            "tableName": "t1",
+            "using": "st",
+            "tagNum": 1,
+            "tags": [{"name": "t1", "type": 4, "value": value}]
+        }))
+        .unwrap()
+    };
+    let diff = child(1).diff(&child(2));
+    assert_eq!(
+        diff.modified_tag_values,
+        [TagValueChange {
+            name: "t1".to_string(),
+            from: Some(1.into()),
+            to: 2.into(),
+        }]
+    );
+    assert!(diff.modified_tags.is_empty());
+}
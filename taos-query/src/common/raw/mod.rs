@@ -37,7 +37,15 @@ use views::*;
 pub use data::*;
 pub use meta::*;
 
+#[cfg(feature = "csv")]
+mod csv;
+#[cfg(feature = "csv")]
+pub(crate) use self::csv::blocks_to_csv;
 mod de;
+mod ndjson;
+pub(crate) use ndjson::blocks_to_ndjson;
+#[cfg(feature = "arrow")]
+mod record_batch;
 mod rows;
 pub use rows::*;
 
@@ -627,6 +635,21 @@ impl RawBlock {
         self.rows
     }
 
+    /// Estimated in-memory size of the block in bytes, without decoding.
+    ///
+    /// It counts the data buffer, which column views are sliced from, and the names
+    /// of the block. Use it to cap memory of buffered blocks, not for exact accounting.
+    pub fn estimated_bytes(&self) -> usize {
+        let data = unsafe { (*self.data.as_ptr()).len() };
+        let names = self.fields.iter().map(String::len).sum::<usize>()
+            + self.table.as_ref().map_or(0, String::len)
+            + self.database.as_ref().map_or(0, String::len);
+        std::mem::size_of::<Self>()
+            + data
+            + names
+            + self.columns.len() * std::mem::size_of::<ColumnView>()
+    }
+
     /// Precision for current block.
     #[inline]
     pub const fn precision(&self) -> Precision {
@@ -824,6 +847,71 @@ impl RawBlock {
         block
     }
 
+    /// Concatenate blocks into one by rows, in order.
+    ///
+    /// All the blocks should have the same column names, types and precision, or else an error
+    /// describing the first mismatch is returned. The table name is kept only if all the blocks
+    /// are of the same table.
+    pub fn concat_blocks(blocks: &[RawBlock]) -> Result<Self, Error> {
+        let (first, rest) = blocks
+            .split_first()
+            .ok_or_else(|| Error::from_string("no blocks to concat"))?;
+        for (i, block) in rest.iter().enumerate() {
+            let i = i + 1;
+            if block.ncols() != first.ncols() {
+                return Err(Error::from_string(format!(
+                    "cannot concat block {i}: it has {} columns but {} expected",
+                    block.ncols(),
+                    first.ncols()
+                )));
+            }
+            if block.precision() != first.precision() {
+                return Err(Error::from_string(format!(
+                    "cannot concat block {i}: its precision is {} but {} expected",
+                    block.precision(),
+                    first.precision()
+                )));
+            }
+            let columns = first
+                .field_names()
+                .iter()
+                .zip(first.column_views())
+                .zip(block.field_names().iter().zip(block.column_views()));
+            for (j, ((name, view), (rhs_name, rhs_view))) in columns.enumerate() {
+                if name != rhs_name || view.as_ty() != rhs_view.as_ty() {
+                    return Err(Error::from_string(format!(
+                        "cannot concat block {i}: column {j} is `{rhs_name}` {} but `{name}` {} expected",
+                        rhs_view.as_ty(),
+                        view.as_ty()
+                    )));
+                }
+            }
+        }
+
+        let views = (0..first.ncols())
+            .map(|j| {
+                rest.iter()
+                    .fold(first.column_views()[j].clone(), |view, block| {
+                        view.concat_strictly(&block.column_views()[j])
+                    })
+            })
+            .collect_vec();
+        let mut block = Self::from_views(&views, first.precision());
+        block.with_field_names(first.field_names());
+        if let Some(table_name) = first.table_name() {
+            if rest
+                .iter()
+                .all(|block| block.table_name() == Some(table_name))
+            {
+                block.with_table_name(table_name);
+            }
+        }
+        if let Some(database) = first.tmq_db_name() {
+            block.with_database_name(database);
+        }
+        Ok(block)
+    }
+
     /// Cast the block into a new block with new precision.
     pub fn cast_precision(&self, precision: Precision) -> Self {
         let views = self
@@ -1427,6 +1515,30 @@ fn test_v2_null() {
     dbg!(raw);
     assert!(null.is_null());
 }
+#[test]
+fn test_block_sizes() {
+    let raw = RawBlock::parse_from_raw_block_v2(
+        &[1, 2, 3, 1, 0, 2, 0, 3, 0][..],
+        &[
+            Field::new("a", Ty::TinyInt, 1),
+            Field::new("b", Ty::SmallInt, 2),
+        ],
+        &[1, 2],
+        3,
+        Precision::Millisecond,
+    );
+    assert_eq!((raw.nrows(), raw.ncols()), (3, 2));
+
+    let block = RawBlock::parse_from_raw_block(
+        Bytes::copy_from_slice(raw.as_raw_bytes()),
+        Precision::Millisecond,
+    );
+    assert_eq!((block.nrows(), block.ncols()), (3, 2));
+    let len = block.as_raw_bytes().len();
+    assert!(block.estimated_bytes() >= len);
+    assert!(block.estimated_bytes() < len + 1024);
+}
+
 #[test]
 fn test_from_v2() {
     let raw = RawBlock::parse_from_raw_block_v2(
@@ -1490,3 +1602,52 @@ fn test_from_v2() {
 
     println!("{}", raw.pretty_format());
 }
+
+#[test]
+fn test_concat_blocks() {
+    let block = |ts: Vec<i64>, names: Vec<Option<&str>>| {
+        let mut block = RawBlock::from_views(
+            &[
+                ColumnView::from_millis_timestamp(ts),
+                ColumnView::from_varchar::<&str, _, _, _>(names),
+            ],
+            Precision::Millisecond,
+        );
+        block
+            .with_field_names(["ts", "name"])
+            .with_table_name("tb1");
+        block
+    };
+    let blocks = [
+        block(vec![1, 2], vec![Some("a"), None]),
+        block(vec![3], vec![Some("bcd")]),
+        block(vec![4, 5, 6], vec![None, Some(""), Some("ef")]),
+    ];
+    let concat = RawBlock::concat_blocks(&blocks).unwrap();
+    assert_eq!((concat.nrows(), concat.ncols()), (6, 2));
+    assert_eq!(concat.field_names(), ["ts", "name"]);
+    assert_eq!(concat.table_name(), Some("tb1"));
+    let values = concat.to_values();
+    assert_eq!(
+        values[2],
+        [
+            Value::Timestamp(crate::common::Timestamp::Milliseconds(3)),
+            Value::VarChar("bcd".to_string())
+        ]
+    );
+    assert_eq!(values[3][1], Value::Null(Ty::VarChar));
+    assert_eq!(values[4][1], Value::VarChar(String::new()));
+    assert_eq!(values[5][1], Value::VarChar("ef".to_string()));
+
+    let mut other = RawBlock::from_views(
+        &[
+            ColumnView::from_millis_timestamp(vec![7]),
+            ColumnView::from_ints(vec![7]),
+        ],
+        Precision::Millisecond,
+    );
+    other.with_field_names(["ts", "name"]);
+    let err = RawBlock::concat_blocks(&[block(vec![1], vec![Some("a")]), other]).unwrap_err();
+    assert!(err.to_string().contains("column 1"), "{err}");
+    assert!(RawBlock::concat_blocks(&[]).is_err());
+}
@@ -0,0 +1,93 @@
+use serde_json::Value;
+
+use super::RawBlock;
+use crate::{common::BorrowedValue, RawError, RawResult};
+
+fn json_value(value: BorrowedValue) -> Value {
+    match value {
+        BorrowedValue::Null(_) => Value::Null,
+        BorrowedValue::Bool(v) => v.into(),
+        BorrowedValue::TinyInt(v) => v.into(),
+        BorrowedValue::SmallInt(v) => v.into(),
+        BorrowedValue::Int(v) => v.into(),
+        BorrowedValue::BigInt(v) => v.into(),
+        BorrowedValue::UTinyInt(v) => v.into(),
+        BorrowedValue::USmallInt(v) => v.into(),
+        BorrowedValue::UInt(v) => v.into(),
+        BorrowedValue::UBigInt(v) => v.into(),
+        // NaN and infinity are not numbers in JSON, they become null.
+        BorrowedValue::Float(v) => v.into(),
+        BorrowedValue::Double(v) => v.into(),
+        BorrowedValue::VarChar(v) => v.into(),
+        BorrowedValue::NChar(v) => v.into_owned().into(),
+        BorrowedValue::Timestamp(ts) => ts.to_rfc3339_utc().into(),
+        BorrowedValue::Json(v) => serde_json::from_slice(&v)
+            .unwrap_or_else(|_| String::from_utf8_lossy(&v).into_owned().into()),
+        BorrowedValue::VarBinary(v) | BorrowedValue::Blob(v) | BorrowedValue::MediumBlob(v) => {
+            v.into()
+        }
+        BorrowedValue::Decimal(v) => v.to_string().into(),
+    }
+}
+
+/// Render `blocks` as JSON lines, one object per row keyed by the field names in column order.
+///
+/// Nulls are `null`, timestamps are RFC3339 strings in UTC with the precision of the block, json
+/// values are embedded as is and binary values are arrays of bytes.
+pub(crate) fn blocks_to_ndjson(blocks: &[RawBlock]) -> RawResult<String> {
+    let mut out = String::new();
+    for block in blocks {
+        let names = block
+            .field_names()
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(RawError::from_any)?;
+        for row in 0..block.nrows() {
+            out.push('{');
+            for (col, name) in names.iter().enumerate() {
+                if col > 0 {
+                    out.push(',');
+                }
+                out.push_str(name);
+                out.push(':');
+                let value = json_value(unsafe { block.get_ref_unchecked(row, col) });
+                out.push_str(&serde_json::to_string(&value).map_err(RawError::from_any)?);
+            }
+            out.push_str("}\n");
+        }
+    }
+    Ok(out)
+}
+
+#[test]
+fn test_blocks_to_ndjson() {
+    use crate::common::{ColumnView, Precision};
+
+    let mut block = RawBlock::from_views(
+        &[
+            ColumnView::from_micros_timestamp(vec![0, 1_500_001]),
+            ColumnView::from_ints(vec![Some(1), None]),
+            ColumnView::from_varchar::<&str, _, _, _>(vec![None, Some("a\"b")]),
+            ColumnView::from_doubles(vec![Some(f64::NAN), Some(0.5)]),
+        ],
+        Precision::Microsecond,
+    );
+    block.with_field_names(["ts", "v", "s", "d"]);
+
+    let ndjson = blocks_to_ndjson(&[block]).unwrap();
+    assert_eq!(
+        ndjson,
+        "{\"ts\":\"1970-01-01T00:00:00.000000Z\",\"v\":1,\"s\":null,\"d\":null}\n\
+         {\"ts\":\"1970-01-01T00:00:01.500001Z\",\"v\":null,\"s\":\"a\\\"b\",\"d\":0.5}\n"
+    );
+    for line in ndjson.lines() {
+        let row: serde_json::Map<String, Value> = serde_json::from_str(line).unwrap();
+        assert_eq!(row.len(), 4);
+    }
+    assert_eq!(blocks_to_ndjson(&[]).unwrap(), "");
+    assert_eq!(
+        json_value(BorrowedValue::Json(b"{\"k\":1}".as_slice().into())),
+        serde_json::json!({"k": 1})
+    );
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use arrow::{
+    array::{
+        ArrayRef, BooleanArray, Float32Array, Float64Array, Int16Array, Int32Array, Int64Array,
+        Int8Array, StringArray, TimestampMicrosecondArray, TimestampMillisecondArray,
+        TimestampNanosecondArray, UInt16Array, UInt32Array, UInt64Array, UInt8Array,
+    },
+    datatypes::{Field, Schema},
+    record_batch::RecordBatch,
+};
+
+use super::{views::TimestampView, ColumnView, RawBlock};
+use crate::{common::Precision, RawError};
+
+fn timestamp_array(view: &TimestampView) -> ArrayRef {
+    let values: Vec<Option<i64>> = view.iter().map(|ts| ts.map(|ts| ts.as_raw_i64())).collect();
+    match view.precision() {
+        Precision::Millisecond => Arc::new(TimestampMillisecondArray::from(values)),
+        Precision::Microsecond => Arc::new(TimestampMicrosecondArray::from(values)),
+        Precision::Nanosecond => Arc::new(TimestampNanosecondArray::from(values)),
+    }
+}
+
+fn column_array(column: &ColumnView) -> ArrayRef {
+    match column {
+        ColumnView::Bool(view) => Arc::new(BooleanArray::from(view.to_vec())),
+        ColumnView::TinyInt(view) => Arc::new(Int8Array::from(view.to_vec())),
+        ColumnView::SmallInt(view) => Arc::new(Int16Array::from(view.to_vec())),
+        ColumnView::Int(view) => Arc::new(Int32Array::from(view.to_vec())),
+        ColumnView::BigInt(view) => Arc::new(Int64Array::from(view.to_vec())),
+        ColumnView::UTinyInt(view) => Arc::new(UInt8Array::from(view.to_vec())),
+        ColumnView::USmallInt(view) => Arc::new(UInt16Array::from(view.to_vec())),
+        ColumnView::UInt(view) => Arc::new(UInt32Array::from(view.to_vec())),
+        ColumnView::UBigInt(view) => Arc::new(UInt64Array::from(view.to_vec())),
+        ColumnView::Float(view) => Arc::new(Float32Array::from(view.to_vec())),
+        ColumnView::Double(view) => Arc::new(Float64Array::from(view.to_vec())),
+        ColumnView::Timestamp(view) => timestamp_array(view),
+        ColumnView::VarChar(view) => Arc::new(StringArray::from_iter(
+            view.iter().map(|s| s.map(|s| s.as_str())),
+        )),
+        ColumnView::NChar(view) => Arc::new(StringArray::from_iter(view.iter())),
+        ColumnView::Json(view) => Arc::new(StringArray::from_iter(
+            view.iter().map(|s| s.map(|s| s.as_str())),
+        )),
+    }
+}
+
+impl RawBlock {
+    /// Convert the block into an Arrow [RecordBatch], enabled by the `arrow` feature.
+    ///
+    /// Columns are named by the field names and all nullable. Types are mapped as:
+    ///
+    /// | TDengine | Arrow |
+    /// | -------- | ----- |
+    /// | BOOL | `Boolean` |
+    /// | TINYINT ~ BIGINT | `Int8` ~ `Int64` |
+    /// | TINYINT UNSIGNED ~ BIGINT UNSIGNED | `UInt8` ~ `UInt64` |
+    /// | FLOAT, DOUBLE | `Float32`, `Float64` |
+    /// | TIMESTAMP | `Timestamp` of the block precision and no time zone |
+    /// | VARCHAR, NCHAR, JSON | `Utf8` |
+    ///
+    /// Timestamps keep their raw epoch values in UTC: a millisecond database gives
+    /// `Timestamp(Millisecond, None)`, microsecond and nanosecond databases give `Microsecond`
+    /// and `Nanosecond` timestamps.
+    pub fn to_record_batch(&self) -> Result<RecordBatch, RawError> {
+        let columns: Vec<ArrayRef> = self.columns().map(column_array).collect();
+        let fields: Vec<Field> = self
+            .field_names()
+            .iter()
+            .zip(&columns)
+            .map(|(name, column)| Field::new(name, column.data_type().clone(), true))
+            .collect();
+        RecordBatch::try_new(Arc::new(Schema::new(fields)), columns).map_err(RawError::from_any)
+    }
+}
+
+#[test]
+fn test_to_record_batch() {
+    use arrow::{
+        array::{Array, AsArray},
+        datatypes::{DataType, Int32Type, TimeUnit, TimestampMillisecondType},
+    };
+
+    let mut block = RawBlock::from_views(
+        &[
+            ColumnView::from_millis_timestamp(vec![1000, 2000]),
+            ColumnView::from_ints(vec![Some(1), None]),
+            ColumnView::from_varchar::<&str, _, _, _>(vec![Some("abc"), None]),
+            ColumnView::from_bools(vec![None, Some(true)]),
+        ],
+        Precision::Millisecond,
+    );
+    block.with_field_names(["ts", "v", "s", "b"]);
+
+    let batch = block.to_record_batch().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    let schema = batch.schema();
+    let names: Vec<_> = schema.fields().iter().map(|f| f.name().as_str()).collect();
+    assert_eq!(names, ["ts", "v", "s", "b"]);
+    assert_eq!(
+        schema.field(0).data_type(),
+        &DataType::Timestamp(TimeUnit::Millisecond, None)
+    );
+    assert_eq!(schema.field(2).data_type(), &DataType::Utf8);
+
+    let ts = batch.column(0).as_primitive::<TimestampMillisecondType>();
+    assert_eq!(ts.values(), &[1000, 2000]);
+    let v = batch.column(1).as_primitive::<Int32Type>();
+    assert_eq!(v.value(0), 1);
+    assert!(v.is_null(1));
+    let s = batch.column(2).as_string::<i32>();
+    assert_eq!(s.value(0), "abc");
+    assert!(s.is_null(1));
+    let b = batch.column(3).as_boolean();
+    assert!(b.is_null(0));
+    assert!(b.value(1));
+}
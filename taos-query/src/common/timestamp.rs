@@ -73,6 +73,14 @@ impl Timestamp {
         Local.from_utc_datetime(&self.to_naive_datetime())
     }
 
+    /// RFC3339 string in UTC with the digits of the precision, like `1970-01-01T00:00:00.000Z`.
+    pub(crate) fn to_rfc3339_utc(self) -> String {
+        use chrono::TimeZone;
+        chrono::Utc
+            .from_utc_datetime(&self.to_naive_datetime())
+            .to_rfc3339_opts(self.precision().to_seconds_format(), true)
+    }
+
     pub fn cast_precision(&self, precision: Precision) -> Timestamp {
         let raw = self.as_raw_i64();
         match (self.precision(), precision) {
@@ -0,0 +1,128 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+/// Sql of `(topic_name, consumer_group)` records, one for each subscribed vgroup.
+pub(crate) const SUBSCRIPTIONS_SQL: &str =
+    "SELECT topic_name, consumer_group FROM information_schema.ins_subscriptions";
+
+/// Sql of `(consumer_id, consumer_group, client_id, status)` records of live consumers.
+pub(crate) const CONSUMERS_SQL: &str =
+    "SELECT consumer_id, consumer_group, client_id, status FROM information_schema.ins_consumers";
+
+/// A consumer in a [ConsumerGroupInfo].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupConsumer {
+    consumer_id: i64,
+    client_id: String,
+    status: String,
+}
+
+impl GroupConsumer {
+    /// Consumer id assigned by the server.
+    pub fn consumer_id(&self) -> i64 {
+        self.consumer_id
+    }
+
+    /// Client id set by `client.id`, empty if not set.
+    pub fn client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    /// Consumer status, like `ready`, `rebalancing` or `lost`.
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+}
+
+/// Information of a consumer group, by `information_schema.ins_subscriptions` and
+/// `information_schema.ins_consumers` records.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConsumerGroupInfo {
+    group_id: String,
+    topics: Vec<String>,
+    consumers: Vec<GroupConsumer>,
+}
+
+impl ConsumerGroupInfo {
+    /// Group id set by `group.id`.
+    pub fn group_id(&self) -> &str {
+        &self.group_id
+    }
+
+    /// Topics the group subscribed, groups without consumers keep their subscriptions until
+    /// dropped.
+    pub fn topics(&self) -> &[String] {
+        &self.topics
+    }
+
+    /// Live consumers of the group.
+    pub fn consumers(&self) -> &[GroupConsumer] {
+        &self.consumers
+    }
+
+    /// Check if the group has any live consumer, the subscriptions of an inactive group could be
+    /// dropped.
+    pub fn is_active(&self) -> bool {
+        !self.consumers.is_empty()
+    }
+
+    /// Merge records of [SUBSCRIPTIONS_SQL] and [CONSUMERS_SQL] into groups ordered by id.
+    pub(crate) fn collect(
+        subscriptions: impl IntoIterator<Item = (String, String)>,
+        consumers: impl IntoIterator<Item = (i64, String, String, String)>,
+    ) -> Vec<Self> {
+        let mut groups: BTreeMap<String, (BTreeSet<String>, Vec<GroupConsumer>)> = BTreeMap::new();
+        for (topic, group_id) in subscriptions {
+            groups.entry(group_id).or_default().0.insert(topic);
+        }
+        for (consumer_id, group_id, client_id, status) in consumers {
+            groups.entry(group_id).or_default().1.push(GroupConsumer {
+                consumer_id,
+                client_id,
+                status,
+            });
+        }
+        groups
+            .into_iter()
+            .map(|(group_id, (topics, consumers))| Self {
+                group_id,
+                topics: topics.into_iter().collect(),
+                consumers,
+            })
+            .collect()
+    }
+}
+
+/// Sql to drop the subscription of `group` on `topic`.
+pub(crate) fn drop_consumer_group_sql(group: &str, topic: &str) -> String {
+    format!("DROP CONSUMER GROUP IF EXISTS `{group}` ON `{topic}`")
+}
+
+#[test]
+fn test_consumer_groups_collect() {
+    let subscriptions = [
+        ("t1", "g2"),
+        ("t1", "g1"),
+        ("t1", "g1"),
+        ("t2", "g1"),
+        ("t0", "g1"),
+    ]
+    .map(|(topic, group)| (topic.to_string(), group.to_string()));
+    let consumers = [(1, "g1".to_string(), "c1".to_string(), "ready".to_string())];
+    let groups = ConsumerGroupInfo::collect(subscriptions, consumers);
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].group_id(), "g1");
+    assert_eq!(groups[0].topics(), ["t0", "t1", "t2"]);
+    assert!(groups[0].is_active());
+    assert_eq!(groups[0].consumers()[0].client_id(), "c1");
+    assert_eq!(groups[0].consumers()[0].status(), "ready");
+    assert_eq!(groups[1].group_id(), "g2");
+    assert!(!groups[1].is_active());
+
+    assert_eq!(
+        drop_consumer_group_sql("g2", "t1"),
+        "DROP CONSUMER GROUP IF EXISTS `g2` ON `t1`"
+    );
+}
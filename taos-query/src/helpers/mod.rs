@@ -1,7 +1,9 @@
+pub(crate) mod consumer;
 mod database;
 mod describe;
 mod topic;
 
+pub use consumer::{ConsumerGroupInfo, GroupConsumer};
 pub use database::*;
 pub use describe::*;
 pub use topic::*;
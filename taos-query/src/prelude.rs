@@ -1,7 +1,7 @@
 mod _priv {
     pub use crate::common::{
         AlterType, BorrowedValue, ColumnView, Field, JsonMeta, MetaAlter, MetaCreate, MetaDrop,
-        Precision, RawBlock, RawMeta, TagWithValue, Ty, Value,
+        Precision, RawBlock, RawData, RawMeta, TagWithValue, Ty, Value,
     };
     pub use crate::util::{Inlinable, InlinableRead, InlinableWrite};
 
@@ -9,7 +9,10 @@ mod _priv {
     pub use mdsn::{Dsn, DsnError, IntoDsn};
     pub use taos_error::{Code, Error as RawError};
 
-    pub use crate::tmq::{IsOffset, MessageSet, Timeout};
+    pub use crate::tmq::{
+        Backoff, ErrorPolicy, IsOffset, MessageSet, OffsetReset, OnEmpty, RebalanceEvent,
+        RetryPolicy, Timeout, TmqConfig,
+    };
 }
 
 pub use crate::tmq::{AsAsyncConsumer, IsAsyncData, IsAsyncMeta};
@@ -191,6 +194,14 @@ pub mod sync {
 
         fn write_raw_meta(&self, _: &RawMeta) -> RawResult<()>;
 
+        /// Write the raw data of a tmq message, a meta or data message is applied as is.
+        ///
+        /// Raw data carries its type, so it is written through
+        /// [write_raw_meta](Self::write_raw_meta).
+        fn write_raw_data(&self, data: &RawData) -> RawResult<()> {
+            self.write_raw_meta(&RawMeta::from(data.clone()))
+        }
+
         fn write_raw_block(&self, _: &RawBlock) -> RawResult<()>;
 
         fn write_raw_block_with_req_id(&self, _: &RawBlock, _: u64) -> RawResult<()>;
@@ -262,6 +273,30 @@ pub mod sync {
                 .map_err(Into::into)
         }
 
+        /// Consumer groups with their subscribed topics and live consumers.
+        ///
+        /// ## Compatibility
+        ///
+        /// This is a 3.x-only API.
+        fn list_consumer_groups(&self) -> RawResult<Vec<ConsumerGroupInfo>> {
+            let subscriptions: Vec<(String, String)> = self
+                .query(consumer::SUBSCRIPTIONS_SQL)?
+                .deserialize()
+                .try_collect()?;
+            let consumers: Vec<(i64, String, String, String)> = self
+                .query(consumer::CONSUMERS_SQL)?
+                .deserialize()
+                .try_collect()?;
+            Ok(ConsumerGroupInfo::collect(subscriptions, consumers))
+        }
+
+        /// Short for `DROP CONSUMER GROUP IF EXISTS {group} ON {topic}`, the group must have no
+        /// live consumers.
+        fn drop_consumer_group(&self, group: &str, topic: &str) -> RawResult<()> {
+            self.exec(consumer::drop_consumer_group_sql(group, topic))?;
+            Ok(())
+        }
+
         fn describe(&self, table: &str) -> RawResult<Describe> {
             Ok(Describe(
                 self.query(format!("describe `{table}`"))?
@@ -501,6 +536,14 @@ mod r#async {
 
         async fn write_raw_meta(&self, meta: &RawMeta) -> RawResult<()>;
 
+        /// Write the raw data of a tmq message, a meta or data message is applied as is.
+        ///
+        /// Raw data carries its type, so it is written through
+        /// [write_raw_meta](Self::write_raw_meta).
+        async fn write_raw_data(&self, data: &RawData) -> RawResult<()> {
+            self.write_raw_meta(&RawMeta::from(data.clone())).await
+        }
+
         async fn write_raw_block(&self, block: &RawBlock) -> RawResult<()>;
 
         async fn write_raw_block_with_req_id(&self, block: &RawBlock, req_id: u64)
@@ -623,6 +666,35 @@ mod r#async {
             Ok(self.query(sql).await?.deserialize().try_collect().await?)
         }
 
+        /// Consumer groups with their subscribed topics and live consumers.
+        ///
+        /// ## Compatibility
+        ///
+        /// This is a 3.x-only API.
+        async fn list_consumer_groups(&self) -> RawResult<Vec<ConsumerGroupInfo>> {
+            let subscriptions: Vec<(String, String)> = self
+                .query(consumer::SUBSCRIPTIONS_SQL)
+                .await?
+                .deserialize()
+                .try_collect()
+                .await?;
+            let consumers: Vec<(i64, String, String, String)> = self
+                .query(consumer::CONSUMERS_SQL)
+                .await?
+                .deserialize()
+                .try_collect()
+                .await?;
+            Ok(ConsumerGroupInfo::collect(subscriptions, consumers))
+        }
+
+        /// Short for `DROP CONSUMER GROUP IF EXISTS {group} ON {topic}`, the group must have no
+        /// live consumers.
+        async fn drop_consumer_group(&self, group: &str, topic: &str) -> RawResult<()> {
+            self.exec(consumer::drop_consumer_group_sql(group, topic))
+                .await?;
+            Ok(())
+        }
+
         /// Get table meta information.
         async fn describe(&self, table: &str) -> RawResult<Describe> {
             Ok(Describe(
@@ -0,0 +1,839 @@
+//! A scripted consumer to unit test pipelines built on [AsAsyncConsumer] without a server.
+//!
+//! It is enabled by the `test-utils` feature. [MockConsumer] delivers the messages of its script
+//! in order, interleaved with the injected errors, and records commits and seeks so that tests
+//! can assert them. Data messages are [MockData] of in-memory blocks, e.g. built with
+//! [RawBlock::from_views]. The sync [AsConsumer](super::AsConsumer) is implemented on the async
+//! one by [SyncOnAsync].
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    sync::Mutex,
+};
+
+use tokio::sync::Notify;
+
+use super::{
+    AsAsyncConsumer, Assignment, IsAsyncData, IsAsyncMeta, IsData, IsOffset, MessageSet,
+    PendingError, SyncOnAsync, Timeout, TopicPattern, VGroupId,
+};
+use crate::{
+    common::{Field, JsonMeta, RawData, RawMeta},
+    RawBlock, RawError, RawResult,
+};
+
+/// Offset of a [MockConsumer] message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MockOffset {
+    database: String,
+    topic: String,
+    vgroup_id: VGroupId,
+    offset: i64,
+}
+
+impl MockOffset {
+    /// Offset of a message of the topic vgroup, the database is `db`.
+    pub fn new(topic: impl Into<String>, vgroup_id: VGroupId, offset: i64) -> Self {
+        Self {
+            database: "db".to_string(),
+            topic: topic.into(),
+            vgroup_id,
+            offset,
+        }
+    }
+
+    pub fn with_database(mut self, database: impl Into<String>) -> Self {
+        self.database = database.into();
+        self
+    }
+}
+
+impl IsOffset for MockOffset {
+    fn database(&self) -> &str {
+        &self.database
+    }
+
+    fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    fn vgroup_id(&self) -> VGroupId {
+        self.vgroup_id
+    }
+
+    fn offset(&self) -> i64 {
+        self.offset
+    }
+}
+
+/// Meta message of a [MockConsumer], it has no raw meta.
+#[derive(Debug, Clone)]
+pub struct MockMeta(JsonMeta);
+
+impl MockMeta {
+    pub fn new(meta: JsonMeta) -> Self {
+        Self(meta)
+    }
+}
+
+#[async_trait::async_trait]
+impl IsAsyncMeta for MockMeta {
+    async fn as_raw_meta(&self) -> RawResult<RawMeta> {
+        Err(RawError::from_string("no raw meta in mock consumer"))
+    }
+
+    async fn as_json_meta(&self) -> RawResult<JsonMeta> {
+        Ok(self.0.clone())
+    }
+}
+
+impl SyncOnAsync for MockMeta {}
+
+/// Data message of a [MockConsumer], the blocks are fetched in order. It has no raw data.
+#[derive(Default)]
+pub struct MockData {
+    blocks: Mutex<VecDeque<RawBlock>>,
+    error: Mutex<Option<RawError>>,
+    block_count: usize,
+    row_count: usize,
+}
+
+impl MockData {
+    /// Data of `blocks`, the block and row counts are given as hints.
+    pub fn new(blocks: impl IntoIterator<Item = RawBlock>) -> Self {
+        let blocks: VecDeque<_> = blocks.into_iter().collect();
+        Self {
+            block_count: blocks.len(),
+            row_count: blocks.iter().map(RawBlock::nrows).sum(),
+            blocks: Mutex::new(blocks),
+            error: Mutex::new(None),
+        }
+    }
+
+    /// Fail the fetch after the blocks with `err`, e.g. a malformed block. Later fetches
+    /// return no block.
+    pub fn with_fetch_error(self, err: RawError) -> Self {
+        *self.error.lock().unwrap() = Some(err);
+        self
+    }
+
+    fn fetch(&self) -> RawResult<Option<RawBlock>> {
+        match self.blocks.lock().unwrap().pop_front() {
+            Some(block) => Ok(Some(block)),
+            None => self.error.lock().unwrap().take().map_or(Ok(None), Err),
+        }
+    }
+
+    fn next_fields(&self) -> Vec<Field> {
+        let blocks = self.blocks.lock().unwrap();
+        blocks.front().map(RawBlock::fields).unwrap_or_default()
+    }
+}
+
+#[async_trait::async_trait]
+impl IsAsyncData for MockData {
+    async fn as_raw_data(&self) -> RawResult<RawData> {
+        Err(RawError::from_string("no raw data in mock consumer"))
+    }
+
+    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
+        self.fetch()
+    }
+
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        Ok(self.next_fields())
+    }
+
+    fn block_count_hint(&self) -> Option<usize> {
+        Some(self.block_count)
+    }
+
+    fn row_count_hint(&self) -> Option<usize> {
+        Some(self.row_count)
+    }
+}
+
+impl IsData for MockData {
+    fn as_raw_data(&self) -> RawResult<RawData> {
+        Err(RawError::from_string("no raw data in mock consumer"))
+    }
+
+    fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
+        self.fetch()
+    }
+
+    fn fields(&self) -> RawResult<Vec<Field>> {
+        Ok(self.next_fields())
+    }
+
+    fn block_count_hint(&self) -> Option<usize> {
+        Some(self.block_count)
+    }
+
+    fn row_count_hint(&self) -> Option<usize> {
+        Some(self.row_count)
+    }
+}
+
+impl Iterator for MockData {
+    type Item = RawResult<RawBlock>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fetch().transpose()
+    }
+}
+
+/// A message in the script of a [MockConsumer].
+pub type MockMessage = (MockOffset, MessageSet<MockMeta, MockData>);
+
+enum Step {
+    Message(Box<MockMessage>),
+    Error(RawError),
+    Empty,
+}
+
+/// A consumer replaying a script of messages, see the [module](self) docs.
+///
+/// When the script is drained, polls wait as their [Timeout] tells: `None` returns at once,
+/// a duration returns `None` after it, and `Never` waits until a message is
+/// [pushed](Self::push). Messages of [paused](AsAsyncConsumer::pause) vgroups stay in the
+/// script until resumed, the other messages and errors are delivered meanwhile.
+///
+/// Assignments are derived from the scripted offsets: each topic vgroup ranges from its lowest
+/// offset to the one after its highest, unless [assigned](Self::assign). Seeks move the
+/// [position](AsAsyncConsumer::position) without reordering the script.
+pub struct MockConsumer {
+    script: Mutex<VecDeque<Step>>,
+    pushed: Notify,
+    timeout: Timeout,
+    topic_timeouts: BTreeMap<String, Timeout>,
+    poll_timeouts: Mutex<Vec<Timeout>>,
+    subscription: Mutex<Vec<String>>,
+    topics: Mutex<Vec<String>>,
+    pattern: TopicPattern,
+    pending: PendingError,
+    ranges: Mutex<BTreeMap<(String, VGroupId), (i64, i64)>>,
+    positions: Mutex<BTreeMap<(String, VGroupId), i64>>,
+    paused: Mutex<BTreeSet<(String, VGroupId)>>,
+    rejected: BTreeSet<(String, VGroupId)>,
+    commits: Mutex<Vec<(String, VGroupId, i64)>>,
+    seeks: Mutex<Vec<(String, VGroupId, i64)>>,
+    resubscribes: Mutex<usize>,
+}
+
+impl MockConsumer {
+    /// Consumer replaying `script`, its default timeout is 100ms.
+    pub fn new(script: impl IntoIterator<Item = MockMessage>) -> Self {
+        let consumer = Self {
+            script: Mutex::new(VecDeque::new()),
+            pushed: Notify::new(),
+            timeout: Timeout::from_millis(100),
+            topic_timeouts: BTreeMap::new(),
+            poll_timeouts: Mutex::new(Vec::new()),
+            subscription: Mutex::new(Vec::new()),
+            topics: Mutex::new(Vec::new()),
+            pattern: TopicPattern::default(),
+            pending: PendingError::default(),
+            ranges: Mutex::new(BTreeMap::new()),
+            positions: Mutex::new(BTreeMap::new()),
+            paused: Mutex::new(BTreeSet::new()),
+            rejected: BTreeSet::new(),
+            commits: Mutex::new(Vec::new()),
+            seeks: Mutex::new(Vec::new()),
+            resubscribes: Mutex::new(0),
+        };
+        for (offset, message) in script {
+            consumer.push(offset, message);
+        }
+        consumer
+    }
+
+    pub fn with_timeout(mut self, timeout: Timeout) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Override the timeout of the topic, see [AsAsyncConsumer::topic_timeout].
+    pub fn with_topic_timeout(mut self, topic: impl Into<String>, timeout: Timeout) -> Self {
+        self.topic_timeouts.insert(topic.into(), timeout);
+        self
+    }
+
+    /// Consumer subscribed to `topics` from the start.
+    pub fn with_subscription<T: Into<String>>(self, topics: impl IntoIterator<Item = T>) -> Self {
+        *self.subscription.lock().unwrap() = topics.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Reject the commits of the topic vgroup with an error, nothing is recorded for them.
+    pub fn with_rejected_commits(mut self, topic: impl Into<String>, vgroup_id: VGroupId) -> Self {
+        self.rejected.insert((topic.into(), vgroup_id));
+        self
+    }
+
+    /// Fail the poll at `position` of the script with `err`, the message there is delivered by
+    /// the next poll. Positions beyond the script fail after all messages are delivered.
+    pub fn with_error_at(self, position: usize, err: RawError) -> Self {
+        {
+            let mut script = self.script.lock().unwrap();
+            let position = position.min(script.len());
+            script.insert(position, Step::Error(err));
+        }
+        self
+    }
+
+    /// Append a message to the script, waking a poll waiting for it.
+    pub fn push(&self, offset: MockOffset, message: MessageSet<MockMeta, MockData>) {
+        let key = (offset.topic.clone(), offset.vgroup_id);
+        self.ranges
+            .lock()
+            .unwrap()
+            .entry(key)
+            .and_modify(|(begin, end)| {
+                *begin = (*begin).min(offset.offset);
+                *end = (*end).max(offset.offset + 1);
+            })
+            .or_insert((offset.offset, offset.offset + 1));
+        self.push_step(Step::Message(Box::new((offset, message))));
+    }
+
+    /// Append an error to the script, waking a poll waiting for it.
+    pub fn push_error(&self, err: RawError) {
+        self.push_step(Step::Error(err));
+    }
+
+    /// Append an empty poll to the script, the poll returns `None` at once whatever its
+    /// timeout, as if no message came in time.
+    pub fn push_empty(&self) {
+        self.push_step(Step::Empty);
+    }
+
+    fn push_step(&self, step: Step) {
+        self.script.lock().unwrap().push_back(step);
+        self.pushed.notify_one();
+    }
+
+    /// Assign the topic vgroup as `assignment`: its range replaces the one derived from the
+    /// script, and its current offset is the position until a message or seek moves it.
+    pub fn assign(&self, topic: impl Into<String>, assignment: Assignment) {
+        let key = (topic.into(), assignment.vgroup_id());
+        self.ranges
+            .lock()
+            .unwrap()
+            .insert(key.clone(), (assignment.begin(), assignment.end()));
+        self.positions
+            .lock()
+            .unwrap()
+            .insert(key, assignment.current_offset());
+    }
+
+    /// Create a topic on the mock server, listed by [topic_names](AsAsyncConsumer::topic_names)
+    /// to be matched by [subscribe_pattern](AsAsyncConsumer::subscribe_pattern).
+    pub fn create_topic(&self, topic: impl Into<String>) {
+        self.topics.lock().unwrap().push(topic.into());
+    }
+
+    /// Messages and errors not delivered yet.
+    pub fn remaining(&self) -> usize {
+        self.script.lock().unwrap().len()
+    }
+
+    /// Committed `(topic, vgroup_id, offset)` in order, both by
+    /// [commit](AsAsyncConsumer::commit) and [commit_offset](AsAsyncConsumer::commit_offset).
+    pub fn commits(&self) -> Vec<(String, VGroupId, i64)> {
+        self.commits.lock().unwrap().clone()
+    }
+
+    /// Sought `(topic, vgroup_id, offset)` in order.
+    pub fn seeks(&self) -> Vec<(String, VGroupId, i64)> {
+        self.seeks.lock().unwrap().clone()
+    }
+
+    /// Timeouts of the polls in order.
+    pub fn poll_timeouts(&self) -> Vec<Timeout> {
+        self.poll_timeouts.lock().unwrap().clone()
+    }
+
+    /// Times of [resubscribe](AsAsyncConsumer::resubscribe).
+    pub fn resubscribes(&self) -> usize {
+        *self.resubscribes.lock().unwrap()
+    }
+
+    fn pop_step(&self) -> Option<Step> {
+        let paused = self.paused.lock().unwrap();
+        let mut script = self.script.lock().unwrap();
+        let next = script.iter().position(|step| match step {
+            Step::Message(message) => !paused.iter().any(|(topic, vgroup_id)| {
+                *topic == message.0.topic && *vgroup_id == message.0.vgroup_id
+            }),
+            _ => true,
+        })?;
+        script.remove(next)
+    }
+
+    async fn next_step(&self) -> Step {
+        loop {
+            let pushed = self.pushed.notified();
+            if let Some(step) = self.pop_step() {
+                return step;
+            }
+            pushed.await;
+        }
+    }
+
+    fn check_topic(&self, topic: &str) -> RawResult<()> {
+        if self.subscription.lock().unwrap().iter().any(|t| t == topic) {
+            Ok(())
+        } else {
+            Err(RawError::from_string(format!(
+                "topic {topic} is not subscribed"
+            )))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl AsAsyncConsumer for MockConsumer {
+    type Offset = MockOffset;
+    type Meta = MockMeta;
+    type Data = MockData;
+
+    fn default_timeout(&self) -> Timeout {
+        self.timeout
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
+    }
+
+    async fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
+        &mut self,
+        topics: I,
+    ) -> RawResult<()> {
+        *self.subscription.lock().unwrap() = topics.into_iter().map(Into::into).collect();
+        self.pattern.clear();
+        Ok(())
+    }
+
+    fn subscription(&self) -> Vec<String> {
+        self.subscription.lock().unwrap().clone()
+    }
+
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        Ok(self.topics.lock().unwrap().clone())
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    async fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        *self.subscription.lock().unwrap() = topics;
+        Ok(())
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending)
+    }
+
+    async fn recv_timeout(&self, timeout: Timeout) -> RawResult<Option<MockMessage>> {
+        self.refresh_subscription_if_due().await;
+        self.poll_timeouts.lock().unwrap().push(timeout);
+        let step = match timeout {
+            Timeout::Never => Some(self.next_step().await),
+            Timeout::None => self.pop_step(),
+            _ => tokio::time::timeout(timeout.as_duration(), self.next_step())
+                .await
+                .ok(),
+        };
+        match step {
+            None | Some(Step::Empty) => Ok(None),
+            Some(Step::Error(err)) => Err(err),
+            Some(Step::Message(message)) => {
+                let (offset, message) = *message;
+                self.positions
+                    .lock()
+                    .unwrap()
+                    .insert((offset.topic.clone(), offset.vgroup_id), offset.offset + 1);
+                Ok(Some((offset, message)))
+            }
+        }
+    }
+
+    /// Commit the offset after the message, as the server does, so that consuming resumes from
+    /// the next message.
+    async fn commit(&self, offset: Self::Offset) -> RawResult<()> {
+        self.commit_offset(&offset.topic, offset.vgroup_id, offset.offset + 1)
+            .await
+    }
+
+    async fn commit_offset(
+        &self,
+        topic_name: &str,
+        vgroup_id: VGroupId,
+        offset: i64,
+    ) -> RawResult<()> {
+        self.check_topic(topic_name)?;
+        if self.rejected.contains(&(topic_name.to_string(), vgroup_id)) {
+            return Err(RawError::from_string("commit rejected"));
+        }
+        self.commits
+            .lock()
+            .unwrap()
+            .push((topic_name.to_string(), vgroup_id, offset));
+        Ok(())
+    }
+
+    async fn list_topics(&self) -> RawResult<Vec<String>> {
+        Ok(self.subscription())
+    }
+
+    async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
+        let mut assignments = Vec::new();
+        for topic in self.subscription() {
+            let topic_assignments = self.topic_assignment(&topic).await.ok()?;
+            assignments.push((topic, topic_assignments));
+        }
+        Some(assignments)
+    }
+
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        self.check_topic(topic)?;
+        let positions = self.positions.lock().unwrap();
+        Ok(self
+            .ranges
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|((t, _), _)| t == topic)
+            .map(|(key, &(begin, end))| {
+                let current = positions.get(key).copied().unwrap_or(begin);
+                Assignment::new(key.1, current, begin, end)
+            })
+            .collect())
+    }
+
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+        self.check_topic(topic)?;
+        self.seeks
+            .lock()
+            .unwrap()
+            .push((topic.to_string(), vgroup_id, offset));
+        self.positions
+            .lock()
+            .unwrap()
+            .insert((topic.to_string(), vgroup_id), offset);
+        Ok(())
+    }
+
+    /// Count the resubscribe, positions are kept.
+    async fn resubscribe(&self) -> RawResult<()> {
+        *self.resubscribes.lock().unwrap() += 1;
+        Ok(())
+    }
+
+    async fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        self.check_topic(topic)?;
+        self.paused
+            .lock()
+            .unwrap()
+            .insert((topic.to_string(), vgroup_id));
+        Ok(())
+    }
+
+    async fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        self.check_topic(topic)?;
+        self.paused
+            .lock()
+            .unwrap()
+            .remove(&(topic.to_string(), vgroup_id));
+        self.pushed.notify_one();
+        Ok(())
+    }
+
+    /// The last committed offset, or `-1` if nothing committed as the server reports.
+    async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        self.check_topic(topic)?;
+        let commits = self.commits.lock().unwrap();
+        let last = commits
+            .iter()
+            .rev()
+            .find(|(t, v, _)| t == topic && *v == vgroup_id);
+        Ok(last.map_or(-1, |c| c.2))
+    }
+
+    async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        let assignments = self.topic_assignment(topic).await?;
+        match assignments.iter().find(|a| a.vgroup_id() == vgroup_id) {
+            Some(a) => Ok(a.current_offset()),
+            None => Err(RawError::from_string(format!(
+                "vgroup {vgroup_id} of topic {topic} is not assigned to current consumer"
+            ))),
+        }
+    }
+}
+
+impl SyncOnAsync for MockConsumer {}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+    use crate::{
+        common::{ColumnView, Precision, Ty},
+        tmq::VGroupOrder,
+    };
+
+    fn data(values: Vec<i32>) -> MessageSet<MockMeta, MockData> {
+        let ts = (0..values.len() as i64).collect();
+        let mut block = RawBlock::from_views(
+            &[
+                ColumnView::from_millis_timestamp(ts),
+                ColumnView::from_ints(values.into_iter().map(Some).collect()),
+            ],
+            Precision::Millisecond,
+        );
+        block.with_field_names(["ts", "v"]);
+        MessageSet::Data(MockData::new([block]))
+    }
+
+    #[tokio::test]
+    async fn mock_consumer_script() -> RawResult<()> {
+        let mut consumer = MockConsumer::new([
+            (MockOffset::new("topic", 1, 0), data(vec![1, 2])),
+            (MockOffset::new("topic", 2, 5), data(vec![3])),
+        ])
+        .with_error_at(1, RawError::from_string("connection blip"));
+        consumer.subscribe(["topic"]).await?;
+
+        let (offset, message) = consumer.recv_timeout(Timeout::None).await?.unwrap();
+        assert_eq!(offset, MockOffset::new("topic", 1, 0));
+        let data = message.into_data().unwrap();
+        assert_eq!(IsAsyncData::block_count_hint(&data), Some(1));
+        assert_eq!(IsAsyncData::row_count_hint(&data), Some(2));
+        let fields = IsAsyncData::fields(&data).await?;
+        assert_eq!(
+            fields,
+            [
+                Field::new("ts", Ty::Timestamp, 8),
+                Field::new("v", Ty::Int, 4)
+            ]
+        );
+        let rows: Vec<(i64, i32)> = IsAsyncData::deserialize(&data).await?;
+        assert!(IsAsyncData::fields(&data).await?.is_empty());
+        assert_eq!(rows, [(0, 1), (1, 2)]);
+        consumer.commit(offset).await?;
+
+        let Err(err) = consumer.recv_timeout(Timeout::None).await else {
+            panic!("expect the injected error");
+        };
+        assert!(err.to_string().contains("blip"));
+
+        let (offset, message) = consumer.recv_timeout(Timeout::None).await?.unwrap();
+        assert_eq!(offset.vgroup_id(), 2);
+        let data = message.into_data().unwrap();
+        assert_eq!(IsAsyncData::fetch_all_blocks(&data).await?.len(), 1);
+        assert!(IsAsyncData::fetch_all_blocks(&data).await?.is_empty());
+        assert!(consumer.recv_timeout(Timeout::None).await?.is_none());
+        assert_eq!(consumer.remaining(), 0);
+
+        assert_eq!(consumer.position("topic", 2).await?, 6);
+        consumer.offset_seek("topic", 2, 5).await?;
+        assert_eq!(consumer.position("topic", 2).await?, 5);
+        assert_eq!(consumer.seeks(), [("topic".to_string(), 2, 5)]);
+
+        assert_eq!(consumer.commits(), [("topic".to_string(), 1, 1)]);
+        assert_eq!(consumer.committed("topic", 1).await?, 1);
+        assert_eq!(consumer.committed("topic", 2).await?, -1);
+
+        let assignments = consumer.topic_assignment("topic").await?;
+        assert_eq!(assignments[1].begin(), 5);
+        assert_eq!(assignments[1].end(), 6);
+        assert!(consumer.commit_offset("other", 1, 0).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mock_consumer_replay_range() -> RawResult<()> {
+        let mut consumer = MockConsumer::new([
+            (MockOffset::new("topic", 2, 0), data(vec![0])),
+            (MockOffset::new("topic", 1, 1), data(vec![1])),
+            (MockOffset::new("topic", 1, 2), data(vec![2, 3])),
+            (MockOffset::new("topic", 1, 3), data(vec![4])),
+        ])
+        .with_timeout(Timeout::from_millis(10));
+        consumer.subscribe(["topic"]).await?;
+
+        let blocks = consumer.replay_range("topic", 1, 0, 3).await?;
+        let rows: Vec<_> = blocks.iter().map(RawBlock::nrows).collect();
+        assert_eq!(rows, [1, 2]);
+        assert_eq!(
+            consumer.seeks(),
+            [
+                ("topic".to_string(), 1, 1),
+                ("topic".to_string(), 2, 0),
+                ("topic".to_string(), 1, 1),
+            ]
+        );
+
+        // Stops at the end of data.
+        consumer.push(MockOffset::new("topic", 1, 1), data(vec![1]));
+        let blocks = consumer.replay_range("topic", 1, 1, 100).await?;
+        assert_eq!(blocks.len(), 1);
+        assert!(consumer.replay_range("topic", 1, 2, 2).await?.is_empty());
+        assert!(consumer.replay_range("topic", 3, 0, 1).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mock_consumer_replay_range_to_end() -> RawResult<()> {
+        let mut consumer = MockConsumer::new([
+            (MockOffset::new("topic", 1, 1), data(vec![1])),
+            (MockOffset::new("topic", 1, 2), data(vec![2])),
+        ]);
+        consumer.subscribe(["topic"]).await?;
+        consumer.push_error(RawError::from_string("boom"));
+
+        // `to` is clamped to the end, no more poll after the last message.
+        let blocks = consumer.replay_range("topic", 1, 1, 100).await?;
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(consumer.remaining(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mock_consumer_replay_range_keeps_error() -> RawResult<()> {
+        let mut consumer = MockConsumer::new([(MockOffset::new("other", 1, 0), data(vec![0]))]);
+        consumer.push_error(RawError::from_string("boom"));
+        consumer.push(MockOffset::new("topic", 1, 1), data(vec![1]));
+        consumer.subscribe(["topic"]).await?;
+
+        // Seeking back to the unsubscribed topic fails, the error of the poll is kept.
+        let err = consumer.replay_range("topic", 1, 1, 2).await.unwrap_err();
+        assert!(err.to_string().contains("boom"), "{err}");
+        assert_eq!(
+            consumer.seeks(),
+            [("topic".to_string(), 1, 1), ("topic".to_string(), 1, 1)]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mock_consumer_seek_all() -> RawResult<()> {
+        let mut consumer = MockConsumer::new([
+            (MockOffset::new("topic", 1, 0), data(vec![0])),
+            (MockOffset::new("topic", 2, 0), data(vec![1])),
+            (MockOffset::new("topic", 3, 0), data(vec![2])),
+        ]);
+        consumer.subscribe(["topic"]).await?;
+
+        let offsets = [("topic", 1, 5), ("topic", 2, 0), ("topic", 3, 7)]
+            .map(|(topic, vgroup_id, offset)| (topic.to_string(), vgroup_id, offset));
+        let outcome = consumer.seek_all(&offsets).await;
+        assert!(outcome.is_ok());
+        assert_eq!(outcome.succeeded, offsets);
+        assert_eq!(consumer.seeks(), offsets);
+        for (topic, vgroup_id, offset) in &offsets {
+            assert_eq!(consumer.position(topic, *vgroup_id).await?, *offset);
+        }
+        consumer.seek_all(&[]).await.into_result()?;
+
+        // The seek of an unsubscribed topic fails, the others are applied.
+        let offsets = [("topic", 1, 1), ("other", 1, 2), ("topic", 2, 3)]
+            .map(|(topic, vgroup_id, offset)| (topic.to_string(), vgroup_id, offset));
+        let outcome = consumer.seek_all(&offsets).await;
+        assert!(!outcome.is_ok());
+        assert_eq!(outcome.succeeded, [offsets[0].clone(), offsets[2].clone()]);
+        assert_eq!(
+            outcome
+                .failed
+                .iter()
+                .map(|(seek, _)| seek)
+                .collect::<Vec<_>>(),
+            [&offsets[1]]
+        );
+        let Err(err) = outcome.into_result() else {
+            panic!("seek of unsubscribed topic succeeded");
+        };
+        let err = err.to_string();
+        assert!(
+            err.contains("seek failed for 1 vgroup(s): topic other vgroup 1 to 2"),
+            "{err}"
+        );
+        assert_eq!(consumer.position("topic", 1).await?, 1);
+        assert_eq!(consumer.position("topic", 2).await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn vgroup_order() -> RawResult<()> {
+        let consumer = MockConsumer::new(
+            [(1, 2), (2, 0), (1, 1), (1, 3), (2, 1)]
+                .map(|(vgroup, offset)| (MockOffset::new("topic", vgroup, offset), data(vec![1]))),
+        );
+        let order = VGroupOrder::new(4);
+        let mut received = Vec::new();
+        while let Some((offset, _)) = order
+            .recv(Timeout::None, |timeout| consumer.recv_timeout(timeout))
+            .await?
+        {
+            received.push((offset.vgroup_id(), offset.offset()));
+        }
+        // The last message comes in the second batch.
+        assert_eq!(received, [(1, 1), (2, 0), (1, 2), (1, 3), (2, 1)]);
+
+        consumer.push(MockOffset::new("topic", 1, 5), data(vec![1]));
+        consumer.push(MockOffset::new("topic", 1, 4), data(vec![1]));
+        consumer.push(MockOffset::new("topic", 2, 2), data(vec![1]));
+        let poll = |timeout| consumer.recv_timeout(timeout);
+        let (offset, _) = order.recv(Timeout::None, poll).await?.unwrap();
+        assert_eq!(offset.offset(), 4);
+        order.reset("topic", 1);
+        let (offset, _) = order.recv(Timeout::None, poll).await?.unwrap();
+        assert_eq!((offset.vgroup_id(), offset.offset()), (2, 2));
+        assert!(order.recv(Timeout::None, poll).await?.is_none());
+
+        // An error in the middle of a batch is returned after the messages before it.
+        let consumer = MockConsumer::new(
+            [1, 0].map(|offset| (MockOffset::new("topic", 1, offset), data(vec![1]))),
+        )
+        .with_error_at(2, RawError::from_string("connection blip"));
+        let order = VGroupOrder::new(4);
+        let poll = |timeout| consumer.recv_timeout(timeout);
+        for expected in [0, 1] {
+            let (offset, _) = order.recv(Timeout::None, poll).await?.unwrap();
+            assert_eq!(offset.offset(), expected);
+        }
+        let Err(err) = order.recv(Timeout::None, poll).await else {
+            panic!("expect the error after the batch");
+        };
+        assert!(err.to_string().contains("blip"));
+        assert!(order.recv(Timeout::None, poll).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mock_consumer_timeout() -> RawResult<()> {
+        let consumer = Arc::new(MockConsumer::new([]));
+
+        let polled = tokio::time::timeout(
+            Duration::from_millis(100),
+            consumer.recv_timeout(Timeout::from_millis(10)),
+        )
+        .await;
+        assert!(polled.unwrap()?.is_none());
+
+        let waiting = tokio::spawn({
+            let consumer = consumer.clone();
+            async move { consumer.recv_timeout(Timeout::Never).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        consumer.push(MockOffset::new("topic", 1, 0), data(vec![1]));
+        let (offset, _) = waiting.await.unwrap()?.unwrap();
+        assert_eq!(offset.offset(), 0);
+        Ok(())
+    }
+}
@@ -1,14 +1,28 @@
-use std::{fmt::Debug, pin::Pin, str::FromStr, time::Duration};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fmt::{Debug, Display},
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use itertools::Itertools;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
-    common::{JsonMeta, RawData, RawMeta},
-    RawBlock, RawResult,
+    common::{Field, JsonMeta, RawData, RawMeta},
+    RawBlock, RawError, RawResult,
 };
 
-#[derive(Debug, Clone, Copy)]
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Timeout {
     /// Wait forever.
     Never,
@@ -16,6 +30,8 @@ pub enum Timeout {
     None,
     /// Wait for a duration of time.
     Duration(Duration),
+    /// Wait until an absolute point of time.
+    Deadline(Instant),
 }
 
 impl Timeout {
@@ -34,20 +50,152 @@ impl Timeout {
     pub fn none() -> Self {
         Self::None
     }
+
+    pub fn deadline(instant: Instant) -> Self {
+        Self::Deadline(instant)
+    }
+
+    /// Timeout in milliseconds for native polls, `-1` to wait forever. Durations too long for
+    /// `i64` saturate to `i64::MAX` rather than becoming `-1`.
     pub fn as_raw_timeout(&self) -> i64 {
         match self {
             Timeout::Never => -1,
             Timeout::None => 0,
-            Timeout::Duration(t) => t.as_millis() as _,
+            Timeout::Duration(_) | Timeout::Deadline(_) => {
+                i64::try_from(self.as_duration().as_millis()).unwrap_or(i64::MAX)
+            }
         }
     }
 
+    /// Duration to wait, the remaining time for [Timeout::Deadline] which is zero once passed.
     pub fn as_duration(&self) -> Duration {
         match self {
             Timeout::Never => Duration::from_secs(i64::MAX as u64 / 1000),
             Timeout::None => Duration::from_secs(0),
             Timeout::Duration(t) => *t,
+            Timeout::Deadline(d) => d.saturating_duration_since(Instant::now()),
+        }
+    }
+
+    /// Fix a relative timeout to a deadline from now, to share it among several waits. Others
+    /// are kept as is, so is a duration too long for a deadline.
+    pub fn into_deadline(self) -> Timeout {
+        match self {
+            Timeout::Duration(t) => Instant::now()
+                .checked_add(t)
+                .map_or(self, Timeout::Deadline),
+            _ => self,
+        }
+    }
+
+    /// Check if the deadline has passed, always `false` for relative timeouts.
+    pub fn is_expired(&self) -> bool {
+        match self {
+            Timeout::Deadline(d) => *d <= Instant::now(),
+            _ => false,
+        }
+    }
+
+    /// Returns the tighter of two timeouts.
+    ///
+    /// [Timeout::None] is the smallest value and [Timeout::Never] is the largest. It is not a
+    /// `const fn` as deadlines are compared by the remaining time, which reads the clock.
+    pub fn min(self, other: Timeout) -> Timeout {
+        if self.order_key() <= other.order_key() {
+            self
+        } else {
+            other
+        }
+    }
+
+    /// Returns the looser of two timeouts.
+    ///
+    /// [Timeout::None] is the smallest value and [Timeout::Never] is the largest. It is not a
+    /// `const fn` as deadlines are compared by the remaining time, which reads the clock.
+    pub fn max(self, other: Timeout) -> Timeout {
+        if self.order_key() >= other.order_key() {
+            self
+        } else {
+            other
+        }
+    }
+
+    /// Extend the timeout by `extra`, saturating instead of overflow.
+    ///
+    /// [Timeout::Never] stays as is and [Timeout::None] becomes a duration of `extra`. A deadline
+    /// is postponed, or becomes [Timeout::Never] if the new instant is not representable.
+    pub fn saturating_add(self, extra: Duration) -> Timeout {
+        match self {
+            Timeout::Never => Timeout::Never,
+            Timeout::None => Timeout::Duration(extra),
+            Timeout::Duration(t) => Timeout::Duration(t.saturating_add(extra)),
+            Timeout::Deadline(d) => d
+                .checked_add(extra)
+                .map_or(Timeout::Never, Timeout::Deadline),
+        }
+    }
+
+    /// Key to compare timeouts, deadlines are compared by the remaining time.
+    fn order_key(&self) -> (u8, Duration) {
+        match self {
+            Timeout::None => (0, Duration::ZERO),
+            Timeout::Duration(_) | Timeout::Deadline(_) => (1, self.as_duration()),
+            Timeout::Never => (2, Duration::ZERO),
+        }
+    }
+
+    /// Check if polling with this timeout may block the caller.
+    ///
+    /// Returns `false` for [Timeout::None] and zero-length durations.
+    pub fn is_blocking(&self) -> bool {
+        match self {
+            Timeout::Never => true,
+            Timeout::None => false,
+            Timeout::Duration(t) => !t.is_zero(),
+            Timeout::Deadline(_) => !self.is_expired(),
+        }
+    }
+}
+
+impl Display for Timeout {
+    /// Format as `never`, `none`, or a humanized duration like `1500ms` and `2m30s`.
+    ///
+    /// Durations are rounded up to milliseconds, and a deadline is formatted as its remaining time.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let duration = match self {
+            Timeout::Never => return f.write_str("never"),
+            Timeout::None => return f.write_str("none"),
+            Timeout::Duration(_) | Timeout::Deadline(_) => self.as_duration(),
+        };
+        let millis = (duration.as_nanos() + 999_999) / 1_000_000;
+        if millis == 0 || millis % 1000 != 0 {
+            return write!(f, "{millis}ms");
         }
+        let secs = millis / 1000;
+        let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+        if hours > 0 {
+            write!(f, "{hours}h")?;
+        }
+        if minutes > 0 {
+            write!(f, "{minutes}m")?;
+        }
+        if secs > 0 {
+            write!(f, "{secs}s")?;
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for Timeout {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timeout {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
     }
 }
 
@@ -62,10 +210,27 @@ pub enum TimeoutError {
 impl FromStr for Timeout {
     type Err = TimeoutError;
 
+    /// Parse timeout from `never`, `none`, a plain integer in milliseconds, or a humanized duration.
+    ///
+    /// A bare number is treated as milliseconds, so `"0"` is a zero-length
+    /// [Timeout::Duration] (not [Timeout::None]), which polls without blocking as well.
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         if s.is_empty() {
             return Err(TimeoutError::Empty);
         }
+        let digits = s.strip_prefix('-').unwrap_or(s);
+        if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+            if digits.len() != s.len() {
+                return Err(TimeoutError::Invalid(
+                    s.to_string(),
+                    "negative timeout is not allowed".to_string(),
+                ));
+            }
+            return s
+                .parse::<u64>()
+                .map(Timeout::from_millis)
+                .map_err(|err| TimeoutError::Invalid(s.to_string(), err.to_string()));
+        }
         match s.to_lowercase().as_str() {
             "never" => Ok(Timeout::Never),
             "none" => Ok(Timeout::None),
@@ -76,436 +241,6028 @@ impl FromStr for Timeout {
     }
 }
 
-pub enum MessageSet<M, D> {
-    Meta(M),
-    Data(D),
-    MetaData(M, D),
+/// Where to start consuming when there is no committed offset, the `auto.offset.reset` param.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OffsetReset {
+    /// Consume from the earliest available message.
+    Earliest,
+    /// Consume new messages only.
+    #[default]
+    Latest,
+    /// Do not reset, the server reports an error when no committed offset exists.
+    None,
 }
 
-impl<M, D> Debug for MessageSet<M, D>
-where
-    M: Debug,
-    D: Debug,
-{
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl OffsetReset {
+    pub const fn as_str(&self) -> &'static str {
         match self {
-            Self::Meta(m) => f.debug_tuple("Meta").field(m).finish(),
-            Self::Data(d) => f.debug_tuple("Data").field(d).finish(),
-            Self::MetaData(m, d) => f.debug_tuple("MetaData").field(m).field(d).finish(),
+            OffsetReset::Earliest => "earliest",
+            OffsetReset::Latest => "latest",
+            OffsetReset::None => "none",
         }
     }
-}
 
-impl<M, D> MessageSet<M, D> {
-    pub fn into_meta(self) -> Option<M> {
-        match self {
-            MessageSet::Meta(m) => Some(m),
-            MessageSet::Data(_) => None,
-            MessageSet::MetaData(m, _) => Some(m),
-        }
-    }
-    pub fn into_data(self) -> Option<D> {
+    /// Where to start consuming the vgroup of `assignment`, `None` for [OffsetReset::None].
+    pub fn start_offset(&self, assignment: &Assignment) -> Option<i64> {
         match self {
-            MessageSet::Meta(_) => None,
-            MessageSet::Data(d) => Some(d),
-            MessageSet::MetaData(_, d) => Some(d),
+            OffsetReset::Earliest => Some(assignment.begin()),
+            OffsetReset::Latest => Some(assignment.end()),
+            OffsetReset::None => None,
         }
     }
+}
 
-    pub fn has_meta(&self) -> bool {
-        matches!(self, &MessageSet::Meta(_) | &MessageSet::MetaData(_, _))
-    }
-    pub fn has_data(&self) -> bool {
-        matches!(self, &MessageSet::Data(_) | &MessageSet::MetaData(_, _))
+impl Display for OffsetReset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
     }
+}
 
-    pub fn meta(&self) -> Option<&M> {
-        match self {
-            MessageSet::Meta(m) => Some(m),
-            MessageSet::Data(_) => None,
-            MessageSet::MetaData(m, _) => Some(m),
-        }
-    }
-    pub fn data(&mut self) -> Option<&mut D> {
-        match self {
-            MessageSet::Meta(_) => None,
-            MessageSet::Data(d) => Some(d),
-            MessageSet::MetaData(_, d) => Some(d),
+impl FromStr for OffsetReset {
+    type Err = RawError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "earliest" => Ok(OffsetReset::Earliest),
+            "latest" => Ok(OffsetReset::Latest),
+            "none" => Ok(OffsetReset::None),
+            _ => Err(RawError::from_string(format!(
+                "invalid auto.offset.reset `{s}`, expect one of earliest, latest or none"
+            ))),
         }
     }
 }
 
-#[async_trait::async_trait]
-pub trait IsAsyncMeta {
-    async fn as_raw_meta(&self) -> RawResult<RawMeta>;
-
-    async fn as_json_meta(&self) -> RawResult<JsonMeta>;
+/// Typed consumer options, the counterpart of the tmq params in a DSN.
+///
+/// Options left `None` are not written to the params, so the ones in a DSN or the consumer
+/// defaults apply.
+///
+/// ```rust
+/// # use taos_query::tmq::*;
+/// # use std::time::Duration;
+/// let config = TmqConfig::new("group1")
+///     .with_offset_reset(OffsetReset::Earliest)
+///     .with_auto_commit(true)
+///     .with_auto_commit_interval(Duration::from_secs(1));
+/// let params = config.to_params();
+/// assert_eq!(params["auto.offset.reset"], "earliest");
+/// assert_eq!(params["auto.commit.interval.ms"], "1000");
+/// assert_eq!(TmqConfig::from_params(&params, true).unwrap(), config);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TmqConfig {
+    /// The consumer group, `group.id`.
+    pub group_id: String,
+    /// `client.id`.
+    pub client_id: Option<String>,
+    /// `auto.offset.reset`, left to the server default when `None`.
+    pub offset_reset: Option<OffsetReset>,
+    /// `enable.auto.commit`.
+    pub auto_commit: Option<bool>,
+    /// `auto.commit.interval.ms`.
+    pub auto_commit_interval: Option<Duration>,
+    /// `with.table.name`.
+    pub with_table_name: Option<bool>,
+    /// `experimental.snapshot.enable`.
+    pub snapshot_enable: Option<bool>,
+    /// Default timeout of polling, `timeout`.
+    pub timeout: Option<Timeout>,
+    /// Other params passed through as is, like `token` or driver specific options.
+    pub params: BTreeMap<String, String>,
 }
 
-impl<T> IsMeta for T
-where
-    T: IsAsyncMeta + SyncOnAsync,
-{
-    fn as_raw_meta(&self) -> RawResult<RawMeta> {
-        crate::block_in_place_or_global(T::as_raw_meta(self))
+impl TmqConfig {
+    /// Params handled by the typed fields.
+    const TYPED_PARAMS: &'static [&'static str] = &[
+        "group.id",
+        "client.id",
+        "auto.offset.reset",
+        "enable.auto.commit",
+        "auto.commit.interval.ms",
+        "with.table.name",
+        "experimental.snapshot.enable",
+        "timeout",
+    ];
+
+    /// Untyped params that are still accepted in strict mode, besides the `td.connect.*` ones.
+    ///
+    /// The dotted ones are native consumer options, which the native drivers pass through.
+    pub const KNOWN_PARAMS: &'static [&'static str] = &[
+        "token",
+        "offset",
+        "libraryPath",
+        "configDir",
+        "maxRetries",
+        "conn_mode",
+        "msg.with.table.name",
+        "msg.consume.excluded",
+        "msg.consume.rawdata",
+        "enable.replay",
+        "enable.heartbeat.background",
+        "session.timeout.ms",
+        "max.poll.interval.ms",
+        "fetch.max.wait.ms",
+        "min.poll.rows",
+    ];
+
+    fn is_known_param(key: &str) -> bool {
+        Self::TYPED_PARAMS.contains(&key)
+            || Self::KNOWN_PARAMS.contains(&key)
+            || key.starts_with("td.connect.")
     }
 
-    fn as_json_meta(&self) -> RawResult<JsonMeta> {
-        crate::block_in_place_or_global(T::as_json_meta(self))
+    pub fn new(group_id: impl Into<String>) -> Self {
+        Self {
+            group_id: group_id.into(),
+            client_id: None,
+            offset_reset: None,
+            auto_commit: None,
+            auto_commit_interval: None,
+            with_table_name: None,
+            snapshot_enable: None,
+            timeout: None,
+            params: BTreeMap::new(),
+        }
     }
-}
 
-#[async_trait::async_trait]
-impl<T> IsAsyncMeta for T
-where
-    T: IsMeta + AsyncOnSync + Send + Sync,
-{
-    async fn as_raw_meta(&self) -> RawResult<RawMeta> {
-        <T as IsMeta>::as_raw_meta(self)
+    pub fn with_client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
     }
 
-    async fn as_json_meta(&self) -> RawResult<JsonMeta> {
-        <T as IsMeta>::as_json_meta(self)
+    pub fn with_offset_reset(mut self, offset_reset: OffsetReset) -> Self {
+        self.offset_reset = Some(offset_reset);
+        self
     }
-}
 
-pub trait IsMeta {
-    fn as_raw_meta(&self) -> RawResult<RawMeta>;
+    pub fn with_auto_commit(mut self, auto_commit: bool) -> Self {
+        self.auto_commit = Some(auto_commit);
+        self
+    }
 
-    fn as_json_meta(&self) -> RawResult<JsonMeta>;
-}
+    pub fn with_auto_commit_interval(mut self, interval: Duration) -> Self {
+        self.auto_commit_interval = Some(interval);
+        self
+    }
 
-#[async_trait::async_trait]
-pub trait IsAsyncData {
-    async fn as_raw_data(&self) -> RawResult<RawData>;
-    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>>;
-}
+    pub fn with_table_name(mut self, with_table_name: bool) -> Self {
+        self.with_table_name = Some(with_table_name);
+        self
+    }
 
-pub trait IsData {
-    fn as_raw_data(&self) -> RawResult<RawData>;
-    fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>>;
-}
+    pub fn with_snapshot(mut self, snapshot_enable: bool) -> Self {
+        self.snapshot_enable = Some(snapshot_enable);
+        self
+    }
 
-#[async_trait::async_trait]
-pub trait AsyncMessage {
-    /// Check if the message contains meta.
-    fn has_meta(&self) -> bool;
-    /// Check if the message contains data.
-    fn has_data(&self) -> bool;
+    pub fn with_timeout(mut self, timeout: Timeout) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 
-    /// Return raw data as bytes.
-    async fn as_raw_data(&self) -> RawResult<RawData>;
+    pub fn with_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(key.into(), value.into());
+        self
+    }
 
-    /// Extract meta message.
-    async fn get_meta(&self) -> RawResult<Option<RawMeta>>;
-    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>>;
-}
+    /// Parse from DSN params.
+    ///
+    /// Empty values are taken as unset, as the consumers do. With `strict`, keys other than the
+    /// typed ones and [TmqConfig::KNOWN_PARAMS] are rejected, to catch typos like `auto.offset.rest`.
+    pub fn from_params(params: &BTreeMap<String, String>, strict: bool) -> RawResult<Self> {
+        fn get<'a>(params: &'a BTreeMap<String, String>, key: &str) -> Option<&'a str> {
+            params
+                .get(key)
+                .map(|s| s.as_str())
+                .filter(|s| !s.is_empty())
+        }
+        fn parse_bool(key: &str, value: &str) -> RawResult<bool> {
+            value.parse().map_err(|_| {
+                RawError::from_string(format!("invalid {key} `{value}`, expect true or false"))
+            })
+        }
 
-pub type VGroupId = i32;
+        if strict {
+            let unknown = params
+                .keys()
+                .filter(|key| !Self::is_known_param(key))
+                .join(", ");
+            if !unknown.is_empty() {
+                return Err(RawError::from_string(format!(
+                    "unknown tmq params: {unknown}"
+                )));
+            }
+        }
+        let group_id =
+            get(params, "group.id").ok_or_else(|| RawError::from_string("group.id is required"))?;
+        let mut config = Self::new(group_id);
+        config.client_id = get(params, "client.id").map(ToString::to_string);
+        config.offset_reset = get(params, "auto.offset.reset")
+            .map(OffsetReset::from_str)
+            .transpose()?;
+        config.auto_commit = get(params, "enable.auto.commit")
+            .map(|value| parse_bool("enable.auto.commit", value))
+            .transpose()?;
+        config.auto_commit_interval = get(params, "auto.commit.interval.ms")
+            .map(|ms| {
+                ms.parse::<u64>().map(Duration::from_millis).map_err(|_| {
+                    RawError::from_string(format!("invalid auto.commit.interval.ms: {ms}"))
+                })
+            })
+            .transpose()?;
+        config.with_table_name = get(params, "with.table.name")
+            .map(|value| parse_bool("with.table.name", value))
+            .transpose()?;
+        config.snapshot_enable = get(params, "experimental.snapshot.enable")
+            .map(|value| parse_bool("experimental.snapshot.enable", value))
+            .transpose()?;
+        config.timeout = get(params, "timeout")
+            .map(Timeout::from_str)
+            .transpose()
+            .map_err(RawError::from_any)?;
+        config.params = params
+            .iter()
+            .filter(|(key, _)| !Self::TYPED_PARAMS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Ok(config)
+    }
 
-/// Extract offset information.
-pub trait IsOffset {
-    /// Database name for current message
-    fn database(&self) -> &str;
+    /// Parse from the params of a DSN, see [TmqConfig::from_params].
+    pub fn from_dsn<D: crate::IntoDsn>(dsn: D, strict: bool) -> RawResult<Self> {
+        let dsn = dsn.into_dsn()?;
+        Self::from_params(&dsn.params, strict)
+    }
 
-    /// Topic name for current message.
-    fn topic(&self) -> &str;
+    /// Format as DSN params, which [TmqConfig::from_params] parses back. Unset options are left
+    /// out.
+    pub fn to_params(&self) -> BTreeMap<String, String> {
+        let mut params = self.params.clone();
+        params.insert("group.id".to_string(), self.group_id.clone());
+        if let Some(client_id) = &self.client_id {
+            params.insert("client.id".to_string(), client_id.clone());
+        }
+        if let Some(offset_reset) = self.offset_reset {
+            params.insert("auto.offset.reset".to_string(), offset_reset.to_string());
+        }
+        if let Some(auto_commit) = self.auto_commit {
+            params.insert("enable.auto.commit".to_string(), auto_commit.to_string());
+        }
+        if let Some(interval) = self.auto_commit_interval {
+            params.insert(
+                "auto.commit.interval.ms".to_string(),
+                interval.as_millis().to_string(),
+            );
+        }
+        if let Some(with_table_name) = self.with_table_name {
+            params.insert("with.table.name".to_string(), with_table_name.to_string());
+        }
+        if let Some(snapshot_enable) = self.snapshot_enable {
+            params.insert(
+                "experimental.snapshot.enable".to_string(),
+                snapshot_enable.to_string(),
+            );
+        }
+        if let Some(timeout) = self.timeout {
+            params.insert("timeout".to_string(), timeout.to_string());
+        }
+        params
+    }
 
-    /// VGroup id for current message.
-    fn vgroup_id(&self) -> VGroupId;
+    /// Merge the params into `dsn`, overriding existing ones.
+    pub fn apply_to(&self, dsn: &mut crate::Dsn) {
+        dsn.params.extend(self.to_params());
+    }
 }
 
-#[repr(C)]
-#[derive(Debug, Default, Copy, Clone, Deserialize, Serialize)]
-pub struct Assignment {
-    vgroup_id: VGroupId,
-    offset: i64,
-    begin: i64,
-    end: i64,
+/// Consumer params with the oldest server versions supporting them, older servers ignore them
+/// silently.
+const VERSIONED_PARAMS: &[(&str, &str)] = &[
+    ("experimental.snapshot.enable", "3.0.0.0"),
+    ("enable.replay", "3.2.1.0"),
+];
+
+/// Params of `params` which are enabled and need a server of at least the paired version, check
+/// them with [check_server_version] before building a consumer.
+pub fn versioned_params<'a>(
+    params: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Vec<(&'static str, &'static str)> {
+    params
+        .into_iter()
+        .filter(|(_, value)| value.eq_ignore_ascii_case("true"))
+        .filter_map(|(key, _)| VERSIONED_PARAMS.iter().find(|(k, _)| *k == key).copied())
+        .collect()
 }
 
-impl Assignment {
-    pub fn new(vgroup_id: VGroupId, offset: i64, begin: i64, end: i64) -> Self {
-        Self {
-            vgroup_id,
-            offset,
-            begin,
-            end,
+/// Fail if the server of `server_version` is older than any version required by `params`, see
+/// [versioned_params].
+pub fn check_server_version(server_version: &str, params: &[(&str, &str)]) -> RawResult<()> {
+    /// Leading numeric parts of a version, like `[3, 2, 1, 0]` of `3.2.1.0.alpha`.
+    fn parts(version: &str) -> Vec<u32> {
+        version
+            .split('.')
+            .map_while(|part| part.trim().parse().ok())
+            .collect()
+    }
+    let server = parts(server_version);
+    for (param, since) in params {
+        if server < parts(since) {
+            return Err(RawError::from_string(format!(
+                "consumer param {param} requires server version {since} or later, \
+                 but the server version is {server_version}"
+            )));
         }
     }
+    Ok(())
+}
 
-    pub fn vgroup_id(&self) -> VGroupId {
-        self.vgroup_id
+/// Sql of all topic names, to match a topic pattern against.
+pub const TOPICS_SQL: &str = "select topic_name from information_schema.ins_topics";
+
+/// Sorted `topics` matching a topic pattern by `is_match`, `None` if they are the same as the
+/// `current` subscription so that the consumer does not have to re-subscribe.
+pub fn matching_topics(
+    topics: impl IntoIterator<Item = String>,
+    current: &[String],
+    is_match: impl Fn(&str) -> bool,
+) -> Option<Vec<String>> {
+    let matched = topics
+        .into_iter()
+        .filter(|topic| is_match(topic))
+        .sorted()
+        .dedup()
+        .collect_vec();
+    if matched == current.iter().cloned().sorted().collect_vec() {
+        None
+    } else {
+        Some(matched)
     }
+}
 
-    pub fn current_offset(&self) -> i64 {
-        self.offset
+/// Topic pattern of a consumer subscribed by [AsAsyncConsumer::subscribe_pattern], and when it
+/// was last matched against the topics.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct TopicPattern(std::sync::Mutex<Option<(regex::Regex, Instant)>>);
+
+impl TopicPattern {
+    /// Interval of matching the pattern again on polls, to pick up topics created later.
+    pub const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+    fn unsupported() -> RawError {
+        RawError::from_string("topic pattern is not supported by the consumer")
     }
 
-    pub fn begin(&self) -> i64 {
-        self.begin
+    fn set(&self, pattern: &str) -> RawResult<()> {
+        let pattern = regex::Regex::new(pattern).map_err(|err| {
+            RawError::from_string(format!("invalid topic pattern {pattern}: {err}"))
+        })?;
+        *self.0.lock().unwrap() = Some((pattern, Instant::now()));
+        Ok(())
     }
 
-    pub fn end(&self) -> i64 {
-        self.end
+    /// Drop the pattern, called on subscribe.
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = None;
     }
-}
 
-pub trait AsConsumer: Sized {
-    type Offset: IsOffset;
-    type Meta: IsMeta;
-    type Data: IntoIterator<Item = RawResult<RawBlock>>;
+    /// Whether a pattern is subscribed and not matched in [TopicPattern::REFRESH_INTERVAL].
+    pub fn is_due(&self) -> bool {
+        match &*self.0.lock().unwrap() {
+            Some((_, matched_at)) => matched_at.elapsed() >= Self::REFRESH_INTERVAL,
+            None => false,
+        }
+    }
 
-    /// Default timeout getter for message stream.
-    fn default_timeout(&self) -> Timeout {
-        Timeout::Never
+    /// The pattern to match the topics against, marked as matched now.
+    fn start_refresh(&self) -> RawResult<regex::Regex> {
+        let mut state = self.0.lock().unwrap();
+        let (pattern, matched_at) = state
+            .as_mut()
+            .ok_or_else(|| RawError::from_string("no topic pattern subscribed"))?;
+        *matched_at = Instant::now();
+        Ok(pattern.clone())
     }
+}
 
-    fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
-        &mut self,
-        topics: I,
-    ) -> RawResult<()>;
+/// Error of a batch receive such as [recv_many](AsAsyncConsumer::recv_many) which occurred after
+/// some messages were received, kept to be returned by the next batch receive.
+#[doc(hidden)]
+#[derive(Debug, Default)]
+pub struct PendingError(std::sync::Mutex<Option<RawError>>);
 
-    /// None means wait until next message come.
-    fn recv_timeout(
-        &self,
-        timeout: Timeout,
-    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>;
+impl PendingError {
+    /// Keep `err` for the next batch receive, replacing the older one.
+    pub fn set(&self, err: RawError) {
+        *self.0.lock().unwrap() = Some(err);
+    }
 
-    fn recv(&self) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
-        self.recv_timeout(self.default_timeout())
+    /// Take the kept error.
+    pub fn take(&self) -> Option<RawError> {
+        self.0.lock().unwrap().take()
     }
+}
 
-    fn iter_data_only(
-        &self,
-        timeout: Timeout,
-    ) -> Box<dyn '_ + Iterator<Item = RawResult<(Self::Offset, Self::Data)>>> {
-        Box::new(
-            self.iter_with_timeout(timeout)
-                .filter_map_ok(|m| m.1.into_data().map(|data| (m.0, data))),
-        )
+/// Keep `err` of a batch receive in `pending`, or log it if the consumer does not keep errors.
+fn keep_pending_error(pending: Option<&PendingError>, err: RawError) {
+    match pending {
+        Some(pending) => pending.set(err),
+        None => log::warn!("batch receive stopped by error: {err}"),
     }
+}
 
-    fn iter_with_timeout(&self, timeout: Timeout) -> MessageSetsIter<'_, Self> {
-        MessageSetsIter {
-            consumer: self,
-            timeout,
+/// Outcome of [seek_all](AsAsyncConsumer::seek_all): the seeks which succeeded and the ones
+/// which failed with their errors, each as `(topic, vgroup_id, offset)` in the requested order.
+#[derive(Debug, Default)]
+pub struct SeekResults {
+    pub succeeded: Vec<(String, VGroupId, i64)>,
+    pub failed: Vec<((String, VGroupId, i64), RawError)>,
+}
+
+impl SeekResults {
+    /// Pair each of `offsets` with the result of seeking it in `results`.
+    pub fn new(
+        offsets: &[(String, VGroupId, i64)],
+        results: impl IntoIterator<Item = RawResult<()>>,
+    ) -> Self {
+        let mut outcome = Self::default();
+        for (seek, res) in offsets.iter().cloned().zip(results) {
+            match res {
+                Ok(()) => outcome.succeeded.push(seek),
+                Err(err) => outcome.failed.push((seek, err)),
+            }
         }
+        outcome
     }
 
-    fn iter(&self) -> MessageSetsIter<'_, Self> {
-        self.iter_with_timeout(self.default_timeout())
+    /// Check if all seeks succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
     }
 
-    fn commit(&self, offset: Self::Offset) -> RawResult<()>;
+    /// Aggregate the failed seeks into one error, as the topic seeks such as
+    /// [seek_to_beginning](AsConsumer::seek_to_beginning) do.
+    pub fn into_result(self) -> RawResult<()> {
+        let errors = self
+            .failed
+            .into_iter()
+            .map(|((topic, vgroup_id, offset), err)| {
+                (format!("topic {topic} vgroup {vgroup_id} to {offset}"), err)
+            })
+            .collect();
+        seek_errors(None, errors)
+    }
+}
 
-    fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()>;
+/// Build a consumer of `B` with typed options, validated at [build](Self::build).
+///
+/// ```rust,no_run
+/// # use taos_query::{tmq::*, AsyncTBuilder};
+/// # use std::time::Duration;
+/// # async fn consume<B: AsyncTBuilder>() -> taos_query::RawResult<()> {
+/// let consumer = ConsumerBuilder::<B>::new()
+///     .server("ws://localhost:6041")
+///     .group_id("group1")
+///     .offset_reset(OffsetReset::Earliest)
+///     .auto_commit(true)
+///     .auto_commit_interval(Duration::from_secs(1))
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct ConsumerBuilder<B> {
+    server: Option<String>,
+    group_id: Option<String>,
+    client_id: Option<String>,
+    offset_reset: Option<OffsetReset>,
+    auto_commit: Option<bool>,
+    auto_commit_interval: Option<Duration>,
+    timeout: Option<Timeout>,
+    params: BTreeMap<String, String>,
+    _builder: std::marker::PhantomData<fn() -> B>,
+}
 
-    fn unsubscribe(self) {
-        drop(self)
+impl<B> Default for ConsumerBuilder<B> {
+    fn default() -> Self {
+        Self {
+            server: None,
+            group_id: None,
+            client_id: None,
+            offset_reset: None,
+            auto_commit: None,
+            auto_commit_interval: None,
+            timeout: None,
+            params: BTreeMap::new(),
+            _builder: std::marker::PhantomData,
+        }
     }
+}
 
-    fn list_topics(&self) -> RawResult<Vec<String>>;
+impl<B> ConsumerBuilder<B> {
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>>;
+    /// The DSN of the server to connect, e.g. `ws://localhost:6041` or `taos://localhost:6030`.
+    ///
+    /// Its params are kept, unless overridden by the typed options.
+    pub fn server(mut self, dsn: impl Into<String>) -> Self {
+        self.server = Some(dsn.into());
+        self
+    }
 
-    fn offset_seek(&mut self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()>;
+    pub fn group_id(mut self, group_id: impl Into<String>) -> Self {
+        self.group_id = Some(group_id.into());
+        self
+    }
+
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    pub fn offset_reset(mut self, offset_reset: OffsetReset) -> Self {
+        self.offset_reset = Some(offset_reset);
+        self
+    }
+
+    pub fn auto_commit(mut self, auto_commit: bool) -> Self {
+        self.auto_commit = Some(auto_commit);
+        self
+    }
+
+    /// Interval of auto commit, requires [auto_commit](Self::auto_commit) enabled.
+    pub fn auto_commit_interval(mut self, interval: Duration) -> Self {
+        self.auto_commit_interval = Some(interval);
+        self
+    }
+
+    /// Default timeout of polling.
+    pub fn timeout(mut self, timeout: Timeout) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set a param not covered by the typed options.
+    pub fn param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.params.insert(key.into(), value.into());
+        self
+    }
+
+    /// Validate the options and merge them into the server DSN.
+    pub fn to_dsn(&self) -> RawResult<crate::Dsn> {
+        use crate::IntoDsn;
+
+        let server = self
+            .server
+            .as_deref()
+            .ok_or_else(|| RawError::from_string("server is required to build a consumer"))?;
+        let mut dsn = server.into_dsn()?;
+        let group_id = self
+            .group_id
+            .clone()
+            .or_else(|| dsn.params.get("group.id").cloned())
+            .filter(|group_id| !group_id.is_empty())
+            .ok_or_else(|| RawError::from_string("group id is required to build a consumer"))?;
+        let mut params = dsn.params.clone();
+        params.insert("group.id".to_string(), group_id);
+        let mut config = TmqConfig::from_params(&params, false)?;
+        if let Some(client_id) = &self.client_id {
+            config.client_id = Some(client_id.clone());
+        }
+        if let Some(offset_reset) = self.offset_reset {
+            config.offset_reset = Some(offset_reset);
+        }
+        if let Some(auto_commit) = self.auto_commit {
+            config.auto_commit = Some(auto_commit);
+        }
+        if let Some(interval) = self.auto_commit_interval {
+            if config.auto_commit != Some(true) {
+                return Err(RawError::from_string(
+                    "auto commit interval is set but auto commit is not enabled",
+                ));
+            }
+            if interval.is_zero() {
+                return Err(RawError::from_string(
+                    "auto commit interval must be positive",
+                ));
+            }
+            config.auto_commit_interval = Some(interval);
+        }
+        if let Some(timeout) = self.timeout {
+            config.timeout = Some(timeout);
+        }
+        config.params.extend(self.params.clone());
+        config.apply_to(&mut dsn);
+        Ok(dsn)
+    }
+}
+
+impl<B: crate::AsyncTBuilder> ConsumerBuilder<B> {
+    /// Validate the options and build the consumer.
+    pub async fn build(self) -> RawResult<B::Target> {
+        B::from_dsn(self.to_dsn()?)?.build().await
+    }
+}
+
+pub enum MessageSet<M, D> {
+    Meta(M),
+    Data(D),
+    MetaData(M, D),
+}
+
+/// Kind of a [MessageSet], without its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MessageKind {
+    Meta,
+    Data,
+    MetaData,
+}
+
+/// Owned and serializable form of a [MessageSet], to persist consumed messages and replay them
+/// later, see [MessageSet::to_envelope].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageEnvelope {
+    pub kind: MessageKind,
+    /// Raw meta in the frame of [RawMeta::to_frame].
+    pub meta: Option<Vec<u8>>,
+    /// Raw data with all the blocks, in the frame of [RawData::to_frame].
+    pub data: Option<Vec<u8>>,
+}
+
+impl<M, D> Debug for MessageSet<M, D>
+where
+    M: Debug,
+    D: Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Meta(m) => f.debug_tuple("Meta").field(m).finish(),
+            Self::Data(d) => f.debug_tuple("Data").field(d).finish(),
+            Self::MetaData(m, d) => f.debug_tuple("MetaData").field(m).field(d).finish(),
+        }
+    }
+}
+
+impl<M, D> MessageSet<M, D> {
+    /// Kind of the message, known from the poll response before any payload is fetched.
+    ///
+    /// E.g. a data-only pipeline skips meta messages by kind without decoding them.
+    #[doc(alias = "message_type")]
+    pub const fn kind(&self) -> MessageKind {
+        match self {
+            MessageSet::Meta(_) => MessageKind::Meta,
+            MessageSet::Data(_) => MessageKind::Data,
+            MessageSet::MetaData(_, _) => MessageKind::MetaData,
+        }
+    }
+
+    pub fn into_meta(self) -> Option<M> {
+        match self {
+            MessageSet::Meta(m) => Some(m),
+            MessageSet::Data(_) => None,
+            MessageSet::MetaData(m, _) => Some(m),
+        }
+    }
+    pub fn into_data(self) -> Option<D> {
+        match self {
+            MessageSet::Meta(_) => None,
+            MessageSet::Data(d) => Some(d),
+            MessageSet::MetaData(_, d) => Some(d),
+        }
+    }
+
+    /// Splits into the meta and data payloads.
+    ///
+    /// Both are `Some` for [MessageSet::MetaData], the missing half is `None` otherwise.
+    ///
+    /// ```rust
+    /// use taos_query::tmq::MessageSet;
+    ///
+    /// let message: MessageSet<&str, u32> = MessageSet::MetaData("create table", 1);
+    /// let (meta, data) = message.into_parts();
+    /// assert_eq!((meta, data), (Some("create table"), Some(1)));
+    /// ```
+    pub fn into_parts(self) -> (Option<M>, Option<D>) {
+        match self {
+            MessageSet::Meta(m) => (Some(m), None),
+            MessageSet::Data(d) => (None, Some(d)),
+            MessageSet::MetaData(m, d) => (Some(m), Some(d)),
+        }
+    }
+
+    pub fn has_meta(&self) -> bool {
+        matches!(self, &MessageSet::Meta(_) | &MessageSet::MetaData(_, _))
+    }
+    pub fn has_data(&self) -> bool {
+        matches!(self, &MessageSet::Data(_) | &MessageSet::MetaData(_, _))
+    }
+
+    pub fn meta(&self) -> Option<&M> {
+        match self {
+            MessageSet::Meta(m) => Some(m),
+            MessageSet::Data(_) => None,
+            MessageSet::MetaData(m, _) => Some(m),
+        }
+    }
+    pub fn meta_mut(&mut self) -> Option<&mut M> {
+        match self {
+            MessageSet::Meta(m) => Some(m),
+            MessageSet::Data(_) => None,
+            MessageSet::MetaData(m, _) => Some(m),
+        }
+    }
+    pub fn data(&mut self) -> Option<&mut D> {
+        match self {
+            MessageSet::Meta(_) => None,
+            MessageSet::Data(d) => Some(d),
+            MessageSet::MetaData(_, d) => Some(d),
+        }
+    }
+    pub fn data_ref(&self) -> Option<&D> {
+        match self {
+            MessageSet::Meta(_) => None,
+            MessageSet::Data(d) => Some(d),
+            MessageSet::MetaData(_, d) => Some(d),
+        }
+    }
+
+    /// Converts from `&MessageSet<M, D>` to `MessageSet<&M, &D>`.
+    pub fn as_ref(&self) -> MessageSet<&M, &D> {
+        match self {
+            MessageSet::Meta(m) => MessageSet::Meta(m),
+            MessageSet::Data(d) => MessageSet::Data(d),
+            MessageSet::MetaData(m, d) => MessageSet::MetaData(m, d),
+        }
+    }
+
+    /// Maps the meta payload with `f`, leaving the data payload untouched.
+    pub fn map_meta<N>(self, f: impl FnOnce(M) -> N) -> MessageSet<N, D> {
+        match self {
+            MessageSet::Meta(m) => MessageSet::Meta(f(m)),
+            MessageSet::Data(d) => MessageSet::Data(d),
+            MessageSet::MetaData(m, d) => MessageSet::MetaData(f(m), d),
+        }
+    }
+
+    /// Maps the data payload with `f`, leaving the meta payload untouched.
+    pub fn map_data<N>(self, f: impl FnOnce(D) -> N) -> MessageSet<M, N> {
+        match self {
+            MessageSet::Meta(m) => MessageSet::Meta(m),
+            MessageSet::Data(d) => MessageSet::Data(f(d)),
+            MessageSet::MetaData(m, d) => MessageSet::MetaData(m, f(d)),
+        }
+    }
+
+    /// Maps the data payload with the fallible `f`, leaving the meta payload untouched.
+    pub fn try_map_data<N, E>(
+        self,
+        f: impl FnOnce(D) -> Result<N, E>,
+    ) -> Result<MessageSet<M, N>, E> {
+        Ok(match self {
+            MessageSet::Meta(m) => MessageSet::Meta(m),
+            MessageSet::Data(d) => MessageSet::Data(f(d)?),
+            MessageSet::MetaData(m, d) => MessageSet::MetaData(m, f(d)?),
+        })
+    }
+}
+
+impl<M: IsMeta, D: IsData> MessageSet<M, D> {
+    /// Copy the raw meta and data of the message into an envelope.
+    pub fn to_envelope(&self) -> RawResult<MessageEnvelope> {
+        let meta = match self.meta() {
+            Some(meta) => Some(meta.as_raw_meta()?.to_frame().to_vec()),
+            None => None,
+        };
+        let data = match self.data_ref() {
+            Some(data) => Some(data.as_raw_data()?.to_frame().to_vec()),
+            None => None,
+        };
+        Ok(MessageEnvelope {
+            kind: self.kind(),
+            meta,
+            data,
+        })
+    }
+}
+
+impl MessageSet<RawMeta, RawData> {
+    /// Reconstruct the raw meta and data of a message from an envelope made by
+    /// [to_envelope](MessageSet::to_envelope), they could be replayed by
+    /// [write_raw_meta](crate::AsyncQueryable::write_raw_meta).
+    ///
+    /// Frames are validated, a truncated or corrupted one is an error.
+    pub fn from_envelope(envelope: MessageEnvelope) -> RawResult<Self> {
+        let MessageEnvelope { kind, meta, data } = envelope;
+        let meta = meta
+            .map(|meta| RawMeta::from_frame(meta.into()))
+            .transpose()?;
+        let data = data
+            .map(|data| RawData::from_frame(data.into()))
+            .transpose()?;
+        match (kind, meta, data) {
+            (MessageKind::Meta, Some(meta), None) => Ok(MessageSet::Meta(meta)),
+            (MessageKind::Data, None, Some(data)) => Ok(MessageSet::Data(data)),
+            (MessageKind::MetaData, Some(meta), Some(data)) => Ok(MessageSet::MetaData(meta, data)),
+            (kind, meta, data) => Err(RawError::from_string(format!(
+                "invalid message envelope of kind {kind:?}, with meta: {}, with data: {}",
+                meta.is_some(),
+                data.is_some()
+            ))),
+        }
+    }
+}
+
+/// Result of a poll, telling a quiet period from the end of subscribed topics.
+pub enum PollResult<O, M, D> {
+    Message(O, MessageSet<M, D>),
+    /// No message in time, but some vgroups are not consumed to the end.
+    Timeout,
+    /// All assigned vgroups are consumed to the end.
+    EndOfTopic,
+}
+
+impl<O, M, D> PollResult<O, M, D> {
+    pub fn is_end_of_topic(&self) -> bool {
+        matches!(self, PollResult::EndOfTopic)
+    }
+
+    /// Converts to the `Option` returned by `recv_timeout`.
+    pub fn into_message(self) -> Option<(O, MessageSet<M, D>)> {
+        match self {
+            PollResult::Message(offset, message) => Some((offset, message)),
+            PollResult::Timeout | PollResult::EndOfTopic => None,
+        }
+    }
+}
+
+impl<O: Debug, M, D> Debug for PollResult<O, M, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Message(offset, message) => f
+                .debug_tuple("Message")
+                .field(offset)
+                .field(&message.kind())
+                .finish(),
+            Self::Timeout => f.write_str("Timeout"),
+            Self::EndOfTopic => f.write_str("EndOfTopic"),
+        }
+    }
+}
+
+/// End of topics is reached when all assigned vgroups are caught up, vgroups with unknown end
+/// are never caught up.
+fn poll_result_of<O, M, D>(
+    message: Option<(O, MessageSet<M, D>)>,
+    assignments: impl FnOnce() -> Option<Vec<(String, Vec<Assignment>)>>,
+) -> PollResult<O, M, D> {
+    if let Some((offset, message)) = message {
+        return PollResult::Message(offset, message);
+    }
+    let assignments = assignments().into_iter().flatten().flat_map(|(_, a)| a);
+    let mut assignments = assignments.peekable();
+    if assignments.peek().is_some() && assignments.all(|a| a.is_caught_up()) {
+        PollResult::EndOfTopic
+    } else {
+        PollResult::Timeout
+    }
+}
+
+#[async_trait::async_trait]
+pub trait IsAsyncMeta {
+    async fn as_raw_meta(&self) -> RawResult<RawMeta>;
+
+    async fn as_json_meta(&self) -> RawResult<JsonMeta>;
+}
+
+impl<T> IsMeta for T
+where
+    T: IsAsyncMeta + SyncOnAsync,
+{
+    fn as_raw_meta(&self) -> RawResult<RawMeta> {
+        crate::block_in_place_or_global(T::as_raw_meta(self))
+    }
+
+    fn as_json_meta(&self) -> RawResult<JsonMeta> {
+        crate::block_in_place_or_global(T::as_json_meta(self))
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> IsAsyncMeta for T
+where
+    T: IsMeta + AsyncOnSync + Send + Sync,
+{
+    async fn as_raw_meta(&self) -> RawResult<RawMeta> {
+        <T as IsMeta>::as_raw_meta(self)
+    }
+
+    async fn as_json_meta(&self) -> RawResult<JsonMeta> {
+        <T as IsMeta>::as_json_meta(self)
+    }
+}
+
+pub trait IsMeta {
+    fn as_raw_meta(&self) -> RawResult<RawMeta>;
+
+    fn as_json_meta(&self) -> RawResult<JsonMeta>;
+}
+
+#[async_trait::async_trait]
+pub trait IsAsyncData {
+    async fn as_raw_data(&self) -> RawResult<RawData>;
+    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>>;
+
+    /// Schema of the next block to fetch, an empty list if all blocks are fetched.
+    ///
+    /// The block is not consumed, it is still returned by the next
+    /// [fetch_raw_block](Self::fetch_raw_block). Blocks of a message from a database topic may
+    /// have different schemas.
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        Err(RawError::from_string("fields is not supported by the data"))
+    }
+
+    /// Number of blocks in the message, `None` if unknown.
+    ///
+    /// It is a hint to size the work. The websocket consumer knows it only after all blocks are
+    /// fetched, since the server does not tell it when polled.
+    fn block_count_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Number of rows of all the blocks in the message, `None` if unknown.
+    ///
+    /// It is a hint to size the work, known like [block_count_hint](Self::block_count_hint).
+    fn row_count_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Fetch all the remaining blocks of the message.
+    async fn fetch_all_blocks(&self) -> RawResult<Vec<RawBlock>> {
+        let mut blocks = Vec::new();
+        while let Some(block) = self.fetch_raw_block().await? {
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Fetch all blocks of the message and deserialize each row into `T`.
+    ///
+    /// Rows are deserialized as in query result sets, see [RawBlock::deserialize]: fields match
+    /// column names, nullable columns map to `Option`, and timestamps can be deserialized into
+    /// `i64`, `String` or `chrono::DateTime`.
+    async fn deserialize<T: DeserializeOwned + Send>(&self) -> RawResult<Vec<T>> {
+        let mut rows = Vec::new();
+        while let Some(block) = self.fetch_raw_block().await? {
+            deserialize_block_into(&block, &mut rows)?;
+        }
+        Ok(rows)
+    }
+
+    /// Fetch the next block along with the name of the child table it belongs to.
+    ///
+    /// The table name is only available when the consumer is created with
+    /// `msg.with.table.name=true`, otherwise it is `None`.
+    async fn fetch_block_with_table(&self) -> RawResult<Option<(Option<String>, RawBlock)>> {
+        Ok(self
+            .fetch_raw_block()
+            .await?
+            .map(|block| (block.table_name().map(ToString::to_string), block)))
+    }
+
+    /// Fetch all blocks of the message and concatenate them into one, see
+    /// [RawBlock::concat_blocks].
+    ///
+    /// Returns `None` if the message has no blocks, or an error if the blocks have different
+    /// schemas.
+    async fn as_single_block(&self) -> RawResult<Option<RawBlock>> {
+        single_block(self.fetch_all_blocks().await?)
+    }
+
+    /// Fetch all the remaining blocks and render them as JSON lines, one object per row keyed by
+    /// the field names.
+    ///
+    /// Nulls are `null`, timestamps are RFC3339 strings in UTC with the precision of the block,
+    /// and json values are embedded as is.
+    async fn to_ndjson(&self) -> RawResult<String> {
+        crate::common::raw::blocks_to_ndjson(&self.fetch_all_blocks().await?)
+    }
+
+    /// Fetch all the remaining blocks and render them as CSV, enabled by the `csv` feature.
+    ///
+    /// The header row is the field names. Nulls are empty, timestamps are RFC3339 in UTC with
+    /// the precision of the block, and binary values are base64 encoded. Returns an error if
+    /// blocks have different fields, e.g. blocks of tables in a database topic.
+    #[cfg(feature = "csv")]
+    async fn to_csv(&self) -> RawResult<String> {
+        crate::common::raw::blocks_to_csv(&self.fetch_all_blocks().await?)
+    }
+}
+
+pub trait IsData {
+    fn as_raw_data(&self) -> RawResult<RawData>;
+    fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>>;
+
+    /// See [IsAsyncData::fields].
+    fn fields(&self) -> RawResult<Vec<Field>> {
+        Err(RawError::from_string("fields is not supported by the data"))
+    }
+
+    /// See [IsAsyncData::block_count_hint].
+    fn block_count_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// See [IsAsyncData::row_count_hint].
+    fn row_count_hint(&self) -> Option<usize> {
+        None
+    }
+
+    /// Fetch all the remaining blocks of the message.
+    fn fetch_all_blocks(&self) -> RawResult<Vec<RawBlock>> {
+        let mut blocks = Vec::new();
+        while let Some(block) = self.fetch_raw_block()? {
+            blocks.push(block);
+        }
+        Ok(blocks)
+    }
+
+    /// Fetch all blocks of the message and deserialize each row into `T`.
+    ///
+    /// Rows are deserialized as in query result sets, see [RawBlock::deserialize]: fields match
+    /// column names, nullable columns map to `Option`, and timestamps can be deserialized into
+    /// `i64`, `String` or `chrono::DateTime`.
+    fn deserialize<T: DeserializeOwned>(&self) -> RawResult<Vec<T>> {
+        let mut rows = Vec::new();
+        while let Some(block) = self.fetch_raw_block()? {
+            deserialize_block_into(&block, &mut rows)?;
+        }
+        Ok(rows)
+    }
+
+    /// Fetch the next block along with the name of the child table it belongs to.
+    ///
+    /// The table name is only available when the consumer is created with
+    /// `msg.with.table.name=true`, otherwise it is `None`.
+    fn fetch_block_with_table(&self) -> RawResult<Option<(Option<String>, RawBlock)>> {
+        Ok(self
+            .fetch_raw_block()?
+            .map(|block| (block.table_name().map(ToString::to_string), block)))
+    }
+
+    /// Fetch all blocks of the message and concatenate them into one, see
+    /// [RawBlock::concat_blocks].
+    ///
+    /// Returns `None` if the message has no blocks, or an error if the blocks have different
+    /// schemas.
+    fn as_single_block(&self) -> RawResult<Option<RawBlock>> {
+        single_block(self.fetch_all_blocks()?)
+    }
+}
+
+fn single_block(mut blocks: Vec<RawBlock>) -> RawResult<Option<RawBlock>> {
+    match blocks.len() {
+        0 => Ok(None),
+        1 => Ok(blocks.pop()),
+        _ => RawBlock::concat_blocks(&blocks).map(Some),
+    }
+}
+
+fn deserialize_block_into<T: DeserializeOwned>(
+    block: &RawBlock,
+    rows: &mut Vec<T>,
+) -> RawResult<()> {
+    for row in block.deserialize::<T>() {
+        rows.push(row.map_err(RawError::from_any)?);
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl<T> IsAsyncData for T
+where
+    T: IsData + AsyncOnSync + Send + Sync,
+{
+    async fn as_raw_data(&self) -> RawResult<RawData> {
+        <T as IsData>::as_raw_data(self)
+    }
+
+    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
+        <T as IsData>::fetch_raw_block(self)
+    }
+
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        <T as IsData>::fields(self)
+    }
+
+    fn block_count_hint(&self) -> Option<usize> {
+        <T as IsData>::block_count_hint(self)
+    }
+
+    fn row_count_hint(&self) -> Option<usize> {
+        <T as IsData>::row_count_hint(self)
+    }
+
+    async fn fetch_all_blocks(&self) -> RawResult<Vec<RawBlock>> {
+        <T as IsData>::fetch_all_blocks(self)
+    }
+}
+
+#[async_trait::async_trait]
+pub trait AsyncMessage {
+    /// Check if the message contains meta.
+    fn has_meta(&self) -> bool;
+    /// Check if the message contains data.
+    fn has_data(&self) -> bool;
+
+    /// Return raw data as bytes.
+    async fn as_raw_data(&self) -> RawResult<RawData>;
+
+    /// Extract meta message.
+    async fn get_meta(&self) -> RawResult<Option<RawMeta>>;
+    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>>;
+}
+
+pub type VGroupId = i32;
+
+/// Extract offset information.
+pub trait IsOffset {
+    /// Database name for current message
+    fn database(&self) -> &str;
+
+    /// Topic name for current message.
+    fn topic(&self) -> &str;
+
+    /// VGroup id for current message.
+    fn vgroup_id(&self) -> VGroupId;
+
+    /// Offset of current message in the vgroup, `-1` if unknown.
+    ///
+    /// Seek to it with `offset_seek(topic, vgroup_id, offset)` to consume from this message again.
+    fn offset(&self) -> i64 {
+        -1
+    }
+
+    /// Time spent polling current message in nanoseconds, `None` if unknown.
+    fn timing(&self) -> Option<i64> {
+        None
+    }
+
+    /// Local time when the message was received, e.g. to measure consuming latency.
+    ///
+    /// It is captured right after the message is read from the connection, `None` if the
+    /// consumer does not record it.
+    fn received_at(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Key of the topic vgroup as `{database}:{topic}:{vgroup_id}`, e.g. to store offsets.
+    ///
+    /// Names may contain colons, use [escaped_offset_key](Self::escaped_offset_key) when the
+    /// key has to be parsed back.
+    fn offset_key(&self) -> String {
+        format!("{}:{}:{}", self.database(), self.topic(), self.vgroup_id())
+    }
+
+    /// Same as [offset_key](Self::offset_key) but with `%` and `:` in names percent-encoded
+    /// as `%25` and `%3A`, so the key splits back into its parts on `:`.
+    fn escaped_offset_key(&self) -> String {
+        format!(
+            "{}:{}:{}",
+            escape_key_part(self.database()),
+            escape_key_part(self.topic()),
+            self.vgroup_id()
+        )
+    }
+}
+
+fn escape_key_part(part: &str) -> Cow<'_, str> {
+    if part.contains(['%', ':']) {
+        Cow::Owned(part.replace('%', "%25").replace(':', "%3A"))
+    } else {
+        Cow::Borrowed(part)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Assignment {
+    vgroup_id: VGroupId,
+    offset: i64,
+    begin: i64,
+    end: i64,
+}
+
+impl Assignment {
+    pub fn new(vgroup_id: VGroupId, offset: i64, begin: i64, end: i64) -> Self {
+        Self {
+            vgroup_id,
+            offset,
+            begin,
+            end,
+        }
+    }
+
+    pub fn vgroup_id(&self) -> VGroupId {
+        self.vgroup_id
+    }
+
+    pub fn current_offset(&self) -> i64 {
+        self.offset
+    }
+
+    pub fn begin(&self) -> i64 {
+        self.begin
+    }
+
+    pub fn end(&self) -> i64 {
+        self.end
+    }
+
+    /// The same assignment with the current offset replaced, e.g. to plan a seek.
+    pub fn with_offset(mut self, offset: i64) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Replace the current offset, `begin` and `end` are kept.
+    pub fn set_offset(&mut self, offset: i64) {
+        self.offset = offset;
+    }
+
+    /// Number of messages behind the end offset, zero if caught up.
+    pub fn lag(&self) -> i64 {
+        (self.end - self.offset).max(0)
+    }
+
+    /// Check if all messages of the vgroup are consumed, `false` if the end is unknown (`-1`).
+    pub fn is_caught_up(&self) -> bool {
+        self.end != -1 && self.offset >= self.end
+    }
+
+    /// Fraction consumed between `begin` and `end`, `1.0` for an empty range.
+    pub fn progress(&self) -> f64 {
+        if self.end <= self.begin {
+            return 1.0;
+        }
+        let consumed = (self.offset - self.begin) as f64 / (self.end - self.begin) as f64;
+        consumed.clamp(0.0, 1.0)
+    }
+}
+
+/// Assignments of a topic.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TopicAssignment {
+    topic: String,
+    assignments: Vec<Assignment>,
+}
+
+impl TopicAssignment {
+    pub fn new(topic: impl Into<String>, assignments: Vec<Assignment>) -> Self {
+        Self {
+            topic: topic.into(),
+            assignments,
+        }
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn assignments(&self) -> &[Assignment] {
+        &self.assignments
+    }
+
+    pub fn into_assignments(self) -> Vec<Assignment> {
+        self.assignments
+    }
+}
+
+impl From<(String, Vec<Assignment>)> for TopicAssignment {
+    fn from((topic, assignments): (String, Vec<Assignment>)) -> Self {
+        Self { topic, assignments }
+    }
+}
+
+/// Configuration a consumer was built with, for logging and admin tools.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConsumerInfo {
+    pub group_id: String,
+    pub client_id: Option<String>,
+    /// Topics subscribed to.
+    pub topics: Vec<String>,
+}
+
+/// Snapshot of the counters of a consumer, see [ConsumerCounters].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ConsumerMetrics {
+    pub polls: u64,
+    /// Polls returning no message.
+    pub empty_polls: u64,
+    pub meta_messages: u64,
+    pub data_messages: u64,
+    pub metadata_messages: u64,
+    /// Raw bytes received for messages.
+    pub bytes: u64,
+    pub commits: u64,
+    pub commit_failures: u64,
+    /// Latency of the last poll in microseconds.
+    pub last_poll_latency_us: u64,
+}
+
+/// Hooks to export consumer metrics, e.g. to prometheus, register one by `with_observer` of the
+/// consumer builders.
+///
+/// Callbacks are called inline in poll and commit, keep them cheap and non-blocking.
+pub trait ConsumerObserver: Send + Sync {
+    /// A poll of `latency` returned a message of `kind`, or no message.
+    fn on_poll(&self, _latency: Duration, _kind: Option<MessageKind>) {}
+
+    /// `bytes` raw bytes received for messages.
+    fn on_bytes(&self, _bytes: usize) {}
+
+    /// `offset` committed, it's the offset of the message for [AsConsumer::commit].
+    fn on_commit(&self, _offset: i64) {}
+
+    /// A poll or commit failed.
+    fn on_error(&self, _err: &RawError) {}
+}
+
+impl Debug for dyn ConsumerObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ConsumerObserver")
+    }
+}
+
+/// Counters maintained by a consumer, they are plain atomics and cost nothing until read.
+#[derive(Debug, Default)]
+pub struct ConsumerCounters {
+    polls: AtomicU64,
+    empty_polls: AtomicU64,
+    meta_messages: AtomicU64,
+    data_messages: AtomicU64,
+    metadata_messages: AtomicU64,
+    bytes: AtomicU64,
+    commits: AtomicU64,
+    commit_failures: AtomicU64,
+    last_poll_latency_us: AtomicU64,
+    observer: Option<Arc<dyn ConsumerObserver>>,
+}
+
+impl ConsumerCounters {
+    /// Counters calling back to `observer` on each record.
+    pub fn with_observer(observer: Option<Arc<dyn ConsumerObserver>>) -> Self {
+        Self {
+            observer,
+            ..Default::default()
+        }
+    }
+
+    /// Record a poll of `latency` returning a message of `kind`, or no message.
+    pub fn record_poll(&self, latency: Duration, kind: Option<MessageKind>) {
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        let counter = match kind {
+            None => &self.empty_polls,
+            Some(MessageKind::Meta) => &self.meta_messages,
+            Some(MessageKind::Data) => &self.data_messages,
+            Some(MessageKind::MetaData) => &self.metadata_messages,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.last_poll_latency_us.store(
+            latency.as_micros().try_into().unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+        if let Some(observer) = &self.observer {
+            observer.on_poll(latency, kind);
+        }
+    }
+
+    pub fn record_bytes(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        if let Some(observer) = &self.observer {
+            observer.on_bytes(bytes);
+        }
+    }
+
+    /// Record a commit of `offset`.
+    pub fn record_commit<T>(&self, offset: i64, res: &RawResult<T>) {
+        self.commits.fetch_add(1, Ordering::Relaxed);
+        match res {
+            Ok(_) => {
+                if let Some(observer) = &self.observer {
+                    observer.on_commit(offset);
+                }
+            }
+            Err(err) => {
+                self.commit_failures.fetch_add(1, Ordering::Relaxed);
+                self.record_error(err);
+            }
+        }
+    }
+
+    /// Record a poll or commit error, only the observer is called.
+    pub fn record_error(&self, err: &RawError) {
+        if let Some(observer) = &self.observer {
+            observer.on_error(err);
+        }
+    }
+
+    pub fn snapshot(&self) -> ConsumerMetrics {
+        ConsumerMetrics {
+            polls: self.polls.load(Ordering::Relaxed),
+            empty_polls: self.empty_polls.load(Ordering::Relaxed),
+            meta_messages: self.meta_messages.load(Ordering::Relaxed),
+            data_messages: self.data_messages.load(Ordering::Relaxed),
+            metadata_messages: self.metadata_messages.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            commits: self.commits.load(Ordering::Relaxed),
+            commit_failures: self.commit_failures.load(Ordering::Relaxed),
+            last_poll_latency_us: self.last_poll_latency_us.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all counters to zero.
+    pub fn reset(&self) {
+        for counter in [
+            &self.polls,
+            &self.empty_polls,
+            &self.meta_messages,
+            &self.data_messages,
+            &self.metadata_messages,
+            &self.bytes,
+            &self.commits,
+            &self.commit_failures,
+            &self.last_poll_latency_us,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Callback of `(topic, vgroup_id, offset, result)` after each commit, set by `set_commit_hook`
+/// of the consumers.
+pub type CommitHookFn = dyn Fn(&str, VGroupId, i64, &RawResult<()>) + Send + Sync;
+
+/// Commit hook of a consumer, shared with its background tasks like auto commit.
+///
+/// The hook is called outside any lock, so it may use the consumer, and its panics are caught
+/// and logged.
+#[derive(Clone, Default)]
+pub struct CommitHook(Arc<std::sync::RwLock<Option<Arc<CommitHookFn>>>>);
+
+impl Debug for CommitHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CommitHook")
+            .field(&self.get().map(|_| "Fn"))
+            .finish()
+    }
+}
+
+impl CommitHook {
+    /// Set the hook, replacing the previous one.
+    pub fn set(&self, hook: impl Fn(&str, VGroupId, i64, &RawResult<()>) + Send + Sync + 'static) {
+        *self.0.write().unwrap_or_else(|e| e.into_inner()) = Some(Arc::new(hook));
+    }
+
+    fn get(&self) -> Option<Arc<CommitHookFn>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Call the hook of a commit of `offset` on `topic` vgroup `vgroup_id`.
+    pub fn call(&self, topic: &str, vgroup_id: VGroupId, offset: i64, res: &RawResult<()>) {
+        let Some(hook) = self.get() else {
+            return;
+        };
+        let called = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            hook(topic, vgroup_id, offset, res)
+        }));
+        if let Err(panic) = called {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown");
+            log::error!(
+                "commit hook panicked on topic {topic} vgroup {vgroup_id} offset {offset}: {reason}"
+            );
+        }
+    }
+}
+
+/// Lag of a vgroup, see [AsAsyncConsumer::lag].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct VGroupLag {
+    pub vgroup_id: VGroupId,
+    /// Messages behind the end, never negative.
+    pub lag: i64,
+    pub begin: i64,
+    pub end: i64,
+    /// Current consume position.
+    pub current: i64,
+}
+
+/// Lag of a topic, see [AsAsyncConsumer::lag].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TopicLag {
+    pub topic: String,
+    pub vgroups: Vec<VGroupLag>,
+    /// Sum of all vgroup lags.
+    pub total: i64,
+}
+
+impl From<&Assignment> for VGroupLag {
+    fn from(a: &Assignment) -> Self {
+        Self {
+            vgroup_id: a.vgroup_id(),
+            lag: a.lag(),
+            begin: a.begin(),
+            end: a.end(),
+            current: a.current_offset(),
+        }
+    }
+}
+
+impl From<TopicAssignment> for TopicLag {
+    fn from(topic: TopicAssignment) -> Self {
+        let vgroups: Vec<VGroupLag> = topic.assignments.iter().map(VGroupLag::from).collect();
+        Self {
+            total: vgroups.iter().map(|v| v.lag).sum(),
+            vgroups,
+            topic: topic.topic,
+        }
+    }
+}
+
+/// Changes of the vgroups assigned to a consumer, when consumers join or leave the group.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct RebalanceEvent {
+    /// Topic vgroups newly assigned to the consumer.
+    pub assigned: Vec<(String, VGroupId)>,
+    /// Topic vgroups no longer assigned to the consumer.
+    pub revoked: Vec<(String, VGroupId)>,
+}
+
+impl RebalanceEvent {
+    /// Diff the assignments of each topic, as returned by [AsAsyncConsumer::assignments].
+    ///
+    /// Only vgroup ids are compared, offset changes are not rebalances.
+    pub fn between(old: &[(String, Vec<Assignment>)], new: &[(String, Vec<Assignment>)]) -> Self {
+        fn vgroups(assignments: &[(String, Vec<Assignment>)]) -> BTreeSet<(String, VGroupId)> {
+            assignments
+                .iter()
+                .flat_map(|(topic, a)| a.iter().map(move |a| (topic.clone(), a.vgroup_id())))
+                .collect()
+        }
+        let (old, new) = (vgroups(old), vgroups(new));
+        Self {
+            assigned: new.difference(&old).cloned().collect(),
+            revoked: old.difference(&new).cloned().collect(),
+        }
+    }
+
+    /// No vgroup is assigned or revoked.
+    pub fn is_empty(&self) -> bool {
+        self.assigned.is_empty() && self.revoked.is_empty()
+    }
+}
+
+pub trait AsConsumer: Sized {
+    type Offset: IsOffset;
+    type Meta: IsMeta;
+    type Data: IntoIterator<Item = RawResult<RawBlock>>;
+
+    /// Default timeout getter for message stream.
+    fn default_timeout(&self) -> Timeout {
+        Timeout::Never
+    }
+
+    /// Timeout override of the topic, see `set_topic_timeout` of the consumers.
+    fn topic_timeout(&self, _topic: &str) -> Option<Timeout> {
+        None
+    }
+
+    /// Timeout used by [recv](Self::recv) and [iter](Self::iter).
+    ///
+    /// A poll may return messages of any subscribed topic, so the topic override only applies
+    /// when the consumer knows which topic it polls, i.e. exactly one topic is subscribed.
+    /// Otherwise it falls back to [default_timeout](Self::default_timeout).
+    fn effective_timeout(&self) -> Timeout {
+        topic_timeout_of(self.subscription(), |topic| self.topic_timeout(topic))
+            .unwrap_or_else(|| self.default_timeout())
+    }
+
+    fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
+        &mut self,
+        topics: I,
+    ) -> RawResult<()>;
+
+    /// None means wait until next message come.
+    fn recv_timeout(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>;
+
+    fn recv(&self) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
+        self.recv_timeout(self.effective_timeout())
+    }
+
+    /// Like [recv_timeout](Self::recv_timeout), but tells whether all assigned vgroups are
+    /// consumed to the end when there's no message.
+    ///
+    /// The ends are fetched by [assignments](Self::assignments) after the poll, messages
+    /// written after that will still be delivered by later polls.
+    fn poll_result(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<PollResult<Self::Offset, Self::Meta, Self::Data>> {
+        let message = self.recv_timeout(timeout)?;
+        Ok(poll_result_of(message, || self.assignments()))
+    }
+
+    /// Receive at most `max` messages in one call.
+    ///
+    /// It waits no longer than `timeout` for the first message, then collects the messages
+    /// that are immediately available. An empty vector is returned if no message comes in time.
+    /// If an error occurs after some messages are received, these messages are returned and the
+    /// error is kept by the consumer and returned by the next batch receive.
+    fn recv_many(
+        &self,
+        max: usize,
+        timeout: Timeout,
+    ) -> RawResult<Vec<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
+        if let Some(err) = self.pending_error().and_then(PendingError::take) {
+            return Err(err);
+        }
+        let mut messages = Vec::new();
+        if max == 0 {
+            return Ok(messages);
+        }
+        match self.recv_timeout(timeout)? {
+            Some(message) => messages.push(message),
+            None => return Ok(messages),
+        }
+        while messages.len() < max {
+            match self.recv_timeout(Timeout::None) {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break,
+                Err(err) => {
+                    keep_pending_error(self.pending_error(), err);
+                    break;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    fn iter_data_only(
+        &self,
+        timeout: Timeout,
+    ) -> Box<dyn '_ + Iterator<Item = RawResult<(Self::Offset, Self::Data)>>> {
+        Box::new(
+            self.iter_with_timeout(timeout)
+                .filter_map_ok(|m| m.1.into_data().map(|data| (m.0, data))),
+        )
+    }
+
+    /// Iterate data messages like [iter_data_only](Self::iter_data_only), skipping errors with
+    /// a warning until `max_consecutive_errors` errors occur in a row: the last one is yielded and
+    /// the iterator ends. The count is reset by each received message.
+    fn iter_data_only_skipping(
+        &self,
+        timeout: Timeout,
+        max_consecutive_errors: usize,
+    ) -> Box<dyn '_ + Iterator<Item = RawResult<(Self::Offset, Self::Data)>>> {
+        let mut messages = self.iter_with_timeout(timeout);
+        let mut errors = 0;
+        let mut stopped = false;
+        Box::new(std::iter::from_fn(move || {
+            if stopped {
+                return None;
+            }
+            loop {
+                match messages.next()? {
+                    Ok((offset, message)) => {
+                        errors = 0;
+                        if let Some(data) = message.into_data() {
+                            return Some(Ok((offset, data)));
+                        }
+                    }
+                    Err(err) => {
+                        errors += 1;
+                        if errors >= max_consecutive_errors {
+                            stopped = true;
+                            return Some(Err(err));
+                        }
+                        log::warn!("skip error ({errors}/{max_consecutive_errors}): {err}");
+                    }
+                }
+            }
+        }))
+    }
+
+    /// Iterate data messages like [iter_data_only](Self::iter_data_only), committing each one
+    /// when the next item is requested.
+    ///
+    /// This gives at-least-once delivery: a message is committed only after it is processed,
+    /// that is when [next](Iterator::next) is called again, including the last call returning
+    /// `None`. If the process crashes or the iterator is dropped before that, the message is
+    /// delivered again to the consumer group. Messages without data are committed as they are
+    /// skipped, after the pending one. A failed commit is yielded as an error and the message
+    /// is left uncommitted, the iteration goes on.
+    fn iter_and_commit(
+        &self,
+        timeout: Timeout,
+    ) -> Box<dyn '_ + Iterator<Item = RawResult<Self::Data>>> {
+        let mut messages = self.iter_with_timeout(timeout);
+        let mut pending: Option<Self::Offset> = None;
+        Box::new(std::iter::from_fn(move || {
+            if let Some(offset) = pending.take() {
+                if let Err(err) = self.commit(offset) {
+                    return Some(Err(err));
+                }
+            }
+            loop {
+                match messages.next()? {
+                    Ok((offset, message)) => match message.into_data() {
+                        Some(data) => {
+                            pending = Some(offset);
+                            return Some(Ok(data));
+                        }
+                        None => {
+                            if let Err(err) = self.commit(offset) {
+                                return Some(Err(err));
+                            }
+                        }
+                    },
+                    Err(err) => return Some(Err(err)),
+                }
+            }
+        }))
+    }
+
+    fn iter_with_timeout(&self, timeout: Timeout) -> MessageSetsIter<'_, Self> {
+        self.iter_with_policy(timeout, OnEmpty::Stop)
+    }
+
+    /// Iterate messages with a policy on empty polls.
+    ///
+    /// With [OnEmpty::Stop], as [iter_with_timeout](Self::iter_with_timeout) does, the
+    /// iterator ends at the first poll without message, which is immediate for
+    /// [Timeout::None]. With [OnEmpty::Retry] it sleeps and polls again until the timeout
+    /// deadline, if any, passes.
+    fn iter_with_policy(&self, timeout: Timeout, on_empty: OnEmpty) -> MessageSetsIter<'_, Self> {
+        MessageSetsIter {
+            consumer: self,
+            timeout,
+            on_empty,
+            empty_polls: 0,
+        }
+    }
+
+    fn iter(&self) -> MessageSetsIter<'_, Self> {
+        self.iter_with_timeout(self.effective_timeout())
+    }
+
+    fn commit(&self, offset: Self::Offset) -> RawResult<()>;
+
+    /// Commit offsets with one commit for each topic vgroup.
+    ///
+    /// Only the highest offset of each topic vgroup is committed, as it covers the lower ones.
+    /// All of them are tried even if some failed, the error reports each failed topic vgroup.
+    fn commit_all<I: IntoIterator<Item = Self::Offset>>(&self, offsets: I) -> RawResult<()> {
+        let mut errors = Vec::new();
+        for offset in highest_offsets(offsets) {
+            let key = (offset.topic().to_string(), offset.vgroup_id());
+            if let Err(err) = self.commit(offset) {
+                errors.push((key, err));
+            }
+        }
+        commit_errors(errors)
+    }
+
+    fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()>;
+
+    /// Unsubscribe and close the consumer.
+    ///
+    /// Dropping a consumer does the same in best effort, use this to get the error if any.
+    fn unsubscribe(self) -> RawResult<()> {
+        drop(self);
+        Ok(())
+    }
+
+    fn list_topics(&self) -> RawResult<Vec<String>>;
+
+    /// Topics the consumer is currently subscribed to.
+    ///
+    /// Updated by a successful [subscribe](Self::subscribe), consumers that do not keep track of
+    /// their subscription return an empty list.
+    fn subscription(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// Names of all topics, which [subscribe_pattern](Self::subscribe_pattern) matches against.
+    ///
+    /// Not supported by default.
+    fn topic_names(&self) -> RawResult<Vec<String>> {
+        Err(TopicPattern::unsupported())
+    }
+
+    /// State of [subscribe_pattern](Self::subscribe_pattern), `None` if the consumer does not
+    /// support it.
+    #[doc(hidden)]
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        None
+    }
+
+    /// Replace the subscription with the topics matching the topic pattern, keeping the pattern.
+    ///
+    /// Unlike [subscribe](Self::subscribe) it takes `&self`, so that polls can pick up topics
+    /// created later. Not supported by default.
+    #[doc(hidden)]
+    fn subscribe_matched(&self, _topics: Vec<String>) -> RawResult<()> {
+        Err(TopicPattern::unsupported())
+    }
+
+    /// Subscribe to all topics whose name matches the regular expression `pattern`.
+    ///
+    /// The pattern is of the [regex](https://docs.rs/regex) crate syntax and matches anywhere in
+    /// the name unless anchored, e.g. `^orders_` for topics starting with `orders_`. Matching is
+    /// done on the client side, and again on polls every 30 seconds to pick up topics
+    /// created later. A later [subscribe](Self::subscribe) drops the pattern.
+    fn subscribe_pattern(&mut self, pattern: &str) -> RawResult<()> {
+        let state = self.topic_pattern().ok_or_else(TopicPattern::unsupported)?;
+        state.set(pattern)?;
+        if let Err(err) = self.refresh_subscription() {
+            state.clear();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Match the topic pattern now and return the subscribed topics.
+    ///
+    /// The consumer is only re-subscribed when the matching topics changed.
+    fn refresh_subscription(&self) -> RawResult<Vec<String>> {
+        let state = self.topic_pattern().ok_or_else(TopicPattern::unsupported)?;
+        let pattern = state.start_refresh()?;
+        let current = self.subscription();
+        match matching_topics(self.topic_names()?, &current, |t| pattern.is_match(t)) {
+            Some(matched) => {
+                self.subscribe_matched(matched.clone())?;
+                Ok(matched)
+            }
+            None => {
+                log::trace!("topics matching {pattern} are unchanged: {current:?}");
+                Ok(current)
+            }
+        }
+    }
+
+    /// Match the topic pattern if it is due, called by consumers on polls.
+    ///
+    /// Failures are logged and retried after [TopicPattern::REFRESH_INTERVAL].
+    #[doc(hidden)]
+    fn refresh_subscription_if_due(&self) {
+        if self.topic_pattern().map_or(false, TopicPattern::is_due) {
+            if let Err(err) = self.refresh_subscription() {
+                log::warn!("refresh topics of the topic pattern failed: {err}");
+            }
+        }
+    }
+
+    /// Error kept by the batch receives for the next call, `None` if the consumer does not keep
+    /// it and the error is logged instead.
+    #[doc(hidden)]
+    fn pending_error(&self) -> Option<&PendingError> {
+        None
+    }
+
+    fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>>;
+
+    /// Assignments of the topic.
+    ///
+    /// Empty if the topic has no vgroup assigned to this consumer, an error if the topic is
+    /// not subscribed or the assignments are not available, e.g. the connection is lost.
+    fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        topic_assignments_of(self.assignments(), topic)
+    }
+
+    /// Assignments of all subscribed topics, with topic names.
+    fn topic_assignments(&self) -> Vec<TopicAssignment> {
+        self.assignments()
+            .into_iter()
+            .flatten()
+            .map(TopicAssignment::from)
+            .collect()
+    }
+
+    /// Lag of each subscribed topic, see [AsAsyncConsumer::lag].
+    fn lag(&self) -> RawResult<Vec<TopicLag>> {
+        topic_lags(self.assignments())
+    }
+
+    /// Seek the vgroup of the topic to `offset`, the next poll of the vgroup starts from it.
+    ///
+    /// It takes `&self`, so a consumer can seek while an [iter](Self::iter) of it is alive.
+    fn offset_seek(&self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()>;
+
+    /// Seek all assigned vgroups of the topic to the earliest offset.
+    ///
+    /// Vgroups with empty ranges are skipped.
+    fn seek_to_beginning(&self, topic: &str) -> RawResult<()> {
+        let assignments = topic_assignments_of(self.assignments(), topic)?;
+        let mut errors = Vec::new();
+        for a in assignments.iter().filter(|a| !is_empty_range(a)) {
+            if let Err(err) = self.offset_seek(topic, a.vgroup_id(), a.begin()) {
+                errors.push((format!("vgroup {}", a.vgroup_id()), err));
+            }
+        }
+        seek_errors(Some(topic), errors)
+    }
+
+    /// Seek all assigned vgroups of the topic to the latest offset.
+    ///
+    /// Vgroups with empty ranges are skipped. It fails without seeking any vgroup if the end
+    /// offset of some vgroup is unknown (`-1`).
+    fn seek_to_end(&self, topic: &str) -> RawResult<()> {
+        let assignments = topic_assignments_of(self.assignments(), topic)?;
+        check_known_end(topic, &assignments)?;
+        let mut errors = Vec::new();
+        for a in assignments.iter().filter(|a| !is_empty_range(a)) {
+            if let Err(err) = self.offset_seek(topic, a.vgroup_id(), a.end()) {
+                errors.push((format!("vgroup {}", a.vgroup_id()), err));
+            }
+        }
+        seek_errors(Some(topic), errors)
+    }
+
+    /// Check if the consumer can go on after [resubscribe](Self::resubscribe) on the error,
+    /// e.g. a lost connection. Defaults to [RawError::is_recoverable] by the error code.
+    fn is_recoverable(&self, err: &RawError) -> bool {
+        err.is_recoverable()
+    }
+
+    /// Subscribe to the current subscription again after a recoverable error, the consume
+    /// position of each vgroup may fall back to the committed offset.
+    ///
+    /// Not supported by default.
+    fn resubscribe(&self) -> RawResult<()> {
+        Err(RawError::from_string(
+            "resubscribe is not supported by the consumer",
+        ))
+    }
+
+    /// Stop receiving messages of the topic vgroup without unsubscribing, until
+    /// [resume](Self::resume), e.g. when the downstream of the vgroup is saturated.
+    ///
+    /// Not supported by default.
+    fn pause(&self, _topic: &str, _vgroup_id: VGroupId) -> RawResult<()> {
+        Err(RawError::from_string(
+            "pause is not supported by the consumer",
+        ))
+    }
+
+    /// Receive messages of a vgroup paused by [pause](Self::pause) again.
+    fn resume(&self, _topic: &str, _vgroup_id: VGroupId) -> RawResult<()> {
+        Err(RawError::from_string(
+            "resume is not supported by the consumer",
+        ))
+    }
+
+    /// Last committed offset of the topic vgroup, returns error if the topic is not subscribed.
+    fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
+
+    /// Current consume position of the topic vgroup, that is the offset of the next message.
+    ///
+    /// Unlike [committed](Self::committed) which is persisted by the server for the consumer
+    /// group, position is kept in memory and advances on every received message, whether
+    /// committed or not.
+    fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
+}
+
+fn topic_timeout_of(
+    subscription: Vec<String>,
+    topic_timeout: impl FnOnce(&str) -> Option<Timeout>,
+) -> Option<Timeout> {
+    match subscription.as_slice() {
+        [topic] => topic_timeout(topic),
+        _ => None,
+    }
+}
+
+fn topic_assignments_of(
+    assignments: Option<Vec<(String, Vec<Assignment>)>>,
+    topic: &str,
+) -> RawResult<Vec<Assignment>> {
+    let assignments =
+        assignments.ok_or_else(|| RawError::from_string("assignments are not available"))?;
+    assignments
+        .into_iter()
+        .find(|(t, _)| t == topic)
+        .map(|(_, assignments)| assignments)
+        .ok_or_else(|| RawError::from_string(format!("topic {topic} is not assigned")))
+}
+
+fn topic_lags(assignments: Option<Vec<(String, Vec<Assignment>)>>) -> RawResult<Vec<TopicLag>> {
+    let assignments =
+        assignments.ok_or_else(|| RawError::from_string("assignments are not available"))?;
+    Ok(assignments
+        .into_iter()
+        .map(|topic| TopicLag::from(TopicAssignment::from(topic)))
+        .collect())
+}
+
+/// An assignment with known end and no message to seek, `-1` means the end is unknown.
+fn is_empty_range(a: &Assignment) -> bool {
+    a.end() != -1 && a.begin() >= a.end()
+}
+
+fn check_known_end(topic: &str, assignments: &[Assignment]) -> RawResult<()> {
+    match assignments.iter().find(|a| a.end() == -1) {
+        Some(a) => Err(RawError::from_string(format!(
+            "cannot seek topic {topic} to end: end offset of vgroup {} is unknown",
+            a.vgroup_id()
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Keep the highest offset of each topic vgroup in the order of first appearance, later ones
+/// win for equal (or unknown) offsets.
+fn highest_offsets<O: IsOffset>(offsets: impl IntoIterator<Item = O>) -> Vec<O> {
+    let mut highest: Vec<O> = Vec::new();
+    for offset in offsets {
+        let same = highest
+            .iter_mut()
+            .find(|o| o.vgroup_id() == offset.vgroup_id() && o.topic() == offset.topic());
+        match same {
+            Some(o) if o.offset() <= offset.offset() => *o = offset,
+            Some(_) => (),
+            None => highest.push(offset),
+        }
+    }
+    highest
+}
+
+/// Aggregate per topic vgroup commit errors into one error.
+fn commit_errors(errors: Vec<((String, VGroupId), RawError)>) -> RawResult<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let details = errors
+        .iter()
+        .map(|((topic, vgroup_id), err)| format!("topic {topic} vgroup {vgroup_id}: {err}"))
+        .join(", ");
+    Err(RawError::from_string(format!(
+        "commit failed for {} topic vgroup(s): {details}",
+        errors.len()
+    )))
+}
+
+/// Aggregate per-vgroup seek errors into one error, `topic` is the topic of all seeks if any.
+fn seek_errors(topic: Option<&str>, errors: Vec<(String, RawError)>) -> RawResult<()> {
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let details = errors
+        .iter()
+        .map(|(vgroup, err)| format!("{vgroup}: {err}"))
+        .join(", ");
+    let topic = topic
+        .map(|topic| format!(" topic {topic}"))
+        .unwrap_or_default();
+    Err(RawError::from_string(format!(
+        "seek{topic} failed for {} vgroup(s): {details}",
+        errors.len()
+    )))
+}
+
+/// What a message iterator does when a poll gets no message.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OnEmpty {
+    /// End the iteration.
+    #[default]
+    Stop,
+    /// Sleep for the backoff delay and poll again.
+    Retry { backoff: Backoff },
+}
+
+/// Delay between polls after consecutive empty polls, see [OnEmpty::Retry].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    exponential: bool,
+}
+
+impl Backoff {
+    /// Always sleep for `delay`.
+    pub fn fixed(delay: Duration) -> Self {
+        Self {
+            initial: delay,
+            max: delay,
+            exponential: false,
+        }
+    }
+
+    /// Start with `initial` and double the delay on each empty poll, capped at `max`.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial: initial.min(max),
+            max,
+            exponential: true,
+        }
+    }
+
+    /// Delay after `empty_polls` consecutive empty polls, starting from 1.
+    pub fn delay(&self, empty_polls: u32) -> Duration {
+        if !self.exponential || empty_polls <= 1 {
+            return self.initial;
+        }
+        let factor = 1u32.checked_shl(empty_polls - 1).unwrap_or(u32::MAX);
+        self.initial
+            .checked_mul(factor)
+            .map_or(self.max, |delay| delay.min(self.max))
+    }
+}
+
+/// How [AsAsyncConsumer::stream_resilient] retries on recoverable errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Retries after the last received message, before giving up.
+    pub max_retries: u32,
+    /// Delay before each retry.
+    pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32, backoff: Backoff) -> Self {
+        Self {
+            max_retries,
+            backoff,
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    /// Up to 5 retries, backing off from 100ms to 10s.
+    fn default() -> Self {
+        Self::new(
+            5,
+            Backoff::exponential(Duration::from_millis(100), Duration::from_secs(10)),
+        )
+    }
+}
+
+/// Poll errors in a row to end a stream of [ErrorPolicy::Route].
+pub const MAX_ROUTED_POLL_ERRORS: usize = 16;
+
+/// What [AsAsyncConsumer::stream_with_policy] does when polling or decoding a message fails.
+#[derive(Default)]
+pub enum ErrorPolicy<O> {
+    /// Yield the error and go on, as [stream_with_timeout](AsAsyncConsumer::stream_with_timeout).
+    #[default]
+    Fail,
+    /// Log the error and go on, until `max_consecutive` errors occur in a row: the last one is
+    /// yielded and the stream ends. The count is reset by each received message.
+    Skip { max_consecutive: usize },
+    /// Hand the error to the callback, e.g. a dead-letter queue, and go on. The offset is the
+    /// one of the message failed to decode, poll errors have none.
+    ///
+    /// A poll error which is not [recoverable](AsAsyncConsumer::is_recoverable), or the
+    /// [MAX_ROUTED_POLL_ERRORS]th in a row, is yielded instead and the stream ends.
+    Route(Box<dyn Fn(RawError, Option<O>) + Send + Sync>),
+}
+
+impl<O> ErrorPolicy<O> {
+    /// Route errors to `f`, see [ErrorPolicy::Route].
+    pub fn route(f: impl Fn(RawError, Option<O>) + Send + Sync + 'static) -> Self {
+        Self::Route(Box::new(f))
+    }
+}
+
+impl<O> Debug for ErrorPolicy<O> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Fail => f.write_str("Fail"),
+            Self::Skip { max_consecutive } => f
+                .debug_struct("Skip")
+                .field("max_consecutive", max_consecutive)
+                .finish(),
+            Self::Route(_) => f.write_str("Route(..)"),
+        }
+    }
+}
+
+/// Fetch the blocks of the data of `message`, for [AsAsyncConsumer::stream_with_policy].
+async fn fetch_blocks<M, D: IsAsyncData + Sync>(
+    message: MessageSet<M, D>,
+) -> RawResult<MessageSet<M, Vec<RawBlock>>> {
+    Ok(match message {
+        MessageSet::Meta(meta) => MessageSet::Meta(meta),
+        MessageSet::Data(data) => MessageSet::Data(data.fetch_all_blocks().await?),
+        MessageSet::MetaData(meta, data) => {
+            MessageSet::MetaData(meta, data.fetch_all_blocks().await?)
+        }
+    })
+}
+
+/// Offsets of the last yielded message of each topic vgroup.
+type Positions = BTreeMap<(String, VGroupId), i64>;
+
+/// Resubscribe and seek back to `positions` after the recoverable error `err`.
+async fn recover<C: AsAsyncConsumer>(
+    consumer: &C,
+    positions: &Positions,
+    policy: &RetryPolicy,
+    retries: &mut u32,
+    mut err: RawError,
+) -> RawResult<()> {
+    loop {
+        if *retries >= policy.max_retries {
+            return Err(err);
+        }
+        *retries += 1;
+        log::warn!(
+            "recover from error ({}/{}): {err}",
+            *retries,
+            policy.max_retries
+        );
+        tokio::time::sleep(policy.backoff.delay(*retries)).await;
+        let res = async {
+            consumer.resubscribe().await?;
+            for ((topic, vgroup_id), offset) in positions {
+                consumer.offset_seek(topic, *vgroup_id, offset + 1).await?;
+            }
+            Ok(())
+        }
+        .await;
+        match res {
+            Ok(()) => return Ok(()),
+            Err(e) if consumer.is_recoverable(&e) => err = e,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub struct MessageSetsIter<'a, C> {
+    consumer: &'a C,
+    timeout: Timeout,
+    on_empty: OnEmpty,
+    empty_polls: u32,
+}
+
+impl<'a, C> Iterator for MessageSetsIter<'a, C>
+where
+    C: AsConsumer,
+{
+    type Item = RawResult<(C::Offset, MessageSet<C::Meta, C::Data>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.timeout.is_expired() {
+                return None;
+            }
+            let backoff = match self.consumer.recv_timeout(self.timeout) {
+                Ok(None) => match self.on_empty {
+                    OnEmpty::Stop => return None,
+                    OnEmpty::Retry { backoff } => backoff,
+                },
+                res => {
+                    self.empty_polls = 0;
+                    return res.transpose();
+                }
+            };
+            self.empty_polls = self.empty_polls.saturating_add(1);
+            let mut delay = backoff.delay(self.empty_polls);
+            if let Timeout::Deadline(_) = self.timeout {
+                delay = delay.min(self.timeout.as_duration());
+            }
+            std::thread::sleep(delay);
+        }
+    }
+}
+
+impl<'a, C> MessageSetsIter<'a, C> {
+    /// Yield messages until the first error, which is kept for [StopOnError::into_error].
+    ///
+    /// ```rust,no_run
+    /// use taos_query::prelude::sync::*;
+    ///
+    /// fn drain(consumer: &impl AsConsumer) -> RawResult<()> {
+    ///     let mut messages = consumer.iter().stop_on_error();
+    ///     for (offset, _message) in &mut messages {
+    ///         consumer.commit(offset)?;
+    ///     }
+    ///     messages.into_error().map_or(Ok(()), Err)
+    /// }
+    /// ```
+    pub fn stop_on_error(self) -> StopOnError<'a, C> {
+        StopOnError {
+            inner: self,
+            error: None,
+        }
+    }
+}
+
+/// Iterator of messages that stops at the first error, see [MessageSetsIter::stop_on_error].
+pub struct StopOnError<'a, C> {
+    inner: MessageSetsIter<'a, C>,
+    error: Option<RawError>,
+}
+
+impl<'a, C> StopOnError<'a, C> {
+    /// The error which stopped the iterator.
+    pub fn error(&self) -> Option<&RawError> {
+        self.error.as_ref()
+    }
+
+    /// Take the error which stopped the iterator, `None` if no error occurred.
+    pub fn into_error(self) -> Option<RawError> {
+        self.error
+    }
+}
+
+impl<'a, C> Iterator for StopOnError<'a, C>
+where
+    C: AsConsumer,
+{
+    type Item = (C::Offset, MessageSet<C::Meta, C::Data>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.inner.next()? {
+            Ok(message) => Some(message),
+            Err(err) => {
+                self.error = Some(err);
+                None
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+pub trait AsAsyncConsumer: Sized + Send + Sync {
+    type Offset: IsOffset;
+    type Meta: IsAsyncMeta;
+    type Data: IsAsyncData;
+
+    fn default_timeout(&self) -> Timeout;
+
+    /// Timeout override of the topic, see `set_topic_timeout` of the consumers.
+    fn topic_timeout(&self, _topic: &str) -> Option<Timeout> {
+        None
+    }
+
+    /// Timeout used by [stream](Self::stream).
+    ///
+    /// A poll may return messages of any subscribed topic, so the topic override only applies
+    /// when the consumer knows which topic it polls, i.e. exactly one topic is subscribed.
+    /// Otherwise it falls back to [default_timeout](Self::default_timeout).
+    fn effective_timeout(&self) -> Timeout {
+        topic_timeout_of(self.subscription(), |topic| self.topic_timeout(topic))
+            .unwrap_or_else(|| self.default_timeout())
+    }
+
+    async fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
+        &mut self,
+        topics: I,
+    ) -> RawResult<()>;
+
+    /// Subscribe topics and seek the given vgroups to their `current_offset`, all done before
+    /// any message is received.
+    ///
+    /// Vgroups that are no longer assigned to this consumer, e.g. after the topic is recreated
+    /// or rebalanced, are skipped with a warning. It stops at the first seek error.
+    async fn subscribe_with_offsets<T, I>(&mut self, topics: I) -> RawResult<()>
+    where
+        T: Into<String>,
+        I: IntoIterator<Item = (T, Vec<Assignment>)> + Send,
+    {
+        let topics: Vec<(String, Vec<Assignment>)> = topics
+            .into_iter()
+            .map(|(topic, offsets)| (topic.into(), offsets))
+            .collect();
+        let names: Vec<String> = topics.iter().map(|(topic, _)| topic.clone()).collect();
+        self.subscribe(names).await?;
+
+        for (topic, offsets) in &topics {
+            let assigned = self.topic_assignment(topic).await?;
+            for offset in offsets {
+                let vgroup_id = offset.vgroup_id();
+                if !assigned.iter().any(|a| a.vgroup_id() == vgroup_id) {
+                    log::warn!("vgroup {vgroup_id} of topic {topic} is not assigned, skip seeking");
+                    continue;
+                }
+                self.offset_seek(topic, vgroup_id, offset.current_offset())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Subscribe topics, and start the vgroups without a committed offset as `reset` tells,
+    /// whatever `auto.offset.reset` the consumer is built with.
+    ///
+    /// A committed offset is kept, so only a brand-new group is affected. With
+    /// [OffsetReset::None], it fails if any assigned vgroup has no committed offset, instead of
+    /// erroring at the first poll.
+    async fn subscribe_with_reset<T, I>(&mut self, topics: I, reset: OffsetReset) -> RawResult<()>
+    where
+        T: Into<String>,
+        I: IntoIterator<Item = T> + Send,
+    {
+        self.subscribe(topics).await?;
+
+        let mut missing = Vec::new();
+        for topic in self.subscription() {
+            for assignment in self.topic_assignment(&topic).await? {
+                let vgroup_id = assignment.vgroup_id();
+                // The server reports a negative offset when nothing is committed.
+                if self.committed(&topic, vgroup_id).await? >= 0 {
+                    continue;
+                }
+                match reset.start_offset(&assignment) {
+                    Some(offset) => self.offset_seek(&topic, vgroup_id, offset).await?,
+                    None => missing.push(format!("{topic}:{vgroup_id}")),
+                }
+            }
+        }
+        if !missing.is_empty() {
+            return Err(RawError::from_string(format!(
+                "no committed offset for vgroups [{}] and auto.offset.reset is none",
+                missing.join(", ")
+            )));
+        }
+        Ok(())
+    }
+
+    /// None means wait until next message come.
+    async fn recv_timeout(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>;
+
+    /// Like [recv_timeout](Self::recv_timeout), but tells whether all assigned vgroups are
+    /// consumed to the end when there's no message.
+    ///
+    /// The ends are fetched by [assignments](Self::assignments) after the poll, messages
+    /// written after that will still be delivered by later polls.
+    async fn poll_result(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<PollResult<Self::Offset, Self::Meta, Self::Data>> {
+        if let Some((offset, message)) = self.recv_timeout(timeout).await? {
+            return Ok(PollResult::Message(offset, message));
+        }
+        let assignments = self.assignments().await;
+        Ok(poll_result_of(None, || assignments))
+    }
+
+    /// Receive at most `max` messages in one call.
+    ///
+    /// It waits no longer than `timeout` for the first message, then collects the messages
+    /// that are immediately available. An empty vector is returned if no message comes in time.
+    /// If an error occurs after some messages are received, these messages are returned and the
+    /// error is kept by the consumer and returned by the next batch receive.
+    async fn recv_many(
+        &self,
+        max: usize,
+        timeout: Timeout,
+    ) -> RawResult<Vec<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send,
+    {
+        if let Some(err) = self.pending_error().and_then(PendingError::take) {
+            return Err(err);
+        }
+        let mut messages = Vec::new();
+        if max == 0 {
+            return Ok(messages);
+        }
+        match self.recv_timeout(timeout).await? {
+            Some(message) => messages.push(message),
+            None => return Ok(messages),
+        }
+        while messages.len() < max {
+            match self.recv_timeout(Timeout::None).await {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break,
+                Err(err) => {
+                    keep_pending_error(self.pending_error(), err);
+                    break;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Receive at most `max` messages by polling one by one, all polls share the `timeout`.
+    ///
+    /// Unlike [recv_many](Self::recv_many) which only waits for the first message, it returns
+    /// early when a poll gets no message or the timeout is exceeded. No poll is made when `max`
+    /// is zero. Errors after some messages are received are kept for the next batch receive as
+    /// in [recv_many](Self::recv_many).
+    async fn recv_batch(
+        &self,
+        max: usize,
+        timeout: Timeout,
+    ) -> RawResult<Vec<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send,
+    {
+        let timeout = match timeout {
+            Timeout::Duration(duration) => Instant::now()
+                .checked_add(duration)
+                .map_or(Timeout::Never, Timeout::Deadline),
+            timeout => timeout,
+        };
+        if let Some(err) = self.pending_error().and_then(PendingError::take) {
+            return Err(err);
+        }
+        let mut messages = Vec::new();
+        while messages.len() < max {
+            if !messages.is_empty() && timeout.is_expired() {
+                break;
+            }
+            match self.recv_timeout(timeout).await {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) => break,
+                Err(err) if messages.is_empty() => return Err(err),
+                Err(err) => {
+                    keep_pending_error(self.pending_error(), err);
+                    break;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Collect `n` messages, or as many as received before the `deadline`.
+    ///
+    /// Unlike [recv_batch](Self::recv_batch), an empty poll does not end the collection, it
+    /// keeps polling until `n` messages are received or the deadline shared by all polls
+    /// passes. A [Timeout::None] deadline stops at the first empty poll. Errors after some
+    /// messages are received are kept for the next batch receive as in
+    /// [recv_many](Self::recv_many).
+    async fn collect_n(
+        &self,
+        n: usize,
+        deadline: Timeout,
+    ) -> RawResult<Vec<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send,
+    {
+        let deadline = match deadline {
+            Timeout::Duration(duration) => Instant::now()
+                .checked_add(duration)
+                .map_or(Timeout::Never, Timeout::Deadline),
+            deadline => deadline,
+        };
+        if let Some(err) = self.pending_error().and_then(PendingError::take) {
+            return Err(err);
+        }
+        let mut messages = Vec::new();
+        while messages.len() < n && !deadline.is_expired() {
+            match self.recv_timeout(deadline).await {
+                Ok(Some(message)) => messages.push(message),
+                Ok(None) if deadline == Timeout::None => break,
+                Ok(None) => continue,
+                Err(err) if messages.is_empty() => return Err(err),
+                Err(err) => {
+                    keep_pending_error(self.pending_error(), err);
+                    break;
+                }
+            }
+        }
+        Ok(messages)
+    }
+
+    fn stream_with_timeout(
+        &self,
+        timeout: Timeout,
+    ) -> Pin<
+        Box<
+            dyn '_
+                + Send
+                + futures::Stream<
+                    Item = RawResult<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>,
+                >,
+        >,
+    > {
+        Box::pin(futures::stream::unfold((), move |_| async move {
+            if timeout.is_expired() {
+                return None;
+            }
+            let weather = self.recv_timeout(timeout).await.transpose();
+            weather.map(|res| (res, ()))
+        }))
+    }
+
+    fn stream(
+        &self,
+    ) -> Pin<
+        Box<
+            dyn '_
+                + Send
+                + futures::Stream<
+                    Item = RawResult<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>,
+                >,
+        >,
+    > {
+        self.stream_with_timeout(self.effective_timeout())
+    }
+
+    /// Stream data messages only, the pure meta messages are skipped and the data of
+    /// meta-data messages are kept.
+    fn stream_data_only(
+        &self,
+        timeout: Timeout,
+    ) -> Pin<Box<dyn '_ + Send + futures::Stream<Item = RawResult<(Self::Offset, Self::Data)>>>>
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send,
+    {
+        use futures::TryStreamExt;
+        Box::pin(
+            self.stream_with_timeout(timeout)
+                .try_filter_map(|(offset, message)| async move {
+                    Ok(message.into_data().map(|data| (offset, data)))
+                }),
+        )
+    }
+
+    /// Stream messages like [stream_with_timeout](Self::stream_with_timeout), recovering from
+    /// [recoverable](Self::is_recoverable) errors instead of yielding them.
+    ///
+    /// On a recoverable error, it sleeps for the policy backoff, [resubscribes](Self::resubscribe)
+    /// and seeks each vgroup next to the last yielded message, then goes on. The retries are
+    /// counted from the last yielded message, once `max_retries` are exhausted the last error is
+    /// yielded and the stream ends. Other errors are yielded as is.
+    fn stream_resilient(
+        &self,
+        timeout: Timeout,
+        policy: RetryPolicy,
+    ) -> Pin<
+        Box<
+            dyn '_
+                + Send
+                + futures::Stream<
+                    Item = RawResult<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>,
+                >,
+        >,
+    >
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send,
+    {
+        Box::pin(futures::stream::unfold(
+            Some(Positions::new()),
+            move |positions| async move {
+                let mut positions = positions?;
+                let mut retries = 0;
+                loop {
+                    if timeout.is_expired() {
+                        return None;
+                    }
+                    let err = match self.recv_timeout(timeout).await {
+                        Ok(Some((offset, message))) => {
+                            if offset.offset() >= 0 {
+                                let key = (offset.topic().to_string(), offset.vgroup_id());
+                                positions.insert(key, offset.offset());
+                            }
+                            return Some((Ok((offset, message)), Some(positions)));
+                        }
+                        Ok(None) => return None,
+                        Err(err) if self.is_recoverable(&err) => err,
+                        Err(err) => return Some((Err(err), Some(positions))),
+                    };
+                    if let Err(err) = recover(self, &positions, &policy, &mut retries, err).await {
+                        return Some((Err(err), None));
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Stream messages like [stream_with_timeout](Self::stream_with_timeout), handling errors by
+    /// the `policy` instead of yielding each of them, see [ErrorPolicy].
+    ///
+    /// Blocks of data messages are fetched before yielded, so that a message failed to decode is
+    /// handled by the policy with its offset.
+    fn stream_with_policy(
+        &self,
+        timeout: Timeout,
+        policy: ErrorPolicy<Self::Offset>,
+    ) -> Pin<
+        Box<
+            dyn '_
+                + Send
+                + futures::Stream<
+                    Item = RawResult<(Self::Offset, MessageSet<Self::Meta, Vec<RawBlock>>)>,
+                >,
+        >,
+    >
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send + Sync,
+    {
+        Box::pin(futures::stream::unfold(
+            Some((policy, 0)),
+            move |state| async move {
+                let (policy, mut errors) = state?;
+                loop {
+                    if timeout.is_expired() {
+                        return None;
+                    }
+                    let (err, offset) = match self.recv_timeout(timeout).await {
+                        Ok(Some((offset, message))) => match fetch_blocks(message).await {
+                            Ok(message) => return Some((Ok((offset, message)), Some((policy, 0)))),
+                            Err(err) => (err, Some(offset)),
+                        },
+                        Ok(None) => return None,
+                        Err(err) => (err, None),
+                    };
+                    match &policy {
+                        ErrorPolicy::Fail => return Some((Err(err), Some((policy, errors)))),
+                        ErrorPolicy::Skip { max_consecutive } => {
+                            errors += 1;
+                            if errors >= *max_consecutive {
+                                return Some((Err(err), None));
+                            }
+                            log::warn!("skip error ({errors}/{max_consecutive}): {err}");
+                        }
+                        ErrorPolicy::Route(route) => {
+                            // Only poll errors count, a message failed to decode is consumed.
+                            errors = if offset.is_some() { 0 } else { errors + 1 };
+                            let fatal = offset.is_none() && !self.is_recoverable(&err);
+                            if fatal || errors >= MAX_ROUTED_POLL_ERRORS {
+                                return Some((Err(err), None));
+                            }
+                            route(err, offset);
+                        }
+                    }
+                }
+            },
+        ))
+    }
+
+    /// Stream messages keyed by the vgroup id of their offsets, to route them to per-vgroup
+    /// workers.
+    ///
+    /// Messages of different vgroups interleave in the order they are received, the order in
+    /// each vgroup is kept. The offset is yielded too, for the worker to commit.
+    fn stream_by_vgroup(
+        &self,
+        timeout: Timeout,
+    ) -> Pin<
+        Box<
+            dyn '_
+                + Send
+                + futures::Stream<
+                    Item = RawResult<(VGroupId, Self::Offset, MessageSet<Self::Meta, Self::Data>)>,
+                >,
+        >,
+    > {
+        use futures::TryStreamExt;
+        Box::pin(
+            self.stream_with_timeout(timeout)
+                .map_ok(|(offset, message)| (offset.vgroup_id(), offset, message)),
+        )
+    }
+
+    /// Stream messages that should be committed after processed, for at-least-once consuming.
+    ///
+    /// Each item is a [Committable], call [Committable::commit] when the message is processed.
+    /// Ordering guarantees:
+    ///
+    /// 1. Messages are yielded in the order they are received, the stream never waits for the
+    ///    commit of previous items.
+    /// 2. Committing an item commits its vgroup offset, so all messages received before it in
+    ///    the same vgroup are committed too. Commit items in the order they are yielded.
+    /// 3. An item dropped without commit only logs a warning, the message will be delivered
+    ///    again after the consumer restarts unless a later message of the same vgroup is
+    ///    committed.
+    fn stream_autocommit(
+        &self,
+        timeout: Timeout,
+    ) -> Pin<Box<dyn '_ + Send + futures::Stream<Item = RawResult<Committable<'_, Self>>>>>
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send,
+    {
+        use futures::TryStreamExt;
+        Box::pin(
+            self.stream_with_timeout(timeout)
+                .map_ok(move |(offset, message)| Committable {
+                    consumer: self,
+                    offset: Some(offset),
+                    message,
+                }),
+        )
+    }
+
+    /// Stream messages polled by a background task, so that the next message is in flight
+    /// while the current one is processed.
+    ///
+    /// No more than `capacity` (at least 1) messages are polled ahead of the stream, offsets are
+    /// yielded with the messages so they can be committed through the shared consumer. The
+    /// stream ends like [stream_with_timeout](Self::stream_with_timeout), and dropping it stops
+    /// the background task. It must be called in a tokio runtime.
+    fn stream_prefetch(self: Arc<Self>, capacity: usize, timeout: Timeout) -> PrefetchStream<Self>
+    where
+        Self: 'static,
+        Self::Offset: Send + 'static,
+        Self::Meta: Send + 'static,
+        Self::Data: Send + 'static,
+    {
+        let (sender, receiver) = tokio::sync::mpsc::channel(capacity.max(1));
+        let poller = tokio::spawn(async move {
+            // Reserve the slot before polling, so buffered and in-flight messages are bounded.
+            while let Ok(permit) = sender.reserve().await {
+                if timeout.is_expired() {
+                    break;
+                }
+                match self.recv_timeout(timeout).await.transpose() {
+                    Some(message) => permit.send(message),
+                    None => break,
+                }
+            }
+        });
+        PrefetchStream { receiver, poller }
+    }
+
+    /// Stream messages with the consumer moved into the stream, so that the stream is `'static`
+    /// and can be moved into a task, e.g. polled in `tokio::select!` of an actor.
+    ///
+    /// Split [committers](OwnedStream::committer) off the stream to commit offsets from
+    /// elsewhere, they fail once the stream and so the consumer are dropped. The stream ends
+    /// like [stream_with_timeout](Self::stream_with_timeout).
+    fn into_stream(self, timeout: Timeout) -> OwnedStream<Self>
+    where
+        Self: 'static,
+        Self::Offset: Send + 'static,
+        Self::Meta: Send + 'static,
+        Self::Data: Send + 'static,
+    {
+        let consumer = Arc::new(self);
+        let inner = Box::pin(futures::stream::unfold(
+            consumer.clone(),
+            move |consumer| async move {
+                if timeout.is_expired() {
+                    return None;
+                }
+                let message = consumer.recv_timeout(timeout).await.transpose();
+                message.map(|res| (res, consumer))
+            },
+        ));
+        OwnedStream { consumer, inner }
+    }
+
+    async fn commit(&self, offset: Self::Offset) -> RawResult<()>;
+
+    /// Guard the offset of a message being processed, it is committed when the guard is
+    /// dropped unless [abandoned](CommitGuard::abandon), so that no return path misses it.
+    ///
+    /// See [CommitGuard] for the difference with an explicit commit.
+    fn guard(&self, offset: Self::Offset) -> CommitGuard<'_, Self> {
+        CommitGuard {
+            consumer: self,
+            offset: Some(offset),
+        }
+    }
+
+    /// Commit offsets with one commit for each topic vgroup, implementations may override it
+    /// with a single round trip.
+    ///
+    /// Only the highest offset of each topic vgroup is committed, as it covers the lower ones.
+    /// All of them are tried even if some failed, the error reports each failed topic vgroup.
+    async fn commit_all<I>(&self, offsets: I) -> RawResult<()>
+    where
+        I: IntoIterator<Item = Self::Offset> + Send,
+        I::IntoIter: Send,
+        Self::Offset: Send,
+    {
+        let mut errors = Vec::new();
+        for offset in highest_offsets(offsets) {
+            let key = (offset.topic().to_string(), offset.vgroup_id());
+            if let Err(err) = self.commit(offset).await {
+                errors.push((key, err));
+            }
+        }
+        commit_errors(errors)
+    }
+
+    /// Commit `offset` of a topic vgroup without holding its message.
+    ///
+    /// The offset is checked by the server: one out of `[begin, end]` of the assignment fails
+    /// with [TMQ_VERSION_OUT_OF_RANGE](crate::prelude::Code::TMQ_VERSION_OUT_OF_RANGE), and a
+    /// vgroup not assigned to the consumer with
+    /// [TMQ_INVALID_VGID](crate::prelude::Code::TMQ_INVALID_VGID).
+    async fn commit_offset(
+        &self,
+        topic_name: &str,
+        vgroup_id: VGroupId,
+        offset: i64,
+    ) -> RawResult<()>;
+
+    /// Unsubscribe and close the consumer.
+    ///
+    /// Dropping a consumer does the same in best effort, use this to get the error if any.
+    /// Use [shutdown](Self::shutdown) to also flush pending auto commits.
+    async fn unsubscribe(self) -> RawResult<()> {
+        drop(self);
+        Ok(())
+    }
+
+    /// Gracefully shut down the consumer: flush pending commits, unsubscribe and close.
+    ///
+    /// Since `self` is consumed, no stream borrowed from the consumer can be alive and
+    /// the consumer can not be shut down twice. Implementations bound the wait for the
+    /// server acknowledgement, the default is the same as [unsubscribe](Self::unsubscribe).
+    /// Dropping the consumer is still a best-effort fallback.
+    async fn shutdown(self) -> RawResult<()> {
+        self.unsubscribe().await
+    }
+
+    /// Close the consumer, the same as [shutdown](Self::shutdown).
+    async fn close(self) -> RawResult<()> {
+        self.shutdown().await
+    }
+
+    async fn list_topics(&self) -> RawResult<Vec<String>>;
+
+    /// Topics the consumer is currently subscribed to.
+    ///
+    /// Updated by a successful [subscribe](Self::subscribe), consumers that do not keep track of
+    /// their subscription return an empty list.
+    fn subscription(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    /// See [AsConsumer::topic_names].
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        Err(TopicPattern::unsupported())
+    }
+
+    /// See [AsConsumer::topic_pattern].
+    #[doc(hidden)]
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        None
+    }
+
+    /// See [AsConsumer::subscribe_matched].
+    #[doc(hidden)]
+    async fn subscribe_matched(&self, _topics: Vec<String>) -> RawResult<()> {
+        Err(TopicPattern::unsupported())
+    }
+
+    /// Subscribe to all topics whose name matches the regular expression `pattern`.
+    ///
+    /// The pattern is of the [regex](https://docs.rs/regex) crate syntax and matches anywhere in
+    /// the name unless anchored, e.g. `^orders_` for topics starting with `orders_`. Matching is
+    /// done on the client side, and again on polls every 30 seconds to pick up topics
+    /// created later. A later [subscribe](Self::subscribe) drops the pattern.
+    async fn subscribe_pattern(&mut self, pattern: &str) -> RawResult<()> {
+        let state = self.topic_pattern().ok_or_else(TopicPattern::unsupported)?;
+        state.set(pattern)?;
+        if let Err(err) = self.refresh_subscription().await {
+            state.clear();
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// See [AsConsumer::refresh_subscription].
+    async fn refresh_subscription(&self) -> RawResult<Vec<String>> {
+        let state = self.topic_pattern().ok_or_else(TopicPattern::unsupported)?;
+        let pattern = state.start_refresh()?;
+        let current = self.subscription();
+        let topics = self.topic_names().await?;
+        match matching_topics(topics, &current, |t| pattern.is_match(t)) {
+            Some(matched) => {
+                self.subscribe_matched(matched.clone()).await?;
+                Ok(matched)
+            }
+            None => {
+                log::trace!("topics matching {pattern} are unchanged: {current:?}");
+                Ok(current)
+            }
+        }
+    }
+
+    /// See [AsConsumer::refresh_subscription_if_due].
+    #[doc(hidden)]
+    async fn refresh_subscription_if_due(&self) {
+        if self.topic_pattern().map_or(false, TopicPattern::is_due) {
+            if let Err(err) = self.refresh_subscription().await {
+                log::warn!("refresh topics of the topic pattern failed: {err}");
+            }
+        }
+    }
+
+    /// See [AsConsumer::pending_error].
+    #[doc(hidden)]
+    fn pending_error(&self) -> Option<&PendingError> {
+        None
+    }
+
+    async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>>;
+
+    /// Assignments of the topic, see [AsConsumer::topic_assignment].
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>>;
+
+    /// Assignments of all subscribed topics, with topic names.
+    async fn topic_assignments(&self) -> Vec<TopicAssignment> {
+        self.assignments()
+            .await
+            .into_iter()
+            .flatten()
+            .map(TopicAssignment::from)
+            .collect()
+    }
+
+    /// Lag of each subscribed topic, measured from the current consume position to the end of
+    /// each vgroup.
+    ///
+    /// Lags are clamped to zero, as the end offset may be fetched before the position moves.
+    /// Vgroups with unknown end (`-1`) have no lag.
+    ///
+    /// ```rust,no_run
+    /// use taos_query::prelude::*;
+    ///
+    /// async fn print_lag(consumer: &impl AsAsyncConsumer) -> RawResult<()> {
+    ///     for topic in consumer.lag().await? {
+    ///         println!("topic {} lag: {}", topic.topic, topic.total);
+    ///         for vgroup in topic.vgroups {
+    ///             println!("  vgroup {} lag: {}", vgroup.vgroup_id, vgroup.lag);
+    ///         }
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    async fn lag(&self) -> RawResult<Vec<TopicLag>> {
+        topic_lags(self.assignments().await)
+    }
+
+    /// Seek the vgroup of the topic to `offset`, the next poll of the vgroup starts from it.
+    ///
+    /// It takes `&self`, so a consumer can seek while a [stream](Self::stream) of it is alive.
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()>;
+
+    /// Seek all assigned vgroups of the topic to the earliest offset.
+    ///
+    /// Vgroups with empty ranges are skipped.
+    async fn seek_to_beginning(&self, topic: &str) -> RawResult<()> {
+        let assignments = topic_assignments_of(self.assignments().await, topic)?;
+        let mut errors = Vec::new();
+        for a in assignments.iter().filter(|a| !is_empty_range(a)) {
+            if let Err(err) = self.offset_seek(topic, a.vgroup_id(), a.begin()).await {
+                errors.push((format!("vgroup {}", a.vgroup_id()), err));
+            }
+        }
+        seek_errors(Some(topic), errors)
+    }
+
+    /// Seek all assigned vgroups of the topic to the latest offset.
+    ///
+    /// Vgroups with empty ranges are skipped. It fails without seeking any vgroup if the end
+    /// offset of some vgroup is unknown (`-1`).
+    async fn seek_to_end(&self, topic: &str) -> RawResult<()> {
+        let assignments = topic_assignments_of(self.assignments().await, topic)?;
+        check_known_end(topic, &assignments)?;
+        let mut errors = Vec::new();
+        for a in assignments.iter().filter(|a| !is_empty_range(a)) {
+            if let Err(err) = self.offset_seek(topic, a.vgroup_id(), a.end()).await {
+                errors.push((format!("vgroup {}", a.vgroup_id()), err));
+            }
+        }
+        seek_errors(Some(topic), errors)
+    }
+
+    /// Seek each `(topic, vgroup_id, offset)` of `offsets`, as [offset_seek](Self::offset_seek).
+    ///
+    /// All seeks are applied even if some fail, and the outcome tells which seeks failed and which
+    /// succeeded. Use [SeekResults::into_result] to fail on any failed seek.
+    async fn seek_all(&mut self, offsets: &[(String, VGroupId, i64)]) -> SeekResults {
+        let mut results = Vec::with_capacity(offsets.len());
+        for (topic, vgroup_id, offset) in offsets {
+            results.push(self.offset_seek(topic, *vgroup_id, *offset).await);
+        }
+        SeekResults::new(offsets, results)
+    }
+
+    /// Replay the messages of offsets in `[from, to)` of a topic vgroup, and return their blocks.
+    ///
+    /// It seeks to `from`, polls until a message reaches `to` and seeks back to the previous
+    /// position if it is known. `from` before the beginning is clamped with a warning, and `to`
+    /// beyond the end of the assignment is clamped to the end. An empty poll is also taken as the
+    /// end of data: polls wait as [default_timeout](Self::default_timeout) tells, or
+    /// [REPLAY_IDLE_TIMEOUT] if it is not a duration. Messages of other vgroups are skipped and
+    /// sought back to be delivered again. If seeking back fails, the error of the replay, if
+    /// any, is returned rather than that of seeking back.
+    async fn replay_range(
+        &mut self,
+        topic: &str,
+        vgroup_id: VGroupId,
+        from: i64,
+        to: i64,
+    ) -> RawResult<Vec<RawBlock>>
+    where
+        Self::Offset: Send,
+        Self::Meta: Send,
+        Self::Data: Send + Sync,
+    {
+        let assignments = self.topic_assignment(topic).await?;
+        let Some(assignment) = assignments.iter().find(|a| a.vgroup_id() == vgroup_id) else {
+            return Err(RawError::from_string(format!(
+                "vgroup {vgroup_id} of topic {topic} is not assigned to current consumer"
+            )));
+        };
+        let previous = assignment.current_offset();
+        let from = if from < assignment.begin() {
+            log::warn!(
+                "replay from {from} is before the beginning {} of topic {topic} vgroup \
+                 {vgroup_id}, clamped",
+                assignment.begin()
+            );
+            assignment.begin()
+        } else {
+            from
+        };
+        let to = to.min(assignment.end());
+        let timeout = match self.default_timeout() {
+            Timeout::Duration(duration) => Timeout::Duration(duration),
+            _ => Timeout::Duration(REPLAY_IDLE_TIMEOUT),
+        };
+
+        let mut blocks = Vec::new();
+        let mut skipped = BTreeMap::new();
+        let replayed: RawResult<()> = async {
+            if from >= to {
+                return Ok(());
+            }
+            self.offset_seek(topic, vgroup_id, from).await?;
+            while let Some((offset, message)) = self.recv_timeout(timeout).await? {
+                if offset.topic() != topic || offset.vgroup_id() != vgroup_id {
+                    skipped
+                        .entry((offset.topic().to_string(), offset.vgroup_id()))
+                        .or_insert(offset.offset());
+                    continue;
+                }
+                if offset.offset() >= to {
+                    break;
+                }
+                if let Some(data) = message.into_data() {
+                    blocks.extend(data.fetch_all_blocks().await?);
+                }
+                if offset.offset() + 1 >= to {
+                    break;
+                }
+            }
+            Ok(())
+        }
+        .await;
+
+        let mut restored = Ok(());
+        let previous = (previous >= 0).then(|| ((topic.to_string(), vgroup_id), previous));
+        for ((topic, vgroup_id), offset) in skipped.into_iter().chain(previous) {
+            if let Err(err) = self.offset_seek(&topic, vgroup_id, offset).await {
+                log::warn!("seek topic {topic} vgroup {vgroup_id} back to {offset} failed: {err}");
+                restored = restored.and(Err(err));
+            }
+        }
+        replayed.and(restored).map(|_| blocks)
+    }
+
+    /// See [AsConsumer::is_recoverable].
+    fn is_recoverable(&self, err: &RawError) -> bool {
+        err.is_recoverable()
+    }
+
+    /// See [AsConsumer::resubscribe].
+    async fn resubscribe(&self) -> RawResult<()> {
+        Err(RawError::from_string(
+            "resubscribe is not supported by the consumer",
+        ))
+    }
+
+    /// See [AsConsumer::pause].
+    async fn pause(&self, _topic: &str, _vgroup_id: VGroupId) -> RawResult<()> {
+        Err(RawError::from_string(
+            "pause is not supported by the consumer",
+        ))
+    }
+
+    /// See [AsConsumer::resume].
+    async fn resume(&self, _topic: &str, _vgroup_id: VGroupId) -> RawResult<()> {
+        Err(RawError::from_string(
+            "resume is not supported by the consumer",
+        ))
+    }
+
+    /// Last committed offset of the topic vgroup, returns error if the topic is not subscribed.
+    async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
+
+    /// Current consume position of the topic vgroup, that is the offset of the next message.
+    ///
+    /// Unlike [committed](Self::committed) which is persisted by the server for the consumer
+    /// group, position is kept in memory and advances on every received message, whether
+    /// committed or not.
+    async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
+}
+
+/// Poll timeout of [AsAsyncConsumer::replay_range] when the default timeout is not a duration.
+pub const REPLAY_IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Messages polled at most at once by [VGroupOrder] to be reordered.
+pub const VGROUP_ORDER_BATCH: usize = 64;
+
+/// Buffer reordering received messages by offset within each vgroup, used by consumers built
+/// with `preserve_vgroup_order(true)`.
+///
+/// Each poll takes the first message in time and then the messages immediately available, up to
+/// a batch. Messages of a vgroup are sorted by offset in the slots they took in the batch, so
+/// cross-vgroup order is kept as received. A message at or behind the last offset delivered of
+/// its vgroup, other than after a [reset](Self::reset), is logged and still delivered.
+///
+/// Messages are polled before they are delivered, so a consumer using it must track offsets to
+/// commit when messages are delivered, and can't let the server auto commit the polled ones.
+pub struct VGroupOrder<O, M, D> {
+    max_batch: usize,
+    pending: std::sync::Mutex<std::collections::VecDeque<(O, MessageSet<M, D>)>>,
+    /// Error of a poll after the first message of a batch, returned after the batch.
+    error: std::sync::Mutex<Option<RawError>>,
+    delivered: std::sync::Mutex<BTreeMap<(String, VGroupId), i64>>,
+}
+
+impl<O, M, D> Debug for VGroupOrder<O, M, D> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VGroupOrder")
+            .field("max_batch", &self.max_batch)
+            .field("pending", &self.pending.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl<O: IsOffset, M, D> VGroupOrder<O, M, D> {
+    /// Buffer polling at most `max_batch` messages at once.
+    pub fn new(max_batch: usize) -> Self {
+        Self {
+            max_batch: max_batch.max(1),
+            pending: Default::default(),
+            error: Default::default(),
+            delivered: Default::default(),
+        }
+    }
+
+    /// Receive the next message, polling by `poll` when nothing is buffered.
+    ///
+    /// An error after the first message of a batch is returned once the batch is delivered.
+    pub async fn recv<F, Fut>(
+        &self,
+        timeout: Timeout,
+        mut poll: F,
+    ) -> RawResult<Option<(O, MessageSet<M, D>)>>
+    where
+        F: FnMut(Timeout) -> Fut,
+        Fut: std::future::Future<Output = RawResult<Option<(O, MessageSet<M, D>)>>>,
+    {
+        let pending = self.pending.lock().unwrap().pop_front();
+        let message = match pending {
+            Some(message) => message,
+            None => {
+                if let Some(err) = self.error.lock().unwrap().take() {
+                    return Err(err);
+                }
+                let Some(first) = poll(timeout).await? else {
+                    return Ok(None);
+                };
+                let mut batch = vec![first];
+                while batch.len() < self.max_batch {
+                    match poll(Timeout::None).await {
+                        Ok(Some(message)) => batch.push(message),
+                        Ok(None) => break,
+                        Err(err) => {
+                            *self.error.lock().unwrap() = Some(err);
+                            break;
+                        }
+                    }
+                }
+                sort_in_vgroups(&mut batch);
+                let mut pending = self.pending.lock().unwrap();
+                pending.extend(batch);
+                pending.pop_front().unwrap()
+            }
+        };
+
+        let offset = &message.0;
+        let key = (offset.topic().to_string(), offset.vgroup_id());
+        if let Some(last) = self.delivered.lock().unwrap().insert(key, offset.offset()) {
+            if offset.offset() <= last {
+                log::warn!(
+                    "message of offset {} in topic {} vgroup {} arrives after offset {last}",
+                    offset.offset(),
+                    offset.topic(),
+                    offset.vgroup_id()
+                );
+            }
+        }
+        Ok(Some(message))
+    }
+
+    /// Forget the vgroup, called on seek: buffered messages of it are dropped and the next one
+    /// is not compared to the offsets delivered before.
+    pub fn reset(&self, topic: &str, vgroup_id: VGroupId) {
+        self.pending
+            .lock()
+            .unwrap()
+            .retain(|(offset, _)| offset.topic() != topic || offset.vgroup_id() != vgroup_id);
+        self.delivered
+            .lock()
+            .unwrap()
+            .remove(&(topic.to_string(), vgroup_id));
+    }
+}
+
+/// Sort messages of each vgroup by offset, in the slots the vgroup takes in `batch`.
+fn sort_in_vgroups<O: IsOffset, T>(batch: &mut Vec<(O, T)>) {
+    let mut slots: BTreeMap<(&str, VGroupId), Vec<usize>> = BTreeMap::new();
+    for (i, (offset, _)) in batch.iter().enumerate() {
+        slots
+            .entry((offset.topic(), offset.vgroup_id()))
+            .or_default()
+            .push(i);
+    }
+    let mut order = vec![0; batch.len()];
+    for slots in slots.values() {
+        let mut sorted = slots.clone();
+        sorted.sort_by_key(|&i| batch[i].0.offset());
+        for (&slot, i) in slots.iter().zip(sorted) {
+            order[slot] = i;
+        }
+    }
+    let mut messages: Vec<_> = batch.drain(..).map(Some).collect();
+    batch.extend(order.into_iter().map(|i| messages[i].take().unwrap()));
+}
+
+/// Vgroups paused on the client side, used by consumers without a server side pause.
+///
+/// Polled messages of a paused vgroup are skipped, and the offset of the first skipped one is
+/// kept to seek back to on resume, so that one seek is done for the whole pause. The skipped
+/// messages are polled all the same, so a consumer using it can't let the server auto commit.
+#[derive(Debug, Default)]
+pub struct PausedVGroups(std::sync::Mutex<BTreeMap<(String, VGroupId), Option<i64>>>);
+
+impl PausedVGroups {
+    /// Pause the vgroup, pausing it again keeps the first skipped offset.
+    pub fn pause(&self, topic: &str, vgroup_id: VGroupId) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry((topic.to_string(), vgroup_id))
+            .or_default();
+    }
+
+    /// Resume the vgroup, returns the offset to seek back to if any message is skipped.
+    pub fn resume(&self, topic: &str, vgroup_id: VGroupId) -> Option<i64> {
+        self.0
+            .lock()
+            .unwrap()
+            .remove(&(topic.to_string(), vgroup_id))
+            .flatten()
+    }
+
+    /// Check a polled message, `true` if it is skipped for its vgroup is paused.
+    ///
+    /// A message without offset can't be sought back to, it is logged and not skipped.
+    pub fn skip(&self, offset: &impl IsOffset) -> bool {
+        let mut paused = self.0.lock().unwrap();
+        let key = (offset.topic().to_string(), offset.vgroup_id());
+        let Some(first) = paused.get_mut(&key) else {
+            return false;
+        };
+        if offset.offset() < 0 {
+            log::warn!(
+                "vgroup {} of topic {} is paused but the message has no offset, deliver it",
+                offset.vgroup_id(),
+                offset.topic()
+            );
+            return false;
+        }
+        first.get_or_insert(offset.offset());
+        true
+    }
+
+    /// Forget the skipped offset of a paused vgroup, called on seek.
+    pub fn reset(&self, topic: &str, vgroup_id: VGroupId) {
+        if let Some(first) = self
+            .0
+            .lock()
+            .unwrap()
+            .get_mut(&(topic.to_string(), vgroup_id))
+        {
+            *first = None;
+        }
+    }
+
+    /// Resume all vgroups without seeking back, called on subscribe.
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    /// The paused vgroups as `(topic, vgroup_id)`.
+    pub fn vgroups(&self) -> BTreeSet<(String, VGroupId)> {
+        self.0.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+/// A received message which should be committed after processed.
+///
+/// See [AsAsyncConsumer::stream_autocommit].
+pub struct Committable<'a, C: AsAsyncConsumer> {
+    consumer: &'a C,
+    offset: Option<C::Offset>,
+    message: MessageSet<C::Meta, C::Data>,
+}
+
+impl<'a, C: AsAsyncConsumer> Committable<'a, C> {
+    /// Offset of the message, `None` after committed.
+    pub fn offset(&self) -> Option<&C::Offset> {
+        self.offset.as_ref()
+    }
+
+    /// The message to process.
+    pub fn message(&self) -> &MessageSet<C::Meta, C::Data> {
+        &self.message
+    }
+
+    /// Commit the message offset.
+    pub async fn commit(mut self) -> RawResult<()> {
+        match self.offset.take() {
+            Some(offset) => self.consumer.commit(offset).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl<'a, C: AsAsyncConsumer> Debug for Committable<'a, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Committable")
+            .field("committed", &self.offset.is_none())
+            .field("kind", &self.message.kind())
+            .finish()
+    }
+}
+
+impl<'a, C: AsAsyncConsumer> Drop for Committable<'a, C> {
+    fn drop(&mut self) {
+        if let Some(offset) = &self.offset {
+            log::warn!(
+                "message of topic {} vgroup {} is dropped without commit",
+                offset.topic(),
+                offset.vgroup_id()
+            );
+        }
+    }
+}
+
+/// Commit an offset when dropped, for at-least-once processing.
+///
+/// Created by [AsAsyncConsumer::guard]. Prefer [CommitGuard::commit] on the happy path to get
+/// the commit error, the drop-time commit blocks in place (so it must not be dropped in a
+/// current-thread runtime) and only logs the error. Call [CommitGuard::abandon] on failure
+/// paths to keep the offset uncommitted, the message will be delivered again.
+#[must_use = "the offset is committed as soon as the guard is dropped"]
+pub struct CommitGuard<'a, C: AsAsyncConsumer> {
+    consumer: &'a C,
+    offset: Option<C::Offset>,
+}
+
+impl<'a, C: AsAsyncConsumer> CommitGuard<'a, C> {
+    /// The guarded offset.
+    pub fn offset(&self) -> Option<&C::Offset> {
+        self.offset.as_ref()
+    }
+
+    /// Commit the offset now.
+    pub async fn commit(mut self) -> RawResult<()> {
+        match self.offset.take() {
+            Some(offset) => self.consumer.commit(offset).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Drop the guard without commit, returns the offset.
+    pub fn abandon(mut self) -> Option<C::Offset> {
+        self.offset.take()
+    }
+}
+
+impl<'a, C: AsAsyncConsumer> Debug for CommitGuard<'a, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CommitGuard")
+            .field("armed", &self.offset.is_some())
+            .finish()
+    }
+}
+
+impl<'a, C: AsAsyncConsumer> Drop for CommitGuard<'a, C> {
+    fn drop(&mut self) {
+        let Some(offset) = self.offset.take() else {
+            return;
+        };
+        let (topic, vgroup_id) = (offset.topic().to_string(), offset.vgroup_id());
+        if let Err(err) = crate::block_in_place_or_global(self.consumer.commit(offset)) {
+            log::warn!("commit of topic {topic} vgroup {vgroup_id} on drop failed: {err}");
+        }
+    }
+}
+
+type MessageOf<C> = (
+    <C as AsAsyncConsumer>::Offset,
+    MessageSet<<C as AsAsyncConsumer>::Meta, <C as AsAsyncConsumer>::Data>,
+);
+
+/// Stream of messages polled ahead by a background task, see [AsAsyncConsumer::stream_prefetch].
+pub struct PrefetchStream<C: AsAsyncConsumer> {
+    receiver: tokio::sync::mpsc::Receiver<RawResult<MessageOf<C>>>,
+    poller: tokio::task::JoinHandle<()>,
+}
+
+impl<C: AsAsyncConsumer> futures::Stream for PrefetchStream<C> {
+    type Item = RawResult<MessageOf<C>>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+}
+
+impl<C: AsAsyncConsumer> Drop for PrefetchStream<C> {
+    fn drop(&mut self) {
+        self.poller.abort();
+    }
+}
+
+/// Stream owning its consumer, see [AsAsyncConsumer::into_stream].
+pub struct OwnedStream<C: AsAsyncConsumer> {
+    consumer: Arc<C>,
+    inner: Pin<Box<dyn futures::Stream<Item = RawResult<MessageOf<C>>> + Send>>,
+}
+
+impl<C: AsAsyncConsumer> OwnedStream<C> {
+    /// A handle to commit offsets of the consumer, which can be cloned and sent to other tasks.
+    pub fn committer(&self) -> Committer<C> {
+        Committer {
+            consumer: Arc::downgrade(&self.consumer),
+        }
+    }
+}
+
+impl<C: AsAsyncConsumer> futures::Stream for OwnedStream<C> {
+    type Item = RawResult<MessageOf<C>>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut().inner.as_mut().poll_next(cx)
+    }
+}
+
+impl<C: AsAsyncConsumer> Debug for OwnedStream<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OwnedStream").finish_non_exhaustive()
+    }
+}
+
+/// Commit offsets of a consumer owned by an [OwnedStream], it does not keep the consumer alive.
+pub struct Committer<C: AsAsyncConsumer> {
+    consumer: std::sync::Weak<C>,
+}
+
+impl<C: AsAsyncConsumer> Committer<C> {
+    fn consumer(&self) -> RawResult<Arc<C>> {
+        self.consumer
+            .upgrade()
+            .ok_or_else(|| RawError::from_string("the consumer is dropped with its stream"))
+    }
+
+    /// See [AsAsyncConsumer::commit].
+    pub async fn commit(&self, offset: C::Offset) -> RawResult<()> {
+        self.consumer()?.commit(offset).await
+    }
+
+    /// See [AsAsyncConsumer::commit_offset].
+    pub async fn commit_offset(
+        &self,
+        topic: &str,
+        vgroup_id: VGroupId,
+        offset: i64,
+    ) -> RawResult<()> {
+        self.consumer()?
+            .commit_offset(topic, vgroup_id, offset)
+            .await
+    }
+
+    /// Whether the consumer is still alive, commits fail otherwise.
+    pub fn is_active(&self) -> bool {
+        self.consumer.strong_count() > 0
+    }
+}
+
+impl<C: AsAsyncConsumer> Clone for Committer<C> {
+    fn clone(&self) -> Self {
+        Self {
+            consumer: self.consumer.clone(),
+        }
+    }
+}
+
+impl<C: AsAsyncConsumer> Debug for Committer<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Committer")
+            .field("active", &self.is_active())
+            .finish()
+    }
+}
+
+/// Marker trait to impl sync on async impl.
+pub trait SyncOnAsync {}
+
+/// Marker trait to impl async on sync impl.
+///
+/// Each async call runs the sync one on a clone of the consumer by `spawn_blocking`, so the
+/// consumer is required to be `Clone + 'static` with clones sharing its state, e.g. `Arc` based.
+pub trait AsyncOnSync {}
+
+impl<C> AsConsumer for C
+where
+    C: AsAsyncConsumer + SyncOnAsync,
+    C::Meta: IsMeta,
+    C::Data: IntoIterator<Item = RawResult<RawBlock>>,
+{
+    type Offset = C::Offset;
+
+    type Meta = C::Meta;
+
+    type Data = C::Data;
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        <C as AsAsyncConsumer>::topic_timeout(self, topic)
+    }
+
+    fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
+        &mut self,
+        topics: I,
+    ) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::subscribe(self, topics))
+    }
+
+    fn recv_timeout(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::recv_timeout(self, timeout))
+    }
+
+    fn commit(&self, offset: Self::Offset) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::commit(self, offset))
+    }
+
+    fn unsubscribe(self) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::unsubscribe(self))
+    }
+
+    fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::commit_offset(
+            self, topic_name, vgroup_id, offset,
+        ))
+    }
+
+    fn list_topics(&self) -> RawResult<Vec<String>> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::list_topics(self))
+    }
+
+    fn subscription(&self) -> Vec<String> {
+        <C as AsAsyncConsumer>::subscription(self)
+    }
+
+    fn topic_names(&self) -> RawResult<Vec<String>> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::topic_names(self))
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        <C as AsAsyncConsumer>::topic_pattern(self)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        <C as AsAsyncConsumer>::pending_error(self)
+    }
+
+    fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::subscribe_matched(self, topics))
+    }
+
+    fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::assignments(self))
+    }
+
+    fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::topic_assignment(self, topic))
+    }
+
+    fn offset_seek(&self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::offset_seek(
+            self, topic, vg_id, offset,
+        ))
+    }
+
+    fn is_recoverable(&self, err: &RawError) -> bool {
+        <C as AsAsyncConsumer>::is_recoverable(self, err)
+    }
+
+    fn resubscribe(&self) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::resubscribe(self))
+    }
+
+    fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::pause(self, topic, vgroup_id))
+    }
+
+    fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::resume(self, topic, vgroup_id))
+    }
+
+    fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::committed(self, topic, vgroup_id))
+    }
+
+    fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        crate::block_in_place_or_global(<C as AsAsyncConsumer>::position(self, topic, vgroup_id))
+    }
+}
+
+/// Run blocking `f` by [spawn_blocking](tokio::task::spawn_blocking), so that the async runtime
+/// is not stalled. Panics of `f` are resumed in the caller.
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> T {
+    match tokio::task::spawn_blocking(f).await {
+        Ok(value) => value,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+#[async_trait::async_trait]
+impl<C> AsAsyncConsumer for C
+where
+    C: AsConsumer + AsyncOnSync + Clone + Send + Sync + 'static,
+    C::Offset: Send + 'static,
+    C::Meta: IsAsyncMeta + Send + 'static,
+    C::Data: IsAsyncData + Send + 'static,
+{
+    type Offset = C::Offset;
+
+    type Meta = C::Meta;
+
+    type Data = C::Data;
+
+    fn default_timeout(&self) -> Timeout {
+        <C as AsConsumer>::default_timeout(self)
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        <C as AsConsumer>::topic_timeout(self, topic)
+    }
+
+    async fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
+        &mut self,
+        topics: I,
+    ) -> RawResult<()> {
+        let topics: Vec<String> = topics.into_iter().map(Into::into).collect();
+        let mut consumer = self.clone();
+        *self = run_blocking(move || {
+            <C as AsConsumer>::subscribe(&mut consumer, topics).map(|_| consumer)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn recv_timeout(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::recv_timeout(&consumer, timeout)).await
+    }
+
+    async fn commit(&self, offset: Self::Offset) -> RawResult<()> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::commit(&consumer, offset)).await
+    }
+
+    async fn commit_offset(
+        &self,
+        topic_name: &str,
+        vgroup_id: VGroupId,
+        offset: i64,
+    ) -> RawResult<()> {
+        let (consumer, topic_name) = (self.clone(), topic_name.to_string());
+        run_blocking(move || {
+            <C as AsConsumer>::commit_offset(&consumer, &topic_name, vgroup_id, offset)
+        })
+        .await
+    }
+
+    async fn unsubscribe(self) -> RawResult<()> {
+        run_blocking(move || <C as AsConsumer>::unsubscribe(self)).await
+    }
+
+    async fn list_topics(&self) -> RawResult<Vec<String>> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::list_topics(&consumer)).await
+    }
+
+    fn subscription(&self) -> Vec<String> {
+        <C as AsConsumer>::subscription(self)
+    }
+
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::topic_names(&consumer)).await
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        <C as AsConsumer>::topic_pattern(self)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        <C as AsConsumer>::pending_error(self)
+    }
+
+    async fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::subscribe_matched(&consumer, topics)).await
+    }
+
+    async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::assignments(&consumer)).await
+    }
+
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        let (consumer, topic) = (self.clone(), topic.to_string());
+        run_blocking(move || <C as AsConsumer>::topic_assignment(&consumer, &topic)).await
+    }
+
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+        let (consumer, topic) = (self.clone(), topic.to_string());
+        run_blocking(move || <C as AsConsumer>::offset_seek(&consumer, &topic, vgroup_id, offset))
+            .await
+    }
+
+    fn is_recoverable(&self, err: &RawError) -> bool {
+        <C as AsConsumer>::is_recoverable(self, err)
+    }
+
+    async fn resubscribe(&self) -> RawResult<()> {
+        let consumer = self.clone();
+        run_blocking(move || <C as AsConsumer>::resubscribe(&consumer)).await
+    }
+
+    async fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        let (consumer, topic) = (self.clone(), topic.to_string());
+        run_blocking(move || <C as AsConsumer>::pause(&consumer, &topic, vgroup_id)).await
+    }
+
+    async fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        let (consumer, topic) = (self.clone(), topic.to_string());
+        run_blocking(move || <C as AsConsumer>::resume(&consumer, &topic, vgroup_id)).await
+    }
+
+    async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        let (consumer, topic) = (self.clone(), topic.to_string());
+        run_blocking(move || <C as AsConsumer>::committed(&consumer, &topic, vgroup_id)).await
+    }
+
+    async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        let (consumer, topic) = (self.clone(), topic.to_string());
+        run_blocking(move || <C as AsConsumer>::position(&consumer, &topic, vgroup_id)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr, sync::Mutex, time::Duration};
+
+    use super::{
+        mock::{MockConsumer, MockData, MockMeta, MockOffset},
+        *,
+    };
+    use crate::prelude::Code;
+
+    /// Entry of a [mock_consumer] script failing the poll with a recoverable error.
+    const BLIP: VGroupId = -1;
+
+    /// Entry of a [mock_consumer] script delivering a message of vgroup 1 failing to fetch its
+    /// blocks.
+    const MALFORMED: VGroupId = -2;
+
+    /// Entry of a [mock_consumer] script failing the poll with an error not recoverable.
+    const LOST: VGroupId = -3;
+
+    /// Entry of a [mock_consumer] script polling no message at once.
+    const EMPTY: VGroupId = -4;
+
+    /// The recoverable error of [BLIP].
+    fn blip() -> RawError {
+        RawError::new(Code::new(0x000B), "connection blip")
+    }
+
+    /// Two blocks of `(ts timestamp, v int)` rows, the second one is of table `tb2`.
+    fn two_blocks() -> MockData {
+        use crate::common::{ColumnView, Precision};
+
+        MockData::new((1..=2).map(|n| {
+            let ts = n as i64 * 1000;
+            let mut block = RawBlock::from_views(
+                &[
+                    ColumnView::from_millis_timestamp(vec![ts, ts + 1]),
+                    ColumnView::from_ints(vec![Some(n), None]),
+                ],
+                Precision::Millisecond,
+            );
+            block.with_field_names(["ts", "v"]);
+            if n > 1 {
+                block.with_table_name(format!("tb{n}"));
+            }
+            block
+        }))
+    }
+
+    /// A mock consumer subscribed to topic `topic`, delivering a data message of
+    /// [two_blocks] for each vgroup id of `script` at the next offset of the vgroup.
+    fn mock_consumer(script: impl IntoIterator<Item = VGroupId>) -> MockConsumer {
+        let consumer = MockConsumer::new([]).with_subscription(["topic"]);
+        let mut offsets = HashMap::new();
+        for vgroup_id in script {
+            let data = match vgroup_id {
+                BLIP => {
+                    consumer.push_error(blip());
+                    continue;
+                }
+                LOST => {
+                    consumer.push_error(RawError::from_string("connection lost"));
+                    continue;
+                }
+                EMPTY => {
+                    consumer.push_empty();
+                    continue;
+                }
+                MALFORMED => {
+                    MockData::new([]).with_fetch_error(RawError::from_string("malformed block"))
+                }
+                _ => two_blocks(),
+            };
+            let vgroup_id = vgroup_id.max(1);
+            let offset = offsets.entry(vgroup_id).or_insert(0);
+            consumer.push(
+                MockOffset::new("topic", vgroup_id, *offset),
+                MessageSet::Data(data),
+            );
+            *offset += 1;
+        }
+        consumer
+    }
+
+    fn committed_vgroups(consumer: &MockConsumer) -> Vec<VGroupId> {
+        consumer.commits().into_iter().map(|c| c.1).collect()
+    }
+
+    fn seeks(consumer: &MockConsumer) -> Vec<(VGroupId, i64)> {
+        let seeks = consumer.seeks().into_iter();
+        seeks
+            .map(|(_, vgroup_id, offset)| (vgroup_id, offset))
+            .collect()
+    }
+
+    /// A sync consumer on [MockConsumer], so that its async consumer is by [AsyncOnSync].
+    #[derive(Clone)]
+    struct SyncMock(Arc<MockConsumer>);
+
+    impl AsyncOnSync for SyncMock {}
+
+    impl AsConsumer for SyncMock {
+        type Offset = MockOffset;
+        type Meta = MockMeta;
+        type Data = MockData;
+
+        fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
+            &mut self,
+            topics: I,
+        ) -> RawResult<()> {
+            // The async consumer subscribes on a clone, so the shared mock is updated in place.
+            let topics = topics.into_iter().map(Into::into).collect();
+            crate::block_in_place_or_global(AsAsyncConsumer::subscribe_matched(&*self.0, topics))
+        }
+
+        fn subscription(&self) -> Vec<String> {
+            AsConsumer::subscription(&*self.0)
+        }
+
+        fn recv_timeout(
+            &self,
+            timeout: Timeout,
+        ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
+            AsConsumer::recv_timeout(&*self.0, timeout)
+        }
+
+        fn commit(&self, offset: Self::Offset) -> RawResult<()> {
+            AsConsumer::commit(&*self.0, offset)
+        }
+
+        fn commit_offset(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+            AsConsumer::commit_offset(&*self.0, topic, vgroup_id, offset)
+        }
+
+        fn list_topics(&self) -> RawResult<Vec<String>> {
+            AsConsumer::list_topics(&*self.0)
+        }
+
+        fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
+            AsConsumer::assignments(&*self.0)
+        }
+
+        fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+            AsConsumer::offset_seek(&*self.0, topic, vgroup_id, offset)
+        }
+
+        fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+            AsConsumer::committed(&*self.0, topic, vgroup_id)
+        }
+
+        fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+            AsConsumer::position(&*self.0, topic, vgroup_id)
+        }
+    }
+
+    #[test]
+    fn timeout_from_millis_str() {
+        let timeout = Timeout::from_str("0").unwrap();
+        assert!(matches!(timeout, Timeout::Duration(d) if d == Duration::ZERO));
+        assert_eq!(timeout.as_raw_timeout(), 0);
+
+        let timeout = Timeout::from_str("500").unwrap();
+        assert!(matches!(timeout, Timeout::Duration(d) if d == Duration::from_millis(500)));
+
+        let timeout = Timeout::from_str("5s").unwrap();
+        assert!(matches!(timeout, Timeout::Duration(d) if d == Duration::from_secs(5)));
+
+        let timeout = Timeout::from_str("18446744073709551615").unwrap();
+        assert_eq!(timeout.as_raw_timeout(), i64::MAX);
+
+        assert!(matches!(
+            Timeout::from_str("18446744073709551616"),
+            Err(TimeoutError::Invalid(_, _))
+        ));
+        assert!(matches!(
+            Timeout::from_str("-1"),
+            Err(TimeoutError::Invalid(_, _))
+        ));
+        assert!(matches!(Timeout::from_str(""), Err(TimeoutError::Empty)));
+    }
+
+    #[test]
+    fn timeout_is_blocking() {
+        assert!(Timeout::Never.is_blocking());
+        assert!(!Timeout::None.is_blocking());
+        assert!(Timeout::from_millis(1).is_blocking());
+        assert!(!Timeout::Duration(Duration::ZERO).is_blocking());
+    }
+
+    #[test]
+    fn timeout_min_max() {
+        let five = Timeout::from_secs(5);
+        let one = Timeout::from_secs(1);
+        assert_eq!(Timeout::Never.min(five), five);
+        assert_eq!(Timeout::None.max(five), five);
+        assert_eq!(Timeout::None.min(five), Timeout::None);
+        assert_eq!(Timeout::Never.max(five), Timeout::Never);
+        assert_eq!(one.min(five), one);
+        assert_eq!(one.max(five), five);
+        assert_eq!(Timeout::Never.min(Timeout::None), Timeout::None);
+    }
+
+    #[test]
+    fn check_versioned_params() {
+        let params = [
+            ("enable.replay", "true"),
+            ("experimental.snapshot.enable", "false"),
+            ("group.id", "true"),
+        ];
+        let versioned = versioned_params(params);
+        assert_eq!(versioned, [("enable.replay", "3.2.1.0")]);
+        assert!(check_server_version("3.2.1.0", &versioned).is_ok());
+        assert!(check_server_version("3.3.0.0.0301", &versioned).is_ok());
+        let err = check_server_version("3.2.0.0", &versioned).unwrap_err();
+        assert!(err.to_string().contains("enable.replay"), "{err}");
+        assert!(err.to_string().contains("3.2.0.0"), "{err}");
+
+        let versioned = versioned_params([("experimental.snapshot.enable", "TRUE")]);
+        assert!(check_server_version("3.0.1.8", &versioned).is_ok());
+        assert!(check_server_version("2.6.0.34", &versioned).is_err());
+        assert!(check_server_version("2.6.0.34", &[]).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_subscribe_pattern() {
+        let mut consumer = MockConsumer::new([]);
+        consumer.create_topic("orders_eu");
+        consumer.create_topic("users");
+        assert!(AsAsyncConsumer::refresh_subscription(&consumer)
+            .await
+            .is_err());
+        assert!(AsAsyncConsumer::subscribe_pattern(&mut consumer, "(")
+            .await
+            .is_err());
+
+        AsAsyncConsumer::subscribe_pattern(&mut consumer, "^orders_")
+            .await
+            .unwrap();
+        assert_eq!(AsConsumer::subscription(&consumer), ["orders_eu"]);
+
+        // A topic created later is picked up by a poll once the pattern is due.
+        let poll = |consumer| AsAsyncConsumer::recv_timeout(consumer, Timeout::None);
+        consumer.create_topic("orders_us");
+        poll(&consumer).await.unwrap();
+        assert_eq!(AsConsumer::subscription(&consumer), ["orders_eu"]);
+        let pattern = AsAsyncConsumer::topic_pattern(&consumer).unwrap();
+        if let Some((_, matched_at)) = &mut *pattern.0.lock().unwrap() {
+            *matched_at = matched_at
+                .checked_sub(TopicPattern::REFRESH_INTERVAL)
+                .unwrap();
+        }
+        poll(&consumer).await.unwrap();
+        assert_eq!(
+            AsConsumer::subscription(&consumer),
+            ["orders_eu", "orders_us"]
+        );
+
+        // Subscribing to topics drops the pattern.
+        AsAsyncConsumer::subscribe(&mut consumer, ["users"])
+            .await
+            .unwrap();
+        assert!(!AsAsyncConsumer::topic_pattern(&consumer).unwrap().is_due());
+        assert!(AsAsyncConsumer::refresh_subscription(&consumer)
+            .await
+            .is_err());
+        assert_eq!(AsConsumer::subscription(&consumer), ["users"]);
+    }
+
+    #[test]
+    fn matching_topics_changed() {
+        // Topics reported by the broker, matched as with `^orders_`.
+        let topics = ["orders_eu", "users", "orders_us", "orders_eu"].map(String::from);
+        let is_match = |topic: &str| topic.starts_with("orders_");
+
+        let matched = matching_topics(topics.clone(), &[], is_match);
+        assert_eq!(matched.unwrap(), ["orders_eu", "orders_us"]);
+        let current = ["orders_us", "orders_eu"].map(String::from);
+        assert_eq!(matching_topics(topics.clone(), &current, is_match), None);
+
+        // A new topic matching the pattern is picked up by the next refresh.
+        let topics = topics.into_iter().chain(["orders_apac".to_string()]);
+        let matched = matching_topics(topics, &current, is_match);
+        assert_eq!(matched.unwrap(), ["orders_apac", "orders_eu", "orders_us"]);
+    }
+
+    #[test]
+    fn timeout_saturating_add() {
+        let extra = Duration::from_secs(1);
+        assert_eq!(Timeout::Never.saturating_add(extra), Timeout::Never);
+        assert_eq!(
+            Timeout::None.saturating_add(extra),
+            Timeout::Duration(extra)
+        );
+        assert_eq!(
+            Timeout::from_secs(2).saturating_add(extra),
+            Timeout::from_secs(3)
+        );
+        let near_max = Duration::MAX - Duration::from_millis(1);
+        assert_eq!(
+            Timeout::Duration(near_max).saturating_add(extra),
+            Timeout::Duration(Duration::MAX)
+        );
+
+        let now = Instant::now();
+        assert_eq!(
+            Timeout::deadline(now).saturating_add(extra),
+            Timeout::deadline(now + extra)
+        );
+        assert_eq!(
+            Timeout::deadline(now).saturating_add(Duration::MAX),
+            Timeout::Never
+        );
+    }
+
+    #[test]
+    fn message_set_map() {
+        let meta: MessageSet<String, u32> = MessageSet::Meta("meta".to_string());
+        let meta = meta.map_meta(|m| m.len());
+        assert!(matches!(meta, MessageSet::Meta(4)));
+
+        let data: MessageSet<String, u32> = MessageSet::Data(1);
+        let data = data.map_meta(|m| m.len());
+        assert!(matches!(data, MessageSet::Data(1)));
+
+        let both: MessageSet<String, u32> = MessageSet::MetaData("abc".to_string(), 2);
+        let both = both.map_meta(|m| m.len());
+        assert!(matches!(both, MessageSet::MetaData(3, 2)));
+
+        let both = both.map_data(|d| d * 10);
+        assert!(matches!(both, MessageSet::MetaData(3, 20)));
+        let meta = meta.map_data(|d| d * 10);
+        assert!(matches!(meta, MessageSet::Meta(4)));
+        let data = data.map_data(|d| d * 10);
+        assert!(matches!(data, MessageSet::Data(10)));
+    }
+
+    #[test]
+    fn message_set_parts() {
+        let mut meta: MessageSet<String, u32> = MessageSet::Meta("meta".to_string());
+        meta.meta_mut().unwrap().push('!');
+        assert_eq!(meta.data_ref(), None);
+        assert_eq!(meta.into_parts(), (Some("meta!".to_string()), None));
+
+        let mut data: MessageSet<String, u32> = MessageSet::Data(1);
+        assert!(data.meta_mut().is_none());
+        assert_eq!(data.data_ref(), Some(&1));
+        assert_eq!(data.into_parts(), (None, Some(1)));
+
+        let mut both: MessageSet<String, u32> = MessageSet::MetaData("meta".to_string(), 2);
+        both.meta_mut().unwrap().clear();
+        assert_eq!(both.data_ref(), Some(&2));
+        assert_eq!(both.into_parts(), (Some(String::new()), Some(2)));
+    }
+
+    #[test]
+    fn message_set_try_map_data() {
+        let parse = |d: &str| d.parse::<u32>();
+        let meta: MessageSet<u8, &str> = MessageSet::Meta(1);
+        assert!(matches!(meta.try_map_data(parse), Ok(MessageSet::Meta(1))));
+
+        let data: MessageSet<u8, &str> = MessageSet::Data("2");
+        assert!(matches!(data.try_map_data(parse), Ok(MessageSet::Data(2))));
+        let data: MessageSet<u8, &str> = MessageSet::Data("x");
+        assert!(data.try_map_data(parse).is_err());
+
+        let both: MessageSet<u8, &str> = MessageSet::MetaData(1, "3");
+        assert!(matches!(
+            both.try_map_data(parse),
+            Ok(MessageSet::MetaData(1, 3))
+        ));
+        let both: MessageSet<u8, &str> = MessageSet::MetaData(1, "x");
+        assert!(both.try_map_data(parse).is_err());
+    }
+
+    #[test]
+    fn message_set_as_ref() {
+        let sets: [MessageSet<String, u32>; 3] = [
+            MessageSet::Meta("meta".to_string()),
+            MessageSet::Data(1),
+            MessageSet::MetaData("meta".to_string(), 1),
+        ];
+        for set in &sets {
+            let borrowed = set.as_ref();
+            assert_eq!(borrowed.has_meta(), set.has_meta());
+            assert_eq!(borrowed.has_data(), set.has_data());
+            assert_eq!(borrowed.into_meta(), set.meta());
+        }
+    }
+
+    #[test]
+    fn seek_aggregated_errors() {
+        let assignments = Some(vec![("t1".to_string(), vec![Assignment::new(1, 0, 0, 10)])]);
+        assert_eq!(
+            topic_assignments_of(assignments.clone(), "t1")
+                .unwrap()
+                .len(),
+            1
+        );
+        let err = topic_assignments_of(assignments, "t2").unwrap_err();
+        assert!(
+            err.to_string().contains("topic t2 is not assigned"),
+            "{err}"
+        );
+        let err = topic_assignments_of(None, "t1").unwrap_err();
+        assert!(
+            err.to_string().contains("assignments are not available"),
+            "{err}"
+        );
+
+        assert!(seek_errors(Some("t1"), vec![]).is_ok());
+        let err = seek_errors(
+            Some("t1"),
+            vec![
+                ("vgroup 1".to_string(), RawError::from_string("seek failed")),
+                ("vgroup 3".to_string(), RawError::from_string("seek failed")),
+            ],
+        )
+        .unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("2 vgroup(s)"), "{err}");
+        assert!(
+            err.contains("vgroup 1") && err.contains("vgroup 3"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn consumer_recv_many() {
+        let consumer = mock_consumer([1, 2, 3, 4, 5, EMPTY]);
+        let messages = AsConsumer::recv_many(&consumer, 3, Timeout::from_secs(1)).unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.0.vgroup_id()).collect_vec(),
+            [1, 2, 3]
+        );
+        let messages = AsConsumer::recv_many(&consumer, 10, Timeout::from_secs(1)).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(AsConsumer::recv_many(&consumer, 0, Timeout::from_secs(1))
+            .unwrap()
+            .is_empty());
+        assert!(AsConsumer::recv_many(&consumer, 10, Timeout::from_secs(1))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn consumer_recv_many_keeps_error() {
+        let consumer = mock_consumer([1, BLIP, 2]);
+        let messages = AsConsumer::recv_many(&consumer, 3, Timeout::from_secs(1)).unwrap();
+        assert_eq!(messages.iter().map(|m| m.0.vgroup_id()).collect_vec(), [1]);
+        let Err(err) = AsConsumer::recv_many(&consumer, 3, Timeout::from_secs(1)) else {
+            panic!("expect the kept error");
+        };
+        assert!(err.to_string().contains("blip"), "{err}");
+        let messages = AsConsumer::recv_many(&consumer, 3, Timeout::from_secs(1)).unwrap();
+        assert_eq!(messages.iter().map(|m| m.0.vgroup_id()).collect_vec(), [2]);
+    }
+
+    #[test]
+    fn message_set_kind() {
+        let meta: MessageSet<&str, u32> = MessageSet::Meta("meta");
+        assert_eq!(meta.kind(), MessageKind::Meta);
+        let data: MessageSet<&str, u32> = MessageSet::Data(1);
+        assert_eq!(data.kind(), MessageKind::Data);
+        let both: MessageSet<&str, u32> = MessageSet::MetaData("meta", 1);
+        assert_eq!(both.kind(), MessageKind::MetaData);
+    }
+
+    #[test]
+    fn consumer_commit_all() {
+        let consumer = mock_consumer(1..=3);
+        let offsets = AsConsumer::iter_with_timeout(&consumer, Timeout::None)
+            .map(|m| m.unwrap().0)
+            .collect_vec();
+        AsConsumer::commit_all(&consumer, offsets).unwrap();
+        assert_eq!(committed_vgroups(&consumer), [1, 2, 3]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn async_on_sync_consumer() {
+        use futures::TryStreamExt;
+
+        let mut consumer = SyncMock(Arc::new(MockConsumer::new([])));
+        AsAsyncConsumer::subscribe(&mut consumer, ["topic"])
+            .await
+            .unwrap();
+        for vgroup_id in 1..=3 {
+            let offset = MockOffset::new("topic", vgroup_id, 0);
+            consumer.0.push(offset, MessageSet::Data(two_blocks()));
+        }
+        let offsets: Vec<_> = AsAsyncConsumer::stream_with_timeout(&consumer, Timeout::None)
+            .map_ok(|(offset, _)| offset)
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            offsets.iter().map(|o| o.vgroup_id()).collect_vec(),
+            [1, 2, 3]
+        );
+
+        AsAsyncConsumer::commit_all(&consumer, offsets)
+            .await
+            .unwrap();
+        assert_eq!(committed_vgroups(&consumer.0), [1, 2, 3]);
+        let assignments = AsAsyncConsumer::topic_assignment(&consumer, "topic")
+            .await
+            .unwrap();
+        assert_eq!(
+            assignments,
+            (1..=3).map(|v| Assignment::new(v, 1, 0, 1)).collect_vec()
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_committed() {
+        let consumer = mock_consumer([1, 1, 2]);
+        let offsets: Vec<_> = AsConsumer::iter_with_timeout(&consumer, Timeout::None)
+            .map(|m| m.unwrap().0)
+            .collect();
+        AsAsyncConsumer::commit_all(&consumer, offsets.into_iter().take(2))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            AsAsyncConsumer::committed(&consumer, "topic", 1)
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            AsAsyncConsumer::committed(&consumer, "topic", 2)
+                .await
+                .unwrap(),
+            -1
+        );
+        assert!(AsAsyncConsumer::committed(&consumer, "other", 1)
+            .await
+            .is_err());
+        assert!(AsConsumer::committed(&consumer, "other", 1).is_err());
+    }
+
+    #[test]
+    fn consumer_position() {
+        let consumer = mock_consumer([1, 1]);
+        assert_eq!(AsConsumer::position(&consumer, "topic", 1).unwrap(), 0);
+
+        let (offset, _) = AsConsumer::recv(&consumer).unwrap().unwrap();
+        assert_eq!(AsConsumer::position(&consumer, "topic", 1).unwrap(), 1);
+        assert_eq!(AsConsumer::committed(&consumer, "topic", 1).unwrap(), -1);
+
+        AsConsumer::commit(&consumer, offset).unwrap();
+        assert_eq!(AsConsumer::committed(&consumer, "topic", 1).unwrap(), 1);
+        assert!(AsConsumer::position(&consumer, "other", 1).is_err());
+    }
+
+    #[test]
+    fn timeout_deadline() {
+        let passed = Timeout::deadline(Instant::now());
+        assert!(passed.is_expired());
+        assert!(!passed.is_blocking());
+        assert_eq!(passed.as_duration(), Duration::ZERO);
+        assert_eq!(passed.as_raw_timeout(), 0);
+
+        let later = Timeout::deadline(Instant::now() + Duration::from_secs(10));
+        assert!(!later.is_expired());
+        assert!(later.as_duration() > Duration::from_secs(9));
+        assert!(later.as_raw_timeout() > 9000);
+        assert_eq!(later.min(Timeout::from_secs(1)), Timeout::from_secs(1));
+        assert_eq!(later.max(Timeout::from_secs(1)), later);
+        assert!(!Timeout::from_secs(1).is_expired());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_stop_after_deadline() {
+        use futures::StreamExt;
+
+        let consumer = mock_consumer([1, 2, 3, EMPTY]);
+        let passed = Timeout::deadline(Instant::now());
+        assert_eq!(AsConsumer::iter_with_timeout(&consumer, passed).count(), 0);
+        assert_eq!(consumer.stream_with_timeout(passed).count().await, 0);
+
+        let later = Timeout::deadline(Instant::now() + Duration::from_secs(10));
+        assert_eq!(AsConsumer::iter_with_timeout(&consumer, later).count(), 3);
+    }
+
+    #[test]
+    fn assignment_lag_progress() {
+        let normal = Assignment::new(1, 25, 0, 100);
+        assert_eq!(normal.lag(), 75);
+        assert_eq!(normal.progress(), 0.25);
+
+        let empty = Assignment::new(1, 10, 10, 10);
+        assert_eq!(empty.lag(), 0);
+        assert_eq!(empty.progress(), 1.0);
+
+        let caught_up = Assignment::new(1, 100, 0, 100);
+        assert_eq!(caught_up.lag(), 0);
+        assert_eq!(caught_up.progress(), 1.0);
+
+        let ahead = Assignment::new(1, 120, 0, 100);
+        assert_eq!(ahead.lag(), 0);
+        assert_eq!(ahead.progress(), 1.0);
+    }
+
+    #[test]
+    fn timeout_display_round_trip() {
+        assert_eq!(Timeout::Never.to_string(), "never");
+        assert_eq!(Timeout::None.to_string(), "none");
+        assert_eq!(Timeout::from_millis(1500).to_string(), "1500ms");
+        assert_eq!(Timeout::from_secs(150).to_string(), "2m30s");
+        assert_eq!(Timeout::from_secs(3600).to_string(), "1h");
+        assert_eq!(Timeout::from_millis(0).to_string(), "0ms");
+
+        let sub_millis = Timeout::Duration(Duration::from_micros(1));
+        assert_eq!(sub_millis.to_string(), "1ms");
+        assert_eq!(
+            Timeout::from_str(&sub_millis.to_string()).unwrap(),
+            Timeout::from_millis(1)
+        );
+
+        let mut timeouts = vec![Timeout::Never, Timeout::None];
+        for millis in [
+            0, 1, 999, 1000, 1001, 59999, 60000, 3599999, 3600000, 86400000,
+        ] {
+            timeouts.push(Timeout::from_millis(millis));
+        }
+        for secs in (0..100_000).step_by(997) {
+            timeouts.push(Timeout::from_secs(secs));
+        }
+        for timeout in timeouts {
+            let s = timeout.to_string();
+            assert_eq!(Timeout::from_str(&s).unwrap(), timeout, "{s}");
+
+            let json = serde_json::to_string(&timeout).unwrap();
+            assert_eq!(json, format!("\"{s}\""));
+            assert_eq!(serde_json::from_str::<Timeout>(&json).unwrap(), timeout);
+        }
+    }
+
+    #[test]
+    fn timeout_display_round_trip_random() {
+        // A fixed-seed linear congruential generator, so failures are reproducible.
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for _ in 0..10_000 {
+            seed = seed
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            // Up to 30 days, half of them in whole seconds to cover the `h`/`m`/`s` forms.
+            let mut millis = (seed >> 33) % (30 * 24 * 3600 * 1000);
+            if seed & 1 == 0 {
+                millis -= millis % 1000;
+            }
+            let timeout = Timeout::from_millis(millis);
+            let s = timeout.to_string();
+            assert_eq!(Timeout::from_str(&s).unwrap(), timeout, "{s}");
+        }
+    }
+
+    #[test]
+    fn assignment_set_offset() {
+        let mut assignments = vec![Assignment::new(1, 5, 2, 10), Assignment::new(2, 3, 0, 8)];
+        for a in &mut assignments {
+            a.set_offset((a.current_offset() - 4).max(a.begin()));
+        }
+        assert_eq!(
+            assignments,
+            [Assignment::new(1, 2, 2, 10), Assignment::new(2, 0, 0, 8)]
+        );
+
+        let a = Assignment::new(3, 1, 0, 4).with_offset(4);
+        assert_eq!(
+            (a.vgroup_id(), a.current_offset(), a.begin(), a.end()),
+            (3, 4, 0, 4)
+        );
+        assert!(a.is_caught_up());
+    }
+
+    #[test]
+    fn assignment_hash_set() {
+        use std::collections::HashSet;
+
+        let set: HashSet<_> = [
+            Assignment::new(1, 0, 0, 10),
+            Assignment::new(1, 0, 0, 10),
+            Assignment::new(1, 5, 0, 10),
+            Assignment::new(2, 0, 0, 10),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(set.len(), 3);
+        assert_eq!(Assignment::new(1, 0, 0, 10), Assignment::new(1, 0, 0, 10));
+        assert_ne!(Assignment::new(1, 0, 0, 10), Assignment::new(2, 0, 0, 10));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn data_deserialize() {
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Row {
+            ts: i64,
+            v: Option<i32>,
+        }
+        #[derive(Debug, Deserialize)]
+        struct DateTimeRow {
+            ts: chrono::DateTime<chrono::Local>,
+            v: Option<String>,
+        }
+
+        let rows: Vec<Row> = IsData::deserialize(&two_blocks()).unwrap();
+        assert_eq!(
+            rows,
+            [
+                Row {
+                    ts: 1000,
+                    v: Some(1)
+                },
+                Row { ts: 1001, v: None },
+                Row {
+                    ts: 2000,
+                    v: Some(2)
+                },
+                Row { ts: 2001, v: None },
+            ]
+        );
+
+        let rows: Vec<DateTimeRow> = IsAsyncData::deserialize(&two_blocks()).await.unwrap();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[0].ts.timestamp_millis(), 1000);
+        assert_eq!(rows[0].v.as_deref(), Some("1"));
+        assert_eq!(rows[1].v, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn data_fetch_block_with_table() {
+        let data = two_blocks();
+        let (name, block) = IsData::fetch_block_with_table(&data).unwrap().unwrap();
+        assert_eq!(name, None);
+        assert_eq!(block.nrows(), 2);
+        let (name, _) = IsAsyncData::fetch_block_with_table(&data)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("tb2"));
+        assert!(IsData::fetch_block_with_table(&data).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn data_as_single_block() {
+        let data = two_blocks();
+        let block = IsAsyncData::as_single_block(&data).await.unwrap().unwrap();
+        assert_eq!((block.nrows(), block.ncols()), (4, 2));
+        assert_eq!(block.field_names(), ["ts", "v"]);
+        assert!(block.is_null(3, 1));
+        assert_eq!(block.table_name(), None);
+        assert!(IsData::as_single_block(&data).unwrap().is_none());
+    }
+
+    #[test]
+    fn assignment_is_caught_up() {
+        assert!(Assignment::new(1, 100, 0, 100).is_caught_up());
+        assert!(!Assignment::new(1, 25, 0, 100).is_caught_up());
+        assert!(!Assignment::new(1, 0, 0, -1).is_caught_up());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_stream_resilient() {
+        use futures::TryStreamExt;
+
+        let consumer = mock_consumer([1, BLIP, BLIP, 2, 1]);
+        let policy = RetryPolicy::new(3, Backoff::fixed(Duration::from_millis(1)));
+        let messages: Vec<_> = AsAsyncConsumer::stream_resilient(&consumer, Timeout::None, policy)
+            .map_ok(|(offset, _)| (offset.vgroup_id(), offset.offset()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(messages, [(1, 0), (2, 0), (1, 1)]);
+        assert_eq!(consumer.resubscribes(), 2);
+        // Seek next to the last message after each resubscribe.
+        assert_eq!(seeks(&consumer), [(1, 1), (1, 1)]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_stream_with_policy() {
+        use futures::StreamExt;
+
+        let consumer = mock_consumer([1, BLIP, 2]);
+        let results: Vec<_> =
+            AsAsyncConsumer::stream_with_policy(&consumer, Timeout::None, ErrorPolicy::Fail)
+                .collect()
+                .await;
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_err());
+
+        // The count of consecutive errors is reset by a message.
+        let consumer = mock_consumer([1, BLIP, 2, BLIP, BLIP, 1]);
+        let policy = ErrorPolicy::Skip { max_consecutive: 2 };
+        let results: Vec<_> = AsAsyncConsumer::stream_with_policy(&consumer, Timeout::None, policy)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 3);
+        assert!(results[..2].iter().all(Result::is_ok));
+        assert!(matches!(&results[2], Err(err) if err.to_string().contains("blip")));
+        assert_eq!(consumer.remaining(), 1);
+
+        let routed = Arc::new(Mutex::new(Vec::new()));
+        let dead_letters = routed.clone();
+        let policy = ErrorPolicy::route(move |err, offset: Option<MockOffset>| {
+            dead_letters
+                .lock()
+                .unwrap()
+                .push((err.to_string(), offset.is_some()));
+        });
+        let consumer = mock_consumer([1, BLIP, BLIP, 2, BLIP]);
+        let results: Vec<_> = AsAsyncConsumer::stream_with_policy(&consumer, Timeout::None, policy)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(
+            *routed.lock().unwrap(),
+            vec![(blip().to_string(), false); 3]
+        );
+
+        // Decode errors are routed with the offset, and data is fetched for the others.
+        let routed = Arc::new(Mutex::new(Vec::new()));
+        let dead_letters = routed.clone();
+        let policy = ErrorPolicy::route(move |err, offset: Option<MockOffset>| {
+            dead_letters.lock().unwrap().push((err.to_string(), offset));
+        });
+        let consumer = mock_consumer([1, MALFORMED, 2]);
+        let results: Vec<_> = AsAsyncConsumer::stream_with_policy(&consumer, Timeout::None, policy)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 2);
+        let blocks = results.iter().map(|r| {
+            let (_, message) = r.as_ref().unwrap();
+            message.data_ref().map_or(0, Vec::len)
+        });
+        assert_eq!(blocks.collect::<Vec<_>>(), [2, 2]);
+        let routed = std::mem::take(&mut *routed.lock().unwrap());
+        assert_eq!(routed.len(), 1);
+        assert_eq!(routed[0].0, "malformed block");
+        assert_eq!(routed[0].1, Some(MockOffset::new("topic", 1, 1)));
+
+        // A fatal poll error ends the stream, and so do too many recoverable ones in a row.
+        let route = || ErrorPolicy::route(|_, _: Option<MockOffset>| {});
+        let consumer = mock_consumer([1, LOST]);
+        let results: Vec<_> =
+            AsAsyncConsumer::stream_with_policy(&consumer, Timeout::None, route())
+                .collect()
+                .await;
+        assert_eq!(results.len(), 2);
+        assert!(matches!(&results[1], Err(err) if err.to_string().contains("lost")));
+
+        let consumer = mock_consumer([BLIP; MAX_ROUTED_POLL_ERRORS + 1]);
+        let results: Vec<_> =
+            AsAsyncConsumer::stream_with_policy(&consumer, Timeout::None, route())
+                .collect()
+                .await;
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+        assert_eq!(consumer.remaining(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_stream_resilient_gives_up() {
+        use futures::StreamExt;
+
+        let consumer = mock_consumer([1, BLIP, BLIP, BLIP, 2]);
+        let policy = RetryPolicy::new(2, Backoff::fixed(Duration::from_millis(1)));
+        let results: Vec<_> = AsAsyncConsumer::stream_resilient(&consumer, Timeout::None, policy)
+            .collect()
+            .await;
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(matches!(&results[1], Err(err) if err.to_string().contains("blip")));
+        assert_eq!(consumer.resubscribes(), 2);
+
+        // Not recoverable errors are yielded as is.
+        let consumer = mock_consumer([1, LOST]);
+        let mut stream = AsAsyncConsumer::stream_resilient(&consumer, Timeout::None, policy);
+        assert!(stream.next().await.unwrap().is_ok());
+        let res = stream.next().await.unwrap();
+        assert!(matches!(res, Err(err) if err.to_string().contains("connection lost")));
+        drop(stream);
+        assert_eq!(consumer.resubscribes(), 0);
+    }
 
-    fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_stream_by_vgroup() {
+        use futures::TryStreamExt;
 
-    fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
-}
+        let consumer = mock_consumer([1, 2, 1, 3, 2]);
+        let messages: Vec<_> = AsAsyncConsumer::stream_by_vgroup(&consumer, Timeout::None)
+            .map_ok(|(vgroup_id, offset, _)| (vgroup_id, offset.vgroup_id(), offset.offset()))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(
+            messages,
+            [(1, 1, 0), (2, 2, 0), (1, 1, 1), (3, 3, 0), (2, 2, 1)]
+        );
+    }
 
-pub struct MessageSetsIter<'a, C> {
-    consumer: &'a C,
-    timeout: Timeout,
-}
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_stream_autocommit() {
+        use futures::TryStreamExt;
 
-impl<'a, C> Iterator for MessageSetsIter<'a, C>
-where
-    C: AsConsumer,
-{
-    type Item = RawResult<(C::Offset, MessageSet<C::Meta, C::Data>)>;
+        let consumer = mock_consumer([1, 2, 2]);
+        let mut stream = AsAsyncConsumer::stream_autocommit(&consumer, Timeout::None);
+        let mut processed = 0;
+        while let Some(message) = stream.try_next().await.unwrap() {
+            assert_eq!(message.message().kind(), MessageKind::Data);
+            processed += 1;
+            message.commit().await.unwrap();
+        }
+        assert_eq!(processed, 3);
+        assert_eq!(committed_vgroups(&consumer), [1, 2, 2]);
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.consumer.recv_timeout(self.timeout).transpose()
+    #[tokio::test(flavor = "multi_thread")]
+    async fn commit_guard() {
+        async fn process(consumer: &MockConsumer, fail: bool) -> RawResult<()> {
+            let (offset, _) = AsAsyncConsumer::recv_timeout(consumer, Timeout::None)
+                .await?
+                .unwrap();
+            let _guard = AsAsyncConsumer::guard(consumer, offset);
+            if fail {
+                return Err(RawError::from_string("early return"));
+            }
+            Ok(())
+        }
+
+        let consumer = mock_consumer([1, 2, 3, 4]);
+
+        // Implicit commit on every return path.
+        process(&consumer, false).await.unwrap();
+        assert!(process(&consumer, true).await.is_err());
+        let commits = consumer.commits().into_iter().map(|c| (c.1, c.2));
+        assert_eq!(commits.collect_vec(), [(1, 1), (2, 1)]);
+
+        // Explicit commit.
+        let (offset, _) = AsAsyncConsumer::recv_timeout(&consumer, Timeout::None)
+            .await
+            .unwrap()
+            .unwrap();
+        let guard = AsAsyncConsumer::guard(&consumer, offset);
+        assert_eq!(guard.offset().map(|o| o.vgroup_id()), Some(3));
+        guard.commit().await.unwrap();
+        assert_eq!(committed_vgroups(&consumer), [1, 2, 3]);
+
+        // Abandoned.
+        let (offset, _) = AsAsyncConsumer::recv_timeout(&consumer, Timeout::None)
+            .await
+            .unwrap()
+            .unwrap();
+        let guard = AsAsyncConsumer::guard(&consumer, offset);
+        assert_eq!(guard.abandon().map(|o| o.vgroup_id()), Some(4));
+        assert_eq!(committed_vgroups(&consumer), [1, 2, 3]);
     }
-}
 
-#[async_trait::async_trait]
-pub trait AsAsyncConsumer: Sized + Send + Sync {
-    type Offset: IsOffset;
-    type Meta: IsAsyncMeta;
-    type Data: IsAsyncData;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn commit_guard_error() {
+        let consumer = mock_consumer([1]).with_rejected_commits("topic", 1);
+        let (offset, _) = AsAsyncConsumer::recv_timeout(&consumer, Timeout::None)
+            .await
+            .unwrap()
+            .unwrap();
+        let guard = AsAsyncConsumer::guard(&consumer, offset);
+        assert!(guard.commit().await.is_err());
+        assert!(consumer.commits().is_empty());
+    }
 
-    fn default_timeout(&self) -> Timeout;
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_seek_to_beginning_end() {
+        let consumer = mock_consumer([]);
+        for assignment in [
+            Assignment::new(1, 5, 2, 10),
+            Assignment::new(2, 3, 3, 3),
+            Assignment::new(3, 4, 0, 8),
+        ] {
+            consumer.assign("topic", assignment);
+        }
+        AsAsyncConsumer::seek_to_beginning(&consumer, "topic")
+            .await
+            .unwrap();
+        assert_eq!(seeks(&consumer), [(1, 2), (3, 0)]);
 
-    async fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
-        &mut self,
-        topics: I,
-    ) -> RawResult<()>;
+        AsAsyncConsumer::seek_to_end(&consumer, "topic")
+            .await
+            .unwrap();
+        assert_eq!(seeks(&consumer)[2..], [(1, 10), (3, 8)]);
 
-    /// None means wait until next message come.
-    async fn recv_timeout(
-        &self,
-        timeout: Timeout,
-    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>>;
+        assert!(AsAsyncConsumer::seek_to_end(&consumer, "other")
+            .await
+            .is_err());
+    }
 
-    fn stream_with_timeout(
-        &self,
-        timeout: Timeout,
-    ) -> Pin<
-        Box<
-            dyn '_
-                + Send
-                + futures::Stream<
-                    Item = RawResult<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>,
-                >,
-        >,
-    > {
-        Box::pin(futures::stream::unfold((), move |_| async move {
-            let weather = self.recv_timeout(timeout).await.transpose();
-            weather.map(|res| (res, ()))
-        }))
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_seek_to_unknown_end() {
+        let consumer = mock_consumer([]);
+        consumer.assign("topic", Assignment::new(1, 5, 2, 10));
+        consumer.assign("topic", Assignment::new(2, 0, 0, -1));
+        let err = AsAsyncConsumer::seek_to_end(&consumer, "topic")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("vgroup 2 is unknown"), "{err}");
+        assert!(consumer.seeks().is_empty());
+
+        AsAsyncConsumer::seek_to_beginning(&consumer, "topic")
+            .await
+            .unwrap();
+        assert_eq!(seeks(&consumer), [(1, 2), (2, 0)]);
     }
 
-    fn stream(
-        &self,
-    ) -> Pin<
-        Box<
-            dyn '_
-                + Send
-                + futures::Stream<
-                    Item = RawResult<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>,
-                >,
-        >,
-    > {
-        self.stream_with_timeout(self.default_timeout())
+    #[test]
+    fn offset_value() {
+        let consumer = mock_consumer([1, 2, 1]);
+        let offsets = AsConsumer::iter_with_timeout(&consumer, Timeout::None)
+            .map(|m| m.map(|(offset, _)| (offset.vgroup_id(), offset.offset())))
+            .collect::<RawResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(offsets, [(1, 0), (2, 0), (1, 1)]);
     }
 
-    async fn commit(&self, offset: Self::Offset) -> RawResult<()>;
+    #[test]
+    fn consumer_topic_timeout() {
+        let control = Timeout::from_secs(1);
+        let mut consumer = mock_consumer([1, 1, 1, 1]).with_topic_timeout("control", control);
+        AsConsumer::recv(&consumer).unwrap();
+        AsConsumer::subscribe(&mut consumer, ["control"]).unwrap();
+        AsConsumer::recv(&consumer).unwrap();
+        AsConsumer::subscribe(&mut consumer, ["data"]).unwrap();
+        AsConsumer::recv(&consumer).unwrap();
+        // The poll may span topics, use the default.
+        AsConsumer::subscribe(&mut consumer, ["control", "data"]).unwrap();
+        AsConsumer::recv(&consumer).unwrap();
 
-    async fn commit_offset(
-        &self,
-        topic_name: &str,
-        vgroup_id: VGroupId,
-        offset: i64,
-    ) -> RawResult<()>;
+        let default = AsConsumer::default_timeout(&consumer);
+        assert_eq!(
+            consumer.poll_timeouts(),
+            [default, control, default, default]
+        );
+    }
 
-    async fn unsubscribe(self) {
-        drop(self)
+    #[tokio::test]
+    async fn consumer_topic_timeout_async() {
+        use futures::TryStreamExt;
+
+        let mut consumer = mock_consumer([1]).with_topic_timeout("control", Timeout::Never);
+        AsAsyncConsumer::subscribe(&mut consumer, ["control"])
+            .await
+            .unwrap();
+        assert_eq!(
+            AsAsyncConsumer::effective_timeout(&consumer),
+            Timeout::Never
+        );
+        let mut stream = AsAsyncConsumer::stream(&consumer);
+        assert!(stream.try_next().await.unwrap().is_some());
+        drop(stream);
+        assert_eq!(consumer.poll_timeouts(), [Timeout::Never]);
     }
 
-    async fn list_topics(&self) -> RawResult<Vec<String>>;
+    #[test]
+    fn consumer_counters() {
+        let counters = ConsumerCounters::default();
+        counters.record_poll(Duration::from_millis(3), Some(MessageKind::Data));
+        counters.record_poll(Duration::from_millis(2), Some(MessageKind::MetaData));
+        counters.record_poll(Duration::from_millis(1), None);
+        counters.record_bytes(100);
+        counters.record_commit(1, &Ok(()));
+        counters.record_commit::<()>(2, &Err(RawError::from_string("commit rejected")));
 
-    async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>>;
+        let metrics = counters.snapshot();
+        assert_eq!(
+            metrics,
+            ConsumerMetrics {
+                polls: 3,
+                empty_polls: 1,
+                meta_messages: 0,
+                data_messages: 1,
+                metadata_messages: 1,
+                bytes: 100,
+                commits: 2,
+                commit_failures: 1,
+                last_poll_latency_us: 1000,
+            }
+        );
+        assert_eq!(serde_json::to_value(metrics).unwrap()["polls"], 3);
 
-    async fn topic_assignment(&self, topic: &str) -> Vec<Assignment>;
+        counters.reset();
+        assert_eq!(counters.snapshot(), ConsumerMetrics::default());
+    }
 
-    async fn offset_seek(&mut self, topic: &str, vgroup_id: VGroupId, offset: i64)
-        -> RawResult<()>;
+    #[test]
+    fn consumer_observer() {
+        #[derive(Default)]
+        struct Counting {
+            polls: AtomicU64,
+            bytes: AtomicU64,
+            commits: Mutex<Vec<i64>>,
+            errors: AtomicU64,
+        }
+        impl ConsumerObserver for Counting {
+            fn on_poll(&self, _latency: Duration, kind: Option<MessageKind>) {
+                assert_eq!(kind, Some(MessageKind::Data));
+                self.polls.fetch_add(1, Ordering::Relaxed);
+            }
+            fn on_bytes(&self, bytes: usize) {
+                self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+            }
+            fn on_commit(&self, offset: i64) {
+                self.commits.lock().unwrap().push(offset);
+            }
+            fn on_error(&self, _err: &RawError) {
+                self.errors.fetch_add(1, Ordering::Relaxed);
+            }
+        }
 
-    async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
+        let observer = Arc::new(Counting::default());
+        let counters = ConsumerCounters::with_observer(Some(observer.clone()));
+        counters.record_poll(Duration::from_millis(1), Some(MessageKind::Data));
+        counters.record_bytes(10);
+        counters.record_commit(5, &Ok(()));
+        counters.record_commit::<()>(6, &Err(RawError::from_string("commit rejected")));
+        counters.record_error(&RawError::from_string("poll failed"));
 
-    async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64>;
-}
+        assert_eq!(observer.polls.load(Ordering::Relaxed), 1);
+        assert_eq!(observer.bytes.load(Ordering::Relaxed), 10);
+        assert_eq!(*observer.commits.lock().unwrap(), [5]);
+        assert_eq!(observer.errors.load(Ordering::Relaxed), 2);
+        assert_eq!(counters.snapshot().commits, 2);
+        assert!(format!("{counters:?}").contains("observer: Some(ConsumerObserver)"));
+    }
 
-/// Marker trait to impl sync on async impl.
-pub trait SyncOnAsync {}
+    #[test]
+    fn commit_hook() {
+        let hook = CommitHook::default();
+        hook.call("topic", 1, 0, &Ok(()));
 
-pub trait AsyncOnSync {}
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        hook.set({
+            let calls = calls.clone();
+            let hook = hook.clone();
+            move |topic, vgroup_id, offset, res| {
+                calls
+                    .lock()
+                    .unwrap()
+                    .push((topic.to_string(), vgroup_id, offset, res.is_ok()));
+                if offset < 0 {
+                    panic!("bad offset");
+                }
+                // Not deadlock, the hook is called outside the lock.
+                if offset == 9 {
+                    hook.set(|_, _, _, _| ());
+                }
+            }
+        });
+        hook.call("topic", 1, 5, &Ok(()));
+        hook.call(
+            "topic",
+            2,
+            -1,
+            &Err(RawError::from_string("commit rejected")),
+        );
+        hook.call("topic", 1, 9, &Ok(()));
+        hook.call("topic", 1, 10, &Ok(()));
 
-impl<C> AsConsumer for C
-where
-    C: AsAsyncConsumer + SyncOnAsync,
-    C::Meta: IsMeta,
-    C::Data: IntoIterator<Item = RawResult<RawBlock>>,
-{
-    type Offset = C::Offset;
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                ("topic".to_string(), 1, 5, true),
+                ("topic".to_string(), 2, -1, false),
+                ("topic".to_string(), 1, 9, true),
+            ]
+        );
+        assert_eq!(format!("{hook:?}"), "CommitHook(Some(\"Fn\"))");
+    }
 
-    type Meta = C::Meta;
+    #[test]
+    fn offset_key() {
+        assert_eq!(MockOffset::new("topic", 3, 0).offset_key(), "db:topic:3");
+        assert_eq!(
+            MockOffset::new("topic", 3, 0).escaped_offset_key(),
+            "db:topic:3"
+        );
 
-    type Data = C::Data;
+        struct Named;
+        impl IsOffset for Named {
+            fn database(&self) -> &str {
+                "a:b"
+            }
 
-    fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
-        &mut self,
-        topics: I,
-    ) -> RawResult<()> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::subscribe(self, topics))
+            fn topic(&self) -> &str {
+                "100%"
+            }
+
+            fn vgroup_id(&self) -> VGroupId {
+                -1
+            }
+        }
+        assert_eq!(Named.offset_key(), "a:b:100%:-1");
+        assert_eq!(Named.escaped_offset_key(), "a%3Ab:100%25:-1");
     }
 
-    fn recv_timeout(
-        &self,
-        timeout: Timeout,
-    ) -> RawResult<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::recv_timeout(self, timeout))
+    #[test]
+    fn offset_seek_round_trip() {
+        let consumer = mock_consumer([1, 1, 1]);
+        let (first, _) = AsConsumer::recv_timeout(&consumer, Timeout::None)
+            .unwrap()
+            .unwrap();
+        let saved = (first.topic().to_string(), first.vgroup_id(), first.offset());
+        AsConsumer::recv_timeout(&consumer, Timeout::None).unwrap();
+        assert_eq!(
+            AsConsumer::position(&consumer, &saved.0, saved.1).unwrap(),
+            2
+        );
+
+        AsConsumer::offset_seek(&consumer, &saved.0, saved.1, saved.2).unwrap();
+        assert_eq!(consumer.seeks(), std::slice::from_ref(&saved));
+        assert_eq!(
+            AsConsumer::position(&consumer, &saved.0, saved.1).unwrap(),
+            saved.2
+        );
     }
 
-    fn commit(&self, offset: Self::Offset) -> RawResult<()> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::commit(self, offset))
+    #[test]
+    fn offset_seek_mid_stream() {
+        // The script replays the messages after the seek, as the server does.
+        let consumer = mock_consumer([1, 1]);
+        consumer.push(
+            MockOffset::new("topic", 1, 0),
+            MessageSet::Data(two_blocks()),
+        );
+        consumer.push(
+            MockOffset::new("topic", 1, 1),
+            MessageSet::Data(two_blocks()),
+        );
+        let mut offsets = Vec::new();
+        for message in AsConsumer::iter_with_timeout(&consumer, Timeout::None) {
+            let (offset, _) = message.unwrap();
+            offsets.push(offset.offset());
+            if offset.offset() == 1 && offsets.len() == 2 {
+                // Seek back while the iterator borrows the consumer.
+                AsConsumer::offset_seek(&consumer, "topic", 1, 0).unwrap();
+            }
+        }
+        assert_eq!(offsets, [0, 1, 0, 1]);
+        assert_eq!(seeks(&consumer), [(1, 0)]);
     }
 
-    fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::commit_offset(
-            self, topic_name, vgroup_id, offset,
-        ))
+    #[tokio::test]
+    async fn offset_seek_mid_stream_async() {
+        use futures::TryStreamExt;
+
+        let consumer = mock_consumer([1, 1]);
+        consumer.push(
+            MockOffset::new("topic", 1, 0),
+            MessageSet::Data(two_blocks()),
+        );
+        let mut stream = AsAsyncConsumer::stream_with_timeout(&consumer, Timeout::None);
+        let mut offsets = Vec::new();
+        while let Some((offset, _)) = stream.try_next().await.unwrap() {
+            offsets.push(offset.offset());
+            if offsets.len() == 2 {
+                AsAsyncConsumer::offset_seek(&consumer, "topic", 1, 0)
+                    .await
+                    .unwrap();
+            }
+        }
+        assert_eq!(offsets, [0, 1, 0]);
+        assert_eq!(seeks(&consumer), [(1, 0)]);
     }
 
-    fn list_topics(&self) -> RawResult<Vec<String>> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::list_topics(self))
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_topic_assignment() {
+        let consumer = SyncMock(Arc::new(mock_consumer([])));
+        consumer.0.assign("topic", Assignment::new(1, 5, 2, 10));
+        let assignments = AsAsyncConsumer::topic_assignment(&consumer, "topic")
+            .await
+            .unwrap();
+        assert_eq!(assignments, [Assignment::new(1, 5, 2, 10)]);
+        assert_eq!(
+            AsConsumer::topic_assignment(&consumer, "topic").unwrap(),
+            assignments
+        );
+
+        let err = AsAsyncConsumer::topic_assignment(&consumer, "other")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("topic other is not assigned"));
     }
 
-    fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::assignments(self))
+    #[test]
+    fn topic_assignments_serde() {
+        let consumer = mock_consumer([]);
+        consumer.assign("topic", Assignment::new(1, 5, 2, 10));
+        let topics = AsConsumer::topic_assignments(&consumer);
+        assert_eq!(topics.len(), 1);
+        assert_eq!(topics[0].topic(), "topic");
+        assert_eq!(
+            topics[0].assignments(),
+            AsConsumer::topic_assignment(&consumer, "topic").unwrap()
+        );
+
+        let json = serde_json::to_value(&topics[0]).unwrap();
+        assert_eq!(json["topic"], "topic");
+        assert_eq!(json["assignments"][0]["vgroup_id"], 1);
+        let back: TopicAssignment = serde_json::from_value(json).unwrap();
+        assert_eq!(back, topics[0]);
     }
 
-    fn offset_seek(&mut self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::offset_seek(
-            self, topic, vg_id, offset,
-        ))
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_lag() {
+        let consumer = mock_consumer([]);
+        for assignment in [
+            Assignment::new(1, 5, 2, 10),
+            Assignment::new(2, 12, 0, 10),
+            Assignment::new(3, 0, 0, -1),
+        ] {
+            consumer.assign("topic", assignment);
+        }
+        let lags = AsAsyncConsumer::lag(&consumer).await.unwrap();
+        assert_eq!(lags.len(), 1);
+        assert_eq!(lags[0].topic, "topic");
+        assert_eq!(
+            lags[0].vgroups.iter().map(|v| v.lag).collect_vec(),
+            [5, 0, 0]
+        );
+        assert_eq!(lags[0].total, 5);
+        assert_eq!(lags[0].vgroups[0].current, 5);
+        assert_eq!(AsConsumer::lag(&consumer).unwrap(), lags);
     }
 
-    fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::committed(self, topic, vgroup_id))
+    #[test]
+    fn iter_stop_on_error() {
+        let consumer = mock_consumer([1, 2, 3, LOST, LOST, LOST, LOST]);
+        let mut messages = AsConsumer::iter_with_timeout(&consumer, Timeout::None).stop_on_error();
+        let vgroups = (&mut messages)
+            .map(|(offset, _)| offset.vgroup_id())
+            .collect_vec();
+        assert_eq!(vgroups, [1, 2, 3]);
+        assert!(messages.error().is_some());
+        assert!(messages.next().is_none());
+        let err = messages.into_error().unwrap();
+        assert!(err.to_string().contains("connection lost"), "{err}");
+
+        // The plain iterator keeps yielding the errors.
+        let messages = AsConsumer::iter_with_timeout(&consumer, Timeout::None);
+        assert_eq!(messages.filter(Result::is_err).count(), 3);
     }
 
-    fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
-        crate::block_in_place_or_global(<C as AsAsyncConsumer>::position(self, topic, vgroup_id))
+    #[test]
+    fn timeout_into_deadline() {
+        let timeout = Timeout::from_millis(100).into_deadline();
+        let Timeout::Deadline(deadline) = timeout else {
+            panic!("expect a deadline, got {timeout:?}");
+        };
+        assert!(deadline > Instant::now());
+        for timeout in [
+            Timeout::None,
+            Timeout::Never,
+            Timeout::Duration(Duration::MAX),
+        ] {
+            assert_eq!(timeout.into_deadline(), timeout);
+        }
     }
-}
 
-// #[async_trait::async_trait]
-// impl<C> AsAsyncConsumer for C
-// where
-//     C: AsConsumer + AsyncOnSync + Send + Sync + 'static,
-//     C::Error: 'static + Sync + Send,
-//     C::Meta: IsAsyncMeta + Send,
-//     C::Offset: 'static + Sync + Send,
-//     C::Data: 'static + Send + Sync,
-// {
-//     type Error = C::Error;
+    #[test]
+    fn paused_vgroups() {
+        let paused = PausedVGroups::default();
+        assert!(!paused.skip(&MockOffset::new("topic", 1, 0)));
+
+        paused.pause("topic", 1);
+        assert!(paused.skip(&MockOffset::new("topic", 1, 3)));
+        assert!(paused.skip(&MockOffset::new("topic", 1, 4)));
+        // Messages of other vgroups, and messages without offsets, are delivered.
+        assert!(!paused.skip(&MockOffset::new("topic", 2, 3)));
+        assert!(!paused.skip(&MockOffset::new("topic", 1, -1)));
+        paused.pause("topic", 1);
+        assert_eq!(paused.vgroups(), BTreeSet::from([("topic".to_string(), 1)]));
+        assert_eq!(paused.resume("topic", 1), Some(3));
+        assert!(!paused.skip(&MockOffset::new("topic", 1, 5)));
+
+        paused.pause("topic", 1);
+        assert!(paused.skip(&MockOffset::new("topic", 1, 5)));
+        paused.reset("topic", 1);
+        assert_eq!(paused.resume("topic", 1), None);
+
+        paused.pause("topic", 2);
+        paused.clear();
+        assert!(paused.vgroups().is_empty());
+    }
+
+    #[test]
+    fn message_envelope() {
+        /// Message of a raw buffer, as both meta and data.
+        struct RawMessage(&'static [u8]);
+
+        impl IsMeta for RawMessage {
+            fn as_raw_meta(&self) -> RawResult<RawMeta> {
+                Ok(RawMeta::new(self.0.into()))
+            }
+
+            fn as_json_meta(&self) -> RawResult<JsonMeta> {
+                Err(RawError::from_string("no json meta"))
+            }
+        }
+
+        impl IsData for RawMessage {
+            fn as_raw_data(&self) -> RawResult<RawData> {
+                Ok(RawData::from(self.0))
+            }
+
+            fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
+                Ok(None)
+            }
+        }
+
+        const META: &[u8] = b"\x04\0\0\0\x32\0meta";
+        const DATA: &[u8] = b"\x04\0\0\0\x02\0data";
+        let messages = [
+            MessageSet::Meta(RawMessage(META)),
+            MessageSet::Data(RawMessage(DATA)),
+            MessageSet::MetaData(RawMessage(META), RawMessage(DATA)),
+        ];
+        for message in messages {
+            let envelope = message.to_envelope().unwrap();
+            assert_eq!(envelope.kind, message.kind());
+            let json = serde_json::to_string(&envelope).unwrap();
+            let envelope: MessageEnvelope = serde_json::from_str(&json).unwrap();
+
+            let decoded = MessageSet::from_envelope(envelope).unwrap();
+            assert_eq!(decoded.kind(), message.kind());
+            assert_eq!(
+                decoded.meta().map(|m| m.as_bytes()),
+                message.meta().map(|m| m.0)
+            );
+            assert_eq!(
+                decoded.data_ref().map(|d| d.as_bytes().to_vec()),
+                message.data_ref().map(|d| d.0.to_vec())
+            );
+        }
+
+        let meta = RawMeta::new(META.into()).to_frame().to_vec();
+        let envelope = MessageEnvelope {
+            kind: MessageKind::MetaData,
+            meta: Some(meta.clone()),
+            data: None,
+        };
+        let err = MessageSet::from_envelope(envelope).unwrap_err();
+        assert!(
+            err.to_string().contains("invalid message envelope"),
+            "{err}"
+        );
+
+        // Truncated or unframed buffers are rejected before their header is read.
+        for meta in [meta[..meta.len() - 1].to_vec(), META.to_vec(), vec![]] {
+            let envelope = MessageEnvelope {
+                kind: MessageKind::Meta,
+                meta: Some(meta),
+                data: None,
+            };
+            let err = MessageSet::from_envelope(envelope).unwrap_err();
+            assert!(err.to_string().contains("invalid raw frame"), "{err}");
+        }
+    }
+
+    #[test]
+    fn iter_data_only_skipping() {
+        let vgroups = |consumer: &MockConsumer, max_consecutive_errors| {
+            AsConsumer::iter_data_only_skipping(consumer, Timeout::None, max_consecutive_errors)
+                .map(|res| res.map(|(offset, _)| offset.vgroup_id()))
+                .collect_vec()
+        };
+
+        // Errors in between messages never reach the threshold.
+        let consumer = mock_consumer([BLIP, 1, BLIP, 2, BLIP]);
+        assert_eq!(vgroups(&consumer, 2).len(), 2);
+
+        let consumer = mock_consumer([1, BLIP, 2, BLIP, BLIP, BLIP, 3]);
+        let results = vgroups(&consumer, 3);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[..2].iter().flatten().collect_vec(), [&1, &2]);
+        assert!(matches!(&results[2], Err(err) if err.to_string().contains("blip")));
+        assert_eq!(consumer.remaining(), 1);
+
+        // Persistent errors end the iteration.
+        let consumer = mock_consumer([1, LOST, LOST, LOST]);
+        let results = vgroups(&consumer, 2);
+        assert_eq!(results.len(), 2);
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn iter_and_commit() {
+        let commits = |consumer: &MockConsumer| {
+            let commits = consumer.commits().into_iter();
+            commits
+                .map(|(_, vgroup_id, offset)| (vgroup_id, offset))
+                .collect_vec()
+        };
+        let consumer = mock_consumer([1, 2, 1]);
+        let mut iter = AsConsumer::iter_and_commit(&consumer, Timeout::None);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(consumer.commits().is_empty());
+        // Advancing commits the previous message.
+        assert!(iter.next().unwrap().is_ok());
+        assert_eq!(commits(&consumer), [(1, 1)]);
+        assert!(iter.next().unwrap().is_ok());
+        assert!(iter.next().is_none());
+        assert_eq!(commits(&consumer), [(1, 1), (2, 1), (1, 2)]);
+
+        // Crash before advancing: the last message is left uncommitted.
+        let consumer = mock_consumer([1, 1]);
+        let mut iter = AsConsumer::iter_and_commit(&consumer, Timeout::None);
+        iter.next().unwrap().unwrap();
+        iter.next().unwrap().unwrap();
+        drop(iter);
+        assert_eq!(commits(&consumer), [(1, 1)]);
+
+        // So it is redelivered from the committed offset after a restart.
+        let committed = AsConsumer::committed(&consumer, "topic", 1).unwrap();
+        let restarted = MockConsumer::new([(
+            MockOffset::new("topic", 1, committed),
+            MessageSet::Data(two_blocks()),
+        )]);
+        let mut iter = AsConsumer::iter_with_timeout(&restarted, Timeout::None);
+        let (offset, _) = iter.next().unwrap().unwrap();
+        assert_eq!(offset.offset(), 1);
+
+        // Failed commits are yielded and the iteration goes on.
+        let consumer = mock_consumer([1, 2]).with_rejected_commits("topic", 1);
+        let results = AsConsumer::iter_and_commit(&consumer, Timeout::None).collect_vec();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(&results[1], Err(err) if err.to_string().contains("rejected")));
+        assert!(results[2].is_ok());
+        assert_eq!(committed_vgroups(&consumer), [2]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_recv_batch() {
+        let consumer = mock_consumer([1, 2, 3, EMPTY, EMPTY]);
+        let batch = AsAsyncConsumer::recv_batch(&consumer, 0, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(consumer.remaining(), 5);
+
+        let batch = AsAsyncConsumer::recv_batch(&consumer, 2, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(batch.iter().map(|m| m.0.vgroup_id()).collect_vec(), [1, 2]);
+
+        // Returns early when no more message.
+        let batch = AsAsyncConsumer::recv_batch(&consumer, 5, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(batch.iter().map(|m| m.0.vgroup_id()).collect_vec(), [3]);
+        let batch = AsAsyncConsumer::recv_batch(&consumer, 5, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert!(batch.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_recv_batch_huge_timeout() {
+        // A duration too long for a deadline waits forever instead of overflow.
+        let consumer = mock_consumer([1, 2, 3]);
+        let timeout: Timeout = "18446744073709551615".parse().unwrap();
+        let batch = AsAsyncConsumer::recv_batch(&consumer, 2, timeout)
+            .await
+            .unwrap();
+        assert_eq!(batch.iter().map(|m| m.0.vgroup_id()).collect_vec(), [1, 2]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_batch_receives_keep_error() {
+        let consumer = mock_consumer([1, BLIP, 2, 3, BLIP, 4]);
+        let batch = AsAsyncConsumer::recv_batch(&consumer, 3, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(batch.iter().map(|m| m.0.vgroup_id()).collect_vec(), [1]);
+        // The kept error is returned by the next batch receive of any kind.
+        let Err(err) = AsAsyncConsumer::collect_n(&consumer, 3, Timeout::from_secs(1)).await else {
+            panic!("expect the kept error");
+        };
+        assert!(err.to_string().contains("blip"), "{err}");
+        let messages = AsAsyncConsumer::collect_n(&consumer, 3, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.0.vgroup_id()).collect_vec(),
+            [2, 3]
+        );
+        assert!(
+            AsAsyncConsumer::recv_many(&consumer, 3, Timeout::from_secs(1))
+                .await
+                .is_err()
+        );
+        let messages = AsAsyncConsumer::recv_many(&consumer, 3, Timeout::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(messages.iter().map(|m| m.0.vgroup_id()).collect_vec(), [4]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_pause_resume() {
+        let consumer = mock_consumer([1, 2, 1, 3]);
+        AsAsyncConsumer::pause(&consumer, "topic", 1).await.unwrap();
+        assert!(AsAsyncConsumer::pause(&consumer, "other", 1).await.is_err());
+
+        let mut vgroups = Vec::new();
+        while let Some((offset, _)) = AsAsyncConsumer::recv_timeout(&consumer, Timeout::None)
+            .await
+            .unwrap()
+        {
+            vgroups.push(offset.vgroup_id());
+        }
+        assert_eq!(vgroups, [2, 3]);
+
+        // Messages of the paused vgroup are received in order once resumed.
+        AsAsyncConsumer::resume(&consumer, "topic", 1)
+            .await
+            .unwrap();
+        let messages = AsConsumer::iter_with_timeout(&consumer, Timeout::None)
+            .map_ok(|m| (m.0.vgroup_id(), m.0.offset()))
+            .try_collect::<_, Vec<_>, _>()
+            .unwrap();
+        assert_eq!(messages, [(1, 0), (1, 1)]);
+    }
+
+    #[derive(Debug)]
+    struct MockTmqBuilder(crate::Dsn);
+
+    #[async_trait::async_trait]
+    impl crate::AsyncTBuilder for MockTmqBuilder {
+        type Target = crate::Dsn;
+
+        fn from_dsn<D: crate::IntoDsn>(dsn: D) -> RawResult<Self> {
+            Ok(Self(dsn.into_dsn()?))
+        }
+
+        fn client_version() -> &'static str {
+            "mock"
+        }
+
+        async fn server_version(&self) -> RawResult<&str> {
+            Ok("mock")
+        }
+
+        async fn get_edition(&self) -> RawResult<crate::util::Edition> {
+            Ok(crate::util::Edition::new("community", false))
+        }
+
+        async fn ping(&self, _: &mut Self::Target) -> RawResult<()> {
+            Ok(())
+        }
+
+        async fn ready(&self) -> bool {
+            true
+        }
+
+        async fn build(&self) -> RawResult<Self::Target> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn consumer_builder() {
+        let dsn = ConsumerBuilder::<MockTmqBuilder>::new()
+            .server("ws://localhost:6041?token=abc&auto.offset.reset=latest")
+            .group_id("g1")
+            .client_id("c1")
+            .offset_reset(OffsetReset::Earliest)
+            .auto_commit(true)
+            .auto_commit_interval(Duration::from_millis(500))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(dsn.driver, "ws");
+        let config = TmqConfig::from_params(&dsn.params, true).unwrap();
+        assert_eq!(config.group_id, "g1");
+        assert_eq!(config.client_id.as_deref(), Some("c1"));
+        assert_eq!(config.offset_reset, Some(OffsetReset::Earliest));
+        assert_eq!(config.auto_commit, Some(true));
+        assert_eq!(
+            config.auto_commit_interval,
+            Some(Duration::from_millis(500))
+        );
+        assert_eq!(config.params["token"], "abc");
+
+        // Group id in the server DSN is fine.
+        let builder = ConsumerBuilder::<MockTmqBuilder>::new().server("taos://?group.id=g2");
+        assert_eq!(builder.to_dsn().unwrap().params["group.id"], "g2");
+    }
+
+    #[tokio::test]
+    async fn consumer_builder_validation() {
+        let build = |builder: ConsumerBuilder<MockTmqBuilder>| async move {
+            builder.build().await.unwrap_err().to_string()
+        };
+        let builder = || ConsumerBuilder::<MockTmqBuilder>::new().server("ws://localhost:6041");
+
+        let err = build(ConsumerBuilder::new().group_id("g1")).await;
+        assert!(err.contains("server is required"), "{err}");
+        let err = build(builder()).await;
+        assert!(err.contains("group id is required"), "{err}");
+        let err = build(builder().group_id("")).await;
+        assert!(err.contains("group id is required"), "{err}");
+
+        let interval = Duration::from_secs(1);
+        let err = build(builder().group_id("g1").auto_commit_interval(interval)).await;
+        assert!(err.contains("auto commit is not enabled"), "{err}");
+        let err = build(
+            builder()
+                .group_id("g1")
+                .auto_commit(false)
+                .auto_commit_interval(interval),
+        )
+        .await;
+        assert!(err.contains("auto commit is not enabled"), "{err}");
+        let err = build(
+            builder()
+                .group_id("g1")
+                .auto_commit(true)
+                .auto_commit_interval(Duration::ZERO),
+        )
+        .await;
+        assert!(err.contains("must be positive"), "{err}");
+
+        // Auto commit enabled in the server DSN.
+        let builder = ConsumerBuilder::<MockTmqBuilder>::new()
+            .server("ws://localhost:6041?enable.auto.commit=true")
+            .group_id("g1")
+            .auto_commit_interval(interval);
+        assert!(builder.build().await.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_into_stream() {
+        use futures::TryStreamExt;
+
+        let stream = mock_consumer([1, 2, 1]).into_stream(Timeout::None);
+        let committer = stream.committer();
+        assert!(committer.is_active());
+
+        // Both the stream and the committer move into tasks.
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let consuming = tokio::spawn(async move {
+            let mut stream = stream;
+            let mut vgroups = Vec::new();
+            while let Some((offset, _)) = stream.try_next().await.unwrap() {
+                vgroups.push(offset.vgroup_id());
+                sender.send(offset).unwrap();
+            }
+            (vgroups, stream)
+        });
+        let committing = {
+            let committer = committer.clone();
+            tokio::spawn(async move {
+                while let Some(offset) = receiver.recv().await {
+                    committer.commit(offset).await.unwrap();
+                }
+            })
+        };
+        let (vgroups, stream) = consuming.await.unwrap();
+        assert_eq!(vgroups, [1, 2, 1]);
+        committing.await.unwrap();
+
+        // Inert once the stream is dropped.
+        drop(stream);
+        assert!(!committer.is_active());
+        assert!(committer
+            .commit(MockOffset::new("topic", 1, 0))
+            .await
+            .is_err());
+        assert!(committer.commit_offset("topic", 1, 1).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_subscribe_with_reset() {
+        // Nothing is committed by the group, `committed` is negative as the server reports.
+        let seeded = || {
+            let consumer = mock_consumer([1, 2]);
+            consumer.assign("topic", Assignment::new(1, 0, 0, 10));
+            consumer.assign("topic", Assignment::new(2, 0, 5, 20));
+            consumer
+        };
+        let positions = |consumer: &MockConsumer| {
+            [1, 2].map(|vgroup_id| AsConsumer::position(consumer, "topic", vgroup_id).unwrap())
+        };
+
+        let mut consumer = seeded();
+        AsAsyncConsumer::subscribe_with_reset(&mut consumer, ["topic"], OffsetReset::Earliest)
+            .await
+            .unwrap();
+        assert_eq!(seeks(&consumer), [(1, 0), (2, 5)]);
+        assert_eq!(positions(&consumer), [0, 5]);
+
+        let mut consumer = seeded();
+        AsAsyncConsumer::subscribe_with_reset(&mut consumer, ["topic"], OffsetReset::Latest)
+            .await
+            .unwrap();
+        assert_eq!(seeks(&consumer), [(1, 10), (2, 20)]);
+        assert_eq!(positions(&consumer), [10, 20]);
+
+        // Committed vgroups start from the committed offsets.
+        let mut consumer = seeded();
+        AsAsyncConsumer::commit_offset(&consumer, "topic", 1, 3)
+            .await
+            .unwrap();
+        AsAsyncConsumer::subscribe_with_reset(&mut consumer, ["topic"], OffsetReset::Earliest)
+            .await
+            .unwrap();
+        assert_eq!(seeks(&consumer), [(2, 5)]);
+
+        let mut consumer = seeded();
+        AsAsyncConsumer::commit_offset(&consumer, "topic", 1, 3)
+            .await
+            .unwrap();
+        let res =
+            AsAsyncConsumer::subscribe_with_reset(&mut consumer, ["topic"], OffsetReset::None)
+                .await;
+        assert!(matches!(res, Err(err) if err.to_string().contains("[topic:2]")));
+        assert!(consumer.seeks().is_empty());
+    }
+
+    #[test]
+    fn rebalance_event_between() {
+        let assign = |vgroups: &[VGroupId]| {
+            vgroups
+                .iter()
+                .map(|id| Assignment::new(*id, 0, 0, 10))
+                .collect_vec()
+        };
+        let old = vec![
+            ("t1".to_string(), assign(&[1, 2])),
+            ("t2".to_string(), assign(&[1])),
+        ];
+        let new = vec![("t1".to_string(), assign(&[2, 3]))];
+        let event = RebalanceEvent::between(&old, &new);
+        assert_eq!(event.assigned, [("t1".to_string(), 3)]);
+        assert_eq!(
+            event.revoked,
+            [("t1".to_string(), 1), ("t2".to_string(), 1)]
+        );
+
+        // Offsets moving on is not a rebalance.
+        let moved = vec![(
+            "t1".to_string(),
+            vec![Assignment::new(2, 5, 0, 10), Assignment::new(3, 1, 0, 10)],
+        )];
+        assert!(RebalanceEvent::between(&new, &moved).is_empty());
+        assert_eq!(RebalanceEvent::between(&[], &new).assigned.len(), 2);
+    }
+
+    #[test]
+    fn tmq_config_params() {
+        let config = TmqConfig::new("g1")
+            .with_client_id("c1")
+            .with_offset_reset(OffsetReset::Earliest)
+            .with_auto_commit(true)
+            .with_auto_commit_interval(Duration::from_millis(200))
+            .with_table_name(false)
+            .with_timeout(Timeout::from_secs(1))
+            .with_param("token", "abc");
+        let params = config.to_params();
+        assert_eq!(params["enable.auto.commit"], "true");
+        assert_eq!(params["timeout"], "1s");
+        assert_eq!(params["token"], "abc");
+        assert_eq!(TmqConfig::from_params(&params, true).unwrap(), config);
+
+        // Defaults apply to missing or empty values, as the consumers do.
+        let dsn = "taos:///?group.id=g1&enable.auto.commit=&with.table.name=";
+        let config = TmqConfig::from_dsn(dsn, true).unwrap();
+        assert_eq!(config, TmqConfig::new("g1"));
+        assert_eq!(
+            TmqConfig::from_params(&config.to_params(), true).unwrap(),
+            config
+        );
+
+        // Only the options set are written, so the others in a DSN are kept.
+        let mut dsn = crate::Dsn::from_str("taos:///?group.id=g0&enable.auto.commit=true").unwrap();
+        TmqConfig::new("g1").apply_to(&mut dsn);
+        assert_eq!(dsn.params["group.id"], "g1");
+        assert_eq!(dsn.params["enable.auto.commit"], "true");
+        assert!(!dsn.params.contains_key("with.table.name"));
+
+        assert!(TmqConfig::from_dsn("taos:///?client.id=c1", false).is_err());
+        assert!(TmqConfig::from_dsn("taos:///?group.id=g&auto.offset.reset=first", false).is_err());
+        assert!(TmqConfig::from_dsn("taos:///?group.id=g&enable.auto.commit=1", false).is_err());
+    }
+
+    #[test]
+    fn tmq_config_strict() {
+        let dsn = "taos:///?group.id=g1&auto.offset.rest=earliest";
+        let err = TmqConfig::from_dsn(dsn, true).unwrap_err();
+        assert!(err.to_string().contains("auto.offset.rest"));
+
+        // Unknown params are passed through when not strict.
+        let config = TmqConfig::from_dsn(dsn, false).unwrap();
+        assert_eq!(config.offset_reset, None);
+        assert_eq!(config.params["auto.offset.rest"], "earliest");
+
+        // Connection params and native consumer options are accepted.
+        let dsn = "taos:///?group.id=g1&conn_mode=1&session.timeout.ms=12000\
+                   &msg.consume.excluded=1&td.connect.ip=localhost";
+        let config = TmqConfig::from_dsn(dsn, true).unwrap();
+        assert_eq!(config.params["session.timeout.ms"], "12000");
+    }
+
+    #[test]
+    fn backoff_delay() {
+        let ms = Duration::from_millis;
+        let backoff = Backoff::exponential(ms(10), ms(50));
+        let delays = (1..=6).map(|n| backoff.delay(n)).collect_vec();
+        assert_eq!(delays, [ms(10), ms(20), ms(40), ms(50), ms(50), ms(50)]);
+        assert_eq!(backoff.delay(u32::MAX), ms(50));
+        assert_eq!(Backoff::fixed(ms(5)).delay(10), ms(5));
+    }
+
+    #[test]
+    fn consumer_iter_retry_on_empty() {
+        let consumer = mock_consumer([1, EMPTY, EMPTY, EMPTY, 2]);
+
+        // Today's semantics, ends at the first empty poll.
+        let messages = AsConsumer::iter_with_timeout(&consumer, Timeout::None)
+            .map_ok(|m| m.0.vgroup_id())
+            .try_collect::<_, Vec<_>, _>()
+            .unwrap();
+        assert_eq!(messages, [1]);
+
+        // Yields the message produced after empty polls.
+        let backoff = Backoff::exponential(Duration::from_millis(1), Duration::from_millis(8));
+        let mut iter =
+            AsConsumer::iter_with_policy(&consumer, Timeout::None, OnEmpty::Retry { backoff });
+        assert_eq!(iter.next().unwrap().unwrap().0.vgroup_id(), 2);
+        assert_eq!(consumer.poll_timeouts().len(), 5);
+
+        // Retries stop at the deadline.
+        let timeout = Timeout::deadline(Instant::now() + Duration::from_millis(30));
+        let mut iter = AsConsumer::iter_with_policy(&consumer, timeout, OnEmpty::Retry { backoff });
+        assert!(iter.next().is_none());
+        assert!(timeout.is_expired());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_collect_n() {
+        let consumer = mock_consumer([1, 2, 3, 4]);
+        let messages = AsAsyncConsumer::collect_n(&consumer, 3, Timeout::from_secs(10))
+            .await
+            .unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.0.vgroup_id()).collect_vec(),
+            [1, 2, 3]
+        );
+        assert_eq!(consumer.remaining(), 1);
+
+        // Stops at the first empty poll without a deadline.
+        let messages = AsAsyncConsumer::collect_n(&consumer, 3, Timeout::None)
+            .await
+            .unwrap();
+        assert_eq!(messages.iter().map(|m| m.0.vgroup_id()).collect_vec(), [4]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_collect_n_huge_deadline() {
+        // A duration too long for a deadline waits forever instead of overflow.
+        let consumer = mock_consumer([1, 2, 3]);
+        let deadline = Timeout::Duration(Duration::from_millis(u64::MAX));
+        let messages = AsAsyncConsumer::collect_n(&consumer, 3, deadline)
+            .await
+            .unwrap();
+        assert_eq!(
+            messages.iter().map(|m| m.0.vgroup_id()).collect_vec(),
+            [1, 2, 3]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_collect_n_deadline() {
+        let consumer = mock_consumer([1, EMPTY, EMPTY, 2]);
+        let start = Instant::now();
+        let messages = AsAsyncConsumer::collect_n(&consumer, 3, Timeout::from_millis(200))
+            .await
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        // The message produced after empty polls is collected.
+        assert_eq!(
+            messages.iter().map(|m| m.0.vgroup_id()).collect_vec(),
+            [1, 2]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_commit_all_highest() {
+        let consumer = mock_consumer([1, 2, 1, 3, 1, 2]).with_rejected_commits("topic", 3);
+        let mut offsets = AsConsumer::iter_with_timeout(&consumer, Timeout::None)
+            .map(|m| m.unwrap().0)
+            .collect_vec();
+        offsets.swap(0, 4);
+
+        let err = AsAsyncConsumer::commit_all(&consumer, offsets)
+            .await
+            .unwrap_err();
+        let err = err.to_string();
+        assert!(err.contains("1 topic vgroup(s)"), "{err}");
+        assert!(err.contains("topic topic vgroup 3"), "{err}");
+        // One commit for each vgroup with the highest offset, 3 failed.
+        let commits = consumer.commits().into_iter().map(|c| (c.1, c.2));
+        assert_eq!(commits.collect_vec(), [(1, 3), (2, 2)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn consumer_stream_prefetch() {
+        use futures::TryStreamExt;
+
+        let consumer = Arc::new(mock_consumer(1..=5));
+        let mut stream = consumer.clone().stream_prefetch(2, Timeout::None);
+        let (offset, _) = stream.try_next().await.unwrap().unwrap();
+        AsAsyncConsumer::commit(&*consumer, offset).await.unwrap();
 
-//     type Offset = C::Offset;
+        // Let the poller fill the buffer, no more than 2 messages are polled ahead.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(consumer.remaining(), 2);
 
-//     type Meta = C::Meta;
+        let rest: Vec<_> = (&mut stream).try_collect().await.unwrap();
+        assert_eq!(
+            rest.iter().map(|(o, _)| o.vgroup_id()).collect_vec(),
+            [2, 3, 4, 5]
+        );
+        assert_eq!(committed_vgroups(&consumer), [1]);
 
-//     type Data = C::Data;
+        // Dropping the stream stops the poller.
+        let consumer = Arc::new(mock_consumer(1..=5));
+        let stream = consumer.clone().stream_prefetch(1, Timeout::None);
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(consumer.remaining() >= 4);
+        assert_eq!(Arc::strong_count(&consumer), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_poll_result() {
+        let consumer = mock_consumer([1]);
+        consumer.assign("topic", Assignment::new(1, 0, 0, 2));
+        consumer.assign("topic", Assignment::new(2, 3, 0, 3));
+        let result = AsAsyncConsumer::poll_result(&consumer, Timeout::None)
+            .await
+            .unwrap();
+        let first = MockOffset::new("topic", 1, 0);
+        assert!(matches!(result, PollResult::Message(offset, _) if offset == first));
+
+        // Vgroup 1 is behind.
+        let result = AsConsumer::poll_result(&consumer, Timeout::None).unwrap();
+        assert!(matches!(result, PollResult::Timeout));
+
+        AsAsyncConsumer::offset_seek(&consumer, "topic", 1, 2)
+            .await
+            .unwrap();
+        let result = AsAsyncConsumer::poll_result(&consumer, Timeout::None)
+            .await
+            .unwrap();
+        assert!(result.is_end_of_topic());
+        assert!(result.into_message().is_none());
 
-//     async fn subscribe<T: Into<String>, I: IntoIterator<Item = T> + Send>(
-//         &mut self,
-//         topics: I,
-//     ) -> Result<()> {
-//         <C as AsConsumer>::subscribe(self, topics)
-//     }
+        // Unknown end is never the end.
+        consumer.assign("topic", Assignment::new(3, 0, 0, -1));
+        let result = AsConsumer::poll_result(&consumer, Timeout::None).unwrap();
+        assert!(matches!(result, PollResult::Timeout));
 
-//     async fn recv_timeout(
-//         &self,
-//         timeout: Timeout,
-//     ) -> Result<Option<(Self::Offset, MessageSet<Self::Meta, Self::Data>)>> {
-//         <C as AsConsumer>::recv_timeout(self, timeout)
-//     }
+        let consumer = mock_consumer([]);
+        let result = AsConsumer::poll_result(&consumer, Timeout::None).unwrap();
+        assert!(matches!(result, PollResult::Timeout));
+    }
 
-//     async fn commit(&self, offset: Self::Offset) -> Result<()> {
-//         <C as AsConsumer>::commit(self, offset)
-//     }
-// }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn consumer_subscribe_with_offsets() {
+        let mut consumer = mock_consumer([]);
+        consumer.assign("topic", Assignment::new(1, 0, 0, 10));
+        AsAsyncConsumer::subscribe_with_offsets(
+            &mut consumer,
+            [(
+                "topic",
+                vec![Assignment::new(1, 7, 0, 10), Assignment::new(9, 3, 0, 10)],
+            )],
+        )
+        .await
+        .unwrap();
+        // Vgroup 9 is not assigned.
+        assert_eq!(seeks(&consumer), [(1, 7)]);
+        assert_eq!(
+            AsAsyncConsumer::position(&consumer, "topic", 1)
+                .await
+                .unwrap(),
+            7
+        );
+    }
+}
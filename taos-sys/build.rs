@@ -25,6 +25,9 @@ fn version2features<V: Into<Version>>(version: V) -> Vec<&'static str> {
         if version >= Version::new(3, 0, 5, 0) {
             feats.push("tmq_offset_seek");
         }
+        if version >= Version::new(3, 1, 0, 0) {
+            feats.push("tmq_committed");
+        }
         feats.push("v3");
         feats.push("tmq");
         feats.push("fetch_raw_block");
@@ -42,7 +42,7 @@ pub mod schemaless;
 
 pub mod tmq;
 use taos_query::util::Edition;
-pub use tmq::{Consumer, TmqBuilder};
+pub use tmq::{Consumer, ConsumerBuilder, TmqBuilder};
 
 mod conn;
 use conn::RawTaos;
@@ -88,6 +88,8 @@ macro_rules! err_or {
 #[derive(Debug)]
 pub struct Taos {
     raw: RawTaos,
+    /// Dsn of the builder, to open other channels like tmq with the same params.
+    dsn: Arc<Dsn>,
 }
 
 impl Drop for Taos {
@@ -209,6 +211,7 @@ pub struct TaosBuilder {
     pass: Option<CString>,
     db: Option<CString>,
     port: u16,
+    dsn: Arc<Dsn>,
     inner_conn: OnceCell<Taos>,
     server_version: OnceCell<String>,
 }
@@ -216,7 +219,10 @@ pub struct TaosBuilder {
 impl TaosBuilder {
     fn from_dsn<D: taos_query::IntoDsn>(dsn: D) -> RawResult<Self> {
         let dsn = dsn.into_dsn()?;
-        let mut builder = TaosBuilder::default();
+        let mut builder = TaosBuilder {
+            dsn: Arc::new(dsn.clone()),
+            ..Default::default()
+        };
         if let Some(addr) = dsn.addresses.into_iter().next() {
             if let Some(host) = addr.host {
                 builder.host.replace(CString::new(host).unwrap());
@@ -292,7 +298,10 @@ impl taos_query::TBuilder for TaosBuilder {
 
     fn from_dsn<D: taos_query::IntoDsn>(dsn: D) -> RawResult<Self> {
         let dsn = dsn.into_dsn()?;
-        let mut builder = TaosBuilder::default();
+        let mut builder = TaosBuilder {
+            dsn: Arc::new(dsn.clone()),
+            ..Default::default()
+        };
         if let Some(addr) = dsn.addresses.into_iter().next() {
             if let Some(host) = addr.host {
                 builder.host.replace(CString::new(host).unwrap());
@@ -354,7 +363,10 @@ impl taos_query::TBuilder for TaosBuilder {
             self.port,
         )?;
 
-        Ok(Taos { raw })
+        Ok(Taos {
+            raw,
+            dsn: self.dsn.clone(),
+        })
     }
 
     fn server_version(&self) -> RawResult<&str> {
@@ -439,7 +451,10 @@ impl taos_query::AsyncTBuilder for TaosBuilder {
 
     fn from_dsn<D: taos_query::IntoDsn>(dsn: D) -> RawResult<Self> {
         let dsn = dsn.into_dsn()?;
-        let mut builder = TaosBuilder::default();
+        let mut builder = TaosBuilder {
+            dsn: Arc::new(dsn.clone()),
+            ..Default::default()
+        };
         if let Some(addr) = dsn.addresses.into_iter().next() {
             if let Some(host) = addr.host {
                 builder.host.replace(CString::new(host).unwrap());
@@ -501,7 +516,10 @@ impl taos_query::AsyncTBuilder for TaosBuilder {
             self.port,
         )?;
 
-        Ok(Taos { raw })
+        Ok(Taos {
+            raw,
+            dsn: self.dsn.clone(),
+        })
     }
 
     async fn server_version(&self) -> RawResult<&str> {
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, os::raw::*};
+use std::{borrow::Cow, ffi::CStr, os::raw::*};
 
 use taos_macros::c_cfg;
 use taos_query::tmq::Assignment;
@@ -19,6 +19,12 @@ impl PartialEq<i32> for tmq_conf_res_t {
 impl tmq_resp_err_t {
     pub const OK: i32 = 0;
 
+    /// Response of the error `code` returned by calls such as `tmq_committed`.
+    #[cfg(taos_tmq_committed)]
+    pub(crate) fn from_code(code: i32) -> Self {
+        Self(code)
+    }
+
     pub fn is_ok(self) -> bool {
         self.0 == Self::OK
     }
@@ -27,10 +33,22 @@ impl tmq_resp_err_t {
         !self.is_ok()
     }
 
-    pub fn ok_or(self, s: impl Into<Cow<'static, str>>) -> Result<(), RawError> {
+    /// The error code, `0` for success.
+    pub fn errno(self) -> i32 {
+        self.0
+    }
+
+    /// Error with the code and the string by `tmq_err2str`, prefixed by `context`.
+    pub fn ok_or(self, context: impl Into<Cow<'static, str>>) -> Result<(), RawError> {
         match self {
             Self(0) => Ok(()),
-            _ => Err(RawError::from_string(s.into())),
+            _ => {
+                let err_str = unsafe { CStr::from_ptr(tmq_err2str(self)) }.to_string_lossy();
+                Err(RawError::new(
+                    self.0,
+                    format!("{}: {err_str}", context.into()),
+                ))
+            }
         }
     }
 }
@@ -163,6 +181,8 @@ extern "C" {
         vgroup_id: i32,
         offset: i64,
     ) -> tmq_resp_err_t;
+
+    pub fn tmq_get_vgroup_offset(res: *mut TAOS_RES) -> i64;
 }
 
 #[cfg(not(taos_tmq_offset_seek))]
@@ -185,11 +205,28 @@ pub unsafe fn tmq_offset_seek(
     unimplemented!()
 }
 
+#[cfg(not(taos_tmq_offset_seek))]
+pub unsafe fn tmq_get_vgroup_offset(res: *mut TAOS_RES) -> i64 {
+    -1
+}
+
 #[cfg(taos_tmq)]
 extern "C" {
     pub fn tmq_get_res_type(res: *mut TAOS_RES) -> tmq_res_t;
 }
 
+#[cfg(taos_tmq_committed)]
+extern "C" {
+    pub fn tmq_committed(tmq: *mut tmq_t, topic_name: *const c_char, vgroup_id: i32) -> i64;
+    pub fn tmq_position(tmq: *mut tmq_t, topic_name: *const c_char, vgroup_id: i32) -> i64;
+    pub fn tmq_commit_offset_sync(
+        tmq: *mut tmq_t,
+        topic_name: *const c_char,
+        vgroup_id: i32,
+        offset: i64,
+    ) -> tmq_resp_err_t;
+}
+
 #[cfg(taos_write_raw_block_with_fields)]
 extern "C" {
     pub fn taos_write_raw_block_with_fields(
@@ -1,18 +1,20 @@
 pub(crate) mod ffi;
 
 use std::{
-    ffi::{CStr, CString}, fmt::Debug, mem::transmute, str::FromStr, time::Duration
+    collections::{BTreeMap, BTreeSet}, ffi::{CStr, CString}, fmt::Debug, mem::transmute, str::FromStr, sync::{Arc, Mutex}, time::{Duration, Instant}
 };
 
 pub(crate) use ffi::*;
 
 use itertools::Itertools;
 use taos_query::{
-    common::{raw_data_t, Precision, RawData, RawMeta},
+    common::{raw_data_t, Field, Precision, RawData, RawMeta},
     prelude::tokio,
     tmq::{
-        AsAsyncConsumer, AsConsumer, Assignment, AsyncOnSync, IsAsyncData, IsData, IsMeta,
-        IsOffset, MessageSet, Timeout, VGroupId,
+        AsAsyncConsumer, AsConsumer, Assignment, AsyncOnSync, CommitHook, ConsumerCounters,
+        ConsumerInfo, ConsumerMetrics, ConsumerObserver, IsAsyncData, IsData, IsMeta, IsOffset,
+        MessageSet, PausedVGroups, PendingError, Timeout, TopicPattern, VGroupId, VGroupOrder,
+        VGROUP_ORDER_BATCH,
     },
     util::Edition,
     Dsn, IntoDsn, RawBlock, RawResult,
@@ -52,6 +54,14 @@ impl RawRes {
         }
     }
 
+    #[inline]
+    pub fn tmq_vgroup_offset(&self) -> Option<i64> {
+        match unsafe { tmq_get_vgroup_offset(self.as_ptr()) } {
+            offset if offset < 0 => None,
+            offset => Some(offset),
+        }
+    }
+
     #[inline]
     pub fn tmq_table_name(&self) -> Option<&str> {
         unsafe {
@@ -110,12 +120,97 @@ impl RawRes {
     }
 }
 
+/// Native auto commit is enabled explicitly by `enable.auto.commit=true`.
+fn is_auto_commit(dsn: &Dsn) -> bool {
+    dsn.params.get("enable.auto.commit").map(String::as_str) == Some("true")
+}
+
 #[derive(Debug)]
 pub struct TmqBuilder {
-    builder: TaosBuilder,
+    builder: Arc<TaosBuilder>,
     dsn: Dsn,
-    conf: Conf,
     timeout: Timeout,
+    observer: Option<Arc<dyn ConsumerObserver>>,
+    preserve_vgroup_order: bool,
+}
+
+/// Typed builder of [Consumer].
+pub type ConsumerBuilder = taos_query::tmq::ConsumerBuilder<TmqBuilder>;
+
+impl TmqBuilder {
+    /// Build a consumer of `group_id` with the dsn of an established connection.
+    ///
+    /// Only the params are cloned, the consumer opens its own connection.
+    pub fn from_taos(taos: &crate::Taos, group_id: &str) -> RawResult<Self> {
+        let mut dsn = (*taos.dsn).clone();
+        dsn.params
+            .insert("group.id".to_string(), group_id.to_string());
+        <Self as taos_query::TBuilder>::from_dsn(dsn)
+    }
+
+    /// Report polls, commits and errors of the consumer to `observer`, see [ConsumerObserver].
+    pub fn with_observer(mut self, observer: impl ConsumerObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Reorder received messages by offset within each vgroup, see [VGroupOrder].
+    ///
+    /// A poll then takes up to [VGROUP_ORDER_BATCH] messages which are immediately available,
+    /// and delivers them one by one in later polls. The native library would auto commit the
+    /// messages polled ahead, so building fails with `enable.auto.commit=true`.
+    pub fn preserve_vgroup_order(mut self, preserve: bool) -> Self {
+        self.preserve_vgroup_order = preserve;
+        self
+    }
+
+    fn vgroup_order(&self) -> RawResult<Option<VGroupOrder<Offset, Meta, Data>>> {
+        if !self.preserve_vgroup_order {
+            return Ok(None);
+        }
+        if is_auto_commit(&self.dsn) {
+            return Err(RawError::from_string(
+                "preserve_vgroup_order needs enable.auto.commit=false",
+            ));
+        }
+        Ok(Some(VGroupOrder::new(VGROUP_ORDER_BATCH)))
+    }
+
+    /// Deliver the data written before the topic is created, as `experimental.snapshot.enable`.
+    ///
+    /// Building fails if the server does not support it.
+    pub fn with_snapshot(mut self, enable: bool) -> Self {
+        self.dsn.params.insert(
+            "experimental.snapshot.enable".to_string(),
+            enable.to_string(),
+        );
+        self
+    }
+
+    /// Consume data messages in the order they are written, as `enable.replay`.
+    ///
+    /// Building fails if the server is older than 3.2.1.0, which ignores it.
+    pub fn with_replay(mut self, enable: bool) -> Self {
+        self.dsn
+            .params
+            .insert("enable.replay".to_string(), enable.to_string());
+        self
+    }
+
+    /// Enabled params which need a server of some version, see
+    /// [versioned_params](taos_query::tmq::versioned_params).
+    fn versioned_params(&self) -> Vec<(&'static str, &'static str)> {
+        taos_query::tmq::versioned_params(
+            self.dsn
+                .params
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        )
+    }
+
+    fn conf(&self) -> RawResult<Conf> {
+        Conf::from_dsn(&self.dsn)
+    }
 }
 
 unsafe impl Send for TmqBuilder {}
@@ -133,17 +228,19 @@ impl taos_query::TBuilder for TmqBuilder {
         let mut dsn = dsn
             .into_dsn()
             .map_err(|e| RawError::from_string(format!("Parse dsn error: {}", e)))?;
-        let conf = Conf::from_dsn(&dsn)?;
+        // Validate the params early, the conf is created again at building.
+        let _ = Conf::from_dsn(&dsn)?;
         let timeout = if let Some(timeout) = dsn.params.remove("timeout") {
             Timeout::from_str(&timeout).map_err(RawError::from_any)?
         } else {
             Timeout::from_millis(500)
         };
         Ok(Self {
-            builder: TaosBuilder::from_dsn(&dsn)?,
+            builder: Arc::new(TaosBuilder::from_dsn(&dsn)?),
             dsn,
-            conf,
             timeout,
+            observer: None,
+            preserve_vgroup_order: false,
         })
     }
 
@@ -160,10 +257,25 @@ impl taos_query::TBuilder for TmqBuilder {
     }
 
     fn build(&self) -> RawResult<Self::Target> {
-        self.conf.build().map(|tmq| Consumer {
+        let params = self.versioned_params();
+        if !params.is_empty() {
+            let version = taos_query::TBuilder::server_version(&*self.builder)?;
+            taos_query::tmq::check_server_version(version, &params)?;
+        }
+        let vgroup_order = self.vgroup_order()?;
+        self.conf()?.build().map(|tmq| Consumer {
             tmq,
             timeout: self.timeout,
             dsn: self.dsn.clone(),
+            builder: self.builder.clone(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: BTreeMap::new(),
+            metrics: ConsumerCounters::with_observer(self.observer.clone()),
+            commit_hook: CommitHook::default(),
+            vgroup_order,
+            paused: PausedVGroups::default(),
+            closed: false,
         })
     }
 
@@ -211,17 +323,19 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
         let mut dsn = dsn
             .into_dsn()
             .map_err(|e| RawError::from_string(format!("Parse dsn error: {}", e)))?;
-        let conf = Conf::from_dsn(&dsn)?;
+        // Validate the params early, the conf is created again at building.
+        let _ = Conf::from_dsn(&dsn)?;
         let timeout = if let Some(timeout) = dsn.params.remove("timeout") {
             Timeout::from_str(&timeout).map_err(RawError::from_any)?
         } else {
             Timeout::from_millis(500)
         };
         Ok(Self {
-            builder: TaosBuilder::from_dsn(&dsn)?,
+            builder: Arc::new(TaosBuilder::from_dsn(&dsn)?),
             dsn,
-            conf,
             timeout,
+            observer: None,
+            preserve_vgroup_order: false,
         })
     }
 
@@ -238,10 +352,25 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
     }
 
     async fn build(&self) -> RawResult<Self::Target> {
-        self.conf.build().map(|tmq| Consumer {
+        let params = self.versioned_params();
+        if !params.is_empty() {
+            let version = taos_query::AsyncTBuilder::server_version(&*self.builder).await?;
+            taos_query::tmq::check_server_version(version, &params)?;
+        }
+        let vgroup_order = self.vgroup_order()?;
+        self.conf()?.build().map(|tmq| Consumer {
             tmq,
             timeout: self.timeout,
             dsn: self.dsn.clone(),
+            builder: self.builder.clone(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: BTreeMap::new(),
+            metrics: ConsumerCounters::with_observer(self.observer.clone()),
+            commit_hook: CommitHook::default(),
+            vgroup_order,
+            paused: PausedVGroups::default(),
+            closed: false,
         })
     }
 
@@ -283,8 +412,9 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
 
 /// Consumer offset.
 ///
-/// When offset is dropped, the message is destroyed.
-pub struct Offset(RawRes);
+/// When offset is dropped, the message is destroyed. It also keeps the time
+/// the message is received and the time spent polling it.
+pub struct Offset(RawRes, Instant, Duration);
 
 unsafe impl Send for Offset {}
 
@@ -296,6 +426,7 @@ impl Debug for Offset {
             .field("ptr", &self.0)
             .field("topic", &self.topic())
             .field("vgroup_id", &self.vgroup_id())
+            .field("offset", &self.offset())
             .field("database", &self.database())
             .finish()
     }
@@ -317,6 +448,17 @@ impl IsOffset for Offset {
             .tmq_vgroup_id()
             .expect("a message should belong to a vgroup")
     }
+    fn offset(&self) -> i64 {
+        self.0.tmq_vgroup_offset().unwrap_or(-1)
+    }
+
+    fn timing(&self) -> Option<i64> {
+        i64::try_from(self.2.as_nanos()).ok()
+    }
+
+    fn received_at(&self) -> Option<Instant> {
+        Some(self.1)
+    }
 }
 
 impl Drop for Offset {
@@ -330,16 +472,154 @@ pub struct Consumer {
     tmq: RawTmq,
     timeout: Timeout,
     dsn: Dsn,
+    builder: Arc<TaosBuilder>,
+    pattern: TopicPattern,
+    pending_error: PendingError,
+    topic_timeouts: BTreeMap<String, Timeout>,
+    metrics: ConsumerCounters,
+    commit_hook: CommitHook,
+    vgroup_order: Option<VGroupOrder<Offset, Meta, Data>>,
+    paused: PausedVGroups,
+    closed: bool,
 }
 
 unsafe impl Send for Consumer {}
 
 unsafe impl Sync for Consumer {}
 
+/// Upper bound for the final commit and close on [`AsAsyncConsumer::shutdown`].
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Consumer {
+    /// Group id, client id and subscribed topics of the consumer.
+    pub fn info(&self) -> ConsumerInfo {
+        ConsumerInfo {
+            group_id: self.dsn.params.get("group.id").cloned().unwrap_or_default(),
+            client_id: self.dsn.params.get("client.id").cloned(),
+            topics: self.tmq.subscription().into_strings(),
+        }
+    }
+
+    /// Snapshot of the poll, message and commit counters of the consumer.
+    ///
+    /// Raw data stays in the native library until blocks are fetched, so `bytes` is not counted.
+    pub fn metrics(&self) -> ConsumerMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Reset the counters of [metrics](Self::metrics).
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+
+    /// Set a hook called with `(topic, vgroup_id, offset, result)` after each commit request,
+    /// replacing the previous one.
+    ///
+    /// Auto commits of `enable.auto.commit` are done inside the native library and are not
+    /// hooked. Panics in the hook are caught and logged.
+    pub fn set_commit_hook(
+        &mut self,
+        hook: impl Fn(&str, VGroupId, i64, &RawResult<()>) + Send + Sync + 'static,
+    ) {
+        self.commit_hook.set(hook);
+    }
+
+    /// The message polled since `polled_at`.
+    fn message_of(raw: RawRes, polled_at: Instant) -> (Offset, MessageSet<Meta, Data>) {
+        let received_at = Instant::now();
+        let offset = Offset(raw, received_at, received_at - polled_at);
+        (offset, raw.into())
+    }
+
+    /// Poll a message, skipping those of paused vgroups until `timeout`.
+    fn poll_sync(&self, timeout: Timeout) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        let timeout = timeout.into_deadline();
+        loop {
+            let polled_at = Instant::now();
+            let Some(raw) = self.tmq.poll_timeout(timeout.as_raw_timeout())? else {
+                return Ok(None);
+            };
+            let message = Self::message_of(raw, polled_at);
+            if !self.paused.skip(&message.0) {
+                return Ok(Some(message));
+            }
+            if timeout.is_expired() {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Poll a message, skipping those of paused vgroups until `timeout`.
+    async fn poll_by(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        let timeout = timeout.into_deadline();
+        loop {
+            let Some(message) = self.poll_once(timeout).await? else {
+                return Ok(None);
+            };
+            if !self.paused.skip(&message.0) {
+                return Ok(Some(message));
+            }
+            if timeout.is_expired() {
+                return Ok(None);
+            }
+        }
+    }
+
+    async fn poll_once(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        let polled_at = Instant::now();
+        let timeout = match timeout {
+            // Poll once without waiting for new messages.
+            Timeout::None => {
+                let tmq = self.tmq;
+                let raw = tokio::task::spawn_blocking(move || tmq.poll_timeout(0))
+                    .await
+                    .map_err(RawError::from_any)??;
+                return Ok(raw.map(|raw| Self::message_of(raw, polled_at)));
+            }
+            Timeout::Never => Duration::MAX,
+            Timeout::Duration(_) | Timeout::Deadline(_) => timeout.as_duration(),
+        };
+        let sleep = tokio::time::sleep(timeout);
+        tokio::pin!(sleep);
+        tokio::select! {
+            _ = &mut sleep, if !sleep.is_elapsed() => Ok(None),
+            raw = self.tmq.poll_async() => Ok(Some(Self::message_of(raw?, polled_at))),
+        }
+    }
+
+    /// Paused vgroups as `(topic, vgroup_id)`, see [pause](AsAsyncConsumer::pause).
+    pub fn paused(&self) -> BTreeSet<(String, VGroupId)> {
+        self.paused.vgroups()
+    }
+
+    /// Override the timeout of [recv](AsConsumer::recv) and [stream](AsAsyncConsumer::stream)
+    /// when `topic` is the only subscribed topic.
+    pub fn set_topic_timeout(&mut self, topic: &str, timeout: Timeout) {
+        self.topic_timeouts.insert(topic.to_string(), timeout);
+    }
+
+    /// Unsubscribe and close the consumer, only the first call takes effect.
+    fn unsubscribe_and_close(&mut self) -> RawResult<()> {
+        if std::mem::replace(&mut self.closed, true) {
+            return Ok(());
+        }
+        let unsubscribed = self.tmq.unsubscribe();
+        let closed = self.tmq.close();
+        unsubscribed.and(closed)
+    }
+}
+
 impl Drop for Consumer {
     fn drop(&mut self) {
-        self.tmq.unsubscribe();
-        self.tmq.close();
+        if let Err(err) = self.unsubscribe_and_close() {
+            log::warn!("close consumer error: {err}");
+        }
     }
 }
 
@@ -352,9 +632,17 @@ impl Iterator for Messages {
     type Item = (Offset, MessageSet<Meta, Data>);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.tmq
+        let polled_at = Instant::now();
+        match self
+            .tmq
             .poll_timeout(self.timeout.map(|t| t.as_millis() as i64).unwrap_or(-1))
-            .map(|raw| (Offset(raw), MessageSet::from(raw)))
+        {
+            Ok(raw) => raw.map(|raw| Consumer::message_of(raw, polled_at)),
+            Err(err) => {
+                log::warn!("poll message error: {err}");
+                None
+            }
+        }
     }
 }
 
@@ -403,6 +691,8 @@ impl Meta {
 pub struct Data {
     raw: RawRes,
     precision: Precision,
+    /// Block fetched for its fields, returned by the next fetch.
+    peeked: Mutex<Option<RawBlock>>,
 }
 
 impl Data {
@@ -410,8 +700,26 @@ impl Data {
         Self {
             precision: raw.precision(),
             raw,
+            peeked: Mutex::new(None),
         }
     }
+
+    fn next_block(&self) -> Option<RawBlock> {
+        self.peeked
+            .lock()
+            .unwrap()
+            .take()
+            .or_else(|| self.raw.fetch_raw_message(self.precision))
+    }
+
+    /// The message is received as a whole, peeking the block copies it out of the result.
+    fn peek_fields(&self) -> Vec<Field> {
+        let mut peeked = self.peeked.lock().unwrap();
+        if peeked.is_none() {
+            *peeked = self.raw.fetch_raw_message(self.precision);
+        }
+        peeked.as_ref().map(RawBlock::fields).unwrap_or_default()
+    }
 }
 
 #[async_trait::async_trait]
@@ -421,7 +729,11 @@ impl IsAsyncData for Data {
     }
 
     async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
-        Ok(self.raw.fetch_raw_message(self.precision))
+        Ok(self.next_block())
+    }
+
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        Ok(self.peek_fields())
     }
 }
 impl IsData for Data {
@@ -430,7 +742,11 @@ impl IsData for Data {
     }
 
     fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
-        Ok(self.raw.fetch_raw_message(self.precision))
+        Ok(self.next_block())
+    }
+
+    fn fields(&self) -> RawResult<Vec<Field>> {
+        Ok(self.peek_fields())
     }
 }
 
@@ -460,7 +776,7 @@ impl Iterator for Data {
     type Item = RawResult<RawBlock>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.raw.fetch_raw_message(self.precision).map(Ok)
+        self.next_block().map(Ok)
     }
 }
 
@@ -486,8 +802,9 @@ impl AsConsumer for Consumer {
         &mut self,
         topics: I,
     ) -> RawResult<()> {
-        let topics = Topics::from_topics(topics.into_iter().map(|s| s.into()))?;
-        self.tmq.subscribe(&topics)
+        let topics = topics.into_iter().map(|s| s.into()).collect_vec();
+        self.pattern.clear();
+        <Self as AsConsumer>::subscribe_matched(self, topics)
     }
 
     fn recv_timeout(
@@ -499,58 +816,143 @@ impl AsConsumer for Consumer {
             taos_query::tmq::MessageSet<Self::Meta, Self::Data>,
         )>,
     > {
-        Ok(self.tmq.poll_timeout(timeout.as_raw_timeout()).map(|raw| {
-            (
-                Offset(raw),
-                match raw.tmq_message_type() {
-                    tmq_res_t::TMQ_RES_INVALID => unreachable!(),
-                    tmq_res_t::TMQ_RES_DATA => taos_query::tmq::MessageSet::Data(Data::new(raw)),
-                    tmq_res_t::TMQ_RES_TABLE_META => {
-                        taos_query::tmq::MessageSet::Meta(Meta::new(raw))
-                    }
-                    tmq_res_t::TMQ_RES_METADATA => {
-                        taos_query::tmq::MessageSet::MetaData(Meta::new(raw), Data::new(raw))
-                    }
-                },
-            )
-        }))
+        <Self as AsConsumer>::refresh_subscription_if_due(self);
+        let start = Instant::now();
+        let message = match &self.vgroup_order {
+            Some(order) => {
+                let poll = |timeout| std::future::ready(self.poll_sync(timeout));
+                taos_query::block_in_place_or_global(order.recv(timeout, poll))?
+            }
+            None => self.poll_sync(timeout)?,
+        };
+        self.metrics
+            .record_poll(start.elapsed(), message.as_ref().map(|m| m.1.kind()));
+        Ok(message)
     }
 
     fn commit(&self, offset: Self::Offset) -> RawResult<()> {
-        self.tmq.commit_sync(offset.0).map(|_| ())
+        let (topic, vgroup_id, committed) = (
+            offset.topic().to_string(),
+            offset.vgroup_id(),
+            offset.offset(),
+        );
+        let res = self.tmq.commit_sync(offset.0).map(|_| ());
+        self.metrics.record_commit(committed, &res);
+        self.commit_hook.call(&topic, vgroup_id, committed, &res);
+        res
     }
 
     fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
-        unimplemented!("commit_offset")
+        let res = self.tmq.commit_offset_sync(topic_name, vgroup_id, offset);
+        self.metrics.record_commit(offset, &res);
+        self.commit_hook.call(topic_name, vgroup_id, offset, &res);
+        res
+    }
+
+    fn unsubscribe(mut self) -> RawResult<()> {
+        self.unsubscribe_and_close()
     }
 
     fn list_topics(&self) -> RawResult<Vec<String>> {
         unimplemented!("list_topics")
     }
 
+    fn subscription(&self) -> Vec<String> {
+        self.tmq.subscription().into_strings()
+    }
+
+    fn topic_names(&self) -> RawResult<Vec<String>> {
+        use taos_query::{Fetchable, Queryable};
+
+        self.builder
+            .inner_connection()?
+            .query(taos_query::tmq::TOPICS_SQL)?
+            .deserialize::<String>()
+            .collect()
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending_error)
+    }
+
+    fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        let topics = Topics::from_topics(topics)?;
+        self.paused.clear();
+        self.tmq.subscribe(&topics)
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
+    }
+
     fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
         let topics = self.tmq.subscription();
         let topics = topics.into_strings();
         let ret = topics
             .into_iter()
             .map(|topic| {
-                let assignments = self.tmq.get_topic_assignment(&topic);
+                let assignments = self.tmq.get_topic_assignment(&topic).unwrap_or_default();
                 (topic, assignments)
             })
             .collect();
         Some(ret)
     }
 
-    fn offset_seek(&mut self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
-        self.tmq.offset_seek(topic, vg_id, offset)
+    fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        self.tmq.get_topic_assignment(topic)
+    }
+
+    fn offset_seek(&self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
+        self.tmq.offset_seek(topic, vg_id, offset)?;
+        if let Some(order) = &self.vgroup_order {
+            order.reset(topic, vg_id);
+        }
+        self.paused.reset(topic, vg_id);
+        Ok(())
     }
 
     fn committed(&self, topic: &str, vg_id: VGroupId) -> RawResult<i64> {
-        unimplemented!("committed")
+        self.tmq.committed(topic, vg_id)
     }
 
     fn position(&self, topic: &str, vg_id: VGroupId) -> RawResult<i64> {
-        unimplemented!("position")
+        self.tmq.position(topic, vg_id)
+    }
+
+    fn resubscribe(&self) -> RawResult<()> {
+        self.tmq.subscribe(&self.tmq.subscription())
+    }
+
+    /// The native library has no pause, so it is done on the client side as [PausedVGroups]:
+    /// polled messages of the vgroup are skipped, and it is sought back to the first skipped one
+    /// on [resume](AsConsumer::resume). Fails with `enable.auto.commit=true`, which would commit
+    /// the skipped messages.
+    fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        if is_auto_commit(&self.dsn) {
+            return Err(RawError::from_string(
+                "pause needs enable.auto.commit=false",
+            ));
+        }
+        let assignments = self.tmq.get_topic_assignment(topic)?;
+        if !assignments.iter().any(|a| a.vgroup_id() == vgroup_id) {
+            return Err(RawError::from_string(format!(
+                "vgroup {vgroup_id} of topic {topic} is not assigned to current consumer"
+            )));
+        }
+        self.paused.pause(topic, vgroup_id);
+        Ok(())
+    }
+
+    /// Resume a paused vgroup, from the first message not delivered.
+    fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        match self.paused.resume(topic, vgroup_id) {
+            Some(offset) => <Self as AsConsumer>::offset_seek(self, topic, vgroup_id, offset),
+            None => Ok(()),
+        }
     }
 }
 
@@ -567,9 +969,9 @@ impl AsAsyncConsumer for Consumer {
         &mut self,
         topics: I,
     ) -> RawResult<()> {
-        let topics = Topics::from_topics(topics.into_iter().map(|s| s.into()))?;
-
-        let r = self.tmq.subscribe(&topics);
+        let topics = topics.into_iter().map(|s| s.into()).collect_vec();
+        self.pattern.clear();
+        let r = <Self as AsConsumer>::subscribe_matched(self, topics);
 
         if let Some(offset) = self.dsn.get("offset") {
             // dbg!(offset);
@@ -609,61 +1011,31 @@ impl AsAsyncConsumer for Consumer {
         )>,
     > {
         log::trace!("waiting for next message");
-        let res = match timeout {
-            Timeout::Never | Timeout::None => {
-                let timeout = Duration::MAX;
-                let sleep = tokio::time::sleep(timeout);
-                tokio::pin!(sleep);
-                tokio::select! {
-                    _ = &mut sleep, if !sleep.is_elapsed() => {
-                       Ok(None)
-                    }
-                    raw = self.tmq.poll_async() => {
-                        let message =    (
-                            Offset(raw),
-                            match raw.tmq_message_type() {
-                                tmq_res_t::TMQ_RES_INVALID => unreachable!(),
-                                tmq_res_t::TMQ_RES_DATA => taos_query::tmq::MessageSet::Data(Data::new(raw)),
-                                tmq_res_t::TMQ_RES_TABLE_META => {
-                                    taos_query::tmq::MessageSet::Meta(Meta::new(raw))
-                                }
-                                tmq_res_t::TMQ_RES_METADATA => taos_query::tmq::MessageSet::MetaData(Meta::new(raw), Data::new(raw))
-                            },
-                        );
-                        Ok(Some(message))
-                    }
-                }
-            }
-            Timeout::Duration(timeout) => {
-                let sleep = tokio::time::sleep(timeout);
-                tokio::pin!(sleep);
-                tokio::select! {
-                    _ = &mut sleep, if !sleep.is_elapsed() => {
-                       Ok(None)
-                    }
-                    raw = self.tmq.poll_async() => {
-                        let message =    (
-                            Offset(raw),
-                            match raw.tmq_message_type() {
-                                tmq_res_t::TMQ_RES_INVALID => unreachable!(),
-                                tmq_res_t::TMQ_RES_DATA => taos_query::tmq::MessageSet::Data(Data::new(raw)),
-                                tmq_res_t::TMQ_RES_TABLE_META => {
-                                    taos_query::tmq::MessageSet::Meta(Meta::new(raw))
-                                }
-                                tmq_res_t::TMQ_RES_METADATA => taos_query::tmq::MessageSet::MetaData(Meta::new(raw), Data::new(raw))
-                            },
-                        );
-                        Ok(Some(message))
-                    }
-                }
-            }
+        <Self as AsAsyncConsumer>::refresh_subscription_if_due(self).await;
+        let start = Instant::now();
+        let res = match &self.vgroup_order {
+            Some(order) => order.recv(timeout, |timeout| self.poll_by(timeout)).await,
+            None => self.poll_by(timeout).await,
         };
-        log::trace!("waiting for next message");
+        match &res {
+            Ok(message) => self
+                .metrics
+                .record_poll(start.elapsed(), message.as_ref().map(|m| m.1.kind())),
+            Err(err) => self.metrics.record_error(err),
+        }
         res
     }
 
     async fn commit(&self, offset: Self::Offset) -> RawResult<()> {
-        self.tmq.commit(offset.0).await.map(|_| ())
+        let (topic, vgroup_id, committed) = (
+            offset.topic().to_string(),
+            offset.vgroup_id(),
+            offset.offset(),
+        );
+        let res = self.tmq.commit(offset.0).await.map(|_| ());
+        self.metrics.record_commit(committed, &res);
+        self.commit_hook.call(&topic, vgroup_id, committed, &res);
+        res
     }
 
     async fn commit_offset(
@@ -672,17 +1044,78 @@ impl AsAsyncConsumer for Consumer {
         vgroup_id: VGroupId,
         offset: i64,
     ) -> RawResult<()> {
-        unimplemented!("commit_offset")
+        let (tmq, topic) = (self.tmq, topic_name.to_string());
+        let res =
+            tokio::task::spawn_blocking(move || tmq.commit_offset_sync(&topic, vgroup_id, offset))
+                .await
+                .map_err(RawError::from_any)
+                .and_then(|res| res);
+        self.metrics.record_commit(offset, &res);
+        self.commit_hook.call(topic_name, vgroup_id, offset, &res);
+        res
     }
 
     fn default_timeout(&self) -> Timeout {
         self.timeout
     }
 
+    async fn unsubscribe(mut self) -> RawResult<()> {
+        self.unsubscribe_and_close()
+    }
+
+    async fn shutdown(mut self) -> RawResult<()> {
+        // The native close commits and waits for the server, keep it off the async runtime.
+        let closing = tokio::task::spawn_blocking(move || self.unsubscribe_and_close());
+        match tokio::time::timeout(SHUTDOWN_TIMEOUT, closing).await {
+            Ok(Ok(res)) => res,
+            Ok(Err(err)) => Err(RawError::from_any(err)),
+            Err(_) => Err(RawError::from_string(format!(
+                "consumer shutdown timed out after {:?}",
+                SHUTDOWN_TIMEOUT
+            ))),
+        }
+    }
+
     async fn list_topics(&self) -> RawResult<Vec<String>> {
         unimplemented!("list_topics")
     }
 
+    fn subscription(&self) -> Vec<String> {
+        self.tmq.subscription().into_strings()
+    }
+
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        let builder = self.builder.clone();
+        // The native query blocks, keep it off the async runtime.
+        tokio::task::spawn_blocking(move || {
+            use taos_query::{Fetchable, Queryable};
+
+            builder
+                .inner_connection()?
+                .query(taos_query::tmq::TOPICS_SQL)?
+                .deserialize::<String>()
+                .collect()
+        })
+        .await
+        .map_err(RawError::from_any)?
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending_error)
+    }
+
+    async fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        <Self as AsConsumer>::subscribe_matched(self, topics)
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
+    }
+
     async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
         let topics = self.tmq.subscription();
         let topics = topics.into_strings();
@@ -690,32 +1123,44 @@ impl AsAsyncConsumer for Consumer {
         let ret: Vec<(String, Vec<Assignment>)> = topics
             .into_iter()
             .map(|topic| {
-                let assignments = self.tmq.get_topic_assignment(&topic);
+                let assignments = self.tmq.get_topic_assignment(&topic).unwrap_or_default();
                 (topic, assignments)
             })
             .collect();
         Some(ret)
     }
 
-    async fn topic_assignment(&self, topic: &str) -> Vec<Assignment> {
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
         self.tmq.get_topic_assignment(topic)
     }
 
-    async fn offset_seek(
-        &mut self,
-        topic: &str,
-        vgroup_id: VGroupId,
-        offset: i64,
-    ) -> RawResult<()> {
-        self.tmq.offset_seek(topic, vgroup_id, offset)
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+        self.tmq.offset_seek(topic, vgroup_id, offset)?;
+        if let Some(order) = &self.vgroup_order {
+            order.reset(topic, vgroup_id);
+        }
+        self.paused.reset(topic, vgroup_id);
+        Ok(())
     }
 
     async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
-        unimplemented!("committed")
+        self.tmq.committed(topic, vgroup_id)
     }
 
     async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
-        unimplemented!("position")
+        self.tmq.position(topic, vgroup_id)
+    }
+
+    async fn resubscribe(&self) -> RawResult<()> {
+        <Self as AsConsumer>::resubscribe(self)
+    }
+
+    async fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        <Self as AsConsumer>::pause(self, topic, vgroup_id)
+    }
+
+    async fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        <Self as AsConsumer>::resume(self, topic, vgroup_id)
     }
 }
 
@@ -816,7 +1261,7 @@ mod tests {
             let _ = consumer.commit(offset);
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         let query = taos.query("describe stb1")?;
         for row in query {
@@ -932,7 +1377,7 @@ mod tests {
             let _ = consumer.commit(offset);
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         let query = taos.query("describe stb1")?;
         for row in query {
@@ -1071,6 +1516,7 @@ mod tests {
                                 using,
                                 tags,
                                 tag_num,
+                                create_list: _,
                             } => {
                                 let _desc = taos.describe(table_name.as_str())?;
                                 // dbg!(desc);
@@ -1157,7 +1603,7 @@ mod tests {
         let assignments = consumer.assignments().unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         taos.exec_many([
             "drop database sys_tmq_meta_sync2",
@@ -1243,7 +1689,7 @@ mod tests {
             })
             .await?;
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         let (c1, c2) = target
             .query_one::<_, (Option<i32>, Option<i32>)>("select c1, c2 from tb1")
@@ -1350,7 +1796,7 @@ mod tests {
             })
             .await?;
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         assert!(target
             .query_one::<_, (Option<i32>, Option<i32>)>("select c1, c2 from tb1")
@@ -1557,7 +2003,7 @@ mod tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         taos.exec_many([
             "drop database sys_tmq_meta2",
@@ -1765,7 +2211,7 @@ mod tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
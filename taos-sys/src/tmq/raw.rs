@@ -3,25 +3,20 @@ pub(super) use list::Topics;
 pub(super) use tmq::RawTmq;
 
 pub(super) mod tmq {
-    use std::ffi::CStr;
-    use std::{os::raw::c_void, time::Duration};
+    use std::{ffi::CStr, os::raw::c_void, time::Duration};
 
     use itertools::Itertools;
-    use taos_query::prelude::tokio;
+    use taos_query::prelude::{tokio, Code};
     use taos_query::tmq::{Assignment, VGroupId};
 
-    use crate::{into_c_str::IntoCStr, RawError, RawRes};
+    use crate::{
+        ffi::{taos_errno, taos_errstr, TAOS_RES},
+        into_c_str::IntoCStr,
+        RawError, RawRes,
+    };
 
     use super::{super::ffi::*, Topics};
 
-    pub fn err_as_str(tmq_resp: tmq_resp_err_t) -> String {
-        unsafe {
-            CStr::from_ptr(tmq_err2str(tmq_resp))
-                .to_string_lossy()
-                .to_string()
-        }
-    }
-
     #[derive(Debug, Clone, Copy)]
     pub(crate) struct RawTmq(pub(crate) *mut tmq_t);
 
@@ -29,7 +24,7 @@ pub(super) mod tmq {
     unsafe impl Sync for RawTmq {}
 
     impl RawTmq {
-        pub(crate) fn subscribe(&mut self, topics: &Topics) -> Result<(), RawError> {
+        pub(crate) fn subscribe(&self, topics: &Topics) -> Result<(), RawError> {
             unsafe { tmq_subscribe(self.0, topics.0) }.ok_or(format!(
                 "subscribe failed with topics: [{}]",
                 topics.iter().join(",")
@@ -108,22 +103,18 @@ pub(super) mod tmq {
         }
 
         /// Wait a message forever
-        pub fn next_or_forever(&self) -> RawRes {
+        pub fn next_or_forever(&self) -> Result<RawRes, RawError> {
             self.poll_timeout(-1)
-                .expect("wait forever if there's no message")
+                .map(|res| res.expect("wait forever if there's no message"))
         }
 
-        pub fn poll_timeout(&self, timeout: i64) -> Option<RawRes> {
+        pub fn poll_timeout(&self, timeout: i64) -> Result<Option<RawRes>, RawError> {
             log::trace!("poll next message with timeout {}", timeout);
             let res = unsafe { tmq_consumer_poll(self.0, timeout) };
-            if res.is_null() {
-                None
-            } else {
-                Some(RawRes(res))
-            }
+            polled(res)
         }
 
-        pub async fn poll_async(&self) -> RawRes {
+        pub async fn poll_async(&self) -> Result<RawRes, RawError> {
             let elapsed = std::time::Instant::now();
 
             loop {
@@ -134,33 +125,28 @@ pub(super) mod tmq {
                 let res = tokio::task::spawn_blocking(move || {
                     let raw = raw;
                     let res = unsafe { tmq_consumer_poll(raw.0, 200) };
-                    if res.is_null() {
-                        None
-                    } else {
-                        Some(RawRes(res))
-                    }
-                    // result
+                    polled(res)
                 })
                 .await
-                .unwrap_or_default();
+                .map_err(RawError::from_any)??;
                 if let Some(res) = res {
                     log::trace!("received tmq message in {:?}", elapsed.elapsed());
-                    break res;
+                    break Ok(res);
                 } else {
                     tokio::time::sleep(Duration::from_millis(1)).await;
                 }
             }
         }
 
-        pub fn unsubscribe(&mut self) {
-            unsafe {
-                log::trace!("close consumer");
-                tmq_unsubscribe(self.0);
-                log::trace!("consumer closed safely");
-            }
+        pub fn unsubscribe(&mut self) -> Result<(), RawError> {
+            log::trace!("close consumer");
+            let tmq_resp = unsafe { tmq_unsubscribe(self.0) };
+            tmq_resp.ok_or("unsubscribe failed")?;
+            log::trace!("consumer closed safely");
+            Ok(())
         }
 
-        pub fn get_topic_assignment(&self, topic_name: &str) -> Vec<Assignment> {
+        pub fn get_topic_assignment(&self, topic_name: &str) -> Result<Vec<Assignment>, RawError> {
             let assignments_ptr: *mut *mut Assignment =
                 Box::into_raw(Box::new(std::ptr::null_mut()));
             let mut assignment_num: i32 = 0;
@@ -174,19 +160,19 @@ pub(super) mod tmq {
                 )
             };
 
-            let err_str = err_as_str(tmq_resp);
-            if tmq_resp.is_err() {
-                return vec![];
+            tmq_resp.ok_or(format!("get assignment of topic {topic_name} failed"))?;
+            if assignment_num == 0 {
+                return Ok(vec![]);
             }
 
             let assignments =
                 unsafe { std::slice::from_raw_parts(*assignments_ptr, assignment_num as usize) };
 
-            assignments.to_vec()
+            Ok(assignments.to_vec())
         }
 
         pub fn offset_seek(
-            &mut self,
+            &self,
             topic_name: &str,
             vgroup_id: VGroupId,
             offset: i64,
@@ -202,16 +188,154 @@ pub(super) mod tmq {
                 offset
             );
 
-            let err_str = err_as_str(tmq_resp);
-            log::trace!("offset_seek tmq_resp as str: {}", err_str);
+            tmq_resp.ok_or(format!(
+                "seek topic {topic_name} vgroup {vgroup_id} to offset {offset} failed"
+            ))
+        }
 
-            tmq_resp.ok_or(format!("offset seek failed: {err_str}"))
+        #[cfg(taos_tmq_committed)]
+        pub fn commit_offset_sync(
+            &self,
+            topic_name: &str,
+            vgroup_id: VGroupId,
+            offset: i64,
+        ) -> Result<(), RawError> {
+            let tmq_resp = unsafe {
+                tmq_commit_offset_sync(self.0, topic_name.into_c_str().as_ptr(), vgroup_id, offset)
+            };
+            tmq_resp.ok_or(format!(
+                "commit offset {offset} of topic {topic_name} vgroup {vgroup_id} failed"
+            ))
         }
 
-        pub fn close(&mut self) {
-            unsafe {
-                tmq_consumer_close(self.0);
+        #[cfg(not(taos_tmq_committed))]
+        pub fn commit_offset_sync(&self, _: &str, _: VGroupId, _: i64) -> Result<(), RawError> {
+            Err(RawError::from_string(
+                "tmq_commit_offset_sync is not supported by current client library",
+            ))
+        }
+
+        /// Committed offset of the topic vgroup, `-1` if nothing is committed.
+        #[cfg(taos_tmq_committed)]
+        pub fn committed(&self, topic_name: &str, vgroup_id: VGroupId) -> Result<i64, RawError> {
+            let offset =
+                unsafe { tmq_committed(self.0, topic_name.into_c_str().as_ptr(), vgroup_id) };
+            log::trace!(
+                "committed offset: {}, topic_name: {}, vgroup_id: {}",
+                offset,
+                topic_name,
+                vgroup_id
+            );
+
+            // tmq_committed returns the committed offset, or a negative error code.
+            if offset >= 0 {
+                return Ok(offset);
+            }
+            let tmq_resp = tmq_resp_err_t::from_code(offset as i32);
+            if Code::from(tmq_resp.errno()) == Code::TMQ_NO_COMMITTED {
+                return Ok(-1);
             }
+            tmq_resp
+                .ok_or(format!(
+                    "get committed offset of topic {topic_name} vgroup {vgroup_id} failed"
+                ))
+                .map(|_| offset)
+        }
+
+        #[cfg(not(taos_tmq_committed))]
+        pub fn committed(&self, _: &str, _: VGroupId) -> Result<i64, RawError> {
+            Err(RawError::from_string(
+                "tmq_committed is not supported by current client library",
+            ))
+        }
+
+        /// Current position of the topic vgroup, the offset of the next message.
+        #[cfg(taos_tmq_committed)]
+        pub fn position(&self, topic_name: &str, vgroup_id: VGroupId) -> Result<i64, RawError> {
+            let offset =
+                unsafe { tmq_position(self.0, topic_name.into_c_str().as_ptr(), vgroup_id) };
+            log::trace!(
+                "position offset: {}, topic_name: {}, vgroup_id: {}",
+                offset,
+                topic_name,
+                vgroup_id
+            );
+
+            // tmq_position returns the current position, or a negative error code.
+            if offset >= 0 {
+                return Ok(offset);
+            }
+            tmq_resp_err_t::from_code(offset as i32)
+                .ok_or(format!(
+                    "get position of topic {topic_name} vgroup {vgroup_id} failed"
+                ))
+                .map(|_| offset)
+        }
+
+        #[cfg(not(taos_tmq_committed))]
+        pub fn position(&self, _: &str, _: VGroupId) -> Result<i64, RawError> {
+            Err(RawError::from_string(
+                "tmq_position is not supported by current client library",
+            ))
+        }
+
+        pub fn close(&mut self) -> Result<(), RawError> {
+            let tmq_resp = unsafe { tmq_consumer_close(self.0) };
+            tmq_resp.ok_or("close consumer failed")
+        }
+    }
+
+    /// Message of a poll, or the error reported by the library when there's no message.
+    ///
+    /// Called on the polling thread since the error number is thread local.
+    fn polled(res: *mut TAOS_RES) -> Result<Option<RawRes>, RawError> {
+        let res = (!res.is_null()).then_some(RawRes(res));
+        poll_result(res, || {
+            let null = std::ptr::null_mut();
+            let err_str = unsafe { CStr::from_ptr(taos_errstr(null)) }.to_string_lossy();
+            (unsafe { taos_errno(null) }, err_str.into_owned())
+        })
+    }
+
+    /// The polled message `res`, or the error by `errno` if there's no message, as the library
+    /// returns NULL for both a timeout and a failure.
+    fn poll_result<T>(
+        res: Option<T>,
+        errno: impl FnOnce() -> (i32, String),
+    ) -> Result<Option<T>, RawError> {
+        if res.is_some() {
+            return Ok(res);
+        }
+        let (code, err_str) = errno();
+        let code = Code::from(code);
+        if code.success() {
+            Ok(None)
+        } else {
+            Err(RawError::new(code, format!("poll failed: {err_str}")))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use taos_query::prelude::Code;
+
+        use super::poll_result;
+
+        #[test]
+        fn test_poll_result() {
+            assert_eq!(poll_result(Some(1), || unreachable!()).unwrap(), Some(1));
+            assert_eq!(
+                poll_result(None::<i32>, || (0, "success".to_string())).unwrap(),
+                None
+            );
+
+            // The library reports the error number in the high bits, e.g. 0x80004001.
+            let err = poll_result(None::<i32>, || {
+                (0x80004001_u32 as i32, "Consumer mismatch".to_string())
+            })
+            .unwrap_err();
+            assert_eq!(err.code(), Code::TMQ_CONSUMER_MISMATCH);
+            assert!(err.to_string().contains("Consumer mismatch"), "{err}");
         }
     }
 }
@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use bytes::Bytes;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
@@ -80,6 +82,8 @@ pub struct TmqInit {
     #[serde(rename = "offset_rest")]
     pub offset_reset: Option<String>, // `offset_reset` is `offset_rest` in taosadapter
     pub snapshot_enable: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub enable_replay: Option<String>,
     pub with_table_name: String,
     pub auto_commit: String,
     pub auto_commit_interval_ms: Option<String>,
@@ -170,6 +174,17 @@ pub struct TmqPoll {
     pub topic: String,
     pub vgroup_id: VGroupId,
     pub message_type: MessageType,
+    /// Message offset, available since taosAdapter 3.0.5.
+    pub offset: Option<i64>,
+    /// Nanoseconds taosAdapter spent on the poll.
+    pub timing: Option<i64>,
+    /// Local time when the response is parsed, right after it is read from the connection.
+    #[serde(skip, default = "received_now")]
+    pub received_at: Option<Instant>,
+}
+
+fn received_now() -> Option<Instant> {
+    Some(Instant::now())
 }
 
 #[derive(Debug, Deserialize, Default, Clone)]
@@ -285,4 +300,30 @@ fn test_serde_recv_data() {
     let _ = dbg!(d.ok());
 }
 
+#[test]
+fn test_serde_poll_received_at() {
+    let json = r#"{
+        "code": 0,
+        "message": "",
+        "action": "poll",
+        "req_id": 1,
+        "have_message": true,
+        "topic": "topic",
+        "database": "db",
+        "vgroup_id": 2,
+        "message_type": 1,
+        "message_id": 3,
+        "timing": 1234
+    }"#;
+    let before = Instant::now();
+    let d: TmqRecv = serde_json::from_str(json).unwrap();
+    let (_, data, _) = d.ok();
+    let TmqRecvData::Poll(poll) = data else {
+        panic!("expect a poll response");
+    };
+    assert!(poll.received_at.unwrap() >= before);
+    assert_eq!(poll.timing, Some(1234));
+    assert!(TmqPoll::default().received_at.is_none());
+}
+
 impl ToMessage for TmqSend {}
@@ -7,15 +7,17 @@ use itertools::Itertools;
 use dashmap::DashMap as HashMap;
 
 use log::warn;
-use taos_query::common::{JsonMeta, RawMeta};
+use taos_query::common::{Field, JsonMeta, RawMeta};
 use taos_query::prelude::{Code, RawError};
 use taos_query::tmq::{
-    AsAsyncConsumer, AsConsumer, Assignment, IsAsyncData, IsAsyncMeta, IsData, IsOffset,
-    MessageSet, SyncOnAsync, Timeout, VGroupId,
+    AsAsyncConsumer, AsConsumer, Assignment, CommitHook, ConsumerCounters, ConsumerInfo,
+    ConsumerMetrics, ConsumerObserver, IsAsyncData, IsAsyncMeta, IsData, IsOffset, MessageSet,
+    PausedVGroups, PendingError, RebalanceEvent, SeekResults, SyncOnAsync, Timeout, TmqConfig,
+    TopicPattern, VGroupId,
 };
 use taos_query::util::{Edition, InlinableRead};
 use taos_query::RawResult;
-use taos_query::{DeError, DsnError, IntoDsn, RawBlock, TBuilder};
+use taos_query::{DeError, Dsn, DsnError, IntoDsn, RawBlock, TBuilder};
 use thiserror::Error;
 
 use taos_query::prelude::tokio;
@@ -30,12 +32,14 @@ use crate::query::infra::{ToMessage, WsConnReq};
 use crate::TaosBuilder;
 use messages::*;
 
+use ws_tool::codec::AsyncDeflateCodec;
 use ws_tool::{errors::WsError as WsErrorWst, frame::OpCode, Message as WsMessage};
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
 use std::str::FromStr;
-use std::sync::atomic::AtomicU64;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 mod messages;
@@ -43,256 +47,688 @@ mod messages;
 type WsSender = tokio::sync::mpsc::Sender<WsMessage<bytes::Bytes>>;
 type WsTmqAgent = Arc<HashMap<ReqId, oneshot::Sender<RawResult<TmqRecvData>>>>;
 
-#[derive(Debug, Clone)]
-struct WsTmqSender {
-    req_id: Arc<AtomicU64>,
-    sender: WsSender,
-    queries: WsTmqAgent,
-    #[allow(dead_code)]
-    timeout: Timeout,
-}
-
-impl WsTmqSender {
-    fn req_id(&self) -> ReqId {
-        self.req_id
-            .fetch_add(1, std::sync::atomic::Ordering::SeqCst)
-    }
-    async fn send_recv(&self, msg: TmqSend) -> RawResult<TmqRecvData> {
-        self.send_recv_timeout(msg, Duration::MAX).await
-    }
-    async fn send_recv_timeout(&self, msg: TmqSend, timeout: Duration) -> RawResult<TmqRecvData> {
-        let send_timeout = Duration::from_millis(5000);
-        let req_id = msg.req_id();
-        let (tx, rx) = oneshot::channel();
-
-        self.queries.insert(req_id, tx);
-
-        self.sender
-            .send_timeout(msg.to_msg(), send_timeout)
-            .await
-            .map_err(WsTmqError::from)?;
-
-        let sleep = tokio::time::sleep(timeout);
-        tokio::pin!(sleep);
-        let data = tokio::select! {
-            _ = &mut sleep, if !sleep.is_elapsed() => {
-               log::trace!("poll timed out");
-               Err(WsTmqError::QueryTimeout("poll".to_string()))?
-            }
-            message = rx => {
-                message.map_err(WsTmqError::from)??
-            }
-        };
-        Ok(data)
-    }
-}
+/// Default interval of websocket pings, see [TmqBuilder::with_keepalive_interval].
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(29);
 
+/// State of the websocket link shared by the consumer, its sender and reader tasks.
 #[derive(Debug)]
-pub struct TmqBuilder {
-    info: TaosBuilder,
-    conf: TmqInit,
-    timeout: Timeout,
+struct WsLink {
+    connected: AtomicBool,
+    last_received: Mutex<Instant>,
 }
 
-impl TBuilder for TmqBuilder {
-    type Target = Consumer;
-
-    fn available_params() -> &'static [&'static str] {
-        &["token", "timeout", "group.id", "client.id"]
-    }
-
-    fn from_dsn<D: IntoDsn>(dsn: D) -> RawResult<Self> {
-        Self::new(dsn)
-    }
-
-    fn client_version() -> &'static str {
-        "0"
-    }
-
-    fn ping(&self, _: &mut Self::Target) -> RawResult<()> {
-        Ok(())
-    }
-
-    fn ready(&self) -> bool {
-        true
+impl Default for WsLink {
+    fn default() -> Self {
+        Self {
+            connected: AtomicBool::new(true),
+            last_received: Mutex::new(Instant::now()),
+        }
     }
+}
 
-    fn build(&self) -> RawResult<Self::Target> {
-        taos_query::block_in_place_or_global(self.build_consumer())
+impl WsLink {
+    fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
     }
 
-    fn server_version(&self) -> RawResult<&str> {
-        todo!()
+    /// Record a frame received from the server.
+    fn received(&self) {
+        *self.last_received.lock().unwrap() = Instant::now();
     }
 
-    fn is_enterprise_edition(&self) -> RawResult<bool> {
-        todo!()
+    /// Time since the last frame was received.
+    fn silence(&self) -> Duration {
+        self.last_received.lock().unwrap().elapsed()
     }
 
-    fn get_edition(&self) -> RawResult<taos_query::util::Edition> {
-        if self
-            .info
-            .addr
-            .matches(".cloud.tdengine.com")
-            .next()
-            .is_some()
-            || self
-                .info
-                .addr
-                .matches(".cloud.taosdata.com")
-                .next()
-                .is_some()
-        {
-            let edition = Edition::new("cloud", false);
-            return Ok(edition);
+    /// Mark the link as dead and fail all pending requests with [WS_ERROR_NO::CONN_CLOSED].
+    ///
+    /// Only the first call fails them, requests sent later fail on the link state by themselves
+    /// and may already belong to a new connection.
+    fn lost(&self, queries: &WsTmqAgent, reason: &str) {
+        if !self.connected.swap(false, Ordering::SeqCst) {
+            return;
         }
-
-        let taos = TBuilder::build(&self.info)?;
-
-        use taos_query::prelude::sync::Queryable;
-        let grant: RawResult<Option<(String, bool)>> = Queryable::query_one(
-            &taos,
-            "select version, (expire_time < now) from information_schema.ins_cluster",
-        );
-
-        let edition = if let Ok(Some((edition, expired))) = grant {
-            Edition::new(edition, expired)
-        } else {
-            let grant: RawResult<Option<(String, (), String)>> =
-                Queryable::query_one(&taos, "show grants");
-
-            if let Ok(Some((edition, _, expired))) = grant {
-                Edition::new(
-                    edition.trim(),
-                    expired.trim() == "false" || expired.trim() == "unlimited",
-                )
-            } else {
-                warn!("Can't check enterprise edition with either \"show cluster\" or \"show grants\"");
-                Edition::new("unknown", true)
+        log::warn!("websocket connection is lost: {reason}");
+        let keys = queries.iter().map(|r| *r.key()).collect_vec();
+        for k in keys {
+            if let Some((_, sender)) = queries.remove(&k) {
+                let _ = sender.send(Err(RawError::new(
+                    WS_ERROR_NO::CONN_CLOSED.as_code(),
+                    reason.to_string(),
+                )));
             }
-        };
-        Ok(edition)
+        }
     }
 }
 
-#[async_trait::async_trait]
-impl taos_query::AsyncTBuilder for TmqBuilder {
-    type Target = Consumer;
-
-    fn from_dsn<D: IntoDsn>(dsn: D) -> RawResult<Self> {
-        Self::new(dsn)
-    }
-
-    fn client_version() -> &'static str {
-        "0"
-    }
-
-    async fn ping(&self, _: &mut Self::Target) -> RawResult<()> {
-        Ok(())
-    }
-
-    async fn ready(&self) -> bool {
-        true
-    }
+/// Request id generator set by [TmqBuilder::with_req_id_fn].
+#[derive(Clone)]
+struct ReqIdFn(Arc<dyn Fn() -> ReqId + Send + Sync>);
 
-    async fn build(&self) -> RawResult<Self::Target> {
-        self.build_consumer().await
+impl Debug for ReqIdFn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReqIdFn")
     }
+}
 
-    async fn server_version(&self) -> RawResult<&str> {
-        todo!()
-    }
+/// Sending half of a websocket connection and the state of its link.
+#[derive(Debug, Clone)]
+struct WsConn {
+    sender: WsSender,
+    link: Arc<WsLink>,
+}
 
-    async fn is_enterprise_edition(&self) -> RawResult<bool> {
-        todo!()
+impl WsConn {
+    fn new(sender: WsSender) -> Self {
+        Self {
+            sender,
+            link: Arc::default(),
+        }
     }
 
-    async fn get_edition(&self) -> RawResult<taos_query::util::Edition> {
-        use taos_query::prelude::AsyncQueryable;
+    /// Spawn the sender and reader tasks of the websocket stream, both end when the link is
+    /// lost or `close` is signaled.
+    fn spawn(
+        stream: AsyncDeflateCodec<tokio::io::BufStream<ws_tool::stream::AsyncStream>>,
+        url: String,
+        queries: WsTmqAgent,
+        metrics: Arc<ConsumerCounters>,
+        keepalive: Duration,
+        close: &watch::Sender<bool>,
+    ) -> Self {
+        let (ws, mut msg_recv) = tokio::sync::mpsc::channel::<WsMessage<bytes::Bytes>>(100);
+        let (mut reader, mut sender) = stream.split();
+        let sending_url = url;
+        let queries_sender = queries.clone();
+        let msg_handler = queries;
+        let metrics_sender = metrics;
+        let conn = Self::new(ws);
+        let link_sender = conn.link.clone();
+        let link_reader = conn.link.clone();
+        let ws2 = conn.sender.clone();
 
-        let taos = taos_query::AsyncTBuilder::build(&self.info).await?;
-        // Ensure server is ready.
-        taos.exec("select server_status()").await?;
+        let mut rx = close.subscribe();
+        let mut close_listener = close.subscribe();
 
-        match self
-            .info
-            .addr
-            .matches(".cloud.tdengine.com")
-            .next()
-            .is_some()
-            || self
-                .info
-                .addr
-                .matches(".cloud.taosdata.com")
-                .next()
-                .is_some()
-        {
-            true => {
-                let edition = Edition::new("cloud", false);
-                return Ok(edition);
-            }
-            false => (),
-        }
+        const PING: &[u8] = b"TAOS";
 
-        let grant: RawResult<Option<(String, bool)>> = AsyncQueryable::query_one(
-            &taos,
-            "select version, (expire_time < now) from information_schema.ins_cluster",
-        )
-        .await;
+        tokio::spawn(async move {
+            let mut interval = time::interval(keepalive);
 
-        let edition = if let Ok(Some((edition, expired))) = grant {
-            Edition::new(edition, expired)
-        } else {
-            let grant: RawResult<Option<(String, (), String)>> =
-                AsyncQueryable::query_one(&taos, "show grants").await;
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        log::trace!("Check websocket message sender alive");
+                        let silence = link_sender.silence();
+                        if silence > keepalive * 2 {
+                            link_sender.lost(&msg_handler, &format!("no message received from server in {silence:?}"));
+                            break;
+                        }
+                        if let Err(err) = sender.send(OpCode::Ping, &serde_json::to_vec(&PING).unwrap()).await {
+                            log::trace!("sending ping message to {sending_url} error: {err:?}");
+                            link_sender.lost(&msg_handler, &format!("WebSocket internal error: {err}"));
+                            break;
+                        }
+                    }
+                    Some(msg) = msg_recv.recv() => {
 
-            if let Ok(Some((edition, _, expired))) = grant {
-                Edition::new(
-                    edition.trim(),
-                    expired.trim() == "false" || expired.trim() == "unlimited",
-                )
-            } else {
-                warn!("Can't check enterprise edition with either \"show cluster\" or \"show grants\"");
-                Edition::new("unknown", true)
+                        log::trace!("send message {msg:?}");
+                        let opcode = msg.code;
+                        let msg = msg.data;
+                        if let Err(err) = sender.send(opcode, &msg).await {
+                            log::trace!("sending message to {sending_url} error: {err:?}");
+                            link_sender.lost(&msg_handler, &format!("WebSocket internal error: {err}"));
+                            break;
+                        }
+                        log::trace!("send message done");
+                    }
+                    _ = rx.changed() => {
+                        let _ = sender.send(OpCode::Close, b"").await;
+                        log::trace!("close tmq sender");
+                        break;
+                    }
+                }
             }
-        };
-        Ok(edition)
-    }
-}
-#[derive(Debug)]
-struct WsMessageBase {
-    sender: WsTmqSender,
-    message_id: MessageId,
-}
-
-impl WsMessageBase {
-    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
-        let req_id = self.sender.req_id();
-
-        let msg = TmqSend::Fetch(MessageArgs {
-            req_id,
-            message_id: self.message_id,
-        });
-        let data = self.sender.send_recv(msg).await?;
-        let fetch = if let TmqRecvData::Fetch(fetch) = data {
-            fetch
-        } else {
-            unreachable!()
-        };
-
-        if fetch.completed {
-            return Ok(None);
-        }
-
-        let msg = TmqSend::FetchBlock(MessageArgs {
-            req_id,
-            message_id: self.message_id,
         });
-        let data = self.sender.send_recv(msg).await?;
-        if let TmqRecvData::Bytes(bytes) = data {
-            let mut raw = RawBlock::parse_from_raw_block(bytes, fetch.precision);
 
-            // for row in 0..raw.nrows() {
+        tokio::spawn(async move {
+            let instant = Instant::now();
+            'ws: loop {
+                tokio::select! {
+                    frame = reader.receive() => {
+                        let (header, payload) = match frame {
+                            Ok(frame) => frame,
+                            Err(err) => {
+                                link_reader.lost(&queries_sender, &format!("WebSocket internal error: {err}"));
+                                break 'ws;
+                            }
+                        };
+                        link_reader.received();
+                        let code = header.code;
+                        match code {
+                            OpCode::Text => {
+                                log::trace!("received json response: {payload}", payload = String::from_utf8_lossy(&payload));
+                                let v: TmqRecv = serde_json::from_slice(&payload).unwrap();
+                                let (req_id, recv, ok) = v.ok();
+                                match &recv {
+                                    TmqRecvData::Subscribe => {
+                                        log::trace!("subscribe with: {:?}", req_id);
+
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("subscribe message received but no receiver alive");
+                                        }
+                                    },
+                                    TmqRecvData::Unsubscribe => {
+                                        log::trace!("unsubscribe with: {:?} successed", req_id);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("unsubscribe message received but no receiver alive");
+                                        }
+                                    },
+                                    TmqRecvData::Poll(_) => {
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("poll message received but no receiver alive");
+                                        }
+                                    },
+                                    TmqRecvData::FetchJsonMeta { data }=> {
+                                        log::trace!("fetch json meta data: {:?}", data);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("poll message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::FetchRaw { meta: _ }=> {
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("poll message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::Commit=> {
+                                        log::trace!("commit done: {:?}", recv);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("poll message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::Fetch(fetch)=> {
+                                        log::trace!("fetch done: {:?}", fetch);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("poll message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::FetchBlock{ data: _ }=> {
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id) {
+                                            let _ = sender.send(Err(RawError::new(
+                                                WS_ERROR_NO::WEBSOCKET_ERROR.as_code(),
+                                                format!("WebSocket internal error")
+                                            )));
+                                        }
+                                        link_reader.lost(&queries_sender, "unexpected fetch block response");
+                                        break 'ws;
+                                    }
+                                    TmqRecvData::Assignment(assignment)=> {
+                                        log::trace!("assignment done: {:?}", assignment);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("assignment message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::Seek { timing }=> {
+                                        log::trace!("seek done: req_id {:?} timing {:?}", &req_id, timing);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("seek message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::Committed { committed }=> {
+                                        log::trace!("committed done: {:?}", committed);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("committed message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::Position { position }=> {
+                                        log::trace!("position done: {:?}", position);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                        {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        }  else {
+                                            log::warn!("position message received but no receiver alive");
+                                        }
+                                    }
+                                    TmqRecvData::CommitOffset { timing }=> {
+                                        log::trace!("commit offset done: {:?}", timing);
+                                        if let Some((_, sender)) = queries_sender.remove(&req_id) {
+                                            let _ = sender.send(ok.map(|_|recv));
+                                        } else {
+                                            log::warn!("commit offset message received but no receiver alive");
+                                        }
+                                    }
+                                    _ => unreachable!("unknown tmq response"),
+                                }
+                            }
+                            OpCode::Binary => {
+                                let block = payload.to_vec();
+                                let mut slice = block.as_slice();
+                                use taos_query::util::InlinableRead;
+                                let offset = 24;
+                                let part = slice[offset..].to_vec();
+                                metrics_sender.record_bytes(part.len());
+
+                                let _timing = {
+                                    let timing = slice.read_u64().unwrap();
+                                    Duration::from_nanos(timing as _)
+                                };
+
+                                let req_id = slice.read_u64().unwrap();
+
+                                if let Some((_, sender)) = queries_sender.remove(&req_id) {
+                                    log::trace!("send data to fetches with id {}", req_id);
+                                    sender.send(Ok(TmqRecvData::Bytes(part.into()))).unwrap();
+                                } else {
+                                    log::warn!("req_id {req_id} not detected, message might be lost");
+                                }
+                            }
+                            OpCode::Close => {
+                                log::warn!("websocket connection is closed normally");
+                                link_reader.lost(&queries_sender, "received close message");
+                                break 'ws;
+                            }
+                            OpCode::Ping => {
+                                let bytes = payload.to_vec();
+                                let _ = ws2.send(WsMessage{
+                                    code: OpCode::Pong,
+                                    data: bytes.into(),
+                                    close_code: None
+                                }).await;
+                            }
+                            OpCode::Pong => {
+                                // do nothing
+                                log::trace!("received pong message, do nothing");
+                            }
+                            _ => {
+                                let frame = payload;
+                                // do nothing
+                                log::warn!("received (unexpected) frame message, do nothing");
+                                log::trace!("* frame data: {frame:?}");
+                            }
+                        }
+                    }
+                    _ = close_listener.changed() => {
+                        log::trace!("close reader task");
+                        break 'ws;
+                    }
+                }
+            }
+            log::trace!("Consuming done in {:?}", instant.elapsed());
+        });
+        conn
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WsTmqSender {
+    req_id: Arc<AtomicU64>,
+    req_id_fn: Option<ReqIdFn>,
+    /// Replaced on [Consumer::reconnect], shared by all the clones.
+    conn: Arc<std::sync::RwLock<WsConn>>,
+    queries: WsTmqAgent,
+    #[allow(dead_code)]
+    timeout: Timeout,
+}
+
+impl WsTmqSender {
+    fn new(req_id_fn: Option<ReqIdFn>, conn: WsConn, queries: WsTmqAgent) -> Self {
+        Self {
+            req_id: Arc::new(AtomicU64::new(1)),
+            req_id_fn,
+            conn: Arc::new(std::sync::RwLock::new(conn)),
+            queries,
+            timeout: Timeout::Duration(Duration::MAX),
+        }
+    }
+
+    fn conn(&self) -> WsConn {
+        self.conn.read().unwrap().clone()
+    }
+
+    fn is_connected(&self) -> bool {
+        self.conn().link.is_connected()
+    }
+
+    fn req_id(&self) -> ReqId {
+        if let Some(req_id_fn) = &self.req_id_fn {
+            return (req_id_fn.0)();
+        }
+        self.req_id.fetch_add(1, Ordering::SeqCst)
+    }
+    async fn send_recv(&self, msg: TmqSend) -> RawResult<TmqRecvData> {
+        self.send_recv_timeout(msg, Duration::MAX).await
+    }
+    async fn send_recv_timeout(&self, msg: TmqSend, timeout: Duration) -> RawResult<TmqRecvData> {
+        let send_timeout = Duration::from_millis(5000);
+        let req_id = msg.req_id();
+        let (tx, rx) = oneshot::channel();
+        let conn = self.conn();
+
+        self.queries.insert(req_id, tx);
+        // Checked after the insertion, a link lost in between has failed the request already.
+        if !conn.link.is_connected() {
+            self.queries.remove(&req_id);
+            return Err(RawError::new(
+                WS_ERROR_NO::CONN_CLOSED.as_code(),
+                "websocket connection is lost",
+            ));
+        }
+
+        conn.sender
+            .send_timeout(msg.to_msg(), send_timeout)
+            .await
+            .map_err(WsTmqError::from)?;
+
+        let sleep = tokio::time::sleep(timeout);
+        tokio::pin!(sleep);
+        let data = tokio::select! {
+            _ = &mut sleep, if !sleep.is_elapsed() => {
+               log::trace!("poll timed out");
+               Err(WsTmqError::QueryTimeout("poll".to_string()))?
+            }
+            message = rx => {
+                message.map_err(WsTmqError::from)??
+            }
+        };
+        Ok(data)
+    }
+}
+
+#[derive(Debug)]
+pub struct TmqBuilder {
+    info: TaosBuilder,
+    conf: TmqInit,
+    timeout: Timeout,
+    auto_commit_interval: Option<Duration>,
+    req_id_fn: Option<ReqIdFn>,
+    max_processing_interval: Option<Duration>,
+    observer: Option<Arc<dyn ConsumerObserver>>,
+    preserve_vgroup_order: bool,
+    keepalive_interval: Duration,
+}
+
+/// Typed builder of [Consumer].
+pub type ConsumerBuilder = taos_query::tmq::ConsumerBuilder<TmqBuilder>;
+
+impl TBuilder for TmqBuilder {
+    type Target = Consumer;
+
+    fn available_params() -> &'static [&'static str] {
+        &["token", "timeout", "group.id", "client.id"]
+    }
+
+    fn from_dsn<D: IntoDsn>(dsn: D) -> RawResult<Self> {
+        Self::new(dsn)
+    }
+
+    fn client_version() -> &'static str {
+        "0"
+    }
+
+    fn ping(&self, _: &mut Self::Target) -> RawResult<()> {
+        Ok(())
+    }
+
+    fn ready(&self) -> bool {
+        true
+    }
+
+    fn build(&self) -> RawResult<Self::Target> {
+        taos_query::block_in_place_or_global(self.build_consumer())
+    }
+
+    fn server_version(&self) -> RawResult<&str> {
+        todo!()
+    }
+
+    fn is_enterprise_edition(&self) -> RawResult<bool> {
+        todo!()
+    }
+
+    fn get_edition(&self) -> RawResult<taos_query::util::Edition> {
+        if self
+            .info
+            .addr
+            .matches(".cloud.tdengine.com")
+            .next()
+            .is_some()
+            || self
+                .info
+                .addr
+                .matches(".cloud.taosdata.com")
+                .next()
+                .is_some()
+        {
+            let edition = Edition::new("cloud", false);
+            return Ok(edition);
+        }
+
+        let taos = TBuilder::build(&self.info)?;
+
+        use taos_query::prelude::sync::Queryable;
+        let grant: RawResult<Option<(String, bool)>> = Queryable::query_one(
+            &taos,
+            "select version, (expire_time < now) from information_schema.ins_cluster",
+        );
+
+        let edition = if let Ok(Some((edition, expired))) = grant {
+            Edition::new(edition, expired)
+        } else {
+            let grant: RawResult<Option<(String, (), String)>> =
+                Queryable::query_one(&taos, "show grants");
+
+            if let Ok(Some((edition, _, expired))) = grant {
+                Edition::new(
+                    edition.trim(),
+                    expired.trim() == "false" || expired.trim() == "unlimited",
+                )
+            } else {
+                warn!("Can't check enterprise edition with either \"show cluster\" or \"show grants\"");
+                Edition::new("unknown", true)
+            }
+        };
+        Ok(edition)
+    }
+}
+
+#[async_trait::async_trait]
+impl taos_query::AsyncTBuilder for TmqBuilder {
+    type Target = Consumer;
+
+    fn from_dsn<D: IntoDsn>(dsn: D) -> RawResult<Self> {
+        Self::new(dsn)
+    }
+
+    fn client_version() -> &'static str {
+        "0"
+    }
+
+    async fn ping(&self, _: &mut Self::Target) -> RawResult<()> {
+        Ok(())
+    }
+
+    async fn ready(&self) -> bool {
+        true
+    }
+
+    async fn build(&self) -> RawResult<Self::Target> {
+        self.build_consumer().await
+    }
+
+    async fn server_version(&self) -> RawResult<&str> {
+        todo!()
+    }
+
+    async fn is_enterprise_edition(&self) -> RawResult<bool> {
+        todo!()
+    }
+
+    async fn get_edition(&self) -> RawResult<taos_query::util::Edition> {
+        use taos_query::prelude::AsyncQueryable;
+
+        let taos = taos_query::AsyncTBuilder::build(&self.info).await?;
+        // Ensure server is ready.
+        taos.exec("select server_status()").await?;
+
+        match self
+            .info
+            .addr
+            .matches(".cloud.tdengine.com")
+            .next()
+            .is_some()
+            || self
+                .info
+                .addr
+                .matches(".cloud.taosdata.com")
+                .next()
+                .is_some()
+        {
+            true => {
+                let edition = Edition::new("cloud", false);
+                return Ok(edition);
+            }
+            false => (),
+        }
+
+        let grant: RawResult<Option<(String, bool)>> = AsyncQueryable::query_one(
+            &taos,
+            "select version, (expire_time < now) from information_schema.ins_cluster",
+        )
+        .await;
+
+        let edition = if let Ok(Some((edition, expired))) = grant {
+            Edition::new(edition, expired)
+        } else {
+            let grant: RawResult<Option<(String, (), String)>> =
+                AsyncQueryable::query_one(&taos, "show grants").await;
+
+            if let Ok(Some((edition, _, expired))) = grant {
+                Edition::new(
+                    edition.trim(),
+                    expired.trim() == "false" || expired.trim() == "unlimited",
+                )
+            } else {
+                warn!("Can't check enterprise edition with either \"show cluster\" or \"show grants\"");
+                Edition::new("unknown", true)
+            }
+        };
+        Ok(edition)
+    }
+}
+#[derive(Debug)]
+struct WsMessageBase {
+    sender: WsTmqSender,
+    message_id: MessageId,
+    /// Header of the next block, kept until the block is fetched.
+    fetched: std::sync::Mutex<Option<TmqFetch>>,
+    /// Blocks and rows fetched so far, and whether the last block is fetched.
+    counts: std::sync::Mutex<(usize, usize, bool)>,
+}
+
+impl WsMessageBase {
+    fn new(sender: WsTmqSender, message_id: MessageId) -> Self {
+        Self {
+            sender,
+            message_id,
+            fetched: Default::default(),
+            counts: Default::default(),
+        }
+    }
+
+    /// Block and row counts of the message, known once all blocks are fetched since the poll
+    /// response does not tell.
+    fn counts(&self) -> Option<(usize, usize)> {
+        match *self.counts.lock().unwrap() {
+            (blocks, rows, true) => Some((blocks, rows)),
+            _ => None,
+        }
+    }
+
+    /// Fetch the header of the next block, the server moves to the next block on each fetch.
+    async fn fetch_header(&self) -> RawResult<TmqFetch> {
+        let fetched = self.fetched.lock().unwrap().clone();
+        if let Some(fetch) = fetched {
+            return Ok(fetch);
+        }
+        let msg = TmqSend::Fetch(MessageArgs {
+            req_id: self.sender.req_id(),
+            message_id: self.message_id,
+        });
+        let data = self.sender.send_recv(msg).await?;
+        let fetch = if let TmqRecvData::Fetch(fetch) = data {
+            fetch
+        } else {
+            unreachable!()
+        };
+        {
+            let mut counts = self.counts.lock().unwrap();
+            if fetch.completed {
+                counts.2 = true;
+            } else {
+                counts.0 += 1;
+                counts.1 += fetch.rows;
+            }
+        }
+        *self.fetched.lock().unwrap() = Some(fetch.clone());
+        Ok(fetch)
+    }
+
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        let fetch = self.fetch_header().await?;
+        if fetch.completed {
+            return Ok(Vec::new());
+        }
+        Ok(fetch.fields())
+    }
+
+    async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
+        let fetch = self.fetch_header().await?;
+        if fetch.completed {
+            return Ok(None);
+        }
+        self.fetched.lock().unwrap().take();
+
+        let req_id = self.sender.req_id();
+        let msg = TmqSend::FetchBlock(MessageArgs {
+            req_id,
+            message_id: self.message_id,
+        });
+        let data = self.sender.send_recv(msg).await?;
+        if let TmqRecvData::Bytes(bytes) = data {
+            let mut raw = RawBlock::parse_from_raw_block(bytes, fetch.precision);
+
+            // for row in 0..raw.nrows() {
             //     for col in 0..raw.ncols() {
             //         log::trace!("at ({}, {})", row, col);
             //         let v = unsafe { raw.get_ref_unchecked(row, col) };
@@ -300,7 +736,7 @@ impl WsMessageBase {
             //     }
             // }
             raw.with_field_names(fetch.fields().iter().map(|f| f.name()));
-            if let Some(name) = fetch.table_name {
+            if let Some(name) = fetch.table_name.filter(|name| !name.is_empty()) {
                 raw.with_table_name(name);
             }
             return Ok(Some(raw));
@@ -337,6 +773,7 @@ impl WsMessageBase {
     }
 }
 
+/// Meta of a message, the payload is fetched only when [IsAsyncMeta] methods are called.
 #[derive(Debug)]
 pub struct Meta(WsMessageBase);
 
@@ -391,6 +828,19 @@ impl IsAsyncData for Data {
     async fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
         self.fetch_block().await
     }
+
+    /// Fields are in the header of the block, the block itself is not fetched.
+    async fn fields(&self) -> RawResult<Vec<Field>> {
+        self.0.fields().await
+    }
+
+    fn block_count_hint(&self) -> Option<usize> {
+        self.0.counts().map(|(blocks, _)| blocks)
+    }
+
+    fn row_count_hint(&self) -> Option<usize> {
+        self.0.counts().map(|(_, rows)| rows)
+    }
 }
 
 impl IsData for Data {
@@ -402,6 +852,18 @@ impl IsData for Data {
     fn fetch_raw_block(&self) -> RawResult<Option<RawBlock>> {
         taos_query::block_in_place_or_global(self.fetch_block())
     }
+
+    fn fields(&self) -> RawResult<Vec<Field>> {
+        taos_query::block_in_place_or_global(self.0.fields())
+    }
+
+    fn block_count_hint(&self) -> Option<usize> {
+        self.0.counts().map(|(blocks, _)| blocks)
+    }
+
+    fn row_count_hint(&self) -> Option<usize> {
+        self.0.counts().map(|(_, rows)| rows)
+    }
 }
 pub enum WsMessageSet {
     Meta(Meta),
@@ -418,6 +880,151 @@ impl WsMessageSet {
 }
 
 impl Consumer {
+    /// Group id, client id and subscribed topics of the consumer.
+    pub fn info(&self) -> ConsumerInfo {
+        ConsumerInfo {
+            group_id: self.tmq_conf.group_id.clone(),
+            client_id: self.tmq_conf.client_id.clone(),
+            topics: self.topics(),
+        }
+    }
+
+    fn topics(&self) -> Vec<String> {
+        self.topics.lock().unwrap().clone()
+    }
+
+    /// Snapshot of the poll, message and commit counters of the consumer.
+    ///
+    /// Bytes are counted for the raw messages received from the server.
+    pub fn metrics(&self) -> ConsumerMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Reset the counters of [metrics](Self::metrics).
+    pub fn reset_metrics(&self) {
+        self.metrics.reset();
+    }
+
+    /// Override the timeout of [recv](AsConsumer::recv) and [stream](AsAsyncConsumer::stream)
+    /// when `topic` is the only subscribed topic.
+    pub fn set_topic_timeout(&mut self, topic: &str, timeout: Timeout) {
+        self.topic_timeouts.insert(topic.to_string(), timeout);
+    }
+
+    /// Set a hook called with `(topic, vgroup_id, offset, result)` after each commit request,
+    /// including auto commits, replacing the previous one.
+    ///
+    /// The offset is of the committed message for [commit](AsAsyncConsumer::commit) and auto
+    /// commits. Panics in the hook are caught and logged.
+    pub fn set_commit_hook(
+        &mut self,
+        hook: impl Fn(&str, VGroupId, i64, &RawResult<()>) + Send + Sync + 'static,
+    ) {
+        self.commit_hook.set(hook);
+    }
+
+    /// Whether offsets are committed in background, see `enable.auto.commit`.
+    pub fn auto_commit_enabled(&self) -> bool {
+        self.auto_commit.is_some()
+    }
+
+    /// Time when the background auto commit last committed offsets, `None` if it never did
+    /// or auto commit is disabled.
+    pub fn last_commit_time(&self) -> Option<Instant> {
+        self.auto_commit
+            .as_ref()
+            .and_then(AutoCommit::last_commit_time)
+    }
+
+    /// Whether the websocket connection is alive, as seen by the keepalive of
+    /// [TmqBuilder::with_keepalive_interval].
+    ///
+    /// Once lost, requests fail with an error of code [WS_ERROR_NO::CONN_CLOSED] and the
+    /// consumer should be built again.
+    pub fn is_connected(&self) -> bool {
+        self.sender.is_connected()
+    }
+
+    /// Time elapsed since the last poll returned, zero before the first poll.
+    pub fn last_poll_elapsed(&self) -> Duration {
+        self.last_poll
+            .lock()
+            .unwrap()
+            .map_or(Duration::ZERO, |polled| polled.elapsed())
+    }
+
+    /// Check the time since the last poll against the max processing interval, see
+    /// [TmqBuilder::with_max_processing_interval].
+    fn check_processing_interval(&self) -> RawResult<()> {
+        let Some(max) = self.max_processing_interval else {
+            return Ok(());
+        };
+        let elapsed = self.last_poll_elapsed();
+        if elapsed > max {
+            return Err(WsTmqError::ProcessingTooSlow { elapsed, max }.into());
+        }
+        Ok(())
+    }
+
+    /// Paused vgroups of each topic.
+    pub fn paused(&self) -> BTreeSet<(String, VGroupId)> {
+        self.paused.vgroups()
+    }
+
+    /// Register a listener called with the vgroups revoked from and newly assigned to this
+    /// consumer, when another consumer joins or leaves the group.
+    ///
+    /// The websocket protocol does not notify rebalances, so assignments are checked on polls,
+    /// empty ones included: at most every 5 seconds, or at once for a message of an unknown vgroup.
+    /// The first check after [subscribe](AsAsyncConsumer::subscribe) reports all the vgroups as
+    /// assigned, use [check_assignments](Self::check_assignments) to check immediately.
+    pub fn on_assignment_change(&mut self, cb: impl Fn(RebalanceEvent) + Send + Sync + 'static) {
+        self.rebalance.listeners.push(Box::new(cb));
+    }
+
+    /// Compare current assignments with the ones at last check, notifying listeners registered
+    /// by [on_assignment_change](Self::on_assignment_change) of changes.
+    pub async fn check_assignments(&self) -> RawResult<RebalanceEvent> {
+        let topics = self.topics();
+        let mut assignments = Vec::with_capacity(topics.len());
+        for topic in &topics {
+            let assignment = <Self as AsAsyncConsumer>::topic_assignment(self, topic).await?;
+            assignments.push((topic.clone(), assignment));
+        }
+        let event = {
+            let mut last = self.rebalance.assignments.lock().unwrap();
+            let event = RebalanceEvent::between(&last, &assignments);
+            *last = assignments;
+            event
+        };
+        *self.rebalance.checked_at.lock().unwrap() = Some(Instant::now());
+        if !event.is_empty() {
+            log::debug!("assignments changed: {event:?}");
+            for listener in &self.rebalance.listeners {
+                listener(event.clone());
+            }
+        }
+        Ok(event)
+    }
+
+    /// Open a new connection in place of the lost one, with the sender shared by the messages
+    /// and the auto-commit task.
+    async fn reconnect(&self) -> RawResult<()> {
+        let url = self.builder.to_tmq_url();
+        let ws = self.builder.build_tmq_stream(url.clone()).await?;
+        let conn = WsConn::spawn(
+            ws,
+            url,
+            self.sender.queries.clone(),
+            self.metrics.clone(),
+            self.keepalive_interval,
+            &self.close_signal,
+        );
+        *self.sender.conn.write().unwrap() = conn;
+        log::info!("websocket connection is reestablished");
+        Ok(())
+    }
+
     // async fn init_poll(&self, timeout: Duration) -> Result<()> {
     //     let req_id = self.sender.req_id();
     //     let action = TmqSend::Poll {
@@ -441,62 +1048,133 @@ impl Consumer {
     //     }
     //     Ok(())
     // }
-    async fn poll_wait(&self) -> RawResult<(Offset, MessageSet<Meta, Data>)> {
-        let elapsed = tokio::time::Instant::now();
+    /// Poll a message, returns `None` if there's no message available now.
+    ///
+    /// Messages of paused vgroups are consumed and skipped by polling again at once, so it ends
+    /// in case of [Timeout::None] too. `timeout` is the timeout of the receiving, for tracing
+    /// only.
+    async fn poll_once(
+        &self,
+        timeout: Duration,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
         loop {
-            let req_id = self.sender.req_id();
-            let action = TmqSend::Poll {
-                req_id,
-                blocking_time: 0,
-            };
+            match self.poll_message(timeout).await? {
+                Some((offset, _)) if self.paused.skip(&offset) => continue,
+                message => return Ok(message),
+            }
+        }
+    }
 
-            let data = self.sender.send_recv(action).await?;
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn poll_message(
+        &self,
+        timeout: Duration,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        let req_id = self.sender.req_id();
+        let action = TmqSend::Poll {
+            req_id,
+            blocking_time: 0,
+        };
 
-            match data {
-                TmqRecvData::Poll(TmqPoll {
+        let data = self.sender.send_recv(action);
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "tmq.poll",
+            topics = ?self.topics(),
+            timeout_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX),
+            req_id,
+            vgroup = tracing::field::Empty,
+        );
+        #[cfg(feature = "tracing")]
+        let data = tracing::Instrument::instrument(data, span.clone());
+        let data = data.await?;
+
+        match data {
+            TmqRecvData::Poll(TmqPoll {
+                message_id,
+                database,
+                have_message,
+                topic,
+                vgroup_id,
+                message_type,
+                offset,
+                timing,
+                received_at,
+            }) => {
+                if !have_message {
+                    if self.rebalance.is_due_idle() {
+                        if let Err(err) = self.check_assignments().await {
+                            warn!("check assignments failed: {err}");
+                        }
+                    }
+                    return Ok(None);
+                }
+                #[cfg(feature = "tracing")]
+                span.record("vgroup", vgroup_id);
+                if self.rebalance.is_due(&topic, vgroup_id) {
+                    // Listeners see the change before messages of a newly assigned vgroup.
+                    if let Err(err) = self.check_assignments().await {
+                        warn!("check assignments failed: {err}");
+                    }
+                }
+                let offset = Offset {
+                    req_id,
                     message_id,
                     database,
-                    have_message,
                     topic,
                     vgroup_id,
-                    message_type,
-                }) => {
-                    if have_message {
-                        let dur = elapsed.elapsed();
-                        let offset = Offset {
-                            message_id,
-                            database,
-                            topic,
-                            vgroup_id,
-                        };
-                        let message = WsMessageBase {
-                            sender: self.sender.clone(),
-                            message_id,
-                        };
-                        log::trace!("Got message in {}ms", dur.as_millis());
-                        break match message_type {
-                            MessageType::Meta => Ok((offset, MessageSet::Meta(Meta(message)))),
-                            MessageType::Data => Ok((offset, MessageSet::Data(Data(message)))),
-                            MessageType::MetaData => Ok((
-                                offset,
-                                MessageSet::MetaData(
-                                    Meta(message),
-                                    Data(WsMessageBase {
-                                        sender: self.sender.clone(),
-                                        message_id,
-                                    }),
-                                ),
-                            )),
-                            MessageType::Invalid => unreachable!(),
-                            // _ => unreachable!(),
-                        };
-                    } else {
-                        tokio::time::sleep(Duration::from_millis(500)).await;
-                        continue;
-                    }
+                    offset: offset.unwrap_or(-1),
+                    timing,
+                    received_at: received_at.unwrap_or_else(Instant::now),
+                };
+                let message = WsMessageBase::new(self.sender.clone(), message_id);
+                match message_type {
+                    MessageType::Meta => Ok(Some((offset, MessageSet::Meta(Meta(message))))),
+                    MessageType::Data => Ok(Some((offset, MessageSet::Data(Data(message))))),
+                    MessageType::MetaData => Ok(Some((
+                        offset,
+                        MessageSet::MetaData(
+                            Meta(message),
+                            Data(WsMessageBase::new(self.sender.clone(), message_id)),
+                        ),
+                    ))),
+                    MessageType::Invalid => unreachable!(),
+                    // _ => unreachable!(),
                 }
-                _ => unreachable!(),
             }
+            _ => unreachable!(),
+        }
+    }
+    async fn poll_by(
+        &self,
+        timeout: Timeout,
+    ) -> RawResult<Option<(Offset, MessageSet<Meta, Data>)>> {
+        match timeout {
+            Timeout::Never => self.poll_timeout(Duration::MAX).await,
+            Timeout::None => self.poll_once(Duration::ZERO).await,
+            Timeout::Duration(timeout) => self.poll_timeout(timeout).await,
+            Timeout::Deadline(_) => self.poll_timeout(timeout.as_duration()).await,
+        }
+    }
+
+    async fn poll_wait(&self, timeout: Duration) -> RawResult<(Offset, MessageSet<Meta, Data>)> {
+        let elapsed = tokio::time::Instant::now();
+        loop {
+            if let Some(message) = self.poll_once(timeout).await? {
+                let dur = elapsed.elapsed();
+                log::trace!("Got message in {}ms", dur.as_millis());
+                break Ok(message);
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+    }
+    fn check_subscribed(&self, topic: &str) -> RawResult<()> {
+        if self.topics.lock().unwrap().iter().any(|t| t == topic) {
+            Ok(())
+        } else {
+            Err(RawError::from_string(format!(
+                "topic {topic} is not subscribed"
+            )))
         }
     }
     pub(crate) async fn poll_timeout(
@@ -509,7 +1187,7 @@ impl Consumer {
             _ = &mut sleep, if !sleep.is_elapsed() => {
                Ok(None)
             }
-            message = self.poll_wait() => {
+            message = self.poll_wait(timeout) => {
                 Ok(Some(message?))
             }
         }
@@ -528,15 +1206,9 @@ impl AsAsyncConsumer for Consumer {
         &mut self,
         topics: I,
     ) -> RawResult<()> {
-        self.topics = topics.into_iter().map(Into::into).collect_vec();
-        let req_id = self.sender.req_id();
-        let action = TmqSend::Subscribe {
-            req_id,
-            req: self.tmq_conf.clone(),
-            topics: self.topics.clone(),
-            conn: self.conn.clone(),
-        };
-        self.sender.send_recv(action).await?;
+        let topics = topics.into_iter().map(Into::into).collect_vec();
+        <Self as AsAsyncConsumer>::subscribe_matched(self, topics).await?;
+        self.pattern.clear();
 
         // dbg!(&self.tmq_conf);
 
@@ -550,7 +1222,7 @@ impl AsAsyncConsumer for Consumer {
                         .collect_vec()
                 })
                 .collect_vec();
-            let topic_name = &self.topics[0];
+            let topic_name = &self.topics()[0];
             for offset in offsets {
                 let vgroup_id = offset[0] as i32;
                 let offset = offset[1];
@@ -580,12 +1252,28 @@ impl AsAsyncConsumer for Consumer {
         Ok(())
     }
 
-    async fn unsubscribe(self) {
+    async fn unsubscribe(mut self) -> RawResult<()> {
+        if let Some(auto_commit) = self.auto_commit.take() {
+            auto_commit.shutdown().await;
+        }
         let req_id = self.sender.req_id();
         log::trace!("unsubscribe {} start", req_id);
         let action = TmqSend::Unsubscribe { req_id };
-        self.sender.send_recv(action).await.unwrap();
-        drop(self)
+        self.sender.send_recv(action).await?;
+        // Connection is closed on drop.
+        drop(self);
+        Ok(())
+    }
+
+    async fn shutdown(self) -> RawResult<()> {
+        let timeout = Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MS);
+        match tokio::time::timeout(timeout, <Self as AsAsyncConsumer>::unsubscribe(self)).await {
+            Ok(res) => res,
+            Err(_) => Err(RawError::from_string(format!(
+                "consumer shutdown timed out after {}ms",
+                DEFAULT_SHUTDOWN_TIMEOUT_MS
+            ))),
+        }
     }
 
     async fn recv_timeout(
@@ -597,21 +1285,52 @@ impl AsAsyncConsumer for Consumer {
             taos_query::tmq::MessageSet<Self::Meta, Self::Data>,
         )>,
     > {
-        match timeout {
-            Timeout::Never | Timeout::None => self.poll_timeout(Duration::MAX).await,
-            Timeout::Duration(timeout) => self.poll_timeout(timeout).await,
+        if let Err(err) = self.check_processing_interval() {
+            // Reported once, polling goes on at next call.
+            *self.last_poll.lock().unwrap() = None;
+            return Err(err);
         }
+        <Self as AsAsyncConsumer>::refresh_subscription_if_due(self).await;
+        let start = Instant::now();
+        let res = self.poll_by(timeout).await;
+        match &res {
+            Ok(message) => {
+                if let (Some(auto_commit), Some((offset, _))) = (&self.auto_commit, message) {
+                    auto_commit.track(offset);
+                }
+                let kind = message.as_ref().map(|(_, message)| message.kind());
+                self.metrics.record_poll(start.elapsed(), kind);
+                *self.last_poll.lock().unwrap() = Some(Instant::now());
+            }
+            Err(err) => self.metrics.record_error(err),
+        }
+        res
     }
 
     async fn commit(&self, offset: Self::Offset) -> RawResult<()> {
+        self.check_processing_interval()?;
         let req_id = self.sender.req_id();
         let action = TmqSend::Commit(MessageArgs {
             req_id,
             message_id: offset.message_id,
         });
 
-        let _ = self.sender.send_recv(action).await?;
-        Ok(())
+        let res = self.sender.send_recv(action);
+        #[cfg(feature = "tracing")]
+        let res = tracing::Instrument::instrument(
+            res,
+            tracing::debug_span!(
+                "tmq.commit",
+                req_id,
+                topic = offset.topic,
+                vgroup = offset.vgroup_id
+            ),
+        );
+        let res = res.await.map(|_| ());
+        self.metrics.record_commit(offset.offset, &res);
+        self.commit_hook
+            .call(&offset.topic, offset.vgroup_id, offset.offset, &res);
+        res
     }
 
     async fn commit_offset(
@@ -620,6 +1339,7 @@ impl AsAsyncConsumer for Consumer {
         vgroup_id: VGroupId,
         offset: i64,
     ) -> RawResult<()> {
+        self.check_processing_interval()?;
         let req_id = self.sender.req_id();
         let action = TmqSend::CommitOffset(OffsetSeekArgs {
             req_id,
@@ -628,55 +1348,108 @@ impl AsAsyncConsumer for Consumer {
             offset,
         });
 
-        let _ = self.sender.send_recv(action).await?;
-        Ok(())
+        let res = self.sender.send_recv(action);
+        #[cfg(feature = "tracing")]
+        let res = tracing::Instrument::instrument(
+            res,
+            tracing::debug_span!(
+                "tmq.commit",
+                req_id,
+                topic = topic_name,
+                vgroup = vgroup_id,
+                offset
+            ),
+        );
+        let res = res.await.map(|_| ());
+        self.metrics.record_commit(offset, &res);
+        self.commit_hook.call(topic_name, vgroup_id, offset, &res);
+        res
     }
 
     async fn list_topics(&self) -> RawResult<Vec<String>> {
-        let topics = self.topics.clone();
-        Ok(topics)
+        Ok(self.topics())
+    }
+
+    fn subscription(&self) -> Vec<String> {
+        self.topics()
+    }
+
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        use futures::TryStreamExt;
+        use taos_query::{AsyncFetchable, AsyncQueryable, AsyncTBuilder};
+
+        let taos = AsyncTBuilder::build(&self.builder).await?;
+        taos.query(taos_query::tmq::TOPICS_SQL)
+            .await?
+            .deserialize::<String>()
+            .try_collect()
+            .await
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending_error)
+    }
+
+    async fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        let req_id = self.sender.req_id();
+        let action = TmqSend::Subscribe {
+            req_id,
+            req: self.tmq_conf.clone(),
+            topics: topics.clone(),
+            conn: self.conn.clone(),
+        };
+        // Subscription is all or nothing, keep the previous topics if rejected.
+        self.sender.send_recv(action).await?;
+        *self.topics.lock().unwrap() = topics;
+        self.paused.clear();
+        *self.rebalance.checked_at.lock().unwrap() = None;
+        Ok(())
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
     }
 
     async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
-        let topics = self.topics.clone();
+        let topics = self.topics();
         log::trace!("topics: {:?}", topics);
 
         let mut ret = Vec::new();
         for topic in topics {
-            let assignments = self.topic_assignment(&topic).await;
+            let assignments = <Self as AsAsyncConsumer>::topic_assignment(self, &topic)
+                .await
+                .unwrap_or_default();
             ret.push((topic, assignments));
         }
 
         Some(ret)
     }
 
-    async fn topic_assignment(&self, topic: &str) -> Vec<Assignment> {
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        self.check_subscribed(topic)?;
         let req_id = self.sender.req_id();
         let action = TmqSend::Assignment(TopicAssignmentArgs {
             req_id,
             topic: topic.to_string(),
         });
 
-        let recv = self.sender.send_recv(action).await.ok();
-        match recv {
-            Some(TmqRecvData::Assignment(TopicAssignment { assignment, timing })) => {
-                // assert_eq!(topic, topic);
-                log::trace!("timing: {:?}", timing);
-                log::trace!("assignment: {:?}", assignment);
-                assignment
-            }
-            _ => {
-                vec![]
-            }
+        let data = self.sender.send_recv(action).await?;
+        if let TmqRecvData::Assignment(TopicAssignment { assignment, timing }) = data {
+            log::trace!("timing: {:?}", timing);
+            log::trace!("assignment: {:?}", assignment);
+            Ok(assignment)
+        } else {
+            Err(RawError::from_string(format!(
+                "unexpected response for assignment request: {data:?}"
+            )))
         }
     }
 
-    async fn offset_seek(
-        &mut self,
-        topic: &str,
-        vgroup_id: VGroupId,
-        offset: i64,
-    ) -> RawResult<()> {
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
         let req_id = self.sender.req_id();
         let action = TmqSend::Seek(OffsetSeekArgs {
             req_id,
@@ -685,11 +1458,73 @@ impl AsAsyncConsumer for Consumer {
             offset,
         });
 
-        let _ = self.sender.send_recv(action).await?;
+        let res = self.sender.send_recv(action);
+        #[cfg(feature = "tracing")]
+        let res = tracing::Instrument::instrument(
+            res,
+            tracing::debug_span!("tmq.seek", req_id, topic, vgroup = vgroup_id, offset),
+        );
+        let _ = res.await?;
+        self.paused.reset(topic, vgroup_id);
+        Ok(())
+    }
+
+    /// Seek all vgroups in one round trip: the seek requests are sent together, without waiting
+    /// for the response of each one in between.
+    async fn seek_all(&mut self, offsets: &[(String, VGroupId, i64)]) -> SeekResults {
+        let this = &*self;
+        let seeks = offsets.iter().map(|(topic, vgroup_id, offset)| {
+            <Self as AsAsyncConsumer>::offset_seek(this, topic, *vgroup_id, *offset)
+        });
+        let results = futures::future::join_all(seeks).await;
+        SeekResults::new(offsets, results)
+    }
+
+    /// Reconnect first if the connection is lost, the new connection joins the consumer group
+    /// with the same topics, which consumes from the committed offsets.
+    async fn resubscribe(&self) -> RawResult<()> {
+        if !self.sender.is_connected() {
+            self.reconnect().await?;
+        }
+        let action = TmqSend::Subscribe {
+            req_id: self.sender.req_id(),
+            req: self.tmq_conf.clone(),
+            topics: self.topics(),
+            conn: self.conn.clone(),
+        };
+        self.sender.send_recv(action).await?;
+        Ok(())
+    }
+
+    /// Pause delivering messages of the vgroup of `topic`, see [paused](Consumer::paused).
+    ///
+    /// The websocket protocol has no pause, so it is done on the client side as [PausedVGroups]:
+    /// polled messages of a paused vgroup are not delivered, and the vgroup is sought back to the
+    /// first of them on [resume](AsAsyncConsumer::resume). Polls go on for the other vgroups.
+    /// Pauses are cleared by [subscribe](AsAsyncConsumer::subscribe).
+    async fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        let assignments = <Self as AsAsyncConsumer>::topic_assignment(self, topic).await?;
+        if !assignments.iter().any(|a| a.vgroup_id() == vgroup_id) {
+            return Err(RawError::from_string(format!(
+                "vgroup {vgroup_id} of topic {topic} is not assigned to current consumer"
+            )));
+        }
+        self.paused.pause(topic, vgroup_id);
         Ok(())
     }
 
+    /// Resume delivering messages of a paused vgroup, from the first message not delivered.
+    async fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        match self.paused.resume(topic, vgroup_id) {
+            Some(offset) => {
+                <Self as AsAsyncConsumer>::offset_seek(self, topic, vgroup_id, offset).await
+            }
+            None => Ok(()),
+        }
+    }
+
     async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        self.check_subscribed(topic)?;
         let req_id = self.sender.req_id();
         let action = TmqSend::Committed(OffsetArgs {
             req_id,
@@ -701,14 +1536,20 @@ impl AsAsyncConsumer for Consumer {
 
         let data = self.sender.send_recv(action).await?;
         if let TmqRecvData::Committed { committed } = data {
-            let offset = committed[0];
-            Ok(offset)
+            committed.first().copied().ok_or_else(|| {
+                RawError::from_string(format!(
+                    "no committed offset returned for topic {topic} vgroup {vgroup_id}"
+                ))
+            })
         } else {
-            Ok(0)
+            Err(RawError::from_string(format!(
+                "unexpected response for committed request: {data:?}"
+            )))
         }
     }
 
     async fn position(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
+        self.check_subscribed(topic)?;
         let req_id = self.sender.req_id();
         let action = TmqSend::Position(OffsetArgs {
             req_id,
@@ -720,10 +1561,15 @@ impl AsAsyncConsumer for Consumer {
 
         let data = self.sender.send_recv(action).await?;
         if let TmqRecvData::Position { position } = data {
-            let offset = position[0];
-            Ok(offset)
+            position.first().copied().ok_or_else(|| {
+                RawError::from_string(format!(
+                    "no position returned for topic {topic} vgroup {vgroup_id}"
+                ))
+            })
         } else {
-            Ok(0)
+            Err(RawError::from_string(format!(
+                "unexpected response for position request: {data:?}"
+            )))
         }
     }
 
@@ -759,6 +1605,10 @@ impl AsConsumer for Consumer {
         taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::commit(self, offset))
     }
 
+    fn unsubscribe(self) -> RawResult<()> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::unsubscribe(self))
+    }
+
     fn commit_offset(&self, topic_name: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
         taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::commit_offset(
             self, topic_name, vgroup_id, offset,
@@ -769,11 +1619,43 @@ impl AsConsumer for Consumer {
         taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::list_topics(self))
     }
 
+    fn subscription(&self) -> Vec<String> {
+        self.topics()
+    }
+
+    fn topic_names(&self) -> RawResult<Vec<String>> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::topic_names(self))
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        Some(&self.pattern)
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        Some(&self.pending_error)
+    }
+
+    fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::subscribe_matched(
+            self, topics,
+        ))
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        self.topic_timeouts.get(topic).copied()
+    }
+
     fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
         taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::assignments(self))
     }
 
-    fn offset_seek(&mut self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
+    fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::topic_assignment(
+            self, topic,
+        ))
+    }
+
+    fn offset_seek(&self, topic: &str, vg_id: VGroupId, offset: i64) -> RawResult<()> {
         taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::offset_seek(
             self, topic, vg_id, offset,
         ))
@@ -790,12 +1672,52 @@ impl AsConsumer for Consumer {
             self, topic, vg_id,
         ))
     }
+
+    fn pause(&self, topic: &str, vg_id: VGroupId) -> RawResult<()> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::pause(
+            self, topic, vg_id,
+        ))
+    }
+
+    fn resume(&self, topic: &str, vg_id: VGroupId) -> RawResult<()> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::resume(
+            self, topic, vg_id,
+        ))
+    }
+
+    fn resubscribe(&self) -> RawResult<()> {
+        taos_query::block_in_place_or_global(<Consumer as AsAsyncConsumer>::resubscribe(self))
+    }
 }
 
 impl TmqBuilder {
+    /// Build from a connection DSN and typed consumer options, overriding params in `dsn`.
+    pub fn from_config<D: IntoDsn>(dsn: D, config: TmqConfig) -> RawResult<Self> {
+        let mut dsn = dsn.into_dsn()?;
+        config.apply_to(&mut dsn);
+        Self::new(dsn)
+    }
+
     pub fn new<D: IntoDsn>(dsn: D) -> RawResult<Self> {
         let dsn = dsn.into_dsn()?;
         let info = TaosBuilder::from_dsn(&dsn)?;
+        Self::with_info(info, &dsn)
+    }
+
+    /// Build a consumer of `group_id` with the endpoint, auth and TLS settings of an
+    /// established connection.
+    ///
+    /// Only the settings are cloned, the consumer opens its own connection and leaves the one
+    /// of `taos` for queries.
+    pub fn from_taos(taos: &crate::Taos, group_id: &str) -> RawResult<Self> {
+        let mut dsn = Dsn::default();
+        dsn.params
+            .insert("group.id".to_string(), group_id.to_string());
+        Self::with_info(taos.dsn.clone(), &dsn)
+    }
+
+    /// Build with connection settings in `info` and tmq params of `dsn`.
+    fn with_info(info: TaosBuilder, dsn: &Dsn) -> RawResult<Self> {
         let group_id = dsn
             .params
             .get("group.id")
@@ -832,9 +1754,15 @@ impl TmqBuilder {
                 }
             })
             .unwrap_or("false".to_string());
+        let enable_replay = dsn
+            .params
+            .get("enable.replay")
+            .filter(|s| !s.is_empty())
+            .map(ToString::to_string);
         let with_table_name = dsn
             .params
             .get("with.table.name")
+            .or_else(|| dsn.params.get("msg.with.table.name"))
             .and_then(|s| {
                 if s.is_empty() {
                     None
@@ -855,6 +1783,18 @@ impl TmqBuilder {
                 Some(s.to_string())
             }
         });
+        // Offsets are committed by the client, see [AutoCommit].
+        let auto_commit_interval = if auto_commit == "true" {
+            let ms = match &auto_commit_interval_ms {
+                Some(ms) => ms.parse::<u64>().map_err(|_| {
+                    RawError::from_string(format!("invalid auto.commit.interval.ms: {ms}"))
+                })?,
+                None => DEFAULT_AUTO_COMMIT_INTERVAL_MS,
+            };
+            Some(Duration::from_millis(ms))
+        } else {
+            None
+        };
         let conf = TmqInit {
             group_id,
             client_id,
@@ -862,347 +1802,133 @@ impl TmqBuilder {
             auto_commit,
             auto_commit_interval_ms,
             snapshot_enable,
+            enable_replay,
             with_table_name,
             offset_seek,
         };
 
         Ok(Self {
             info,
-            conf,
-            timeout,
-        })
-    }
-
-    #[allow(dead_code)]
-    async fn tung_build_consumer(&self) -> RawResult<Consumer> {
-        let url = self.info.to_tmq_url();
-        // let (ws, _) = taos_query::block_in_place_or_global(connect_async(url))?;
-        let (ws, _) = connect_async(&url).await.map_err(WsTmqError::from)?;
-        let (mut sender, mut reader) = ws.split();
-
-        let queries = Arc::new(HashMap::<ReqId, tokio::sync::oneshot::Sender<_>>::new());
-
-        let queries_sender = queries.clone();
-        let msg_handler = queries.clone();
-
-        let (ws, mut msg_recv) = tokio::sync::mpsc::channel::<Message>(100);
-        let ws2 = ws.clone();
-
-        // Connection watcher
-        let (tx, mut rx) = watch::channel(false);
-        let mut close_listener = rx.clone();
-
-        let sending_url = url.clone();
-        static PING_INTERVAL: u64 = 29;
-        const PING: &[u8] = b"TAOSX";
-
-        tokio::spawn(async move {
-            let mut interval = time::interval(Duration::from_secs(PING_INTERVAL));
-
-            loop {
-                tokio::select! {
-                    _ = interval.tick() => {
-                        log::trace!("Check websocket message sender alive");
-                        if let Err(err) = sender.send(Message::Ping(PING.to_vec())).await {
-                            log::trace!("sending ping message to {sending_url} error: {err:?}");
-                            // let mut keys = Vec::new();
-                            let keys = msg_handler.iter().map(|r| *r.key()).collect_vec();
-
-                            // msg_handler.for_each_async(|k, _| {
-                            //     keys.push(*k);
-                            // }).await;
-                            for k in keys {
-                                if let Some((_, sender)) = msg_handler.remove(&k) {
-                                    let _ = sender.send(Err(RawError::new(WS_ERROR_NO::CONN_CLOSED.as_code(),
-                                        format!("WebSocket internal error: {err}"))));
-                                }
-                            }
-                        }
-                    }
-                    Some(msg) = msg_recv.recv() => {
-                        if msg.is_close() {
-                            let _ = sender.send(msg).await;
-                            let _ = sender.close().await;
-                            break;
-                        }
-                        log::trace!("send message {msg:?}");
-                        if let Err(err) = sender.send(msg).await {
-                            log::trace!("sending message to {sending_url} error: {err:?}");
-                            let keys = msg_handler.iter().map(|r| *r.key()).collect_vec();
-                            for k in keys {
-                                if let Some((_, sender)) = msg_handler.remove(&k) {
-                                    let _ = sender.send(Err(RawError::new(WS_ERROR_NO::CONN_CLOSED.as_code(),
-                                        format!("WebSocket internal error: {err}"))));
-                                }
-                            }
-                        }
-                        log::trace!("send message done");
-                    }
-                    _ = rx.changed() => {
-                        let _= sender.send(Message::Close(None)).await;
-                        let _ = sender.close().await;
-                        log::trace!("close tmq sender");
-                        break;
-                    }
-                }
-            }
-        });
-
-        tokio::spawn(async move {
-            let instant = Instant::now();
-            'ws: loop {
-                tokio::select! {
-                    Some(message) = reader.next() => {
-                        match message {
-                            Ok(message) => match message {
-                                Message::Text(text) => {
-                                    log::trace!("json response: {}", text);
-                                    let v: TmqRecv = serde_json::from_str(&text).expect(&text);
-                                    let (req_id, recv, ok) = v.ok();
-                                    match &recv {
-                                        TmqRecvData::Subscribe => {
-                                            log::trace!("subscribe with: {:?}", req_id);
-
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("subscribe message received but no receiver alive");
-                                            }
-                                        },
-                                        TmqRecvData::Unsubscribe => {
-                                            log::trace!("unsubscribe with: {:?} successed", req_id);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("unsubscribe message received but no receiver alive");
-                                            }
-                                        },
-                                        TmqRecvData::Poll(_) => {
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("poll message received but no receiver alive");
-                                            }
-                                        },
-                                        TmqRecvData::FetchJsonMeta { data }=> {
-                                            log::trace!("fetch json meta data: {:?}", data);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("poll message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::FetchRaw { meta: _ }=> {
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("poll message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::Commit=> {
-                                            log::trace!("commit done: {:?}", recv);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("poll message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::Fetch(fetch)=> {
-                                            log::trace!("fetch done: {:?}", fetch);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("poll message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::FetchBlock{ data: _ }=> {
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id) {
-                                                let _ = sender.send(Err(RawError::new(
-                                                    WS_ERROR_NO::WEBSOCKET_ERROR.as_code(),
-                                                    format!("WebSocket internal error: {:?}", &text)
-                                                )));
-                                            }
-                                            break 'ws;
-                                        }
-                                        TmqRecvData::Assignment(assignment)=> {
-                                            log::trace!("assignment done: {:?}", assignment);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("assignment message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::Seek { timing }=> {
-                                            log::trace!("seek done: req_id {:?} timing {:?}", &req_id, timing);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("seek message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::Committed { committed }=> {
-                                            log::trace!("committed done: {:?}", committed);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("committed message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::Position { position }=> {
-                                            log::trace!("position done: {:?}", position);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                            {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            }  else {
-                                                log::warn!("position message received but no receiver alive");
-                                            }
-                                        }
-                                        TmqRecvData::CommitOffset { timing }=> {
-                                            log::trace!("commit offset done: {:?}", timing);
-                                            if let Some((_, sender)) = queries_sender.remove(&req_id) {
-                                                let _ = sender.send(ok.map(|_|recv));
-                                            } else {
-                                                log::warn!("commit offset message received but no receiver alive");
-                                            }
-                                        }
+            conf,
+            timeout,
+            auto_commit_interval,
+            req_id_fn: None,
+            max_processing_interval: None,
+            observer: None,
+            preserve_vgroup_order: false,
+            keepalive_interval: KEEPALIVE_INTERVAL,
+        })
+    }
 
-                                        _ => unreachable!("unknown tmq response"),
-                                    }
-                                }
-                                Message::Binary(data) => {
-                                    // writeUint64(message.buffer, req.ReqID)
-                                    // writeUint64(message.buffer, req.MessageID)
-                                    // writeUint64(message.buffer, TMQRawMetaMessage)
-                                    // writeUint32(message.buffer, length)
-                                    // writeUint16(message.buffer, metaType)
-                                    let mut bytes = Bytes::from(data);
-                                    let part = bytes.slice(24..);
-                                    // dbg!(&bytes);
-                                    use bytes::Buf;
-                                    let timing = bytes.get_u64_le();
-                                    let req_id = bytes.get_u64_le();
-                                    let message_id = bytes.get_u64_le();
+    /// Generate the request ids of consumer messages by `req_id_fn` instead of a counter, to
+    /// correlate polls, commits and seeks with the server logs.
+    ///
+    /// Request ids route the responses, so they must be unique among in-flight requests of the
+    /// consumer. The id of the poll delivering a message is [Offset::req_id].
+    pub fn with_req_id_fn(mut self, req_id_fn: impl Fn() -> u64 + Send + Sync + 'static) -> Self {
+        self.req_id_fn = Some(ReqIdFn(Arc::new(req_id_fn)));
+        self
+    }
 
+    /// Report polls, commits and errors of the consumer to `observer`, see [ConsumerObserver].
+    pub fn with_observer(mut self, observer: impl ConsumerObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
 
-                                    log::trace!("[{:.2}ms] receive binary message with req_id {} message_id {}",
-                                        Duration::from_nanos(timing).as_secs_f64() / 1000.,
-                                        req_id, message_id);
+    /// Deliver the data written before the topic is created, as `experimental.snapshot.enable`.
+    ///
+    /// Building fails if the server does not support it.
+    pub fn with_snapshot(mut self, enable: bool) -> Self {
+        self.conf.snapshot_enable = enable.to_string();
+        self
+    }
 
-                                    if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                    {
-                                        sender.send(Ok(TmqRecvData::Bytes(part))).unwrap();
-                                    }  else {
-                                        log::warn!("poll message received but no receiver alive");
-                                    }
+    /// Consume data messages in the order they are written, as `enable.replay`.
+    ///
+    /// Building fails if the server is older than 3.2.1.0, which ignores it.
+    pub fn with_replay(mut self, enable: bool) -> Self {
+        self.conf.enable_replay = Some(enable.to_string());
+        self
+    }
 
+    /// Fail if the enabled params are not supported by the server, see
+    /// [versioned_params](taos_query::tmq::versioned_params).
+    async fn check_server_version(&self) -> RawResult<()> {
+        let versioned = taos_query::tmq::versioned_params([
+            (
+                "experimental.snapshot.enable",
+                self.conf.snapshot_enable.as_str(),
+            ),
+            (
+                "enable.replay",
+                self.conf.enable_replay.as_deref().unwrap_or_default(),
+            ),
+        ]);
+        if versioned.is_empty() {
+            return Ok(());
+        }
+        let version = taos_query::AsyncTBuilder::server_version(&self.info).await?;
+        taos_query::tmq::check_server_version(version, &versioned)
+    }
 
-                                }
-                                Message::Close(close) => {
-                                    log::warn!("websocket connection is closed (unexpected?)");
+    /// Fail the next poll or commit with [WsTmqError::ProcessingTooSlow] when it comes later
+    /// than `interval` after the last poll returned, as the server may have evicted the consumer
+    /// from the group meanwhile.
+    ///
+    /// The error converts into a [RawError] of the client-side code
+    /// [WS_ERROR_NO::PROCESSING_TOO_SLOW] which tells the elapsed time, unlike an eviction
+    /// reported by the server with [Code::TMQ_CONSUMER_MISMATCH]. It is not
+    /// [recoverable](Code::is_recoverable). A poll reports it once and then polls as usual, see
+    /// [Consumer::last_poll_elapsed] to check the processing time beforehand.
+    pub fn with_max_processing_interval(mut self, interval: Duration) -> Self {
+        self.max_processing_interval = Some(interval);
+        self
+    }
 
-                                    let keys = queries_sender.iter().map(|r| *r.key()).collect_vec();
-                                    let err = if let Some(close) = close {
-                                        format!("WebSocket internal error: {}", close)
-                                    } else {
-                                        "WebSocket internal error, connection is reset by server".to_string()
-                                    };
-                                    for k in keys {
-                                        if let Some((_, sender)) = queries_sender.remove(&k) {
-                                            let _ = sender.send(Err(RawError::new(WS_ERROR_NO::CONN_CLOSED.as_code(), err.clone())));
-                                        }
-                                    }
-                                    break 'ws;
-                                }
-                                Message::Ping(bytes) => {
-                                    ws2.send(Message::Pong(bytes)).await.unwrap();
-                                }
-                                Message::Pong(bytes) => {
-                                    if bytes == PING {
-                                        log::trace!("ping/pong handshake success");
-                                    } else {
-                                        // do nothing
-                                        log::warn!("received (unexpected) pong message, do nothing");
-                                    }
-                                }
-                                Message::Frame(frame) => {
-                                    // do no`thing
-                                    log::warn!("received (unexpected) frame message, do nothing");
-                                    log::trace!("* frame data: {frame:?}");
-                                }
-                            },
-                            Err(err) => {
-                                let keys = queries_sender.iter().map(|r| *r.key()).collect_vec();
-                                for k in keys {
-                                    if let Some((_, sender)) = queries_sender.remove(&k) {
-                                        let _ = sender.send(Err(RawError::new(
-                                            WS_ERROR_NO::CONN_CLOSED.as_code(),
-                                            format!("WebSocket internal error: {err}")
-                                        )));
-                                    }
-                                }
-                                break 'ws;
-                            }
-                        }
-                    }
-                    _ = close_listener.changed() => {
-                        log::trace!("close reader task");
-                        break 'ws;
-                    }
-                }
-            }
-            log::trace!("Consuming done in {:?}", instant.elapsed());
-        });
-        let (ws, mut _msg_recv) = tokio::sync::mpsc::channel(100);
-        let ws_cloned: tokio::sync::mpsc::Sender<WsMessage<bytes::Bytes>> = ws.clone();
-        let consumer = Consumer {
-            conn: self.info.to_conn_request(),
-            tmq_conf: self.conf.clone(),
-            sender: WsTmqSender {
-                req_id: Arc::new(AtomicU64::new(1)),
-                queries,
-                sender: ws_cloned,
-                timeout: Timeout::Duration(Duration::MAX),
-            },
-            // fetches,
-            close_signal: tx,
-            timeout: self.timeout,
-            topics: vec![],
-        };
+    /// Reorder received messages by offset within each vgroup, not supported by websocket.
+    ///
+    /// Reordering polls messages ahead of delivery, while taosAdapter only fetches the data of
+    /// the last polled message, so building fails if it is set.
+    pub fn preserve_vgroup_order(mut self, preserve: bool) -> Self {
+        self.preserve_vgroup_order = preserve;
+        self
+    }
 
-        Ok(consumer)
+    /// Ping the server every `interval`, 29 seconds by default.
+    ///
+    /// The connection is taken as lost when a ping fails or nothing is received for two
+    /// intervals: pending polls then return at once with an error of code
+    /// [WS_ERROR_NO::CONN_CLOSED], and so do later requests, see [Consumer::is_connected].
+    pub fn with_keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = interval;
+        self
     }
 
-    async fn build_consumer(&self) -> RawResult<Consumer> {
+    #[allow(dead_code)]
+    async fn tung_build_consumer(&self) -> RawResult<Consumer> {
         let url = self.info.to_tmq_url();
-        let sending_url = url.clone();
-
-        let ws = self.info.build_tmq_stream(url).await?;
-        let (mut reader, mut sender) = ws.split();
+        // let (ws, _) = taos_query::block_in_place_or_global(connect_async(url))?;
+        let (ws, _) = connect_async(&url).await.map_err(WsTmqError::from)?;
+        let (mut sender, mut reader) = ws.split();
 
         let queries = Arc::new(HashMap::<ReqId, tokio::sync::oneshot::Sender<_>>::new());
 
         let queries_sender = queries.clone();
+        let metrics = Arc::new(ConsumerCounters::with_observer(self.observer.clone()));
+        let metrics_sender = metrics.clone();
         let msg_handler = queries.clone();
 
-        let (ws, mut msg_recv) = tokio::sync::mpsc::channel::<WsMessage<bytes::Bytes>>(100);
+        let (ws, mut msg_recv) = tokio::sync::mpsc::channel::<Message>(100);
         let ws2 = ws.clone();
 
         // Connection watcher
         let (tx, mut rx) = watch::channel(false);
         let mut close_listener = rx.clone();
 
+        let sending_url = url.clone();
         static PING_INTERVAL: u64 = 29;
-        const PING: &[u8] = b"TAOS";
+        const PING: &[u8] = b"TAOSX";
 
         tokio::spawn(async move {
             let mut interval = time::interval(Duration::from_secs(PING_INTERVAL));
@@ -1211,11 +1937,14 @@ impl TmqBuilder {
                 tokio::select! {
                     _ = interval.tick() => {
                         log::trace!("Check websocket message sender alive");
-                        if let Err(err) = sender.send(OpCode::Ping, &serde_json::to_vec(&PING).unwrap()).await {
-
+                        if let Err(err) = sender.send(Message::Ping(PING.to_vec())).await {
                             log::trace!("sending ping message to {sending_url} error: {err:?}");
+                            // let mut keys = Vec::new();
                             let keys = msg_handler.iter().map(|r| *r.key()).collect_vec();
 
+                            // msg_handler.for_each_async(|k, _| {
+                            //     keys.push(*k);
+                            // }).await;
                             for k in keys {
                                 if let Some((_, sender)) = msg_handler.remove(&k) {
                                     let _ = sender.send(Err(RawError::new(WS_ERROR_NO::CONN_CLOSED.as_code(),
@@ -1225,11 +1954,13 @@ impl TmqBuilder {
                         }
                     }
                     Some(msg) = msg_recv.recv() => {
-
+                        if msg.is_close() {
+                            let _ = sender.send(msg).await;
+                            let _ = sender.close().await;
+                            break;
+                        }
                         log::trace!("send message {msg:?}");
-                        let opcode = msg.code;
-                        let msg = msg.data;
-                        if let Err(err) = sender.send(opcode, &msg).await {
+                        if let Err(err) = sender.send(msg).await {
                             log::trace!("sending message to {sending_url} error: {err:?}");
                             let keys = msg_handler.iter().map(|r| *r.key()).collect_vec();
                             for k in keys {
@@ -1242,7 +1973,8 @@ impl TmqBuilder {
                         log::trace!("send message done");
                     }
                     _ = rx.changed() => {
-                        let _ = sender.send(OpCode::Close, b"").await;
+                        let _= sender.send(Message::Close(None)).await;
+                        let _ = sender.close().await;
                         log::trace!("close tmq sender");
                         break;
                     }
@@ -1254,185 +1986,207 @@ impl TmqBuilder {
             let instant = Instant::now();
             'ws: loop {
                 tokio::select! {
-                    Ok(frame) = reader.receive() => {
-                        let (header, payload) = frame;
-                        let code = header.code;
-                        match code {
-                            OpCode::Text => {
-                                log::trace!("received json response: {payload}", payload = String::from_utf8_lossy(&payload));
-                                let v: TmqRecv = serde_json::from_slice(&payload).unwrap();
-                                let (req_id, recv, ok) = v.ok();
-                                match &recv {
-                                    TmqRecvData::Subscribe => {
-                                        log::trace!("subscribe with: {:?}", req_id);
+                    Some(message) = reader.next() => {
+                        match message {
+                            Ok(message) => match message {
+                                Message::Text(text) => {
+                                    log::trace!("json response: {}", text);
+                                    let v: TmqRecv = serde_json::from_str(&text).expect(&text);
+                                    let (req_id, recv, ok) = v.ok();
+                                    match &recv {
+                                        TmqRecvData::Subscribe => {
+                                            log::trace!("subscribe with: {:?}", req_id);
 
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("subscribe message received but no receiver alive");
-                                        }
-                                    },
-                                    TmqRecvData::Unsubscribe => {
-                                        log::trace!("unsubscribe with: {:?} successed", req_id);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("unsubscribe message received but no receiver alive");
-                                        }
-                                    },
-                                    TmqRecvData::Poll(_) => {
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("poll message received but no receiver alive");
-                                        }
-                                    },
-                                    TmqRecvData::FetchJsonMeta { data }=> {
-                                        log::trace!("fetch json meta data: {:?}", data);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("poll message received but no receiver alive");
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("subscribe message received but no receiver alive");
+                                            }
+                                        },
+                                        TmqRecvData::Unsubscribe => {
+                                            log::trace!("unsubscribe with: {:?} successed", req_id);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("unsubscribe message received but no receiver alive");
+                                            }
+                                        },
+                                        TmqRecvData::Poll(_) => {
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("poll message received but no receiver alive");
+                                            }
+                                        },
+                                        TmqRecvData::FetchJsonMeta { data }=> {
+                                            log::trace!("fetch json meta data: {:?}", data);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("poll message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::FetchRaw { meta: _ }=> {
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("poll message received but no receiver alive");
+                                        TmqRecvData::FetchRaw { meta: _ }=> {
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("poll message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::Commit=> {
-                                        log::trace!("commit done: {:?}", recv);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("poll message received but no receiver alive");
+                                        TmqRecvData::Commit=> {
+                                            log::trace!("commit done: {:?}", recv);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("poll message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::Fetch(fetch)=> {
-                                        log::trace!("fetch done: {:?}", fetch);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("poll message received but no receiver alive");
+                                        TmqRecvData::Fetch(fetch)=> {
+                                            log::trace!("fetch done: {:?}", fetch);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("poll message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::FetchBlock{ data: _ }=> {
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id) {
-                                            let _ = sender.send(Err(RawError::new(
-                                                WS_ERROR_NO::WEBSOCKET_ERROR.as_code(),
-                                                format!("WebSocket internal error")
-                                            )));
+                                        TmqRecvData::FetchBlock{ data: _ }=> {
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id) {
+                                                let _ = sender.send(Err(RawError::new(
+                                                    WS_ERROR_NO::WEBSOCKET_ERROR.as_code(),
+                                                    format!("WebSocket internal error: {:?}", &text)
+                                                )));
+                                            }
+                                            break 'ws;
                                         }
-                                        break 'ws;
-                                    }
-                                    TmqRecvData::Assignment(assignment)=> {
-                                        log::trace!("assignment done: {:?}", assignment);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("assignment message received but no receiver alive");
+                                        TmqRecvData::Assignment(assignment)=> {
+                                            log::trace!("assignment done: {:?}", assignment);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("assignment message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::Seek { timing }=> {
-                                        log::trace!("seek done: req_id {:?} timing {:?}", &req_id, timing);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("seek message received but no receiver alive");
+                                        TmqRecvData::Seek { timing }=> {
+                                            log::trace!("seek done: req_id {:?} timing {:?}", &req_id, timing);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("seek message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::Committed { committed }=> {
-                                        log::trace!("committed done: {:?}", committed);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("committed message received but no receiver alive");
+                                        TmqRecvData::Committed { committed }=> {
+                                            log::trace!("committed done: {:?}", committed);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("committed message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::Position { position }=> {
-                                        log::trace!("position done: {:?}", position);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id)
-                                        {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        }  else {
-                                            log::warn!("position message received but no receiver alive");
+                                        TmqRecvData::Position { position }=> {
+                                            log::trace!("position done: {:?}", position);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                            {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            }  else {
+                                                log::warn!("position message received but no receiver alive");
+                                            }
                                         }
-                                    }
-                                    TmqRecvData::CommitOffset { timing }=> {
-                                        log::trace!("commit offset done: {:?}", timing);
-                                        if let Some((_, sender)) = queries_sender.remove(&req_id) {
-                                            let _ = sender.send(ok.map(|_|recv));
-                                        } else {
-                                            log::warn!("commit offset message received but no receiver alive");
+                                        TmqRecvData::CommitOffset { timing }=> {
+                                            log::trace!("commit offset done: {:?}", timing);
+                                            if let Some((_, sender)) = queries_sender.remove(&req_id) {
+                                                let _ = sender.send(ok.map(|_|recv));
+                                            } else {
+                                                log::warn!("commit offset message received but no receiver alive");
+                                            }
                                         }
+
+                                        _ => unreachable!("unknown tmq response"),
                                     }
-                                    _ => unreachable!("unknown tmq response"),
                                 }
-                            }
-                            OpCode::Binary => {
-                                let block = payload.to_vec();
-                                let mut slice = block.as_slice();
-                                use taos_query::util::InlinableRead;
-                                let offset = 24;
-                                let part = slice[offset..].to_vec();
+                                Message::Binary(data) => {
+                                    // writeUint64(message.buffer, req.ReqID)
+                                    // writeUint64(message.buffer, req.MessageID)
+                                    // writeUint64(message.buffer, TMQRawMetaMessage)
+                                    // writeUint32(message.buffer, length)
+                                    // writeUint16(message.buffer, metaType)
+                                    let mut bytes = Bytes::from(data);
+                                    let part = bytes.slice(24..);
+                                    metrics_sender.record_bytes(part.len());
+                                    // dbg!(&bytes);
+                                    use bytes::Buf;
+                                    let timing = bytes.get_u64_le();
+                                    let req_id = bytes.get_u64_le();
+                                    let message_id = bytes.get_u64_le();
 
-                                let _timing = {
-                                    let timing = slice.read_u64().unwrap();
-                                    Duration::from_nanos(timing as _)
-                                };
 
-                                let req_id = slice.read_u64().unwrap();
+                                    log::trace!("[{:.2}ms] receive binary message with req_id {} message_id {}",
+                                        Duration::from_nanos(timing).as_secs_f64() / 1000.,
+                                        req_id, message_id);
+
+                                    if let Some((_, sender)) = queries_sender.remove(&req_id)
+                                    {
+                                        sender.send(Ok(TmqRecvData::Bytes(part))).unwrap();
+                                    }  else {
+                                        log::warn!("poll message received but no receiver alive");
+                                    }
+
 
-                                if let Some((_, sender)) = queries_sender.remove(&req_id) {
-                                    log::trace!("send data to fetches with id {}", req_id);
-                                    sender.send(Ok(TmqRecvData::Bytes(part.into()))).unwrap();
-                                } else {
-                                    log::warn!("req_id {req_id} not detected, message might be lost");
                                 }
-                            }
-                            OpCode::Close => {
-                                log::warn!("websocket connection is closed normally");
-                                let mut keys = Vec::new();
-                                for e in queries_sender.iter() {
-                                    keys.push(*e.key());
+                                Message::Close(close) => {
+                                    log::warn!("websocket connection is closed (unexpected?)");
+
+                                    let keys = queries_sender.iter().map(|r| *r.key()).collect_vec();
+                                    let err = if let Some(close) = close {
+                                        format!("WebSocket internal error: {}", close)
+                                    } else {
+                                        "WebSocket internal error, connection is reset by server".to_string()
+                                    };
+                                    for k in keys {
+                                        if let Some((_, sender)) = queries_sender.remove(&k) {
+                                            let _ = sender.send(Err(RawError::new(WS_ERROR_NO::CONN_CLOSED.as_code(), err.clone())));
+                                        }
+                                    }
+                                    break 'ws;
+                                }
+                                Message::Ping(bytes) => {
+                                    ws2.send(Message::Pong(bytes)).await.unwrap();
+                                }
+                                Message::Pong(bytes) => {
+                                    if bytes == PING {
+                                        log::trace!("ping/pong handshake success");
+                                    } else {
+                                        // do nothing
+                                        log::warn!("received (unexpected) pong message, do nothing");
+                                    }
+                                }
+                                Message::Frame(frame) => {
+                                    // do no`thing
+                                    log::warn!("received (unexpected) frame message, do nothing");
+                                    log::trace!("* frame data: {frame:?}");
                                 }
+                            },
+                            Err(err) => {
+                                let keys = queries_sender.iter().map(|r| *r.key()).collect_vec();
                                 for k in keys {
                                     if let Some((_, sender)) = queries_sender.remove(&k) {
-                                        let _ = sender.send(Err(RawError::new(WS_ERROR_NO::CONN_CLOSED.as_code(), "received close message")));
+                                        let _ = sender.send(Err(RawError::new(
+                                            WS_ERROR_NO::CONN_CLOSED.as_code(),
+                                            format!("WebSocket internal error: {err}")
+                                        )));
                                     }
                                 }
                                 break 'ws;
                             }
-                            OpCode::Ping => {
-                                let bytes = payload.to_vec();
-                                ws2.send(WsMessage{
-                                    code: OpCode::Pong,
-                                    data: bytes.into(),
-                                    close_code: None
-                                }).await.unwrap();
-                            }
-                            OpCode::Pong => {
-                                // do nothing
-                                log::trace!("received pong message, do nothing");
-                            }
-                            _ => {
-                                let frame = payload;
-                                // do nothing
-                                log::warn!("received (unexpected) frame message, do nothing");
-                                log::trace!("* frame data: {frame:?}");
-                            }
                         }
                     }
                     _ = close_listener.changed() => {
@@ -1441,48 +2195,324 @@ impl TmqBuilder {
                     }
                 }
             }
-            log::trace!("Consuming done in {:?}", instant.elapsed());
-        });
-        let consumer = Consumer {
-            conn: self.info.to_conn_request(),
-            tmq_conf: self.conf.clone(),
-            sender: WsTmqSender {
-                req_id: Arc::new(AtomicU64::new(1)),
-                queries,
-                sender: ws,
-                timeout: Timeout::Duration(Duration::MAX),
-            },
-            // fetches,
-            close_signal: tx,
-            timeout: self.timeout,
-            topics: vec![],
-        };
+            log::trace!("Consuming done in {:?}", instant.elapsed());
+        });
+        let (ws, mut _msg_recv) = tokio::sync::mpsc::channel(100);
+        let ws_cloned: tokio::sync::mpsc::Sender<WsMessage<bytes::Bytes>> = ws.clone();
+        let consumer = Consumer {
+            conn: self.info.to_conn_request(),
+            builder: self.info.clone(),
+            tmq_conf: self.conf.clone(),
+            sender: WsTmqSender::new(self.req_id_fn.clone(), WsConn::new(ws_cloned), queries),
+            // fetches,
+            close_signal: Arc::new(tx),
+            timeout: self.timeout,
+            topics: Mutex::default(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: BTreeMap::new(),
+            paused: PausedVGroups::default(),
+            rebalance: Rebalance::default(),
+            metrics,
+            auto_commit: None,
+            commit_hook: CommitHook::default(),
+            max_processing_interval: self.max_processing_interval,
+            last_poll: Mutex::default(),
+            keepalive_interval: self.keepalive_interval,
+        };
+
+        Ok(consumer)
+    }
+
+    async fn build_consumer(&self) -> RawResult<Consumer> {
+        if self.preserve_vgroup_order {
+            return Err(RawError::from_string(
+                "preserve_vgroup_order is not supported by the websocket consumer",
+            ));
+        }
+        self.check_server_version().await?;
+        let url = self.info.to_tmq_url();
+        let ws = self.info.build_tmq_stream(url.clone()).await?;
+
+        let queries = WsTmqAgent::default();
+        let metrics = Arc::new(ConsumerCounters::with_observer(self.observer.clone()));
+        // Connection watcher
+        let (tx, _) = watch::channel(false);
+        let conn = WsConn::spawn(
+            ws,
+            url,
+            queries.clone(),
+            metrics.clone(),
+            self.keepalive_interval,
+            &tx,
+        );
+        let sender = WsTmqSender::new(self.req_id_fn.clone(), conn, queries);
+        let close_signal = Arc::new(tx);
+        let commit_hook = CommitHook::default();
+        let auto_commit = self.auto_commit_interval.map(|interval| {
+            AutoCommit::spawn(
+                sender.clone(),
+                interval,
+                close_signal.clone(),
+                commit_hook.clone(),
+            )
+        });
+        let consumer = Consumer {
+            conn: self.info.to_conn_request(),
+            builder: self.info.clone(),
+            tmq_conf: self.conf.clone(),
+            sender,
+            // fetches,
+            close_signal,
+            timeout: self.timeout,
+            topics: Mutex::default(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: BTreeMap::new(),
+            paused: PausedVGroups::default(),
+            rebalance: Rebalance::default(),
+            metrics,
+            auto_commit,
+            commit_hook,
+            max_processing_interval: self.max_processing_interval,
+            last_poll: Mutex::default(),
+            keepalive_interval: self.keepalive_interval,
+        };
+
+        Ok(consumer)
+    }
+}
+
+#[derive(Debug)]
+pub struct Consumer {
+    conn: WsConnReq,
+    builder: TaosBuilder,
+    tmq_conf: TmqInit,
+    sender: WsTmqSender,
+    close_signal: Arc<watch::Sender<bool>>,
+    timeout: Timeout,
+    topics: Mutex<Vec<String>>,
+    pattern: TopicPattern,
+    pending_error: PendingError,
+    topic_timeouts: BTreeMap<String, Timeout>,
+    paused: PausedVGroups,
+    rebalance: Rebalance,
+    metrics: Arc<ConsumerCounters>,
+    auto_commit: Option<AutoCommit>,
+    commit_hook: CommitHook,
+    max_processing_interval: Option<Duration>,
+    /// When the last poll returned, `None` before the first one.
+    last_poll: Mutex<Option<Instant>>,
+    keepalive_interval: Duration,
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        match self.auto_commit.take() {
+            // The auto-commit task closes the connection after the final commit.
+            Some(auto_commit) => auto_commit.stop(true),
+            None => {
+                let _ = self.close_signal.send(true);
+            }
+        }
+    }
+}
+
+const DEFAULT_AUTO_COMMIT_INTERVAL_MS: u64 = 5000;
+
+/// Interval of checking assignments for [rebalance listeners](Consumer::on_assignment_change).
+const ASSIGNMENT_CHECK_INTERVAL_MS: u64 = 5000;
+
+/// Listeners of assignment changes, and the assignments they were last notified with.
+#[derive(Default)]
+struct Rebalance {
+    listeners: Vec<Box<dyn Fn(RebalanceEvent) + Send + Sync>>,
+    assignments: Mutex<Vec<(String, Vec<Assignment>)>>,
+    checked_at: Mutex<Option<Instant>>,
+}
+
+impl Debug for Rebalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rebalance")
+            .field("listeners", &self.listeners.len())
+            .field("assignments", &self.assignments)
+            .field("checked_at", &self.checked_at)
+            .finish()
+    }
+}
+
+impl Rebalance {
+    /// Whether assignments should be checked after a message of the vgroup is polled.
+    fn is_due(&self, topic: &str, vgroup_id: VGroupId) -> bool {
+        if self.listeners.is_empty() {
+            return false;
+        }
+        let is_known = self
+            .assignments
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(t, a)| t == topic && a.iter().any(|a| a.vgroup_id() == vgroup_id));
+        match *self.checked_at.lock().unwrap() {
+            Some(at) if is_known => {
+                at.elapsed() >= Duration::from_millis(ASSIGNMENT_CHECK_INTERVAL_MS)
+            }
+            _ => true,
+        }
+    }
 
-        Ok(consumer)
+    /// Whether assignments should be checked after an empty poll, so that vgroups revoked from
+    /// an idle consumer are reported too.
+    fn is_due_idle(&self) -> bool {
+        if self.listeners.is_empty() {
+            return false;
+        }
+        match *self.checked_at.lock().unwrap() {
+            Some(at) => at.elapsed() >= Duration::from_millis(ASSIGNMENT_CHECK_INTERVAL_MS),
+            None => true,
+        }
     }
 }
 
+/// Upper bound for the final commit and unsubscribe acknowledgement on shutdown.
+const DEFAULT_SHUTDOWN_TIMEOUT_MS: u64 = 5000;
+
+/// Client-side auto commit, enabled by `enable.auto.commit=true`.
+///
+/// The latest received message of each topic vgroup is committed every
+/// `auto.commit.interval.ms` milliseconds, and once more when the consumer
+/// is unsubscribed or dropped.
 #[derive(Debug)]
-pub struct Consumer {
-    conn: WsConnReq,
-    tmq_conf: TmqInit,
-    sender: WsTmqSender,
-    close_signal: watch::Sender<bool>,
-    timeout: Timeout,
-    topics: Vec<String>,
+struct AutoCommit {
+    /// Latest received message and its offset of each topic vgroup.
+    pending: Arc<HashMap<(String, VGroupId), (MessageId, i64)>>,
+    last_commit: Arc<Mutex<Option<Instant>>>,
+    stop: oneshot::Sender<bool>,
+    handle: tokio::task::JoinHandle<()>,
 }
 
-impl Drop for Consumer {
-    fn drop(&mut self) {
-        let _ = self.close_signal.send(true);
+impl AutoCommit {
+    fn spawn(
+        sender: WsTmqSender,
+        interval: Duration,
+        close_signal: Arc<watch::Sender<bool>>,
+        hook: CommitHook,
+    ) -> Self {
+        let pending = Arc::new(HashMap::new());
+        let (stop, mut stopped) = oneshot::channel();
+
+        let last_commit = Arc::new(Mutex::new(None));
+
+        let offsets = pending.clone();
+        let committed = last_commit.clone();
+        let handle = tokio::spawn(async move {
+            let mut interval = time::interval(interval);
+            // The first tick completes immediately.
+            interval.tick().await;
+            let close = loop {
+                tokio::select! {
+                    _ = interval.tick() => Self::flush(&sender, &offsets, &committed, &hook).await,
+                    close = &mut stopped => break close.unwrap_or(true),
+                }
+            };
+            // The final commit must not keep the connection open if the server does not respond.
+            let timeout = Duration::from_millis(DEFAULT_SHUTDOWN_TIMEOUT_MS);
+            let flush = Self::flush(&sender, &offsets, &committed, &hook);
+            if time::timeout(timeout, flush).await.is_err() {
+                log::warn!(
+                    "final auto commit timed out after {}ms",
+                    DEFAULT_SHUTDOWN_TIMEOUT_MS
+                );
+            }
+            log::trace!("auto commit task stopped");
+            if close {
+                let _ = close_signal.send(true);
+            }
+        });
+
+        Self {
+            pending,
+            last_commit,
+            stop,
+            handle,
+        }
+    }
+
+    fn track(&self, offset: &Offset) {
+        self.pending.insert(
+            (offset.topic.clone(), offset.vgroup_id),
+            (offset.message_id, offset.offset),
+        );
+    }
+
+    /// Time of the last round which committed at least one offset.
+    fn last_commit_time(&self) -> Option<Instant> {
+        *self.last_commit.lock().unwrap()
+    }
+
+    async fn flush(
+        sender: &WsTmqSender,
+        pending: &HashMap<(String, VGroupId), (MessageId, i64)>,
+        last_commit: &Mutex<Option<Instant>>,
+        hook: &CommitHook,
+    ) {
+        let keys = pending.iter().map(|r| r.key().clone()).collect_vec();
+        let mut committed = false;
+        for key in keys {
+            let Some((key, (message_id, offset))) = pending.remove(&key) else {
+                continue;
+            };
+            let action = TmqSend::Commit(MessageArgs {
+                req_id: sender.req_id(),
+                message_id,
+            });
+            let res = sender.send_recv(action).await.map(|_| ());
+            hook.call(&key.0, key.1, offset, &res);
+            if let Err(err) = res {
+                log::warn!(
+                    "auto commit of topic {} vgroup {} failed: {err}",
+                    key.0,
+                    key.1
+                );
+                // Retry in next round unless a newer message is received.
+                pending.entry(key).or_insert((message_id, offset));
+            } else {
+                committed = true;
+            }
+        }
+        if committed {
+            *last_commit.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    /// Stop the task after a final commit, closing the connection if `close` is true.
+    fn stop(self, close: bool) {
+        let _ = self.stop.send(close);
+    }
+
+    /// Stop the task and wait for the final commit.
+    async fn shutdown(self) {
+        let _ = self.stop.send(false);
+        let _ = self.handle.await;
     }
 }
 #[derive(Debug)]
 pub struct Offset {
+    req_id: ReqId,
     message_id: MessageId,
     database: String,
     topic: String,
     vgroup_id: i32,
+    offset: i64,
+    timing: Option<i64>,
+    received_at: Instant,
+}
+
+impl Offset {
+    /// Request id of the poll which delivered the message.
+    pub fn req_id(&self) -> ReqId {
+        self.req_id
+    }
 }
 
 impl IsOffset for Offset {
@@ -1497,6 +2527,18 @@ impl IsOffset for Offset {
     fn vgroup_id(&self) -> i32 {
         self.vgroup_id
     }
+
+    fn offset(&self) -> i64 {
+        self.offset
+    }
+
+    fn timing(&self) -> Option<i64> {
+        self.timing
+    }
+
+    fn received_at(&self) -> Option<Instant> {
+        Some(self.received_at)
+    }
 }
 
 #[derive(Debug, Error)]
@@ -1521,6 +2563,10 @@ pub enum WsTmqError {
     TaosError(#[from] RawError),
     #[error("Receive timeout in {0}")]
     QueryTimeout(String),
+    /// Client-side watchdog of [TmqBuilder::with_max_processing_interval], not an eviction by
+    /// the server.
+    #[error("Processing took {elapsed:?} since last poll, longer than max processing interval {max:?}, the consumer may be evicted from the group")]
+    ProcessingTooSlow { elapsed: Duration, max: Duration },
 }
 
 unsafe impl Send for WsTmqError {}
@@ -1531,6 +2577,9 @@ impl WsTmqError {
     pub const fn errno(&self) -> Code {
         match self {
             WsTmqError::TaosError(error) => error.code(),
+            WsTmqError::ProcessingTooSlow { .. } => {
+                Code::new(WS_ERROR_NO::PROCESSING_TOO_SLOW as _)
+            }
             _ => Code::FAILED,
         }
     }
@@ -1565,6 +2614,25 @@ mod tests {
     use super::{TaosBuilder, TmqBuilder};
     use taos_query::prelude::tokio;
 
+    #[test]
+    fn test_ws_tmq_from_taos() -> anyhow::Result<()> {
+        use taos_query::prelude::sync::*;
+
+        let mut dsn: Dsn = "ws://root@localhost:6041?group.id=g1".parse()?;
+        dsn.password = Some("pässwörd密码".to_string());
+        let tmq = TmqBuilder::new(&dsn)?;
+        assert!(matches!(&tmq.info.auth, crate::WsAuth::Plain(_, pass) if pass == "pässwörd密码"));
+
+        // No connection is opened to build the connection or the consumer builder.
+        let taos = TaosBuilder::from_dsn(&dsn)?.build()?;
+        let tmq = TmqBuilder::from_taos(&taos, "g2")?;
+        assert_eq!(tmq.conf.group_id, "g2");
+        assert_eq!(tmq.info.addr, "localhost:6041");
+        assert!(matches!(&tmq.info.auth, crate::WsAuth::Plain(user, pass)
+            if user == "root" && pass == "pässwörd密码"));
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_ws_tmq_meta() -> anyhow::Result<()> {
         use taos_query::prelude::*;
@@ -1708,33 +2776,222 @@ mod tests {
                 consumer.commit(offset).await?;
             }
         }
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        taos.exec_many([
+            "drop database ws_tmq_meta2",
+            "drop topic ws_tmq_meta",
+            "drop database ws_tmq_meta",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_ws_tmq_meta_sync() -> anyhow::Result<()> {
+        use taos_query::prelude::sync::*;
+        // pretty_env_logger::formatted_builder()
+        //     .filter_level(log::LevelFilter::Info)
+        //     .init();
+
+        let taos = TaosBuilder::from_dsn("ws://localhost:6041")?.build()?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_meta_sync",
+            "drop database if exists ws_tmq_meta_sync",
+            "create database ws_tmq_meta_sync wal_retention_period 3600",
+            "create topic ws_tmq_meta_sync with meta as database ws_tmq_meta_sync",
+            "use ws_tmq_meta_sync",
+            // kind 1: create super table using all types
+            "create table stb1(ts timestamp, c1 bool, c2 tinyint, c3 smallint, c4 int, c5 bigint,\
+            c6 timestamp, c7 float, c8 double, c9 varchar(10), c10 nchar(16),\
+            c11 tinyint unsigned, c12 smallint unsigned, c13 int unsigned, c14 bigint unsigned)\
+            tags(t1 json)",
+            // kind 2: create child table with json tag
+            "create table tb0 using stb1 tags('{\"name\":\"value\"}')",
+            "create table tb1 using stb1 tags(NULL)",
+            "insert into tb0 values(now, NULL, NULL, NULL, NULL, NULL,
+            NULL, NULL, NULL, NULL, NULL,
+            NULL, NULL, NULL, NULL)
+            tb1 values(now, true, -2, -3, -4, -5, \
+            '2022-02-02 02:02:02.222', -0.1, -0.12345678910, 'abc 和我', 'Unicode + 涛思',\
+            254, 65534, 1, 1)",
+            // kind 3: create super table with all types except json (especially for tags)
+            "create table stb2(ts timestamp, c1 bool, c2 tinyint, c3 smallint, c4 int, c5 bigint,\
+            c6 timestamp, c7 float, c8 double, c9 varchar(10), c10 nchar(10),\
+            c11 tinyint unsigned, c12 smallint unsigned, c13 int unsigned, c14 bigint unsigned)\
+            tags(t1 bool, t2 tinyint, t3 smallint, t4 int, t5 bigint,\
+            t6 timestamp, t7 float, t8 double, t9 varchar(10), t10 nchar(16),\
+            t11 tinyint unsigned, t12 smallint unsigned, t13 int unsigned, t14 bigint unsigned)",
+            // kind 4: create child table with all types except json
+            "create table tb2 using stb2 tags(true, -2, -3, -4, -5, \
+            '2022-02-02 02:02:02.222', -0.1, -0.12345678910, 'abc 和我', 'Unicode + 涛思',\
+            254, 65534, 1, 1)",
+            "create table tb3 using stb2 tags( NULL, NULL, NULL, NULL, NULL,
+            NULL, NULL, NULL, NULL, NULL,
+            NULL, NULL, NULL, NULL)",
+            // kind 5: create common table
+            "create table `table` (ts timestamp, v int)",
+            // kind 6: column in super table
+            "alter table stb1 add column new1 bool",
+            "alter table stb1 add column new2 tinyint",
+            "alter table stb1 add column new10 nchar(16)",
+            "alter table stb1 modify column new10 nchar(32)",
+            "alter table stb1 drop column new10",
+            "alter table stb1 drop column new2",
+            "alter table stb1 drop column new1",
+            // kind 7: add tag in super table
+            "alter table `stb2` add tag new1 bool",
+            "alter table `stb2` rename tag new1 new1_new",
+            "alter table `stb2` modify tag t10 nchar(32)",
+            "alter table `stb2` drop tag new1_new",
+            // kind 8: column in common table
+            "alter table `table` add column new1 bool",
+            "alter table `table` add column new2 tinyint",
+            "alter table `table` add column new10 nchar(16)",
+            "alter table `table` modify column new10 nchar(32)",
+            "alter table `table` rename column new10 new10_new",
+            "alter table `table` drop column new10_new",
+            "alter table `table` drop column new2",
+            "alter table `table` drop column new1",
+            // kind 9: drop normal table
+            "drop table `table`",
+            // kind 10: drop child table
+            "drop table `tb2`, `tb1`",
+            // kind 11: drop super table
+            "drop table `stb2`",
+            "drop table `stb1`",
+        ])?;
+
+        taos.exec_many([
+            "drop database if exists ws_tmq_meta_sync2",
+            "create database if not exists ws_tmq_meta_sync2 wal_retention_period 3600",
+            "use ws_tmq_meta_sync2",
+        ])?;
+
+        let builder = TmqBuilder::new(
+            "taos://localhost:6041?group.id=10&timeout=1000ms&auto.offset.reset=earliest",
+        )?;
+        let mut consumer = builder.build()?;
+        consumer.subscribe(["ws_tmq_meta_sync"])?;
+
+        let topics = consumer.list_topics();
+        log::debug!("topics: {:?}", topics);
+
+        let iter = consumer.iter_with_timeout(Timeout::from_secs(1));
+
+        for msg in iter {
+            let (offset, message) = msg?;
+            // Offset contains information for topic name, database name and vgroup id,
+            //  similar to kafka topic/partition/offset.
+            let _ = offset.topic();
+            let _ = offset.database();
+            let _ = offset.vgroup_id();
+
+            // Different to kafka message, TDengine consumer would consume two kind of messages.
+            //
+            // 1. meta
+            // 2. data
+            match message {
+                MessageSet::Meta(meta) => {
+                    let _raw = meta.as_raw_meta()?;
+                    // taos.write_meta(raw)?;
+
+                    // meta data can be write to an database seamlessly by raw or json (to sql).
+                    let json = meta.as_json_meta()?;
+                    let sql = json.to_string();
+                    log::debug!("sql: {}", sql);
+                    if let Err(err) = taos.exec(sql) {
+                        match err.code() {
+                            Code::TAG_ALREADY_EXIST => log::trace!("tag already exists"),
+                            Code::TAG_NOT_EXIST => log::trace!("tag not exist"),
+                            Code::COLUMN_EXISTS => log::trace!("column already exists"),
+                            Code::COLUMN_NOT_EXIST => log::trace!("column not exists"),
+                            Code::INVALID_COLUMN_NAME => log::trace!("invalid column name"),
+                            Code::MODIFIED_ALREADY => log::trace!("modified already done"),
+                            Code::TABLE_NOT_EXIST => log::trace!("table does not exists"),
+                            Code::STABLE_NOT_EXIST => log::trace!("stable does not exists"),
+                            _ => {
+                                log::error!("{}", err);
+                            }
+                        }
+                    }
+                }
+                MessageSet::Data(data) => {
+                    // data message may have more than one data block for various tables.
+                    for block in data {
+                        let _block = block?;
+                        // dbg!(block.table_name());
+                        // dbg!(block);
+                    }
+                }
+                _ => unreachable!(),
+            }
+            consumer.commit(offset)?;
+        }
+
+        // get assignments
+        let assignments = consumer.assignments();
+        log::debug!("assignments all: {:?}", assignments);
+
+        if let Some(assignments) = assignments {
+            for (topic, assignment_vec) in assignments {
+                for assignment in assignment_vec {
+                    log::debug!("assignment: {:?} {:?}", topic, assignment);
+                    let vgroup_id = assignment.vgroup_id();
+                    let end = assignment.end();
+
+                    let position = consumer.position(&topic, vgroup_id);
+                    log::debug!("position: {:?}", position);
+                    let committed = consumer.committed(&topic, vgroup_id);
+                    log::debug!("committed: {:?}", committed);
+
+                    let res = consumer.offset_seek(&topic, vgroup_id, end);
+                    log::debug!("seek: {:?}", res);
+
+                    let position = consumer.position(&topic, vgroup_id);
+                    log::debug!("after seek position: {:?}", position);
+                    let committed = consumer.committed(&topic, vgroup_id);
+                    log::debug!("after seek committed: {:?}", committed);
+
+                    let res = consumer.commit_offset(&topic, vgroup_id, end);
+                    log::debug!("commit offset: {:?}", res);
+
+                    let position = consumer.position(&topic, vgroup_id);
+                    log::debug!("after commit offset position: {:?}", position);
+                    let committed = consumer.committed(&topic, vgroup_id);
+                    log::debug!("after commit offset committed: {:?}", committed);
+                }
+            }
+        }
+
+        consumer.unsubscribe()?;
 
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        std::thread::sleep(Duration::from_secs(5));
 
         taos.exec_many([
-            "drop database ws_tmq_meta2",
-            "drop topic ws_tmq_meta",
-            "drop database ws_tmq_meta",
-        ])
-        .await?;
+            "drop database ws_tmq_meta_sync2",
+            "drop topic ws_tmq_meta_sync",
+            "drop database ws_tmq_meta_sync",
+        ])?;
         Ok(())
     }
 
     #[test]
-    fn test_ws_tmq_meta_sync() -> anyhow::Result<()> {
+    fn test_ws_tmq_metadata() -> anyhow::Result<()> {
         use taos_query::prelude::sync::*;
         // pretty_env_logger::formatted_builder()
-        //     .filter_level(log::LevelFilter::Info)
+        //     .filter_level(log::LevelFilter::Debug)
         //     .init();
 
         let taos = TaosBuilder::from_dsn("ws://localhost:6041")?.build()?;
         taos.exec_many([
-            "drop topic if exists ws_tmq_meta_sync",
-            "drop database if exists ws_tmq_meta_sync",
-            "create database ws_tmq_meta_sync wal_retention_period 3600",
-            "create topic ws_tmq_meta_sync with meta as database ws_tmq_meta_sync",
-            "use ws_tmq_meta_sync",
+            "drop topic if exists ws_tmq_meta_sync3",
+            "drop database if exists ws_tmq_meta_sync3",
+            "create database ws_tmq_meta_sync3 wal_retention_period 3600",
+            "create topic ws_tmq_meta_sync3 with meta as database ws_tmq_meta_sync3",
+            "use ws_tmq_meta_sync3",
             // kind 1: create super table using all types
             "create table stb1(ts timestamp, c1 bool, c2 tinyint, c3 smallint, c4 int, c5 bigint,\
             c6 timestamp, c7 float, c8 double, c9 varchar(10), c10 nchar(16),\
@@ -1797,19 +3054,16 @@ mod tests {
         ])?;
 
         taos.exec_many([
-            "drop database if exists ws_tmq_meta_sync2",
-            "create database if not exists ws_tmq_meta_sync2 wal_retention_period 3600",
-            "use ws_tmq_meta_sync2",
+            "drop database if exists ws_tmq_meta_sync32",
+            "create database if not exists ws_tmq_meta_sync32 wal_retention_period 3600",
+            "use ws_tmq_meta_sync32",
         ])?;
 
         let builder = TmqBuilder::new(
             "taos://localhost:6041?group.id=10&timeout=1000ms&auto.offset.reset=earliest",
         )?;
         let mut consumer = builder.build()?;
-        consumer.subscribe(["ws_tmq_meta_sync"])?;
-
-        let topics = consumer.list_topics();
-        log::debug!("topics: {:?}", topics);
+        consumer.subscribe(["ws_tmq_meta_sync3"])?;
 
         let iter = consumer.iter_with_timeout(Timeout::from_secs(1));
 
@@ -1862,201 +3116,671 @@ mod tests {
             }
             consumer.commit(offset)?;
         }
+        consumer.unsubscribe()?;
 
-        // get assignments
-        let assignments = consumer.assignments();
-        log::debug!("assignments all: {:?}", assignments);
+        std::thread::sleep(Duration::from_secs(5));
 
-        if let Some(assignments) = assignments {
-            for (topic, assignment_vec) in assignments {
-                for assignment in assignment_vec {
-                    log::debug!("assignment: {:?} {:?}", topic, assignment);
-                    let vgroup_id = assignment.vgroup_id();
-                    let end = assignment.end();
+        taos.exec_many([
+            "drop database ws_tmq_meta_sync32",
+            "drop topic ws_tmq_meta_sync3",
+            "drop database ws_tmq_meta_sync3",
+        ])?;
+        Ok(())
+    }
 
-                    let position = consumer.position(&topic, vgroup_id);
-                    log::debug!("position: {:?}", position);
-                    let committed = consumer.committed(&topic, vgroup_id);
-                    log::debug!("committed: {:?}", committed);
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_auto_commit() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
 
-                    let res = consumer.offset_seek(&topic, vgroup_id, end);
-                    log::debug!("seek: {:?}", res);
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_auto_commit",
+            "drop database if exists ws_tmq_auto_commit",
+            "create database ws_tmq_auto_commit wal_retention_period 3600",
+            "create topic ws_tmq_auto_commit as database ws_tmq_auto_commit",
+            "use ws_tmq_auto_commit",
+            "create table tb1(ts timestamp, v int)",
+            "insert into tb1 values(now, 1)",
+            "insert into tb1 values(now + 1s, 2)",
+        ])
+        .await?;
 
-                    let position = consumer.position(&topic, vgroup_id);
-                    log::debug!("after seek position: {:?}", position);
-                    let committed = consumer.committed(&topic, vgroup_id);
-                    log::debug!("after seek committed: {:?}", committed);
+        let dsn = "taos://localhost:6041?group.id=auto_commit&auto.offset.reset=earliest\
+            &enable.auto.commit=true&auto.commit.interval.ms=100";
+
+        let mut consumer = TmqBuilder::new(dsn)?.build().await?;
+        assert!(consumer.auto_commit_enabled());
+        assert_eq!(consumer.last_commit_time(), None);
+        consumer.subscribe(["ws_tmq_auto_commit"]).await?;
+        let polled = std::time::Instant::now();
+        let mut received = 0;
+        while let Some((_offset, message)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            if let Some(data) = message.into_data() {
+                while let Some(block) = data.fetch_raw_block().await? {
+                    received += block.nrows();
+                }
+            }
+        }
+        assert_eq!(received, 2);
+        // No explicit commit, offsets are committed in background.
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let committed = consumer.last_commit_time().expect("auto commit should run");
+        assert!(committed > polled);
+        consumer.unsubscribe().await?;
+
+        // Restarting in the same group must not receive the messages again.
+        let mut consumer = TmqBuilder::new(dsn)?.build().await?;
+        consumer.subscribe(["ws_tmq_auto_commit"]).await?;
+        let message = consumer.recv_timeout(Timeout::from_secs(2)).await?;
+        assert!(message.is_none());
+        consumer.unsubscribe().await?;
 
-                    let res = consumer.commit_offset(&topic, vgroup_id, end);
-                    log::debug!("commit offset: {:?}", res);
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_tmq_auto_commit",
+            "drop database ws_tmq_auto_commit",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_stream_data_only() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_data_only",
+            "drop database if exists ws_tmq_data_only",
+            "create database ws_tmq_data_only wal_retention_period 3600",
+            "create topic ws_tmq_data_only with meta as database ws_tmq_data_only",
+            "use ws_tmq_data_only",
+            "create table tb1(ts timestamp, v int)",
+            "insert into tb1 values(now, 1)",
+            "create table tb2(ts timestamp, v int)",
+            "insert into tb2 values(now, 2)",
+        ])
+        .await?;
+
+        let mut consumer =
+            TmqBuilder::new("taos://localhost:6041?group.id=data_only&auto.offset.reset=earliest")?
+                .build()
+                .await?;
+        consumer.subscribe(["ws_tmq_data_only"]).await?;
+
+        let mut received = 0;
+        let mut stream = consumer.stream_data_only(Timeout::from_secs(2));
+        while let Some((offset, data)) = stream.try_next().await? {
+            while let Some(block) = data.fetch_raw_block().await? {
+                received += block.nrows();
+            }
+            consumer.commit(offset).await?;
+        }
+        drop(stream);
+        assert_eq!(received, 2);
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_tmq_data_only",
+            "drop database ws_tmq_data_only",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_shutdown() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_shutdown",
+            "drop database if exists ws_tmq_shutdown",
+            "create database ws_tmq_shutdown wal_retention_period 3600",
+            "create topic ws_tmq_shutdown as database ws_tmq_shutdown",
+            "use ws_tmq_shutdown",
+            "create table tb1(ts timestamp, v int)",
+            "insert into tb1 values(now, 1)",
+        ])
+        .await?;
+
+        let dsn = "taos://localhost:6041?group.id=shutdown&auto.offset.reset=earliest\
+            &enable.auto.commit=true&auto.commit.interval.ms=600000";
+        let mut consumer = TmqBuilder::new(dsn)?.build().await?;
+        consumer.subscribe(["ws_tmq_shutdown"]).await?;
+        let mut stream = consumer.stream_with_timeout(Timeout::from_secs(2));
+        while stream.try_next().await?.is_some() {}
+        drop(stream);
+        // The final auto commit is flushed before unsubscribing.
+        consumer.shutdown().await?;
+
+        let mut consumer = TmqBuilder::new(dsn)?.build().await?;
+        consumer.subscribe(["ws_tmq_shutdown"]).await?;
+        let next = consumer.recv_timeout(Timeout::from_secs(2)).await?;
+        assert!(next.is_none());
+        consumer.shutdown().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_tmq_shutdown",
+            "drop database ws_tmq_shutdown",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_info() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_info",
+            "drop database if exists ws_tmq_info",
+            "create database ws_tmq_info wal_retention_period 3600",
+            "create topic ws_tmq_info as database ws_tmq_info",
+        ])
+        .await?;
+
+        let mut consumer = TmqBuilder::new("taos://localhost:6041?group.id=g1&client.id=c1")?
+            .build()
+            .await?;
+        let info = consumer.info();
+        assert_eq!(info.group_id, "g1");
+        assert_eq!(info.client_id.as_deref(), Some("c1"));
+        assert!(info.topics.is_empty());
+
+        consumer.subscribe(["ws_tmq_info"]).await?;
+        assert_eq!(consumer.info().topics, ["ws_tmq_info"]);
+        assert_eq!(AsAsyncConsumer::subscription(&consumer), ["ws_tmq_info"]);
+        // A rejected subscription keeps the acknowledged topics.
+        assert!(consumer.subscribe(["ws_tmq_info_not_exist"]).await.is_err());
+        assert_eq!(AsAsyncConsumer::subscription(&consumer), ["ws_tmq_info"]);
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many(["drop topic ws_tmq_info", "drop database ws_tmq_info"])
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_subscribe_pattern() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_pattern_a1",
+            "drop topic if exists ws_pattern_a2",
+            "drop topic if exists ws_pattern_b1",
+            "drop database if exists ws_pattern",
+            "create database ws_pattern wal_retention_period 3600",
+            "create topic ws_pattern_a1 as database ws_pattern",
+            "create topic ws_pattern_b1 as database ws_pattern",
+        ])
+        .await?;
+
+        let mut consumer = TmqBuilder::new("taos://localhost:6041?group.id=pattern")?
+            .build()
+            .await?;
+        assert!(consumer.refresh_subscription().await.is_err());
+        assert!(consumer.subscribe_pattern("(").await.is_err());
+
+        consumer.subscribe_pattern("^ws_pattern_a").await?;
+        assert_eq!(consumer.info().topics, ["ws_pattern_a1"]);
+        assert_eq!(consumer.refresh_subscription().await?, ["ws_pattern_a1"]);
+
+        taos.exec("create topic ws_pattern_a2 as database ws_pattern")
+            .await?;
+        let mut topics = consumer.refresh_subscription().await?;
+        topics.sort();
+        assert_eq!(topics, ["ws_pattern_a1", "ws_pattern_a2"]);
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_pattern_a1",
+            "drop topic ws_pattern_a2",
+            "drop topic ws_pattern_b1",
+            "drop database ws_pattern",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_metrics() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+        use taos_query::tmq::ConsumerMetrics;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_metrics",
+            "drop database if exists ws_tmq_metrics",
+            "create database ws_tmq_metrics wal_retention_period 3600",
+            "create topic ws_tmq_metrics as database ws_tmq_metrics",
+            "use ws_tmq_metrics",
+            "create table tb1(ts timestamp, v int)",
+            "insert into tb1 values(now, 1)",
+        ])
+        .await?;
+
+        let mut consumer =
+            TmqBuilder::new("taos://localhost:6041?group.id=metrics&auto.offset.reset=earliest")?
+                .build()
+                .await?;
+        consumer.subscribe(["ws_tmq_metrics"]).await?;
+        assert_eq!(consumer.metrics(), ConsumerMetrics::default());
+
+        let mut messages = 0;
+        while let Some((offset, message)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            if let Some(data) = message.into_data() {
+                while data.fetch_raw_block().await?.is_some() {}
+            }
+            consumer.commit(offset).await?;
+            messages += 1;
+        }
+        let metrics = consumer.metrics();
+        assert_eq!(metrics.polls, messages + 1);
+        assert_eq!(metrics.empty_polls, 1);
+        assert_eq!(metrics.data_messages, messages);
+        assert_eq!(metrics.commits, messages);
+        assert_eq!(metrics.commit_failures, 0);
+        assert!(metrics.bytes > 0);
+
+        consumer.reset_metrics();
+        assert_eq!(consumer.metrics(), ConsumerMetrics::default());
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many(["drop topic ws_tmq_metrics", "drop database ws_tmq_metrics"])
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_pause() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_pause",
+            "drop database if exists ws_tmq_pause",
+            "create database ws_tmq_pause vgroups 2 wal_retention_period 3600",
+            "create topic ws_tmq_pause as database ws_tmq_pause",
+            "use ws_tmq_pause",
+            "create table stb(ts timestamp, v int) tags(t int)",
+        ])
+        .await?;
+        for i in 0..8 {
+            taos.exec(format!(
+                "insert into tb{i} using stb tags({i}) values(now, {i})"
+            ))
+            .await?;
+        }
+
+        let mut consumer =
+            TmqBuilder::new("taos://localhost:6041?group.id=pause&auto.offset.reset=earliest")?
+                .build()
+                .await?;
+        consumer.subscribe(["ws_tmq_pause"]).await?;
+        let assignments = consumer.topic_assignment("ws_tmq_pause").await?;
+        let paused = assignments[0].vgroup_id();
+        consumer.pause("ws_tmq_pause", paused).await?;
+        assert_eq!(
+            consumer.paused(),
+            std::collections::BTreeSet::from([("ws_tmq_pause".to_string(), paused)])
+        );
+        assert!(consumer.pause("ws_tmq_pause", -1).await.is_err());
+
+        let mut vgroups = Vec::new();
+        while let Some((offset, _)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            vgroups.push(offset.vgroup_id());
+        }
+        assert!(!vgroups.contains(&paused));
+
+        consumer.resume("ws_tmq_pause", paused).await?;
+        assert!(consumer.paused().is_empty());
+        vgroups.clear();
+        while let Some((offset, _)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            vgroups.push(offset.vgroup_id());
+        }
+        assert!(!vgroups.is_empty());
+        assert!(vgroups.iter().all(|vgroup_id| *vgroup_id == paused));
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many(["drop topic ws_tmq_pause", "drop database ws_tmq_pause"])
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ws_tmq_req_id() -> anyhow::Result<()> {
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::Arc;
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_req_id",
+            "drop database if exists ws_tmq_req_id",
+            "create database ws_tmq_req_id wal_retention_period 3600",
+            "create topic ws_tmq_req_id as database ws_tmq_req_id",
+            "use ws_tmq_req_id",
+            "create table tb(ts timestamp, v int)",
+            "insert into tb values(now, 1)",
+        ])
+        .await?;
+
+        let next = Arc::new(AtomicU64::new(1 << 40));
+        let req_id_fn = {
+            let next = next.clone();
+            move || next.fetch_add(1, Ordering::SeqCst)
+        };
+        let mut consumer =
+            TmqBuilder::new("taos://localhost:6041?group.id=req_id&auto.offset.reset=earliest")?
+                .with_req_id_fn(req_id_fn)
+                .build()
+                .await?;
+        consumer.subscribe(["ws_tmq_req_id"]).await?;
+        let (offset, _) = consumer
+            .recv_timeout(Timeout::from_secs(5))
+            .await?
+            .expect("message of the inserted row");
+        assert!(offset.req_id() >= 1 << 40);
+        assert!(offset.req_id() < next.load(Ordering::SeqCst));
+        consumer.commit(offset).await?;
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many(["drop topic ws_tmq_req_id", "drop database ws_tmq_req_id"])
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_ws_tmq_processing_too_slow() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        use crate::query::asyn::WS_ERROR_NO;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_too_slow",
+            "drop database if exists ws_tmq_too_slow",
+            "create database ws_tmq_too_slow wal_retention_period 3600",
+            "create topic ws_tmq_too_slow as database ws_tmq_too_slow",
+            "use ws_tmq_too_slow",
+            "create table tb(ts timestamp, v int)",
+            "insert into tb values(now, 1)",
+        ])
+        .await?;
+
+        let mut consumer =
+            TmqBuilder::new("taos://localhost:6041?group.id=too_slow&auto.offset.reset=earliest")?
+                .with_max_processing_interval(Duration::from_millis(200))
+                .build()
+                .await?;
+        consumer.subscribe(["ws_tmq_too_slow"]).await?;
+        assert_eq!(consumer.last_poll_elapsed(), Duration::ZERO);
+        let (offset, _) = consumer
+            .recv_timeout(Timeout::from_secs(5))
+            .await?
+            .expect("message of the inserted row");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(consumer.last_poll_elapsed() >= Duration::from_millis(300));
+
+        let err = consumer.commit(offset).await.unwrap_err();
+        assert_eq!(err.code(), WS_ERROR_NO::PROCESSING_TOO_SLOW.as_code());
+        assert!(!err.is_recoverable());
+        assert!(err.to_string().contains("max processing interval"), "{err}");
+        // Reported once by the next poll, then polling goes on.
+        let err = consumer
+            .recv_timeout(Timeout::from_secs(1))
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), WS_ERROR_NO::PROCESSING_TOO_SLOW.as_code());
+        consumer.recv_timeout(Timeout::from_secs(1)).await?;
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_tmq_too_slow",
+            "drop database ws_tmq_too_slow",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ws_tmq_received_at() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_received_at",
+            "drop database if exists ws_tmq_received_at",
+            "create database ws_tmq_received_at vgroups 2 wal_retention_period 3600",
+            "create topic ws_tmq_received_at as database ws_tmq_received_at",
+            "use ws_tmq_received_at",
+            "create table stb(ts timestamp, v int) tags(t int)",
+        ])
+        .await?;
+        for i in 0..4 {
+            taos.exec(format!(
+                "insert into tb{i} using stb tags({i}) values(now, {i})"
+            ))
+            .await?;
+        }
+
+        let mut consumer = TmqBuilder::new(
+            "taos://localhost:6041?group.id=received_at&auto.offset.reset=earliest",
+        )?
+        .build()
+        .await?;
+        consumer.subscribe(["ws_tmq_received_at"]).await?;
+        let mut received = Vec::new();
+        while let Some((offset, _)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            let received_at = offset
+                .received_at()
+                .expect("ws consumer records received time");
+            received.push(received_at);
+            consumer.commit(offset).await?;
+        }
+        assert!(received.len() > 1);
+        assert!(received.windows(2).all(|w| w[0] <= w[1]));
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_tmq_received_at",
+            "drop database ws_tmq_received_at",
+        ])
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ws_tmq_data_fields() -> anyhow::Result<()> {
+        use taos_query::common::{Field, Ty};
+        use taos_query::prelude::*;
 
-                    let position = consumer.position(&topic, vgroup_id);
-                    log::debug!("after commit offset position: {:?}", position);
-                    let committed = consumer.committed(&topic, vgroup_id);
-                    log::debug!("after commit offset committed: {:?}", committed);
-                }
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_data_fields",
+            "drop database if exists ws_tmq_data_fields",
+            "create database ws_tmq_data_fields wal_retention_period 3600",
+            "create topic ws_tmq_data_fields as database ws_tmq_data_fields",
+            "use ws_tmq_data_fields",
+            "create table tb(ts timestamp, v int, s varchar(20))",
+            "insert into tb values(now, 1, 'a')",
+        ])
+        .await?;
+
+        let mut consumer = TmqBuilder::new(
+            "taos://localhost:6041?group.id=data_fields&auto.offset.reset=earliest",
+        )?
+        .build()
+        .await?;
+        consumer.subscribe(["ws_tmq_data_fields"]).await?;
+        let mut checked = false;
+        while let Some((offset, message)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            if let Some(data) = message.into_data() {
+                let fields = data.fields().await?;
+                assert_eq!(
+                    fields,
+                    [
+                        Field::new("ts", Ty::Timestamp, 8),
+                        Field::new("v", Ty::Int, 4),
+                        Field::new("s", Ty::VarChar, 20),
+                    ]
+                );
+                let block = data.fetch_raw_block().await?.unwrap();
+                assert_eq!(block.fields(), fields);
+                assert!(data.fields().await?.is_empty());
+                checked = true;
             }
+            consumer.commit(offset).await?;
         }
+        assert!(checked);
+        consumer.unsubscribe().await?;
 
-        consumer.unsubscribe();
-
-        std::thread::sleep(Duration::from_secs(5));
-
+        tokio::time::sleep(Duration::from_secs(2)).await;
         taos.exec_many([
-            "drop database ws_tmq_meta_sync2",
-            "drop topic ws_tmq_meta_sync",
-            "drop database ws_tmq_meta_sync",
-        ])?;
+            "drop topic ws_tmq_data_fields",
+            "drop database ws_tmq_data_fields",
+        ])
+        .await?;
         Ok(())
     }
 
-    #[test]
-    fn test_ws_tmq_metadata() -> anyhow::Result<()> {
-        use taos_query::prelude::sync::*;
-        // pretty_env_logger::formatted_builder()
-        //     .filter_level(log::LevelFilter::Debug)
-        //     .init();
-
-        let taos = TaosBuilder::from_dsn("ws://localhost:6041")?.build()?;
-        taos.exec_many([
-            "drop topic if exists ws_tmq_meta_sync3",
-            "drop database if exists ws_tmq_meta_sync3",
-            "create database ws_tmq_meta_sync3 wal_retention_period 3600",
-            "create topic ws_tmq_meta_sync3 with meta as database ws_tmq_meta_sync3",
-            "use ws_tmq_meta_sync3",
-            // kind 1: create super table using all types
-            "create table stb1(ts timestamp, c1 bool, c2 tinyint, c3 smallint, c4 int, c5 bigint,\
-            c6 timestamp, c7 float, c8 double, c9 varchar(10), c10 nchar(16),\
-            c11 tinyint unsigned, c12 smallint unsigned, c13 int unsigned, c14 bigint unsigned)\
-            tags(t1 json)",
-            // kind 2: create child table with json tag
-            "create table tb0 using stb1 tags('{\"name\":\"value\"}')",
-            "create table tb1 using stb1 tags(NULL)",
-            "insert into tb0 values(now, NULL, NULL, NULL, NULL, NULL,
-            NULL, NULL, NULL, NULL, NULL,
-            NULL, NULL, NULL, NULL)
-            tb1 values(now, true, -2, -3, -4, -5, \
-            '2022-02-02 02:02:02.222', -0.1, -0.12345678910, 'abc 和我', 'Unicode + 涛思',\
-            254, 65534, 1, 1)",
-            // kind 3: create super table with all types except json (especially for tags)
-            "create table stb2(ts timestamp, c1 bool, c2 tinyint, c3 smallint, c4 int, c5 bigint,\
-            c6 timestamp, c7 float, c8 double, c9 varchar(10), c10 nchar(10),\
-            c11 tinyint unsigned, c12 smallint unsigned, c13 int unsigned, c14 bigint unsigned)\
-            tags(t1 bool, t2 tinyint, t3 smallint, t4 int, t5 bigint,\
-            t6 timestamp, t7 float, t8 double, t9 varchar(10), t10 nchar(16),\
-            t11 tinyint unsigned, t12 smallint unsigned, t13 int unsigned, t14 bigint unsigned)",
-            // kind 4: create child table with all types except json
-            "create table tb2 using stb2 tags(true, -2, -3, -4, -5, \
-            '2022-02-02 02:02:02.222', -0.1, -0.12345678910, 'abc 和我', 'Unicode + 涛思',\
-            254, 65534, 1, 1)",
-            "create table tb3 using stb2 tags( NULL, NULL, NULL, NULL, NULL,
-            NULL, NULL, NULL, NULL, NULL,
-            NULL, NULL, NULL, NULL)",
-            // kind 5: create common table
-            "create table `table` (ts timestamp, v int)",
-            // kind 6: column in super table
-            "alter table stb1 add column new1 bool",
-            "alter table stb1 add column new2 tinyint",
-            "alter table stb1 add column new10 nchar(16)",
-            "alter table stb1 modify column new10 nchar(32)",
-            "alter table stb1 drop column new10",
-            "alter table stb1 drop column new2",
-            "alter table stb1 drop column new1",
-            // kind 7: add tag in super table
-            "alter table `stb2` add tag new1 bool",
-            "alter table `stb2` rename tag new1 new1_new",
-            "alter table `stb2` modify tag t10 nchar(32)",
-            "alter table `stb2` drop tag new1_new",
-            // kind 8: column in common table
-            "alter table `table` add column new1 bool",
-            "alter table `table` add column new2 tinyint",
-            "alter table `table` add column new10 nchar(16)",
-            "alter table `table` modify column new10 nchar(32)",
-            "alter table `table` rename column new10 new10_new",
-            "alter table `table` drop column new10_new",
-            "alter table `table` drop column new2",
-            "alter table `table` drop column new1",
-            // kind 9: drop normal table
-            "drop table `table`",
-            // kind 10: drop child table
-            "drop table `tb2`, `tb1`",
-            // kind 11: drop super table
-            "drop table `stb2`",
-            "drop table `stb1`",
-        ])?;
+    #[tokio::test]
+    async fn test_ws_tmq_with_snapshot() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
 
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
         taos.exec_many([
-            "drop database if exists ws_tmq_meta_sync32",
-            "create database if not exists ws_tmq_meta_sync32 wal_retention_period 3600",
-            "use ws_tmq_meta_sync32",
-        ])?;
+            "drop topic if exists ws_tmq_snapshot",
+            "drop database if exists ws_tmq_snapshot",
+            "create database ws_tmq_snapshot wal_retention_period 3600",
+            "use ws_tmq_snapshot",
+            "create table tb(ts timestamp, v int)",
+            "insert into tb values(now, 1)(now+1s, 2)",
+            "create topic ws_tmq_snapshot as select * from ws_tmq_snapshot.tb",
+        ])
+        .await?;
 
-        let builder = TmqBuilder::new(
-            "taos://localhost:6041?group.id=10&timeout=1000ms&auto.offset.reset=earliest",
-        )?;
-        let mut consumer = builder.build()?;
-        consumer.subscribe(["ws_tmq_meta_sync3"])?;
+        let mut consumer =
+            TmqBuilder::new("taos://localhost:6041?group.id=snapshot&auto.offset.reset=earliest")?
+                .with_snapshot(true)
+                .build()
+                .await?;
+        consumer.subscribe(["ws_tmq_snapshot"]).await?;
+        let mut rows = 0;
+        while let Some((offset, message)) = consumer.recv_timeout(Timeout::from_secs(2)).await? {
+            if let Some(data) = message.into_data() {
+                while let Some(block) = data.fetch_raw_block().await? {
+                    rows += block.nrows();
+                }
+            }
+            consumer.commit(offset).await?;
+        }
+        assert_eq!(rows, 2);
+        consumer.unsubscribe().await?;
 
-        let iter = consumer.iter_with_timeout(Timeout::from_secs(1));
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic ws_tmq_snapshot",
+            "drop database ws_tmq_snapshot",
+        ])
+        .await?;
+        Ok(())
+    }
 
-        for msg in iter {
-            let (offset, message) = msg?;
-            // Offset contains information for topic name, database name and vgroup id,
-            //  similar to kafka topic/partition/offset.
-            let _ = offset.topic();
-            let _ = offset.database();
-            let _ = offset.vgroup_id();
+    #[tokio::test]
+    async fn test_ws_tmq_assignment_change() -> anyhow::Result<()> {
+        use std::sync::{Arc, Mutex};
+        use taos_query::prelude::*;
 
-            // Different to kafka message, TDengine consumer would consume two kind of messages.
-            //
-            // 1. meta
-            // 2. data
-            match message {
-                MessageSet::Meta(meta) => {
-                    let _raw = meta.as_raw_meta()?;
-                    // taos.write_meta(raw)?;
+        let taos = TaosBuilder::from_dsn("taos://localhost:6041")?
+            .build()
+            .await?;
+        taos.exec_many([
+            "drop topic if exists ws_tmq_rebalance",
+            "drop database if exists ws_tmq_rebalance",
+            "create database ws_tmq_rebalance vgroups 2 wal_retention_period 3600",
+            "create topic ws_tmq_rebalance as database ws_tmq_rebalance",
+            "use ws_tmq_rebalance",
+            "create table stb(ts timestamp, v int) tags(t int)",
+        ])
+        .await?;
+        for i in 0..8 {
+            taos.exec(format!(
+                "insert into tb{i} using stb tags({i}) values(now, {i})"
+            ))
+            .await?;
+        }
 
-                    // meta data can be write to an database seamlessly by raw or json (to sql).
-                    let json = meta.as_json_meta()?;
-                    let sql = json.to_string();
-                    log::debug!("sql: {}", sql);
-                    if let Err(err) = taos.exec(sql) {
-                        match err.code() {
-                            Code::TAG_ALREADY_EXIST => log::trace!("tag already exists"),
-                            Code::TAG_NOT_EXIST => log::trace!("tag not exist"),
-                            Code::COLUMN_EXISTS => log::trace!("column already exists"),
-                            Code::COLUMN_NOT_EXIST => log::trace!("column not exists"),
-                            Code::INVALID_COLUMN_NAME => log::trace!("invalid column name"),
-                            Code::MODIFIED_ALREADY => log::trace!("modified already done"),
-                            Code::TABLE_NOT_EXIST => log::trace!("table does not exists"),
-                            Code::STABLE_NOT_EXIST => log::trace!("stable does not exists"),
-                            _ => {
-                                log::error!("{}", err);
-                            }
-                        }
-                    }
-                }
-                MessageSet::Data(data) => {
-                    // data message may have more than one data block for various tables.
-                    for block in data {
-                        let _block = block?;
-                        // dbg!(block.table_name());
-                        // dbg!(block);
-                    }
-                }
-                _ => unreachable!(),
-            }
-            consumer.commit(offset)?;
+        let dsn = "taos://localhost:6041?group.id=rebalance&auto.offset.reset=earliest";
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut consumer = TmqBuilder::new(dsn)?.build().await?;
+        {
+            let events = events.clone();
+            consumer.on_assignment_change(move |event| events.lock().unwrap().push(event));
+        }
+        consumer.subscribe(["ws_tmq_rebalance"]).await?;
+        let _ = consumer.recv_timeout(Timeout::from_secs(2)).await?;
+        {
+            let events = events.lock().unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].assigned.len(), 2);
+            assert!(events[0].revoked.is_empty());
         }
-        consumer.unsubscribe();
 
-        std::thread::sleep(Duration::from_secs(5));
+        // Another consumer takes some of the vgroups.
+        let mut other = TmqBuilder::new(dsn)?.build().await?;
+        other.subscribe(["ws_tmq_rebalance"]).await?;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let event = consumer.check_assignments().await?;
+        assert!(!event.revoked.is_empty());
+        assert_eq!(events.lock().unwrap().last(), Some(&event));
+        assert!(consumer.check_assignments().await?.is_empty());
+
+        other.unsubscribe().await?;
+        consumer.unsubscribe().await?;
 
+        tokio::time::sleep(Duration::from_secs(2)).await;
         taos.exec_many([
-            "drop database ws_tmq_meta_sync32",
-            "drop topic ws_tmq_meta_sync3",
-            "drop database ws_tmq_meta_sync3",
-        ])?;
+            "drop topic ws_tmq_rebalance",
+            "drop database ws_tmq_rebalance",
+        ])
+        .await?;
         Ok(())
     }
 
@@ -2193,7 +3917,7 @@ mod tests {
                 consumer.commit(offset).await?;
             }
         }
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(2)).await;
 
@@ -2205,4 +3929,250 @@ mod tests {
         .await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_close_commits_pending_offset() -> anyhow::Result<()> {
+        use std::sync::{Arc, Mutex};
+
+        use taos_query::tmq::{
+            AsAsyncConsumer, PausedVGroups, PendingError, Timeout, TopicPattern,
+        };
+
+        use super::{
+            AutoCommit, CommitHook, Consumer, ConsumerCounters, Offset, Rebalance, TmqRecvData,
+            WsConn, WsTmqAgent, WsTmqSender,
+        };
+
+        let queries = WsTmqAgent::default();
+        let (ws, mut ws_recv) = tokio::sync::mpsc::channel(100);
+        let sender = WsTmqSender::new(None, WsConn::new(ws), queries.clone());
+        let (close_signal, _) = tokio::sync::watch::channel(false);
+        let close_signal = Arc::new(close_signal);
+        let commit_hook = CommitHook::default();
+        let auto_commit = AutoCommit::spawn(
+            sender.clone(),
+            Duration::from_secs(3600),
+            close_signal.clone(),
+            commit_hook.clone(),
+        );
+        auto_commit.track(&Offset {
+            req_id: 1,
+            message_id: 7,
+            database: "db".to_string(),
+            topic: "t1".to_string(),
+            vgroup_id: 2,
+            offset: 10,
+            timing: None,
+            received_at: std::time::Instant::now(),
+        });
+        let builder = TaosBuilder::from_dsn("ws://localhost:6041")?;
+        let consumer = Consumer {
+            conn: builder.to_conn_request(),
+            builder,
+            tmq_conf: Default::default(),
+            sender,
+            close_signal,
+            timeout: Timeout::Never,
+            topics: Mutex::default(),
+            pattern: TopicPattern::default(),
+            pending_error: PendingError::default(),
+            topic_timeouts: Default::default(),
+            paused: PausedVGroups::default(),
+            rebalance: Rebalance::default(),
+            metrics: Arc::new(ConsumerCounters::with_observer(None)),
+            auto_commit: Some(auto_commit),
+            commit_hook,
+            max_processing_interval: None,
+            last_poll: Mutex::default(),
+            keepalive_interval: Duration::from_secs(30),
+        };
+
+        // Acknowledge the requests in place of the server.
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            while let Some(msg) = ws_recv.recv().await {
+                let request: serde_json::Value = serde_json::from_slice(&msg.data).unwrap();
+                let action = request["action"].as_str().unwrap().to_string();
+                let req_id = request["args"]["req_id"].as_u64().unwrap();
+                let data = match action.as_str() {
+                    "commit" => TmqRecvData::Commit,
+                    _ => TmqRecvData::Unsubscribe,
+                };
+                let (_, tx) = queries.remove(&req_id).unwrap();
+                let _ = tx.send(Ok(data));
+                requests.push((action, request["args"]["message_id"].as_u64()));
+                if requests.len() == 2 {
+                    break;
+                }
+            }
+            requests
+        });
+
+        consumer.close().await?;
+        assert_eq!(
+            server.await?,
+            [
+                ("commit".to_string(), Some(7)),
+                ("unsubscribe".to_string(), None)
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_processing_too_slow_code() {
+        use taos_query::prelude::{Code, RawError};
+
+        use super::{WsTmqError, WS_ERROR_NO};
+
+        let err = RawError::from(WsTmqError::ProcessingTooSlow {
+            elapsed: Duration::from_millis(300),
+            max: Duration::from_millis(200),
+        });
+        assert_eq!(err.code(), WS_ERROR_NO::PROCESSING_TOO_SLOW.as_code());
+        assert_ne!(err.code(), Code::TMQ_CONSUMER_MISMATCH);
+        assert!(!err.is_recoverable());
+        assert!(err.to_string().contains("max processing interval"), "{err}");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drop_bounds_final_commit() -> anyhow::Result<()> {
+        use std::sync::Arc;
+
+        use super::{AutoCommit, CommitHook, Offset, WsConn, WsTmqAgent, WsTmqSender};
+
+        let (ws, mut ws_recv) = tokio::sync::mpsc::channel(100);
+        let sender = WsTmqSender::new(None, WsConn::new(ws), WsTmqAgent::default());
+        let (close_signal, mut closed) = tokio::sync::watch::channel(false);
+        let auto_commit = AutoCommit::spawn(
+            sender,
+            Duration::from_secs(3600),
+            Arc::new(close_signal),
+            CommitHook::default(),
+        );
+        auto_commit.track(&Offset {
+            req_id: 1,
+            message_id: 7,
+            database: "db".to_string(),
+            topic: "t1".to_string(),
+            vgroup_id: 2,
+            offset: 10,
+            timing: None,
+            received_at: std::time::Instant::now(),
+        });
+        auto_commit.stop(true);
+
+        // The commit is never acknowledged, the connection is closed after the shutdown timeout.
+        let request = ws_recv.recv().await.unwrap();
+        let request: serde_json::Value = serde_json::from_slice(&request.data)?;
+        assert_eq!(request["action"], "commit");
+        let start = tokio::time::Instant::now();
+        closed.changed().await?;
+        assert!(*closed.borrow());
+        assert_eq!(
+            start.elapsed().as_millis(),
+            u128::from(super::DEFAULT_SHUTDOWN_TIMEOUT_MS)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_data_counts_from_fetches() -> anyhow::Result<()> {
+        use taos_query::tmq::IsAsyncData;
+
+        use super::{Data, TmqFetch, TmqRecvData, WsConn, WsMessageBase, WsTmqAgent, WsTmqSender};
+
+        let queries = WsTmqAgent::default();
+        let (ws, mut ws_recv) = tokio::sync::mpsc::channel(100);
+        let sender = WsTmqSender::new(None, WsConn::new(ws), queries.clone());
+
+        // Answer the fetches in place of the server, two blocks of 3 and 2 rows.
+        tokio::spawn(async move {
+            for rows in [Some(3), Some(2), None] {
+                let msg = ws_recv.recv().await.unwrap();
+                let request: serde_json::Value = serde_json::from_slice(&msg.data).unwrap();
+                let req_id = request["args"]["req_id"].as_u64().unwrap();
+                let fetch = TmqFetch {
+                    completed: rows.is_none(),
+                    rows: rows.unwrap_or_default(),
+                    ..Default::default()
+                };
+                let (_, tx) = queries.remove(&req_id).unwrap();
+                let _ = tx.send(Ok(TmqRecvData::Fetch(fetch)));
+            }
+        });
+
+        let data = Data(WsMessageBase::new(sender, 1));
+        for _ in 0..2 {
+            assert!(!data.0.fetch_header().await?.completed);
+            assert_eq!(IsAsyncData::block_count_hint(&data), None);
+            // Move to the next block without fetching this one.
+            data.0.fetched.lock().unwrap().take();
+        }
+        assert!(data.0.fetch_header().await?.completed);
+        assert_eq!(IsAsyncData::block_count_hint(&data), Some(2));
+        assert_eq!(IsAsyncData::row_count_hint(&data), Some(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebalance_due_when_idle() {
+        use std::time::Instant;
+
+        use super::{Rebalance, ASSIGNMENT_CHECK_INTERVAL_MS};
+
+        let mut rebalance = Rebalance::default();
+        assert!(!rebalance.is_due_idle());
+
+        rebalance.listeners.push(Box::new(|_| {}));
+        assert!(rebalance.is_due_idle());
+        *rebalance.checked_at.lock().unwrap() = Some(Instant::now());
+        assert!(!rebalance.is_due_idle());
+        let interval = Duration::from_millis(ASSIGNMENT_CHECK_INTERVAL_MS);
+        *rebalance.checked_at.lock().unwrap() = Instant::now().checked_sub(interval);
+        assert!(rebalance.is_due_idle());
+    }
+
+    #[tokio::test]
+    async fn test_link_lost_wakes_pending_requests() {
+        use std::sync::Arc;
+
+        use super::{TmqSend, WsConn, WsTmqSender, WS_ERROR_NO};
+
+        let (ws, _msg_recv) = tokio::sync::mpsc::channel(100);
+        let sender = WsTmqSender::new(None, WsConn::new(ws), Arc::default());
+        let link = sender.conn().link;
+        assert!(link.is_connected());
+
+        let poll = |req_id| TmqSend::Poll {
+            req_id,
+            blocking_time: 0,
+        };
+        let pending = tokio::spawn({
+            let sender = sender.clone();
+            async move { sender.send_recv(poll(1)).await }
+        });
+        while sender.queries.is_empty() {
+            tokio::task::yield_now().await;
+        }
+        link.lost(&sender.queries, "test");
+        assert!(!sender.is_connected());
+
+        let err = tokio::time::timeout(Duration::from_secs(1), pending)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.code(), WS_ERROR_NO::CONN_CLOSED.as_code());
+
+        let err = sender.send_recv(poll(2)).await.unwrap_err();
+        assert_eq!(err.code(), WS_ERROR_NO::CONN_CLOSED.as_code());
+        assert!(sender.queries.is_empty());
+
+        // A later loss of the same link leaves requests of a new connection alone.
+        let (tx, _rx) = tokio::sync::oneshot::channel();
+        sender.queries.insert(3, tx);
+        link.lost(&sender.queries, "test");
+        assert_eq!(sender.queries.len(), 1);
+    }
 }
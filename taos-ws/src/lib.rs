@@ -14,7 +14,7 @@ pub use stmt::Stmt;
 
 // pub mod tmq;
 pub mod consumer;
-pub use consumer::{Consumer, Offset, TmqBuilder};
+pub use consumer::{Consumer, ConsumerBuilder, Offset, TmqBuilder};
 
 pub mod query;
 pub use query::ResultSet;
@@ -251,6 +251,8 @@ pub enum WS_ERROR_NO {
     RECV_MESSAGE_TIMEOUT = 0xE004,
     IO_ERROR = 0xE005,
     UNAUTHORIZED = 0xE006,
+    /// A consumer polled later than its max processing interval, reported by the client.
+    PROCESSING_TOO_SLOW = 0xE007,
 }
 
 impl WS_ERROR_NO {
@@ -793,7 +795,7 @@ impl WsTaos {
         meta.write_u64_le(message_id).map_err(Error::from)?;
         meta.write_u64_le(raw_meta_message as u64)
             .map_err(Error::from)?;
-        meta.write_all(&raw.as_bytes()).map_err(Error::from)?;
+        meta.write_all(raw.as_bytes()).map_err(Error::from)?;
         let len = meta.len();
 
         log::trace!("write meta with req_id: {req_id}, raw data length: {len}",);
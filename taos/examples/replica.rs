@@ -0,0 +1,71 @@
+use anyhow::Context;
+use taos::*;
+
+// Replicate database `tmq` of the source cluster to the sink cluster by applying the raw payload
+// of every tmq message as is.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    pretty_env_logger::init();
+    let source = "taos://localhost:6030";
+    let sink = "taos://localhost:6030";
+    let db = "tmq";
+    let replica = "tmq_replica";
+
+    let taos = TaosBuilder::from_dsn(source)?.build().await?;
+    // subscribe with meta, so that tables are created on the sink before data comes.
+    taos.exec_many([
+        "DROP TOPIC IF EXISTS tmq_replica".to_string(),
+        format!("CREATE TOPIC IF NOT EXISTS tmq_replica WITH META AS DATABASE `{db}`"),
+    ])
+    .await?;
+
+    let sink = TaosBuilder::from_dsn(sink)?.build().await?;
+    sink.exec_many([
+        format!("CREATE DATABASE IF NOT EXISTS `{replica}`"),
+        format!("USE `{replica}`"),
+    ])
+    .await?;
+
+    let tmq = TmqBuilder::from_dsn(format!(
+        "{source}/?group.id=replica&auto.offset.reset=earliest&experimental.snapshot.enable=false"
+    ))?;
+    let mut consumer = tmq.build().await?;
+    consumer.subscribe(["tmq_replica"]).await?;
+
+    {
+        let mut stream = consumer.stream();
+        while let Some((offset, message)) = stream.try_next().await? {
+            match message {
+                // A meta message may contain several DDLs, they are applied in one batch.
+                MessageSet::Meta(meta) => {
+                    let raw = meta.as_raw_meta().await?;
+                    sink.write_raw_meta(&raw).await?;
+                }
+                // The raw payload of a meta-data message holds both the child tables to create
+                // and their data, write it once.
+                MessageSet::MetaData(meta, _) => {
+                    let raw = meta.as_raw_meta().await?;
+                    sink.write_raw_meta(&raw).await?;
+                }
+                MessageSet::Data(data) => {
+                    let raw = data.as_raw_data().await?;
+                    if let Err(err) = sink.write_raw_data(&raw).await {
+                        // The child table is created by a meta message consumed before, which is
+                        // not the case when the group started after the table creation.
+                        let mut tables = Vec::new();
+                        while let Some(block) = data.fetch_raw_block().await? {
+                            tables.extend(block.table_name().map(ToString::to_string));
+                        }
+                        return Err(err).with_context(|| {
+                            format!("write data of tables {tables:?}, missing on the sink?")
+                        });
+                    }
+                }
+            }
+            consumer.commit(offset).await?;
+        }
+    }
+
+    consumer.unsubscribe().await?;
+    Ok(())
+}
@@ -95,7 +95,7 @@ async fn main() -> anyhow::Result<()> {
         }
     }
 
-    consumer.unsubscribe().await;
+    consumer.unsubscribe().await?;
 
     task.await??;
 
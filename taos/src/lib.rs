@@ -22,7 +22,7 @@ pub use stmt::Stmt;
 #[cfg(all(feature = "ws", any(feature = "native", feature = "optin")))]
 mod tmq;
 #[cfg(all(feature = "ws", any(feature = "native", feature = "optin")))]
-pub use tmq::{Consumer, Data, MessageSet, Meta, Offset, TmqBuilder};
+pub use tmq::{Consumer, ConsumerBuilder, Data, MessageSet, Meta, Offset, TmqBuilder};
 
 #[cfg(all(feature = "ws", any(feature = "native", feature = "optin")))]
 mod query;
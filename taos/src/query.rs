@@ -1230,7 +1230,7 @@ mod tests {
             let _ = consumer.commit(offset);
         }
 
-        consumer.unsubscribe();
+        consumer.unsubscribe()?;
 
         let mut query = taos.query("describe stb1")?;
         for row in query.rows() {
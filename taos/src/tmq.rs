@@ -1,13 +1,16 @@
 use taos_query::{
     prelude::{AsAsyncConsumer, RawMeta, Timeout},
-    tmq::{Assignment, VGroupId},
-    RawBlock, RawResult,
+    tmq::{
+        Assignment, ConsumerInfo, ConsumerMetrics, ConsumerObserver, PendingError, SeekResults,
+        TmqConfig, TopicPattern, VGroupId,
+    },
+    RawBlock, RawError, RawResult,
 };
 
 #[derive(Debug)]
 enum TmqBuilderInner {
-    Native(crate::sys::TmqBuilder),
-    Ws(taos_ws::consumer::TmqBuilder),
+    Native(Box<crate::sys::TmqBuilder>),
+    Ws(Box<taos_ws::consumer::TmqBuilder>),
 }
 
 #[derive(Debug)]
@@ -45,12 +48,106 @@ pub struct Data(DataInner);
 
 pub type MessageSet<Meta, Data> = taos_query::tmq::MessageSet<Meta, Data>;
 
+/// Typed builder of [Consumer], e.g. `ConsumerBuilder::new().server(dsn).group_id("g").build()`.
+pub type ConsumerBuilder = taos_query::tmq::ConsumerBuilder<TmqBuilder>;
+
 #[derive(Debug)]
 pub struct TmqBuilder(TmqBuilderInner);
 
 #[derive(Debug)]
 pub struct Consumer(ConsumerInner);
 
+impl TmqBuilder {
+    /// Build from a connection DSN and typed consumer options, see [TmqConfig].
+    ///
+    /// Params in `config` override the ones with the same keys in `dsn`.
+    pub fn from_config<D: taos_query::IntoDsn>(dsn: D, config: TmqConfig) -> RawResult<Self> {
+        let mut dsn = dsn.into_dsn()?;
+        config.apply_to(&mut dsn);
+        <Self as taos_query::TBuilder>::from_dsn(dsn)
+    }
+
+    /// Build from a parsed [Dsn](taos_query::Dsn), e.g. with credentials resolved from a
+    /// secrets manager, instead of a dsn string.
+    pub fn from_dsn_obj(dsn: taos_query::Dsn) -> RawResult<Self> {
+        <Self as taos_query::TBuilder>::from_dsn(dsn)
+    }
+
+    /// Build a consumer of `group_id` with the settings of an established connection, e.g. one
+    /// from a pool.
+    ///
+    /// Only the connection settings are cloned, the consumer opens its own connection and
+    /// leaves the one of `taos` for queries.
+    pub fn from_taos(taos: &crate::Taos, group_id: &str) -> RawResult<Self> {
+        match &taos.0 {
+            crate::query::TaosInner::Native(taos) => Ok(Self(TmqBuilderInner::Native(Box::new(
+                crate::sys::TmqBuilder::from_taos(taos, group_id)?,
+            )))),
+            crate::query::TaosInner::Ws(taos) => Ok(Self(TmqBuilderInner::Ws(Box::new(
+                taos_ws::consumer::TmqBuilder::from_taos(taos, group_id)?,
+            )))),
+        }
+    }
+
+    /// Like `from_dsn`, but reject unknown params, see [TmqConfig::from_params].
+    pub fn from_dsn_strict<D: taos_query::IntoDsn>(dsn: D) -> RawResult<Self> {
+        let dsn = dsn.into_dsn()?;
+        TmqConfig::from_params(&dsn.params, true)?;
+        <Self as taos_query::TBuilder>::from_dsn(dsn)
+    }
+
+    /// Deliver the data written before the topic is created, as `experimental.snapshot.enable`.
+    ///
+    /// Building fails if the server does not support it.
+    pub fn with_snapshot(self, enable: bool) -> Self {
+        match self.0 {
+            TmqBuilderInner::Native(b) => {
+                Self(TmqBuilderInner::Native(Box::new(b.with_snapshot(enable))))
+            }
+            TmqBuilderInner::Ws(b) => Self(TmqBuilderInner::Ws(Box::new(b.with_snapshot(enable)))),
+        }
+    }
+
+    /// Consume data messages in the order they are written, as `enable.replay`.
+    ///
+    /// Building fails if the server is older than 3.2.1.0, which ignores it.
+    pub fn with_replay(self, enable: bool) -> Self {
+        match self.0 {
+            TmqBuilderInner::Native(b) => {
+                Self(TmqBuilderInner::Native(Box::new(b.with_replay(enable))))
+            }
+            TmqBuilderInner::Ws(b) => Self(TmqBuilderInner::Ws(Box::new(b.with_replay(enable)))),
+        }
+    }
+
+    /// Report polls, commits and errors of the consumer to `observer`, see [ConsumerObserver].
+    pub fn with_observer(self, observer: impl ConsumerObserver + 'static) -> Self {
+        match self.0 {
+            TmqBuilderInner::Native(b) => {
+                Self(TmqBuilderInner::Native(Box::new(b.with_observer(observer))))
+            }
+            TmqBuilderInner::Ws(b) => {
+                Self(TmqBuilderInner::Ws(Box::new(b.with_observer(observer))))
+            }
+        }
+    }
+
+    /// Reorder received messages by offset within each vgroup, see
+    /// [VGroupOrder](taos_query::tmq::VGroupOrder).
+    ///
+    /// Building fails for websocket, and for native with `enable.auto.commit=true`.
+    pub fn preserve_vgroup_order(self, preserve: bool) -> Self {
+        match self.0 {
+            TmqBuilderInner::Native(b) => Self(TmqBuilderInner::Native(Box::new(
+                b.preserve_vgroup_order(preserve),
+            ))),
+            TmqBuilderInner::Ws(b) => Self(TmqBuilderInner::Ws(Box::new(
+                b.preserve_vgroup_order(preserve),
+            ))),
+        }
+    }
+}
+
 impl taos_query::TBuilder for TmqBuilder {
     type Target = Consumer;
 
@@ -63,13 +160,13 @@ impl taos_query::TBuilder for TmqBuilder {
         // dbg!(&dsn);
         match (dsn.driver.as_str(), dsn.protocol.as_deref()) {
             ("ws" | "wss" | "http" | "https" | "taosws", _) => Ok(Self(TmqBuilderInner::Ws(
-                taos_ws::consumer::TmqBuilder::from_dsn(dsn)?,
+                Box::new(taos_ws::consumer::TmqBuilder::from_dsn(dsn)?),
             ))),
-            ("taos" | "tmq", None) => Ok(Self(TmqBuilderInner::Native(
+            ("taos" | "tmq", None) => Ok(Self(TmqBuilderInner::Native(Box::new(
                 crate::sys::TmqBuilder::from_dsn(dsn)?,
-            ))),
+            )))),
             ("taos" | "tmq", Some("ws" | "wss" | "http" | "https")) => Ok(Self(
-                TmqBuilderInner::Ws(taos_ws::consumer::TmqBuilder::from_dsn(dsn)?),
+                TmqBuilderInner::Ws(Box::new(taos_ws::consumer::TmqBuilder::from_dsn(dsn)?)),
             )),
             (driver, _) => Err(taos_query::DsnError::InvalidDriver(driver.to_string()).into()),
         }
@@ -131,13 +228,13 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
         // dbg!(&dsn);
         match (dsn.driver.as_str(), dsn.protocol.as_deref()) {
             ("ws" | "wss" | "http" | "https" | "taosws", _) => Ok(Self(TmqBuilderInner::Ws(
-                taos_ws::consumer::TmqBuilder::from_dsn(dsn)?,
+                Box::new(taos_ws::consumer::TmqBuilder::from_dsn(dsn)?),
             ))),
-            ("taos" | "tmq", None) => Ok(Self(TmqBuilderInner::Native(
+            ("taos" | "tmq", None) => Ok(Self(TmqBuilderInner::Native(Box::new(
                 crate::sys::TmqBuilder::from_dsn(dsn)?,
-            ))),
+            )))),
             ("taos" | "tmq", Some("ws" | "wss" | "http" | "https")) => Ok(Self(
-                TmqBuilderInner::Ws(taos_ws::consumer::TmqBuilder::from_dsn(dsn)?),
+                TmqBuilderInner::Ws(Box::new(taos_ws::consumer::TmqBuilder::from_dsn(dsn)?)),
             )),
             (driver, _) => Err(taos_query::DsnError::InvalidDriver(driver.to_string()).into()),
         }
@@ -196,6 +293,62 @@ impl taos_query::AsyncTBuilder for TmqBuilder {
     }
 }
 
+impl Consumer {
+    /// Group id, client id and subscribed topics of the consumer.
+    pub fn info(&self) -> ConsumerInfo {
+        match &self.0 {
+            ConsumerInner::Native(c) => c.info(),
+            ConsumerInner::Ws(c) => c.info(),
+        }
+    }
+
+    /// Snapshot of the poll, message and commit counters of the consumer.
+    pub fn metrics(&self) -> ConsumerMetrics {
+        match &self.0 {
+            ConsumerInner::Native(c) => c.metrics(),
+            ConsumerInner::Ws(c) => c.metrics(),
+        }
+    }
+
+    /// Reset the counters of [metrics](Self::metrics).
+    pub fn reset_metrics(&self) {
+        match &self.0 {
+            ConsumerInner::Native(c) => c.reset_metrics(),
+            ConsumerInner::Ws(c) => c.reset_metrics(),
+        }
+    }
+
+    /// Paused vgroups as `(topic, vgroup_id)`, see [pause](AsAsyncConsumer::pause).
+    pub fn paused(&self) -> std::collections::BTreeSet<(String, VGroupId)> {
+        match &self.0 {
+            ConsumerInner::Native(c) => c.paused(),
+            ConsumerInner::Ws(c) => c.paused(),
+        }
+    }
+
+    /// Set a hook called with `(topic, vgroup_id, offset, result)` after each commit request.
+    ///
+    /// Websocket auto commits are hooked too, native auto commits are done inside the library
+    /// and are not. Panics in the hook are caught and logged.
+    pub fn set_commit_hook(
+        &mut self,
+        hook: impl Fn(&str, VGroupId, i64, &RawResult<()>) + Send + Sync + 'static,
+    ) {
+        match &mut self.0 {
+            ConsumerInner::Native(c) => c.set_commit_hook(hook),
+            ConsumerInner::Ws(c) => c.set_commit_hook(hook),
+        }
+    }
+
+    /// Override the timeout of `recv` and `stream` when `topic` is the only subscribed topic.
+    pub fn set_topic_timeout(&mut self, topic: &str, timeout: Timeout) {
+        match &mut self.0 {
+            ConsumerInner::Native(c) => c.set_topic_timeout(topic, timeout),
+            ConsumerInner::Ws(c) => c.set_topic_timeout(topic, timeout),
+        }
+    }
+}
+
 impl taos_query::tmq::IsOffset for Offset {
     fn database(&self) -> &str {
         match &self.0 {
@@ -229,6 +382,39 @@ impl taos_query::tmq::IsOffset for Offset {
             }
         }
     }
+
+    fn offset(&self) -> i64 {
+        match &self.0 {
+            OffsetInner::Native(offset) => {
+                <crate::sys::tmq::Offset as taos_query::tmq::IsOffset>::offset(offset)
+            }
+            OffsetInner::Ws(offset) => {
+                <taos_ws::consumer::Offset as taos_query::tmq::IsOffset>::offset(offset)
+            }
+        }
+    }
+
+    fn timing(&self) -> Option<i64> {
+        match &self.0 {
+            OffsetInner::Native(offset) => {
+                <crate::sys::tmq::Offset as taos_query::tmq::IsOffset>::timing(offset)
+            }
+            OffsetInner::Ws(offset) => {
+                <taos_ws::consumer::Offset as taos_query::tmq::IsOffset>::timing(offset)
+            }
+        }
+    }
+
+    fn received_at(&self) -> Option<std::time::Instant> {
+        match &self.0 {
+            OffsetInner::Native(offset) => {
+                <crate::sys::tmq::Offset as taos_query::tmq::IsOffset>::received_at(offset)
+            }
+            OffsetInner::Ws(offset) => {
+                <taos_ws::consumer::Offset as taos_query::tmq::IsOffset>::received_at(offset)
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -295,6 +481,39 @@ impl taos_query::tmq::IsAsyncData for Data {
             }
         }
     }
+
+    async fn fields(&self) -> RawResult<Vec<taos_query::common::Field>> {
+        match &self.0 {
+            DataInner::Native(data) => {
+                <crate::sys::tmq::Data as taos_query::tmq::IsAsyncData>::fields(data).await
+            }
+            DataInner::Ws(data) => {
+                <taos_ws::consumer::Data as taos_query::tmq::IsAsyncData>::fields(data).await
+            }
+        }
+    }
+
+    fn block_count_hint(&self) -> Option<usize> {
+        match &self.0 {
+            DataInner::Native(data) => {
+                <crate::sys::tmq::Data as taos_query::tmq::IsAsyncData>::block_count_hint(data)
+            }
+            DataInner::Ws(data) => {
+                <taos_ws::consumer::Data as taos_query::tmq::IsAsyncData>::block_count_hint(data)
+            }
+        }
+    }
+
+    fn row_count_hint(&self) -> Option<usize> {
+        match &self.0 {
+            DataInner::Native(data) => {
+                <crate::sys::tmq::Data as taos_query::tmq::IsAsyncData>::row_count_hint(data)
+            }
+            DataInner::Ws(data) => {
+                <taos_ws::consumer::Data as taos_query::tmq::IsAsyncData>::row_count_hint(data)
+            }
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -334,7 +553,7 @@ impl AsAsyncConsumer for Consumer {
         }
     }
 
-    async fn unsubscribe(self) {
+    async fn unsubscribe(self) -> RawResult<()> {
         match self.0 {
             ConsumerInner::Native(c) => {
                 <crate::sys::Consumer as AsAsyncConsumer>::unsubscribe(c).await
@@ -345,6 +564,17 @@ impl AsAsyncConsumer for Consumer {
         }
     }
 
+    async fn shutdown(self) -> RawResult<()> {
+        match self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::shutdown(c).await
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::shutdown(c).await
+            }
+        }
+    }
+
     async fn recv_timeout(
         &self,
         timeout: Timeout,
@@ -454,6 +684,66 @@ impl AsAsyncConsumer for Consumer {
         }
     }
 
+    fn subscription(&self) -> Vec<String> {
+        match &self.0 {
+            ConsumerInner::Native(c) => <crate::sys::Consumer as AsAsyncConsumer>::subscription(c),
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::subscription(c)
+            }
+        }
+    }
+
+    async fn topic_names(&self) -> RawResult<Vec<String>> {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::topic_names(c).await
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::topic_names(c).await
+            }
+        }
+    }
+
+    fn topic_pattern(&self) -> Option<&TopicPattern> {
+        match &self.0 {
+            ConsumerInner::Native(c) => <crate::sys::Consumer as AsAsyncConsumer>::topic_pattern(c),
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::topic_pattern(c)
+            }
+        }
+    }
+
+    fn pending_error(&self) -> Option<&PendingError> {
+        match &self.0 {
+            ConsumerInner::Native(c) => <crate::sys::Consumer as AsAsyncConsumer>::pending_error(c),
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::pending_error(c)
+            }
+        }
+    }
+
+    async fn subscribe_matched(&self, topics: Vec<String>) -> RawResult<()> {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::subscribe_matched(c, topics).await
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::subscribe_matched(c, topics).await
+            }
+        }
+    }
+
+    fn topic_timeout(&self, topic: &str) -> Option<Timeout> {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::topic_timeout(c, topic)
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::topic_timeout(c, topic)
+            }
+        }
+    }
+
     async fn assignments(&self) -> Option<Vec<(String, Vec<Assignment>)>> {
         match &self.0 {
             ConsumerInner::Native(c) => {
@@ -465,7 +755,7 @@ impl AsAsyncConsumer for Consumer {
         }
     }
 
-    async fn topic_assignment(&self, topic: &str) -> Vec<Assignment> {
+    async fn topic_assignment(&self, topic: &str) -> RawResult<Vec<Assignment>> {
         match &self.0 {
             ConsumerInner::Native(c) => {
                 <crate::sys::Consumer as AsAsyncConsumer>::topic_assignment(c, topic).await
@@ -476,13 +766,8 @@ impl AsAsyncConsumer for Consumer {
         }
     }
 
-    async fn offset_seek(
-        &mut self,
-        topic: &str,
-        vgroup_id: VGroupId,
-        offset: i64,
-    ) -> RawResult<()> {
-        match &mut self.0 {
+    async fn offset_seek(&self, topic: &str, vgroup_id: VGroupId, offset: i64) -> RawResult<()> {
+        match &self.0 {
             ConsumerInner::Native(c) => {
                 <crate::sys::Consumer as AsAsyncConsumer>::offset_seek(c, topic, vgroup_id, offset)
                     .await
@@ -496,6 +781,43 @@ impl AsAsyncConsumer for Consumer {
         }
     }
 
+    async fn seek_all(&mut self, offsets: &[(String, VGroupId, i64)]) -> SeekResults {
+        match &mut self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::seek_all(c, offsets).await
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::seek_all(c, offsets).await
+            }
+        }
+    }
+
+    async fn pause(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::pause(c, topic, vgroup_id)
+                    .await
+                    .map_err(Into::into)
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::pause(c, topic, vgroup_id).await
+            }
+        }
+    }
+
+    async fn resume(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<()> {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::resume(c, topic, vgroup_id)
+                    .await
+                    .map_err(Into::into)
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::resume(c, topic, vgroup_id).await
+            }
+        }
+    }
+
     async fn committed(&self, topic: &str, vgroup_id: VGroupId) -> RawResult<i64> {
         match &self.0 {
             ConsumerInner::Native(c) => {
@@ -525,6 +847,28 @@ impl AsAsyncConsumer for Consumer {
             }
         }
     }
+
+    fn is_recoverable(&self, err: &RawError) -> bool {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::is_recoverable(c, err)
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::is_recoverable(c, err)
+            }
+        }
+    }
+
+    async fn resubscribe(&self) -> RawResult<()> {
+        match &self.0 {
+            ConsumerInner::Native(c) => {
+                <crate::sys::Consumer as AsAsyncConsumer>::resubscribe(c).await
+            }
+            ConsumerInner::Ws(c) => {
+                <taos_ws::consumer::Consumer as AsAsyncConsumer>::resubscribe(c).await
+            }
+        }
+    }
 }
 
 impl taos_query::tmq::SyncOnAsync for Consumer {}
@@ -564,6 +908,17 @@ mod tests {
         let _tmq = TmqBuilder::from_dsn(dsn)?;
         Ok(())
     }
+
+    #[test]
+    fn builder_from_dsn_obj() -> taos_query::RawResult<()> {
+        use taos_query::prelude::*;
+        let mut dsn: Dsn = "taos+ws://root@localhost:6041".parse()?;
+        dsn.password = Some("pässwörd密码".to_string());
+        dsn.set("group.id", "group1");
+
+        let _tmq = TmqBuilder::from_dsn_obj(dsn)?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -573,6 +928,46 @@ mod async_tests {
     use super::TmqBuilder;
     use crate::TaosBuilder;
 
+    #[tokio::test]
+    async fn test_tmq_from_pooled_taos() -> anyhow::Result<()> {
+        use taos_query::prelude::*;
+        let dsn = std::env::var("TEST_DSN").unwrap_or("taos+ws://localhost:6041".to_string());
+        let pool = TaosBuilder::from_dsn(&dsn)?.pool()?;
+        let taos = pool.get().await?;
+        taos.exec_many([
+            "drop topic if exists tmq_from_pooled",
+            "drop database if exists tmq_from_pooled",
+            "create database tmq_from_pooled wal_retention_period 3600",
+            "create topic tmq_from_pooled as database tmq_from_pooled",
+            "create table tmq_from_pooled.tb(ts timestamp, v int)",
+            "insert into tmq_from_pooled.tb values(now, 1)",
+        ])
+        .await?;
+
+        let mut consumer = TmqBuilder::from_taos(&taos, "from_pooled")?.build().await?;
+        consumer.subscribe(["tmq_from_pooled"]).await?;
+        let (offset, _) = consumer
+            .recv_timeout(Timeout::from_secs(5))
+            .await?
+            .expect("message of the inserted row");
+        consumer.commit(offset).await?;
+        consumer.unsubscribe().await?;
+
+        // The pooled connection is still there for queries.
+        let count: Option<i64> = taos
+            .query_one("select count(*) from tmq_from_pooled.tb")
+            .await?;
+        assert_eq!(count, Some(1));
+
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        taos.exec_many([
+            "drop topic tmq_from_pooled",
+            "drop database tmq_from_pooled",
+        ])
+        .await?;
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_ws_tmq_meta() -> taos_query::RawResult<()> {
         // pretty_env_logger::formatted_timed_builder()
@@ -732,7 +1127,7 @@ mod async_tests {
                 consumer.commit(offset).await?;
             }
         }
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(2)).await;
 
@@ -943,7 +1338,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -1157,7 +1552,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -1344,7 +1739,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::info!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -1485,7 +1880,7 @@ mod async_tests {
             }
         }
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -1712,7 +2107,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::info!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -1932,7 +2327,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::info!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -2155,7 +2550,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -2406,7 +2801,7 @@ mod async_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -2418,6 +2813,56 @@ mod async_tests {
         .await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_ws_tmq_position() -> taos_query::RawResult<()> {
+        use taos_query::prelude::*;
+
+        let mut dsn = Dsn::from_str("tmq+ws://localhost:6041")?;
+        let taos = TaosBuilder::from_dsn(&dsn)?.build().await?;
+
+        let db = "ws_tmq_position";
+        taos.exec_many([
+            format!("drop topic if exists {db}"),
+            format!("drop database if exists {db}"),
+            format!("create database {db} vgroups 1 wal_retention_period 3600"),
+            format!("create topic {db} as database {db}"),
+            format!("create table {db}.tb1 (ts timestamp, v int)"),
+            format!("insert into {db}.tb1 values(now, 1)"),
+            format!("insert into {db}.tb1 values(now + 1s, 2)"),
+        ])
+        .await?;
+
+        dsn.params.insert("group.id".to_string(), db.to_string());
+        dsn.params
+            .insert("auto.offset.reset".to_string(), "earliest".to_string());
+        let mut consumer = TmqBuilder::from_dsn(&dsn)?.build().await?;
+        consumer.subscribe([db]).await?;
+
+        let assignments = consumer.topic_assignment(db).await?;
+        let assignment = assignments[0];
+        let vgroup_id = assignment.vgroup_id();
+        consumer
+            .offset_seek(db, vgroup_id, assignment.begin())
+            .await?;
+        consumer
+            .commit_offset(db, vgroup_id, assignment.begin())
+            .await?;
+        let committed = consumer.committed(db, vgroup_id).await?;
+
+        let message = consumer.recv_timeout(Timeout::from_secs(5)).await?;
+        assert!(message.is_some());
+
+        let position = consumer.position(db, vgroup_id).await?;
+        assert!(position > committed);
+
+        consumer.unsubscribe().await?;
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        taos.exec_many([format!("drop topic {db}"), format!("drop database {db}")])
+            .await?;
+        Ok(())
+    }
 }
 
 #[cfg(feature = "deflate")]
@@ -2717,7 +3162,7 @@ mod tmq_deflate_tests {
         let assignments = consumer.assignments().await.unwrap();
         log::debug!("after seek offset assignments: {:?}", assignments);
 
-        consumer.unsubscribe().await;
+        consumer.unsubscribe().await?;
 
         tokio::time::sleep(Duration::from_secs(1)).await;
 
@@ -149,7 +149,7 @@ fn _test_tmq_meta_sync() -> anyhow::Result<()> {
         consumer.commit(offset)?;
     }
 
-    consumer.unsubscribe();
+    consumer.unsubscribe()?;
 
     std::thread::sleep(Duration::from_secs(2));
 